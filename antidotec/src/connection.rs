@@ -1,12 +1,24 @@
 use self::crdts::Crdt;
+#[cfg(feature = "faults")]
+use crate::faults::{Fault, Faults, Injector};
 use crate::protos::{antidote::*, ApbMessage, ApbMessageCode, MessageCodeError};
 use async_std::io::BufReader;
 use async_std::{
     io::{self, prelude::*},
     net::TcpStream,
+    task,
 };
 use protobuf::ProtobufError;
+use std::future::{self, Future};
 use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+
+/* How often a request waiting for its reply checks whether it was
+interrupted. */
+const INTERRUPT_POLL: Duration = Duration::from_millis(50);
 use std::{convert::TryFrom, u32};
 use thiserror::Error;
 
@@ -47,10 +59,27 @@ pub enum Error {
     Antidote(#[from] AntidoteError),
     #[error("antdote replied with an error message: ({0}) {1}")]
     AntidoteErrResp(AntidoteError, String),
+    #[error("interrupted before sending the request")]
+    Interrupted,
 }
 
 type TxId = Vec<u8>;
 
+/// A point in time of the database, as returned when committing. Opaque
+/// bytes, which can be stored to read the same snapshot later on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timestamp(Vec<u8>);
+
+impl Timestamp {
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 macro_rules! checkr {
     ($resp:expr) => {{
         let resp = $resp;
@@ -68,20 +97,72 @@ pub struct Connection {
     stream: TcpStream,
     scratchpad: Vec<u8>,
     dropped: Option<TxId>,
+    round_trips: u64,
+    broken: bool,
+    timeout: Option<Duration>,
+    interrupt: Option<Arc<AtomicBool>>,
+    #[cfg(feature = "faults")]
+    faults: Option<Injector>,
 }
 
 impl Connection {
     pub async fn new(address: &str) -> Result<Self, Error> {
-        let stream = TcpStream::connect(address).await?;
+        Self::with_timeout(address, None).await
+    }
+
+    /// A connection giving up on connecting, and then on sending a request
+    /// or receiving its reply, after `timeout`. The connection is broken
+    /// once a request timed out, its transaction being left to antidote to
+    /// abort once the connection is closed.
+    pub async fn with_timeout(address: &str, timeout: Option<Duration>) -> Result<Self, Error> {
+        let stream = within(timeout, TcpStream::connect(address)).await?;
         let _ = stream.set_nodelay(true);
 
         Ok(Self {
             stream,
             scratchpad: Vec::new(),
             dropped: None,
+            round_trips: 0,
+            broken: false,
+            timeout,
+            interrupt: None,
+            #[cfg(feature = "faults")]
+            faults: None,
         })
     }
 
+    /// Injects `faults` into the requests of the connection.
+    #[cfg(feature = "faults")]
+    pub fn with_faults(mut self, faults: &Faults) -> Self {
+        self.faults = Some(Injector::new(faults));
+        self
+    }
+
+    /// Once `interrupt` is set, requests are refused with
+    /// `Error::Interrupted` rather than sent, the transaction in progress
+    /// being aborted as a dropped one. A request waiting for its reply gives
+    /// up as a timed out one, with an `Interrupted` io error.
+    pub fn set_interrupt(&mut self, interrupt: Option<Arc<AtomicBool>>) {
+        self.interrupt = interrupt;
+    }
+
+    /// Number of request/response exchanged with antidote since the
+    /// connection was opened.
+    pub fn round_trips(&self) -> u64 {
+        self.round_trips
+    }
+
+    /// Whether the stream failed or desynchronized, once broken a connection
+    /// must not be used anymore.
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// Checks the node is still answering by opening an empty transaction.
+    pub async fn ping(&mut self) -> Result<(), Error> {
+        self.transaction().await?.commit().await
+    }
+
     pub async fn transaction(&mut self) -> Result<Transaction<'_>, Error> {
         self.transaction_with_locks(TransactionLocks::new()).await
     }
@@ -89,13 +170,28 @@ impl Connection {
     pub async fn transaction_with_locks(
         &mut self,
         locks: TransactionLocks,
+    ) -> Result<Transaction<'_>, Error> {
+        self.transaction_at(None, locks).await
+    }
+
+    /// Starts a transaction reading the snapshot at `timestamp` when given,
+    /// the latest one otherwise.
+    #[tracing::instrument(level = "debug", skip(self, timestamp, locks))]
+    pub async fn transaction_at(
+        &mut self,
+        timestamp: Option<&Timestamp>,
+        locks: TransactionLocks,
     ) -> Result<Transaction<'_>, Error> {
         // Dangling transactions leading to errors, shouldn't bubble up.
         if let Err(error) = self.abort_pending_transaction().await {
             tracing::warn!(?error, "aborting dangling transaction");
         }
+        self.check_interrupt()?;
 
         let mut transaction = ApbStartTransaction::new();
+        if let Some(timestamp) = timestamp {
+            transaction.set_timestamp(timestamp.0.clone());
+        }
 
         let mut properties = ApbTxnProperties::default();
         properties.set_exclusive_locks(protobuf::RepeatedField::from_vec(locks.exclusive));
@@ -112,15 +208,124 @@ impl Connection {
         })
     }
 
+    /// Reads objects in a transaction of their own, begun and committed by
+    /// antidote in a single round trip. Such a transaction takes no lock.
+    #[tracing::instrument(level = "debug", skip(self, timestamp, bucket, queries))]
+    pub async fn read_static(
+        &mut self,
+        timestamp: Option<&Timestamp>,
+        bucket: impl Into<RawIdent>,
+        queries: impl IntoIterator<Item = ReadQuery>,
+    ) -> Result<ReadReply, Error> {
+        if let Err(error) = self.abort_pending_transaction().await {
+            tracing::warn!(?error, "aborting dangling transaction");
+        }
+        self.check_interrupt()?;
+
+        let mut transaction = ApbStartTransaction::new();
+        if let Some(timestamp) = timestamp {
+            transaction.set_timestamp(timestamp.0.clone());
+        }
+
+        let mut message = ApbStaticReadObjects::new();
+        message.set_transaction(transaction);
+        message.set_objects(protobuf::RepeatedField::from(bind(bucket.into(), queries)));
+
+        self.send(message).await?;
+        let mut response = self.recv::<ApbStaticReadObjectsResp>().await?;
+        let mut objects = checkr!(response.take_objects());
+
+        Ok(ReadReply {
+            objects: objects.take_objects().into_iter().map(Some).collect(),
+        })
+    }
+
     async fn send<P>(&mut self, request: P) -> Result<(), Error>
     where
         P: ApbMessage,
     {
-        let code = P::code();
+        self.inject().await?;
+        let result = self.write_message(request).await;
+        self.track_broken(result)
+    }
 
-        let message_size = request.compute_size() + 1 /* code byte */;
+    async fn recv<R>(&mut self) -> Result<R, Error>
+    where
+        R: ApbMessage,
+    {
+        let result = self.read_message().await;
+        self.track_broken(result)
+    }
+
+    /* Requests go through untouched unless faults are injected, a dropped
+    connection failing as one closed by the node would. */
+    async fn inject(&mut self) -> Result<(), Error> {
+        #[cfg(feature = "faults")]
+        match self.faults.as_mut().and_then(Injector::request) {
+            Some(Fault::Delay(latency)) => async_std::task::sleep(latency).await,
+            Some(Fault::Drop) => {
+                let _ = self.stream.shutdown(std::net::Shutdown::Both);
+                let error = io::Error::new(io::ErrorKind::ConnectionReset, "injected drop");
+                return self.track_broken(Err(error.into()));
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    fn check_interrupt(&self) -> Result<(), Error> {
+        match &self.interrupt {
+            Some(interrupt) if interrupt.load(Ordering::Relaxed) => Err(Error::Interrupted),
+            _ => Ok(()),
+        }
+    }
+
+    /* An aborted commit is never sent, its transaction is aborted as a
+    dropped one is. */
+    fn inject_abort(&mut self) -> bool {
+        #[cfg(feature = "faults")]
+        if let Some(faults) = &mut self.faults {
+            return faults.commit();
+        }
+
+        false
+    }
+
+    /* Errors on the stream itself leave it in an unknown state, unlike
+    errors replied by antidote. */
+    fn track_broken<T>(&mut self, result: Result<T, Error>) -> Result<T, Error> {
+        match result {
+            Err(Error::Io(_))
+            | Err(Error::Protobuf(_))
+            | Err(Error::CodeMismatch { .. })
+            | Err(Error::UnknownCode(_)) => self.broken = true,
+            _ => {}
+        }
 
+        result
+    }
+
+    async fn write_message<P>(&mut self, request: P) -> Result<(), Error>
+    where
+        P: ApbMessage,
+    {
         self.scratchpad.clear();
+        self.encode(request)?;
+        within(self.timeout, self.stream.write_all(&self.scratchpad[..])).await?;
+
+        Ok(())
+    }
+
+    /* Appends the message to the scratchpad, pipelined requests are written
+    all at once. */
+    fn encode<P>(&mut self, request: P) -> Result<(), Error>
+    where
+        P: ApbMessage,
+    {
+        let code = P::code();
+
+        let message_size = request.compute_size() + 1 /* code byte */;
 
         let mut header: [u8; 5] = [0; 5];
         header[0..4].copy_from_slice(&message_size.to_be_bytes());
@@ -128,12 +333,95 @@ impl Connection {
 
         self.scratchpad.extend_from_slice(&header[..]);
         request.write_to_vec(&mut self.scratchpad)?;
-        self.stream.write_all(&self.scratchpad[..]).await?;
 
         Ok(())
     }
 
-    async fn recv<R>(&mut self) -> Result<R, Error>
+    async fn send_pipeline(&mut self, txid: &[u8], requests: Vec<Request>) -> Result<(), Error> {
+        self.inject().await?;
+        let result = self.write_pipeline(txid, requests).await;
+        self.track_broken(result)
+    }
+
+    async fn write_pipeline(&mut self, txid: &[u8], requests: Vec<Request>) -> Result<(), Error> {
+        self.scratchpad.clear();
+        for request in requests {
+            match request {
+                Request::Read(objects) => {
+                    let mut message = ApbReadObjects::new();
+                    message.set_transaction_descriptor(txid.to_vec());
+                    message.set_boundobjects(protobuf::RepeatedField::from(objects));
+                    self.encode(message)?;
+                }
+                Request::Update(updates) => {
+                    let mut message = ApbUpdateObjects::new();
+                    message.set_transaction_descriptor(txid.to_vec());
+                    message.set_updates(protobuf::RepeatedField::from(updates));
+                    self.encode(message)?;
+                }
+                Request::Commit => {
+                    let mut message = ApbCommitTransaction::new();
+                    message.set_transaction_descriptor(txid.to_vec());
+                    self.encode(message)?;
+                }
+            }
+        }
+        within(self.timeout, self.stream.write_all(&self.scratchpad[..])).await?;
+
+        Ok(())
+    }
+
+    /* Every reply is read even once one of them is an error, so that the
+    next request doesn't get the replies of this one. */
+    async fn recv_pipeline(
+        &mut self,
+        kinds: impl IntoIterator<Item = RequestKind>,
+    ) -> Result<(Vec<ReadReply>, Option<Timestamp>), Error> {
+        let mut replies = Vec::new();
+        let mut commit_time = None;
+        let mut first_error = None;
+
+        for kind in kinds {
+            if self.broken {
+                break;
+            }
+
+            match self.recv_reply(kind).await {
+                Ok(Reply::Read(reply)) => replies.push(reply),
+                Ok(Reply::Update) => {}
+                Ok(Reply::Commit(timestamp)) => commit_time = Some(timestamp),
+                Err(error) => {
+                    first_error.get_or_insert(error);
+                }
+            }
+        }
+
+        match first_error {
+            Some(error) => Err(error),
+            None => Ok((replies, commit_time)),
+        }
+    }
+
+    async fn recv_reply(&mut self, kind: RequestKind) -> Result<Reply, Error> {
+        match kind {
+            RequestKind::Read => {
+                let mut response = checkr!(self.recv::<ApbReadObjectsResp>().await?);
+                Ok(Reply::Read(ReadReply {
+                    objects: response.take_objects().into_iter().map(Some).collect(),
+                }))
+            }
+            RequestKind::Update => {
+                checkr!(self.recv::<ApbOperationResp>().await?);
+                Ok(Reply::Update)
+            }
+            RequestKind::Commit => {
+                let mut response = checkr!(self.recv::<ApbCommitResp>().await?);
+                Ok(Reply::Commit(Timestamp(response.take_commit_time())))
+            }
+        }
+    }
+
+    async fn read_message<R>(&mut self) -> Result<R, Error>
     where
         R: ApbMessage,
     {
@@ -141,15 +429,20 @@ impl Connection {
         a buffered stream ourselves). However since we are acting only on a
         request/response scheme, we should not drop any data by creating
         a BufReader each time. */
+        self.round_trips += 1;
         let mut stream = BufReader::new(&mut self.stream);
+        let scratchpad = &mut self.scratchpad;
 
-        let mut size_buffer: [u8; 4] = [0; 4];
-        stream.read_exact(&mut size_buffer).await?;
-        let message_size = u32::from_be_bytes(size_buffer);
-        self.scratchpad.resize(message_size as usize, 0);
+        let read = within(self.timeout, async {
+            let mut size_buffer: [u8; 4] = [0; 4];
+            stream.read_exact(&mut size_buffer).await?;
+            let message_size = u32::from_be_bytes(size_buffer);
+            scratchpad.resize(message_size as usize, 0);
 
-        assert_eq!((&self.scratchpad[..]).len(), message_size as usize);
-        stream.read_exact(&mut self.scratchpad[..]).await?;
+            assert_eq!(scratchpad.len(), message_size as usize);
+            stream.read_exact(&mut scratchpad[..]).await
+        });
+        interruptible(self.interrupt.as_deref(), read).await?;
 
         let code = ApbMessageCode::try_from(self.scratchpad[0])?;
         if code == ApbMessageCode::ApbErrorResp {
@@ -191,13 +484,65 @@ impl Connection {
     }
 }
 
+async fn within<T>(
+    timeout: Option<Duration>,
+    f: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    match timeout {
+        Some(timeout) => io::timeout(timeout, f).await,
+        None => f.await,
+    }
+}
+
+/* A reply given up on, as a timed out one, leaves the connection broken. */
+async fn interruptible<T>(
+    interrupt: Option<&AtomicBool>,
+    f: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    let interrupt = match interrupt {
+        Some(interrupt) => interrupt,
+        None => return f.await,
+    };
+
+    let mut f = Box::pin(f);
+    let mut tick = Box::pin(task::sleep(INTERRUPT_POLL));
+    future::poll_fn(|cx| {
+        if let Poll::Ready(result) = f.as_mut().poll(cx) {
+            return Poll::Ready(result);
+        }
+
+        while tick.as_mut().poll(cx).is_ready() {
+            if interrupt.load(Ordering::Relaxed) {
+                let interrupted = io::Error::new(io::ErrorKind::Interrupted, "reply given up");
+                return Poll::Ready(Err(interrupted));
+            }
+            tick.set(task::sleep(INTERRUPT_POLL));
+        }
+        Poll::Pending
+    })
+    .await
+}
+
 pub struct Transaction<'a> {
     connection: &'a mut Connection,
     txid: TxId,
 }
 
 impl Transaction<'_> {
-    pub async fn commit(mut self) -> Result<(), Error> {
+    pub async fn commit(self) -> Result<(), Error> {
+        self.commit_at().await?;
+        Ok(())
+    }
+
+    /// Commits and returns the time of the snapshot the transaction ended
+    /// up writing, or reading for a read-only one.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub async fn commit_at(mut self) -> Result<Timestamp, Error> {
+        self.connection.check_interrupt()?;
+        if self.connection.inject_abort() {
+            return Err(Error::Antidote(AntidoteError::Aborted));
+        }
+
         let mut message = ApbCommitTransaction::new();
         message.set_transaction_descriptor(self.txid.clone());
 
@@ -208,33 +553,63 @@ impl Transaction<'_> {
         self.txid = Vec::new();
         mem::forget(self);
 
-        checkr!(result?);
-        Ok(())
+        let mut response = checkr!(result?);
+        Ok(Timestamp(response.take_commit_time()))
     }
 
+    /// Sends the requests of `pipeline` back to back and waits for their
+    /// replies, in a single round trip. Returns the replies of the reads, in
+    /// order.
+    #[tracing::instrument(level = "debug", skip(self, pipeline))]
+    pub async fn pipeline(&mut self, pipeline: Pipeline) -> Result<Vec<ReadReply>, Error> {
+        self.connection.check_interrupt()?;
+        let kinds: Vec<_> = pipeline.requests.iter().map(Request::kind).collect();
+
+        self.connection
+            .send_pipeline(&self.txid, pipeline.requests)
+            .await?;
+        let (replies, _) = self.connection.recv_pipeline(kinds).await?;
+
+        Ok(replies)
+    }
+
+    /// As `pipeline`, committing in the same round trip. Antidote aborts a
+    /// transaction once one of its updates failed, its commit then fails too.
+    #[tracing::instrument(level = "debug", skip(self, pipeline))]
+    pub async fn commit_pipeline(mut self, pipeline: Pipeline) -> Result<Vec<ReadReply>, Error> {
+        self.connection.check_interrupt()?;
+        if self.connection.inject_abort() {
+            return Err(Error::Antidote(AntidoteError::Aborted));
+        }
+
+        let mut requests = pipeline.requests;
+        requests.push(Request::Commit);
+        let kinds: Vec<_> = requests.iter().map(Request::kind).collect();
+
+        self.connection.send_pipeline(&self.txid, requests).await?;
+        let result = self.connection.recv_pipeline(kinds).await;
+
+        /* Don't drop to avoid calling abort */
+        self.txid = Vec::new();
+        mem::forget(self);
+
+        let (replies, _) = result?;
+        Ok(replies)
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, bucket, queries))]
     pub async fn read(
         &mut self,
         bucket: impl Into<RawIdent>,
         queries: impl IntoIterator<Item = ReadQuery>,
     ) -> Result<ReadReply, Error> {
+        self.connection.check_interrupt()?;
         let bucket = bucket.into();
 
         let mut message = ApbReadObjects::new();
         message.set_transaction_descriptor(self.txid.clone());
 
-        let bound_objects: Vec<_> = queries
-            .into_iter()
-            .map(|q| {
-                let mut bound = ApbBoundObject::new();
-                bound.set_bucket(bucket.clone());
-                bound.set_field_type(q.ty);
-                bound.set_key(q.key);
-
-                bound
-            })
-            .collect();
-
-        message.set_boundobjects(protobuf::RepeatedField::from(bound_objects));
+        message.set_boundobjects(protobuf::RepeatedField::from(bind(bucket, queries)));
 
         self.connection.send(message).await?;
         let mut response: ApbReadObjectsResp =
@@ -245,32 +620,19 @@ impl Transaction<'_> {
         })
     }
 
+    #[tracing::instrument(level = "debug", skip(self, bucket, queries))]
     pub async fn update(
         &mut self,
         bucket: impl Into<RawIdent>,
         queries: impl IntoIterator<Item = UpdateQuery>,
     ) -> Result<(), Error> {
+        self.connection.check_interrupt()?;
         let bucket = bucket.into();
 
         let mut message = ApbUpdateObjects::new();
         message.set_transaction_descriptor(self.txid.clone());
 
-        let bound_objects: Vec<_> = queries
-            .into_iter()
-            .map(|q| {
-                let mut bound = ApbBoundObject::new();
-                bound.set_bucket(bucket.clone());
-                bound.set_field_type(q.ty);
-                bound.set_key(q.key);
-
-                let mut op = ApbUpdateOp::new();
-                op.set_boundobject(bound);
-                op.set_operation(q.update);
-
-                op
-            })
-            .collect();
-        message.set_updates(protobuf::RepeatedField::from(bound_objects));
+        message.set_updates(protobuf::RepeatedField::from(bind_updates(bucket, queries)));
 
         self.connection.send(message).await?;
         checkr!(self.connection.recv::<ApbOperationResp>().await?);
@@ -324,6 +686,103 @@ impl TransactionLocks {
 pub type RawIdent = Vec<u8>;
 pub type RawIdentSlice<'a> = &'a [u8];
 
+/// Requests of a transaction sent back to back, without waiting for the
+/// reply of each one before sending the next.
+#[derive(Default)]
+pub struct Pipeline {
+    requests: Vec<Request>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read(
+        mut self,
+        bucket: impl Into<RawIdent>,
+        queries: impl IntoIterator<Item = ReadQuery>,
+    ) -> Self {
+        self.requests
+            .push(Request::Read(bind(bucket.into(), queries)));
+        self
+    }
+
+    pub fn update(
+        mut self,
+        bucket: impl Into<RawIdent>,
+        queries: impl IntoIterator<Item = UpdateQuery>,
+    ) -> Self {
+        self.requests
+            .push(Request::Update(bind_updates(bucket.into(), queries)));
+        self
+    }
+}
+
+enum Request {
+    Read(Vec<ApbBoundObject>),
+    Update(Vec<ApbUpdateOp>),
+    Commit,
+}
+
+impl Request {
+    fn kind(&self) -> RequestKind {
+        match self {
+            Request::Read(_) => RequestKind::Read,
+            Request::Update(_) => RequestKind::Update,
+            Request::Commit => RequestKind::Commit,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum RequestKind {
+    Read,
+    Update,
+    Commit,
+}
+
+enum Reply {
+    Read(ReadReply),
+    Update,
+    Commit(Timestamp),
+}
+
+fn bind_updates(
+    bucket: RawIdent,
+    queries: impl IntoIterator<Item = UpdateQuery>,
+) -> Vec<ApbUpdateOp> {
+    queries
+        .into_iter()
+        .map(|q| {
+            let mut bound = ApbBoundObject::new();
+            bound.set_bucket(bucket.clone());
+            bound.set_field_type(q.ty);
+            bound.set_key(q.key);
+
+            let mut op = ApbUpdateOp::new();
+            op.set_boundobject(bound);
+            op.set_operation(q.update);
+
+            op
+        })
+        .collect()
+}
+
+fn bind(bucket: RawIdent, queries: impl IntoIterator<Item = ReadQuery>) -> Vec<ApbBoundObject> {
+    queries
+        .into_iter()
+        .map(|q| {
+            let mut bound = ApbBoundObject::new();
+            bound.set_bucket(bucket.clone());
+            bound.set_field_type(q.ty);
+            bound.set_key(q.key);
+
+            bound
+        })
+        .collect()
+}
+
 pub struct ReadQuery {
     key: RawIdent,
     ty: CRDT_type,
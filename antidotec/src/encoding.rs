@@ -69,8 +69,21 @@ pub mod lwwreg {
 
 pub mod mvreg {
     use crate::connection::{mvreg::*, RawIdent, UpdateQuery};
+    use std::mem;
+    use std::time::Duration;
 
     pub fn set_u64(key: impl Into<RawIdent>, x: u64) -> UpdateQuery {
         set(key, (x.to_le_bytes())[..].into())
     }
+
+    /// Encoded as with `lwwreg::set_duration`, each value is read back with
+    /// `lwwreg::read_duration`.
+    pub fn set_duration(key: impl Into<RawIdent>, duration: Duration) -> UpdateQuery {
+        let mut buffer = Vec::with_capacity(mem::size_of::<u64>() + mem::size_of::<u32>());
+
+        buffer.extend_from_slice(&duration.as_secs().to_le_bytes());
+        buffer.extend_from_slice(&duration.subsec_nanos().to_le_bytes());
+
+        set(key, buffer)
+    }
 }
@@ -0,0 +1,125 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Failures injected into the requests of connections, for tests only.
+/// Each connection draws from a sequence of its own, derived from `seed`
+/// and the order connections were opened in, so that a run can be replayed.
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    pub seed: u64,
+    /// Delay added before a request, to a `latency_rate` of them.
+    pub latency: Duration,
+    pub latency_rate: f64,
+    /// Share of requests on which the connection is closed, failing them
+    /// with an io error and breaking the connection.
+    pub drop_rate: f64,
+    /// Share of commits turned into aborts, as antidote does on conflicts.
+    pub abort_rate: f64,
+    opened: Arc<AtomicU64>,
+}
+
+/// What happens to a request.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Fault {
+    Delay(Duration),
+    Drop,
+}
+
+/* Parsed from `seed=1,latency=5ms@0.1,drop=0.01,abort=0.05`, any of them
+can be left out. */
+impl FromStr for Faults {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut faults = Faults::default();
+
+        for setting in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (name, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("expected name=value, got: {}", setting))?;
+
+            match name {
+                "seed" => faults.seed = value.parse().map_err(|_| invalid(setting))?,
+                "latency" => {
+                    let (latency, rate) = value.split_once('@').unwrap_or((value, "1"));
+                    faults.latency = parse_millis(latency).ok_or_else(|| invalid(setting))?;
+                    faults.latency_rate = parse_rate(rate).ok_or_else(|| invalid(setting))?;
+                }
+                "drop" => faults.drop_rate = parse_rate(value).ok_or_else(|| invalid(setting))?,
+                "abort" => faults.abort_rate = parse_rate(value).ok_or_else(|| invalid(setting))?,
+                _ => return Err(format!("unknown fault: {}", name)),
+            }
+        }
+
+        Ok(faults)
+    }
+}
+
+fn invalid(setting: &str) -> String {
+    format!("invalid fault: {}", setting)
+}
+
+fn parse_millis(s: &str) -> Option<Duration> {
+    let millis = s.strip_suffix("ms").unwrap_or(s).parse().ok()?;
+    Some(Duration::from_millis(millis))
+}
+
+fn parse_rate(s: &str) -> Option<f64> {
+    let rate: f64 = s.parse().ok()?;
+    if (0.0..=1.0).contains(&rate) {
+        Some(rate)
+    } else {
+        None
+    }
+}
+
+/// The faults of one connection.
+#[derive(Debug)]
+pub(crate) struct Injector {
+    faults: Faults,
+    state: u64,
+}
+
+impl Injector {
+    pub(crate) fn new(faults: &Faults) -> Self {
+        let opened = faults.opened.fetch_add(1, Ordering::Relaxed);
+        let seed = faults.seed ^ opened.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+
+        Self {
+            faults: faults.clone(),
+            state: seed | 1,
+        }
+    }
+
+    pub(crate) fn request(&mut self) -> Option<Fault> {
+        if self.roll(self.faults.drop_rate) {
+            return Some(Fault::Drop);
+        }
+
+        if self.roll(self.faults.latency_rate) {
+            return Some(Fault::Delay(self.faults.latency));
+        }
+
+        None
+    }
+
+    pub(crate) fn commit(&mut self) -> bool {
+        self.roll(self.faults.abort_rate)
+    }
+
+    /* Rates of zero draw nothing, leaving the sequence of the others as
+    it would be without them. */
+    fn roll(&mut self, rate: f64) -> bool {
+        if rate <= 0.0 {
+            return false;
+        }
+
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        let draw = (self.state >> 11) as f64 / (1u64 << 53) as f64;
+        draw < rate
+    }
+}
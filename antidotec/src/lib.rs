@@ -1,5 +1,9 @@
 pub mod connection;
 pub mod encoding;
+#[cfg(feature = "faults")]
+pub mod faults;
 pub(crate) mod protos;
 
 pub use crate::connection::*;
+#[cfg(feature = "faults")]
+pub use crate::faults::Faults;
@@ -0,0 +1,62 @@
+use elmerfs::bench::{self, DriverTarget, MountTarget, Options, Workload};
+use elmerfs::{AddressBook, Bucket, Config, View};
+use std::env;
+use std::sync::Arc;
+
+const BENCH_VIEW: View = 0;
+const BENCH_BUCKET: Bucket = Bucket::new(2);
+const ANTIDOTE_URL: &str = "127.0.0.1:8101";
+
+const WORKLOADS: &[(&str, Workload)] = &[
+    ("seq-write", Workload::SequentialWrite),
+    ("seq-read", Workload::SequentialRead),
+    ("rand-write", Workload::RandomWrite),
+    ("rand-read", Workload::RandomRead),
+    ("metadata", Workload::Metadata),
+];
+
+fn config() -> Config {
+    let addresses = Arc::new(AddressBook::with_addresses(vec![String::from(
+        ANTIDOTE_URL,
+    )]));
+    Config::new(BENCH_VIEW, BENCH_BUCKET, addresses)
+}
+
+/* Small enough to run on every change, as `cargo bench` does; the `bench`
+subcommand is meant for longer runs. Reports of the same workload are
+compared from one release to the next. */
+fn options(workload: Workload) -> Options {
+    Options {
+        workload,
+        threads: 4,
+        ops: 256,
+        block_size: 64 * 1024,
+        file_size: 4 * 1024 * 1024,
+    }
+}
+
+fn main() {
+    /* `cargo bench -- NAME` runs the workloads named so only. */
+    let filter: Vec<String> = env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .collect();
+    let selected = WORKLOADS
+        .iter()
+        .filter(|(name, _)| filter.is_empty() || filter.iter().any(|f| name.contains(f.as_str())));
+
+    let driver = DriverTarget::connect(config()).expect("failed to connect to antidote");
+    let mountpoint = tempfile::tempdir().expect("failed to create mountpoint tmpdir");
+    let mount = elmerfs::mount(config(), mountpoint.path()).expect("failed to mount");
+    let mounted = MountTarget::new(mountpoint.path()).expect("failed to prepare the mount");
+
+    for (name, workload) in selected {
+        let report = bench::run(&driver, &options(*workload)).expect("benchmark failed");
+        println!("driver/{}\n{}", name, report);
+
+        let report = bench::run(&mounted, &options(*workload)).expect("benchmark failed");
+        println!("mount/{}\n{}", name, report);
+    }
+
+    mount.unmount().expect("failed to unmount");
+}
@@ -0,0 +1,401 @@
+use crate::driver::{runtime, Config, Driver, ROOT_INO};
+use crate::model::inode::Owner;
+use crate::view::NameRef;
+use nix::libc;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const BENCH_DIR_NAME: &str = "elmerfs-bench";
+
+/// What a benchmark does over and over.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Workload {
+    SequentialWrite,
+    SequentialRead,
+    RandomWrite,
+    RandomRead,
+    /// Creates a file, looks it up and removes it.
+    Metadata,
+}
+
+impl FromStr for Workload {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "seq-write" => Ok(Workload::SequentialWrite),
+            "seq-read" => Ok(Workload::SequentialRead),
+            "rand-write" => Ok(Workload::RandomWrite),
+            "rand-read" => Ok(Workload::RandomRead),
+            "metadata" => Ok(Workload::Metadata),
+            _ => Err(format!("unknown workload: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub workload: Workload,
+    /// Threads running the workload, each on a file of its own.
+    pub threads: usize,
+    /// Operations each thread does.
+    pub ops: usize,
+    /// Bytes read or written by each operation.
+    pub block_size: usize,
+    /// Size of the file of each thread, offsets wrap around it.
+    pub file_size: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            workload: Workload::SequentialWrite,
+            threads: 1,
+            ops: 1024,
+            block_size: 64 * 1024,
+            file_size: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// What a benchmark sustained.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub ops: usize,
+    pub bytes: u64,
+    pub elapsed: Duration,
+    /// Latency of every operation, sorted.
+    pub latencies: Vec<Duration>,
+}
+
+impl Report {
+    pub fn iops(&self) -> f64 {
+        self.ops as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Bytes per second.
+    pub fn throughput(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// The latency `percentile` percent of the operations stayed under.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::from_secs(0);
+        }
+
+        let rank = (percentile / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} ops in {:.3}s", self.ops, self.elapsed.as_secs_f64())?;
+        writeln!(f, "iops: {:.1}", self.iops())?;
+        writeln!(
+            f,
+            "throughput: {:.1} MiB/s",
+            self.throughput() / (1024.0 * 1024.0)
+        )?;
+        for percentile in &[50.0, 90.0, 99.0, 100.0] {
+            writeln!(
+                f,
+                "p{}: {:.3}ms",
+                percentile,
+                self.percentile(*percentile).as_secs_f64() * 1000.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Where a benchmark runs, files being named after the thread using them.
+pub trait Target: Sync {
+    type File: Send;
+
+    fn create(&self, name: &str) -> io::Result<Self::File>;
+    fn write_at(&self, file: &mut Self::File, data: &[u8], offset: u64) -> io::Result<()>;
+    fn read_at(&self, file: &mut Self::File, len: usize, offset: u64) -> io::Result<usize>;
+    fn close(&self, file: Self::File) -> io::Result<()>;
+    fn stat(&self, name: &str) -> io::Result<()>;
+    fn remove(&self, name: &str) -> io::Result<()>;
+}
+
+/// Runs in a directory of a mounted filesystem, through the kernel.
+#[derive(Debug)]
+pub struct MountTarget {
+    dir: PathBuf,
+}
+
+impl MountTarget {
+    pub fn new(mountpoint: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = mountpoint.into().join(BENCH_DIR_NAME);
+        fs::create_dir_all(&dir)?;
+
+        Ok(Self { dir })
+    }
+}
+
+impl Target for MountTarget {
+    type File = File;
+
+    fn create(&self, name: &str) -> io::Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(self.dir.join(name))
+    }
+
+    fn write_at(&self, file: &mut File, data: &[u8], offset: u64) -> io::Result<()> {
+        file.write_all_at(data, offset)
+    }
+
+    fn read_at(&self, file: &mut File, len: usize, offset: u64) -> io::Result<usize> {
+        let mut buffer = vec![0; len];
+        FileExt::read_at(file, &mut buffer, offset)
+    }
+
+    fn close(&self, file: File) -> io::Result<()> {
+        file.sync_all()
+    }
+
+    fn stat(&self, name: &str) -> io::Result<()> {
+        fs::metadata(self.dir.join(name)).map(|_| ())
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        fs::remove_file(self.dir.join(name))
+    }
+}
+
+/// Runs against the driver directly, without a mount nor the kernel, as
+/// root.
+#[derive(Debug)]
+pub struct DriverTarget {
+    driver: Driver,
+    dir: u64,
+    unique: AtomicU64,
+}
+
+impl DriverTarget {
+    pub fn connect(cfg: Config) -> io::Result<Self> {
+        let driver = runtime::block_on(Driver::new(cfg))?;
+
+        /* Creations are told apart from those of previous runs. */
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let mut target = Self {
+            driver,
+            dir: ROOT_INO,
+            unique: AtomicU64::new(unique),
+        };
+
+        let name = target.name(BENCH_DIR_NAME)?;
+        let dir = match runtime::block_on(target.driver.lookup(ROOT, ROOT_INO, name.clone())) {
+            Ok(entry) => entry.attr.ino,
+            Err(_) => {
+                let unique = target.unique();
+                runtime::block_on(target.driver.mkdir(ROOT, 0o755, ROOT_INO, name, unique))?
+                    .attr
+                    .ino
+            }
+        };
+        target.dir = dir;
+
+        Ok(target)
+    }
+
+    fn name(&self, name: &str) -> io::Result<NameRef> {
+        self.driver
+            .naming()
+            .parse(name.as_bytes())
+            .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))
+    }
+
+    fn unique(&self) -> u64 {
+        self.unique.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn ino(&self, name: &str) -> io::Result<u64> {
+        let name = self.name(name)?;
+        Ok(runtime::block_on(self.driver.lookup(ROOT, self.dir, name))?
+            .attr
+            .ino)
+    }
+}
+
+const ROOT: Owner = Owner { uid: 0, gid: 0 };
+
+impl Target for DriverTarget {
+    /* The inode and its handle. */
+    type File = (u64, u64);
+
+    fn create(&self, name: &str) -> io::Result<(u64, u64)> {
+        let name = self.name(name)?;
+        let flags = (libc::O_RDWR | libc::O_TRUNC) as u32;
+        let (entry, fh) = runtime::block_on(self.driver.create(
+            ROOT,
+            0o644,
+            flags,
+            self.dir,
+            name,
+            self.unique(),
+        ))?;
+
+        Ok((entry.attr.ino, fh))
+    }
+
+    fn write_at(&self, file: &mut (u64, u64), data: &[u8], offset: u64) -> io::Result<()> {
+        let (ino, fh) = *file;
+        let buffer = self.driver.buffer(data);
+        Ok(runtime::block_on(
+            self.driver.write(ino, fh, buffer, offset),
+        )?)
+    }
+
+    fn read_at(&self, file: &mut (u64, u64), len: usize, offset: u64) -> io::Result<usize> {
+        let (ino, _) = *file;
+        Ok(runtime::block_on(self.driver.read(ino, offset, len as u32))?.len())
+    }
+
+    fn close(&self, file: (u64, u64)) -> io::Result<()> {
+        let (ino, fh) = file;
+        runtime::block_on(self.driver.fsync(ino))?;
+        Ok(runtime::block_on(self.driver.release(ino, fh, None))?)
+    }
+
+    fn stat(&self, name: &str) -> io::Result<()> {
+        self.ino(name).map(|_| ())
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        let name = self.name(name)?;
+        Ok(runtime::block_on(self.driver.unlink(ROOT, self.dir, name))?)
+    }
+}
+
+/// Runs `options.ops` operations of the workload from each thread, files
+/// being written first, unmeasured, when read back.
+pub fn run<T: Target>(target: &T, options: &Options) -> io::Result<Report> {
+    assert!(options.threads > 0 && options.block_size > 0);
+    assert!(options.file_size >= options.block_size as u64);
+
+    let started = Instant::now();
+    let results: Vec<io::Result<(Vec<Duration>, u64)>> = thread::scope(|scope| {
+        let threads: Vec<_> = (0..options.threads)
+            .map(|id| scope.spawn(move || run_thread(target, options, id)))
+            .collect();
+
+        threads
+            .into_iter()
+            .map(|thread| thread.join().expect("benchmark thread panicked"))
+            .collect()
+    });
+    let elapsed = started.elapsed();
+
+    let mut latencies = Vec::with_capacity(options.threads * options.ops);
+    let mut bytes = 0;
+    for result in results {
+        let (thread_latencies, thread_bytes) = result?;
+        latencies.extend(thread_latencies);
+        bytes += thread_bytes;
+    }
+    latencies.sort();
+
+    Ok(Report {
+        ops: latencies.len(),
+        bytes,
+        elapsed,
+        latencies,
+    })
+}
+
+fn run_thread<T: Target>(
+    target: &T,
+    options: &Options,
+    id: usize,
+) -> io::Result<(Vec<Duration>, u64)> {
+    let name = format!("bench-{}", id);
+    let mut latencies = Vec::with_capacity(options.ops);
+    let mut bytes = 0;
+
+    if options.workload == Workload::Metadata {
+        for op in 0..options.ops {
+            let name = format!("{}-{}", name, op);
+            let started = Instant::now();
+
+            let file = target.create(&name)?;
+            target.close(file)?;
+            target.stat(&name)?;
+            target.remove(&name)?;
+
+            latencies.push(started.elapsed());
+        }
+        return Ok((latencies, bytes));
+    }
+
+    let block = vec![0xA5u8; options.block_size];
+    let blocks = options.file_size / options.block_size as u64;
+    let mut random = Random::new(id as u64);
+
+    let mut file = target.create(&name)?;
+    let reads = matches!(
+        options.workload,
+        Workload::SequentialRead | Workload::RandomRead
+    );
+    if reads {
+        for index in 0..blocks {
+            target.write_at(&mut file, &block, index * options.block_size as u64)?;
+        }
+    }
+
+    for op in 0..options.ops {
+        let index = match options.workload {
+            Workload::SequentialWrite | Workload::SequentialRead => op as u64 % blocks,
+            _ => random.next() % blocks,
+        };
+        let offset = index * options.block_size as u64;
+        let started = Instant::now();
+
+        if reads {
+            bytes += target.read_at(&mut file, options.block_size, offset)? as u64;
+        } else {
+            target.write_at(&mut file, &block, offset)?;
+            bytes += options.block_size as u64;
+        }
+
+        latencies.push(started.elapsed());
+    }
+
+    target.close(file)?;
+    target.remove(&name)?;
+    Ok((latencies, bytes))
+}
+
+/* Offsets of random workloads, the same ones from one run to the next. */
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
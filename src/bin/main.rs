@@ -1,66 +1,928 @@
-use clap::{App, Arg};
-use elmerfs::{self, AddressBook, Bucket, Config, View};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use elmerfs::{
+    self,
+    bench::{self, DriverTarget, MountTarget},
+    config::{FileConfig, Locks},
+    AddressBook, AtimePolicy, AttrShape, Bucket, Client, Compression, Concurrency, Config,
+    Consistency, EncryptionKey, FuseOptions, IdMap, LockPolicy, Naming, Owner, PageConflicts,
+    RoundTripBudget, Squash, View, DEFAULT_ANONYMOUS_OWNER, DEFAULT_PAGE_SIZE, DEFAULT_POOL_SIZE,
+    DEFAULT_POOL_TIMEOUT, DEFAULT_REQUEST_TIMEOUT, DEFAULT_RETRIES,
+};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tracing_subscriber::{self, filter::EnvFilter};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 const MAIN_BUCKET: Bucket = Bucket::new(0);
+const DEFAULT_ANTIDOTE: &str = "127.0.0.1:8101";
+const KEY_ENV: &str = "ELMERFS_KEY";
 
 fn main() {
-    let filter = EnvFilter::try_from_default_env()
-        .unwrap_or_default()
-        .add_directive("polling=warn".parse().unwrap())
-        .add_directive("fuse::request=info".parse().unwrap())
-        .add_directive("async_io=info".parse().unwrap())
-        .add_directive("async_std=info".parse().unwrap());
-
-    let (non_blocking_appender, _guard) = tracing_appender::non_blocking(std::io::stdout());
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_writer(non_blocking_appender)
-        .init();
-
     let args = App::new("elmerfs")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("mountpoint")
                 .long("mount")
                 .short("m")
                 .value_name("MOUNTPOINT")
                 .takes_value(true)
-                .required(true),
+                .required_unless("config"),
         )
         .arg(
             Arg::with_name("antidote")
                 .long("antidote")
                 .short("s")
                 .value_name("URL")
-                .default_value("127.0.0.1:8101")
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("fallback_dc")
+                .long("fallback-dc")
+                .value_name("URL,...")
+                .takes_value(true)
+                .number_of_values(1)
                 .multiple(true),
         )
         .arg(Arg::with_name("nlocks").long("no-locks").takes_value(false))
+        .arg(
+            Arg::with_name("locks")
+                .long("locks")
+                .value_name("POLICY")
+                .possible_values(&["none", "namespace", "full"])
+                .conflicts_with("nlocks")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("consistency")
+                .long("consistency")
+                .value_name("MODE")
+                .possible_values(&["strong", "eventual"])
+                .conflicts_with_all(&["nlocks", "locks"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("page_size")
+                .long("page-size")
+                .value_name("BYTES")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("page_conflicts")
+                .long("page-conflicts")
+                .value_name("POLICY")
+                .possible_values(&["last-writer", "versioned"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("compression")
+                .long("compression")
+                .value_name("CODEC")
+                .possible_values(&["none", "lz4", "zstd"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("key_file")
+                .long("key-file")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("coarse_timestamps")
+                .long("coarse-timestamps")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("squash_owner")
+                .long("squash-owner")
+                .value_name("UID:GID")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("squash")
+                .long("squash")
+                .value_name("POLICY")
+                .possible_values(&["none", "root", "all"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("anonymous_owner")
+                .long("anonymous-owner")
+                .value_name("UID:GID")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("uid_map")
+                .long("uid-map")
+                .value_name("LOCAL:STORED")
+                .takes_value(true)
+                .number_of_values(1)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("gid_map")
+                .long("gid-map")
+                .value_name("LOCAL:STORED")
+                .takes_value(true)
+                .number_of_values(1)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("round_trip_budget")
+                .long("round-trip-budget")
+                .value_name("ROUND_TRIPS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("strict_round_trip_budget")
+                .long("strict-round-trip-budget")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("default_permissions")
+                .long("default-permissions")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("flush_on_close")
+                .long("flush-on-close")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("big_writes")
+                .long("big-writes")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("max_write")
+                .long("max-write")
+                .value_name("BYTES")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max_readahead")
+                .long("max-readahead")
+                .value_name("BYTES")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("allow_other")
+                .long("allow-other")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("auto_unmount")
+                .long("auto-unmount")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("fuse_threads")
+                .long("fuse-threads")
+                .value_name("COUNT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("retries")
+                .long("retries")
+                .value_name("COUNT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pool_size")
+                .long("pool-size")
+                .value_name("CONNECTIONS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pool_timeout")
+                .long("pool-timeout")
+                .value_name("SECONDS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("request_timeout")
+                .long("request-timeout")
+                .value_name("SECONDS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max_metadata_ops")
+                .long("max-metadata-ops")
+                .value_name("COUNT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max_read_ops")
+                .long("max-read-ops")
+                .value_name("COUNT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max_write_ops")
+                .long("max-write-ops")
+                .value_name("COUNT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max_other_ops")
+                .long("max-other-ops")
+                .value_name("COUNT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("metrics")
+                .long("metrics")
+                .value_name("ADDRESS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("otlp_endpoint")
+                .long("otlp-endpoint")
+                .value_name("URL")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("name_separator")
+                .long("name-separator")
+                .value_name("CHAR")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("show_local_view")
+                .long("show-local-view")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("atime")
+                .long("atime")
+                .value_name("POLICY")
+                .possible_values(&["relatime", "strictatime", "noatime"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("attr_timeout")
+                .long("attr-timeout")
+                .value_name("SECONDS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("entry_timeout")
+                .long("entry-timeout")
+                .value_name("SECONDS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .short("c")
+                .value_name("FILE")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("view")
                 .long("view")
                 .value_name("VIEW")
-                .required(true),
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("view_file")
+                .long("view-file")
+                .value_name("FILE")
+                .conflicts_with("view")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("snapshot")
+                .long("snapshot")
+                .value_name("NAME")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("root_path")
+                .long("root-path")
+                .value_name("PATH")
+                .takes_value(true),
+        )
+        .subcommand(
+            SubCommand::with_name("fsck")
+                .about("Checks the consistency of the filesystem, which must not be mounted")
+                .arg(Arg::with_name("repair").long("repair").takes_value(false)),
+        )
+        .subcommand(
+            SubCommand::with_name("gc")
+                .about("Removes orphans left by views that went away, none must be mounted"),
+        )
+        .subcommand(
+            SubCommand::with_name("rm")
+                .about("Removes a directory and everything under it, in batches")
+                .arg(Arg::with_name("path").value_name("PATH").required(true)),
+        )
+        .subcommand(SubCommand::with_name("stats").about("Prints what the bucket holds"))
+        .subcommand(
+            SubCommand::with_name("snapshot")
+                .about("Manages the named snapshots of the bucket")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("create")
+                        .arg(Arg::with_name("name").value_name("NAME").required(true)),
+                )
+                .subcommand(SubCommand::with_name("list"))
+                .subcommand(
+                    SubCommand::with_name("delete")
+                        .arg(Arg::with_name("name").value_name("NAME").required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("views")
+                .about("Manages the views known to the bucket")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("list"))
+                .subcommand(
+                    SubCommand::with_name("forget")
+                        .arg(Arg::with_name("view").value_name("VIEW").required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("Measures what the bucket sustains, directly or through a mount")
+                .arg(
+                    Arg::with_name("workload")
+                        .long("workload")
+                        .value_name("WORKLOAD")
+                        .possible_values(&[
+                            "seq-write",
+                            "seq-read",
+                            "rand-write",
+                            "rand-read",
+                            "metadata",
+                        ])
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("threads")
+                        .long("threads")
+                        .value_name("COUNT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("ops")
+                        .long("ops")
+                        .value_name("COUNT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("block_size")
+                        .long("block-size")
+                        .value_name("BYTES")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("file_size")
+                        .long("file-size")
+                        .value_name("BYTES")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("mounted")
+                        .long("mounted")
+                        .value_name("MOUNTPOINT")
+                        .takes_value(true),
+                ),
         )
         .get_matches();
 
-    let mountpoint = args.value_of_os("mountpoint").unwrap();
+    let file = args
+        .value_of("config")
+        .map(|path| FileConfig::load(path).expect("invalid config file"))
+        .unwrap_or_default();
+
+    let _telemetry = elmerfs::telemetry::init(
+        file.log_level.as_deref(),
+        args.value_of("otlp_endpoint")
+            .or(file.otlp_endpoint.as_deref()),
+    );
+
     let addresses = args
         .values_of("antidote")
-        .unwrap()
-        .map(String::from)
-        .collect();
-    let locks = !args.is_present("nlocks");
+        .map(|addresses| addresses.map(String::from).collect())
+        .or(file.antidote)
+        .unwrap_or_else(|| vec![String::from(DEFAULT_ANTIDOTE)]);
+    let fallbacks = args
+        .values_of("fallback_dc")
+        .map(|dcs| {
+            dcs.map(|dc| dc.split(',').map(String::from).collect())
+                .collect()
+        })
+        .or(file.fallback_dcs)
+        .unwrap_or_default();
+    let bucket = file.bucket.map(Bucket::new).unwrap_or(MAIN_BUCKET);
+    let locks: LockPolicy = if args.is_present("nlocks") {
+        LockPolicy::None
+    } else {
+        let file_locks = file.locks.as_ref().map(|locks| match locks {
+            Locks::Enabled(true) => String::from("full"),
+            Locks::Enabled(false) => String::from("none"),
+            Locks::Policy(policy) => policy.clone(),
+        });
 
-    let view = args.value_of("view").unwrap();
-    let view: View = view.parse().unwrap();
+        let consistency = args
+            .value_of("consistency")
+            .map(String::from)
+            .or(file.consistency)
+            .map(|mode| {
+                mode.parse::<Consistency>()
+                    .expect("invalid consistency mode")
+                    .locks()
+            });
 
-    let cfg = Config {
-        view,
-        bucket: MAIN_BUCKET,
-        addresses: Arc::new(AddressBook::with_addresses(addresses)),
+        args.value_of("locks")
+            .map(String::from)
+            .or(file_locks)
+            .map(|locks| locks.parse().expect("invalid lock policy"))
+            .or(consistency)
+            .unwrap_or_default()
+    };
+    let page_size = args
+        .value_of("page_size")
+        .map(|size| size.parse().expect("invalid page size"))
+        .or(file.page_size)
+        .unwrap_or(DEFAULT_PAGE_SIZE);
+    let attr_shape = AttrShape {
+        subsec_timestamps: !args.is_present("coarse_timestamps")
+            && !file.coarse_timestamps.unwrap_or(false),
+        squash_owner: args
+            .value_of("squash_owner")
+            .or(file.squash_owner.as_deref())
+            .map(parse_owner),
+    };
+    let squash: Squash = args
+        .value_of("squash")
+        .or(file.squash.as_deref())
+        .map(|squash| squash.parse().expect("invalid squash policy"))
+        .unwrap_or_default();
+    let anonymous_owner = args
+        .value_of("anonymous_owner")
+        .or(file.anonymous_owner.as_deref())
+        .map(parse_owner)
+        .unwrap_or(DEFAULT_ANONYMOUS_OWNER);
+    let mut idmap = IdMap::new(squash, anonymous_owner);
+    let uid_map = args
+        .values_of("uid_map")
+        .map(|ids| ids.map(String::from).collect())
+        .or(file.uid_map)
+        .unwrap_or_default();
+    for ids in uid_map {
+        let (local, stored) = parse_id_mapping(&ids);
+        idmap.map_uid(local, stored);
+    }
+    let gid_map = args
+        .values_of("gid_map")
+        .map(|ids| ids.map(String::from).collect())
+        .or(file.gid_map)
+        .unwrap_or_default();
+    for ids in gid_map {
+        let (local, stored) = parse_id_mapping(&ids);
+        idmap.map_gid(local, stored);
+    }
+
+    let strict_round_trip_budget = args.is_present("strict_round_trip_budget")
+        || file.strict_round_trip_budget.unwrap_or(false);
+    let round_trip_budget = args
+        .value_of("round_trip_budget")
+        .map(|limit| limit.parse().expect("invalid round trip budget"))
+        .or(file.round_trip_budget)
+        .map(|limit| RoundTripBudget {
+            limit,
+            strict: strict_round_trip_budget,
+        });
+    let default_permissions =
+        args.is_present("default_permissions") || file.default_permissions.unwrap_or(false);
+    let flush_on_close = args.is_present("flush_on_close") || file.flush_on_close.unwrap_or(false);
+    let fuse = FuseOptions {
+        big_writes: args.is_present("big_writes") || file.big_writes.unwrap_or(false),
+        max_write: args
+            .value_of("max_write")
+            .map(|max| max.parse().expect("invalid max write"))
+            .or(file.max_write),
+        max_readahead: args
+            .value_of("max_readahead")
+            .map(|max| max.parse().expect("invalid max readahead"))
+            .or(file.max_readahead),
+        allow_other: args.is_present("allow_other") || file.allow_other.unwrap_or(false),
+        auto_unmount: args.is_present("auto_unmount") || file.auto_unmount.unwrap_or(false),
+        threads: args
+            .value_of("fuse_threads")
+            .map(|threads| threads.parse().expect("invalid thread count"))
+            .or(file.fuse_threads)
+            .unwrap_or(0),
+    };
+    let retries = args
+        .value_of("retries")
+        .map(|retries| retries.parse().expect("invalid retry count"))
+        .or(file.retries)
+        .unwrap_or(DEFAULT_RETRIES);
+    let pool_size = args
+        .value_of("pool_size")
+        .map(|size| size.parse().expect("invalid pool size"))
+        .or(file.pool_size)
+        .unwrap_or(DEFAULT_POOL_SIZE);
+    assert_ne!(pool_size, 0, "the pool needs at least one connection");
+    let pool_timeout = args
+        .value_of("pool_timeout")
+        .map(|timeout| timeout.parse().expect("invalid pool timeout"))
+        .or(file.pool_timeout)
+        .map_or(DEFAULT_POOL_TIMEOUT, Duration::from_secs_f64);
+    /* Zero waits for Antidote as long as it takes. */
+    let request_timeout = args
+        .value_of("request_timeout")
+        .map(|timeout| timeout.parse().expect("invalid request timeout"))
+        .or(file.request_timeout)
+        .map_or(Some(DEFAULT_REQUEST_TIMEOUT), |timeout: f64| {
+            Some(Duration::from_secs_f64(timeout))
+                .filter(|timeout| *timeout > Duration::from_secs(0))
+        });
+
+    let max_ops = |flag: &str, file: Option<usize>, default: usize| {
+        let max = args
+            .value_of(flag)
+            .map(|max| max.parse().expect("invalid operation count"))
+            .or(file)
+            .unwrap_or(default);
+        assert_ne!(max, 0, "at least one operation of each kind must be let in");
+        max
+    };
+    let defaults = Concurrency::default();
+    let concurrency = Concurrency {
+        metadata: max_ops("max_metadata_ops", file.max_metadata_ops, defaults.metadata),
+        reads: max_ops("max_read_ops", file.max_read_ops, defaults.reads),
+        writes: max_ops("max_write_ops", file.max_write_ops, defaults.writes),
+        others: max_ops("max_other_ops", file.max_other_ops, defaults.others),
+    };
+    let metrics_address = args.value_of("metrics").map(String::from).or(file.metrics);
+    let naming = Naming {
+        separator: args
+            .value_of("name_separator")
+            .map(parse_separator)
+            .or(file.name_separator)
+            .unwrap_or(Naming::default().separator),
+        hide_local_view: !args.is_present("show_local_view")
+            && !file.show_local_view.unwrap_or(false),
+    };
+    assert!(
+        Naming::valid_separator(naming.separator),
+        "invalid name separator"
+    );
+
+    let compression: Compression = args
+        .value_of("compression")
+        .or(file.compression.as_deref())
+        .map(|compression| compression.parse().expect("invalid compression"))
+        .unwrap_or_default();
+
+    let page_conflicts: PageConflicts = args
+        .value_of("page_conflicts")
+        .or(file.page_conflicts.as_deref())
+        .map(|policy| policy.parse().expect("invalid page conflict policy"))
+        .unwrap_or_default();
+
+    /* The key is better kept out of the config file, which is often
+    shared, only its path is read from there. */
+    let key_file = args.value_of("key_file").or(file.key_file.as_deref());
+    let encryption_key = match (key_file, env::var(KEY_ENV)) {
+        (Some(path), _) => Some(EncryptionKey::load(path).expect("invalid key file")),
+        (None, Ok(hex)) => Some(EncryptionKey::from_hex(&hex).expect("invalid encryption key")),
+        (None, Err(_)) => None,
+    };
+
+    let atime: AtimePolicy = args
+        .value_of("atime")
+        .or(file.atime.as_deref())
+        .map(|atime| atime.parse().expect("invalid atime policy"))
+        .unwrap_or_default();
+
+    let attr_ttl = args
+        .value_of("attr_timeout")
+        .map(|timeout| timeout.parse().expect("invalid attribute timeout"))
+        .or(file.attr_timeout)
+        .map_or(Duration::from_secs(0), Duration::from_secs_f64);
+    let entry_ttl = args
+        .value_of("entry_timeout")
+        .map(|timeout| timeout.parse().expect("invalid entry timeout"))
+        .or(file.entry_timeout)
+        .map_or(Duration::from_secs(0), Duration::from_secs_f64);
+
+    let addresses = Arc::new(AddressBook::with_datacenters(addresses, fallbacks));
+    let mut cfg = Config {
         locks,
+        page_size,
+        compression,
+        encryption_key,
+        page_conflicts,
+        attr_shape,
+        idmap: Arc::new(idmap),
+        round_trip_budget,
+        default_permissions,
+        flush_on_close,
+        fuse,
+        retries,
+        pool_size,
+        pool_timeout,
+        request_timeout,
+        concurrency,
+        metrics_address,
+        naming,
+        atime,
+        attr_ttl,
+        entry_ttl,
+        ..Config::new(0, bucket, addresses)
     };
 
+    /* Without a mountpoint, each mount of the config file is served,
+    with a view and a bucket of its own. */
+    if args.subcommand_name().is_none() && !args.is_present("mountpoint") {
+        let sections = file
+            .mount
+            .filter(|sections| !sections.is_empty())
+            .expect("a mountpoint is required, either with --mount or in the config file");
+
+        let mounts = sections
+            .into_iter()
+            .map(|section| {
+                let mut cfg = Config {
+                    bucket: section.bucket.map(Bucket::new).unwrap_or(bucket),
+                    ..cfg.clone()
+                };
+                set_view(&mut cfg, section.view, section.view_file.as_deref());
+                cfg.root_path = section.root_path.map(PathBuf::from);
+                if let Some(name) = &section.snapshot {
+                    let snapshot =
+                        elmerfs::find_snapshot(&cfg, name).expect("failed to find the snapshot");
+                    cfg.snapshot = Some(snapshot.timestamp);
+                }
+
+                (cfg, PathBuf::from(section.mountpoint))
+            })
+            .collect();
+        return elmerfs::run_all(mounts);
+    }
+
+    let view: Option<View> = args
+        .value_of("view")
+        .map(|view| view.parse().expect("invalid view"))
+        .or(file.view);
+    set_view(
+        &mut cfg,
+        view,
+        args.value_of("view_file").or(file.view_file.as_deref()),
+    );
+
+    match args.subcommand() {
+        ("fsck", Some(fsck_args)) => return fsck(cfg, fsck_args.is_present("repair")),
+        ("gc", _) => return gc(cfg),
+        ("rm", Some(rm_args)) => return rm(cfg, rm_args),
+        ("stats", _) => return stats(cfg),
+        ("snapshot", Some(snapshot_args)) => return snapshot(cfg, snapshot_args),
+        ("views", Some(views_args)) => return views(cfg, views_args),
+        ("bench", Some(bench_args)) => return bench(cfg, bench_args),
+        _ => {}
+    }
+
+    if let Some(name) = args.value_of("snapshot").or(file.snapshot.as_deref()) {
+        let snapshot = elmerfs::find_snapshot(&cfg, name).expect("failed to find the snapshot");
+        cfg.snapshot = Some(snapshot.timestamp);
+    }
+    cfg.root_path = args
+        .value_of("root_path")
+        .or(file.root_path.as_deref())
+        .map(PathBuf::from);
+
+    let mountpoint = args.value_of_os("mountpoint").unwrap();
     elmerfs::run(cfg, mountpoint);
 }
+
+fn fsck(cfg: Config, repair: bool) {
+    let client = Client::connect(cfg).expect("failed to connect to antidote");
+    let report = client.check(repair).expect("check failed");
+
+    for problem in &report.problems {
+        println!("{}", problem);
+    }
+    println!(
+        "{} inodes checked, {} problems found, {} repaired",
+        report.inodes,
+        report.problems.len(),
+        report.repaired
+    );
+
+    if report.repaired < report.problems.len() {
+        std::process::exit(1);
+    }
+}
+
+fn gc(cfg: Config) {
+    let client = Client::connect(cfg).expect("failed to connect to antidote");
+    let collected = client.collect_orphans().expect("gc failed");
+
+    println!("{} orphans removed", collected);
+}
+
+fn rm(cfg: Config, args: &ArgMatches) {
+    let path = Path::new(args.value_of_os("path").unwrap());
+    let client = Client::connect(cfg).expect("failed to connect to antidote");
+    let removed = client.remove_tree(path).expect("rm failed");
+
+    println!("{} inodes removed", removed);
+}
+
+fn stats(cfg: Config) {
+    let bucket = cfg.bucket;
+    let view = cfg.view;
+    let client = Client::connect(cfg).expect("failed to connect to antidote");
+    let stats = client.stats().expect("stats failed");
+
+    println!("bucket: {:?}", bucket);
+    println!("directories: {}", stats.directories);
+    println!("regular files: {}", stats.regular_files);
+    println!("symlinks: {}", stats.symlinks);
+    println!("special files: {}", stats.special_files);
+    println!("orphans: {}", stats.orphans);
+    println!("file bytes: {}", stats.file_bytes);
+    println!("stored bytes: {}", stats.stored_bytes);
+    println!("page size: {}", stats.page_size);
+    match stats.ino_counter {
+        Some((next, remaining)) => println!(
+            "inode counter of view {}: next {}, {} remaining",
+            view, next, remaining
+        ),
+        None => println!("inode counter of view {}: unused", view),
+    }
+}
+
+fn snapshot(cfg: Config, args: &ArgMatches) {
+    let client = Client::connect(cfg).expect("failed to connect to antidote");
+
+    match args.subcommand() {
+        ("create", Some(args)) => {
+            let name = args.value_of("name").unwrap();
+            client
+                .create_snapshot(name)
+                .expect("failed to create snapshot");
+        }
+        ("list", _) => {
+            for snapshot in client.snapshots().expect("failed to list snapshots") {
+                println!("{}", snapshot.name);
+            }
+        }
+        ("delete", Some(args)) => {
+            let name = args.value_of("name").unwrap();
+            client
+                .delete_snapshot(name)
+                .expect("failed to delete snapshot");
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn views(cfg: Config, args: &ArgMatches) {
+    let client = Client::connect(cfg).expect("failed to connect to antidote");
+
+    match args.subcommand() {
+        ("list", _) => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            for record in client.views().expect("failed to list views") {
+                let age = now.checked_sub(record.last_seen).unwrap_or_default();
+                println!(
+                    "{} {} last seen {}s ago",
+                    record.view,
+                    record.hostname,
+                    age.as_secs()
+                );
+            }
+        }
+        ("forget", Some(args)) => {
+            let view: View = args
+                .value_of("view")
+                .unwrap()
+                .parse()
+                .expect("invalid view");
+            client.forget_view(view).expect("failed to forget view");
+        }
+        _ => unreachable!(),
+    }
+}
+
+/* The view stored in the view file is used when none is given, one is
+allocated and stored in it the first time. */
+fn set_view(cfg: &mut Config, view: Option<View>, view_file: Option<&str>) {
+    let stored = view_file.and_then(|path| read_view_file(path).expect("invalid view file"));
+
+    cfg.view = match view.or(stored) {
+        Some(view) => view,
+        None => {
+            let path = view_file.expect(
+                "a view is required, either with --view, --view-file or in the config file",
+            );
+            let view = elmerfs::allocate_view(cfg).expect("failed to allocate a view");
+            write_view_file(path, view).expect("failed to store the view");
+            view
+        }
+    };
+}
+
+fn read_view_file(path: &str) -> io::Result<Option<View>> {
+    match fs::read_to_string(path) {
+        Ok(content) => content
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/* Through a rename, a mount stopped halfway never leaves an empty file
+behind that would have it allocate yet another view. */
+fn write_view_file(path: &str, view: View) -> io::Result<()> {
+    let path = Path::new(path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, format!("{}\n", view))?;
+    fs::rename(&temporary, path)
+}
+
+fn bench(cfg: Config, args: &ArgMatches) {
+    let defaults = bench::Options::default();
+    let options = bench::Options {
+        workload: args
+            .value_of("workload")
+            .map_or(defaults.workload, |workload| workload.parse().unwrap()),
+        threads: args
+            .value_of("threads")
+            .map_or(defaults.threads, |threads| {
+                threads.parse().expect("invalid thread count")
+            }),
+        ops: args
+            .value_of("ops")
+            .map_or(defaults.ops, |ops| ops.parse().expect("invalid op count")),
+        block_size: args
+            .value_of("block_size")
+            .map_or(defaults.block_size, |size| {
+                size.parse().expect("invalid block size")
+            }),
+        file_size: args
+            .value_of("file_size")
+            .map_or(defaults.file_size, |size| {
+                size.parse().expect("invalid file size")
+            }),
+    };
+
+    let report = match args.value_of("mounted") {
+        Some(mountpoint) => {
+            let target = MountTarget::new(mountpoint).expect("failed to prepare the mount");
+            bench::run(&target, &options)
+        }
+        None => {
+            let target = DriverTarget::connect(cfg).expect("failed to connect to antidote");
+            bench::run(&target, &options)
+        }
+    };
+
+    print!("{}", report.expect("benchmark failed"));
+}
+
+fn parse_owner(owner: &str) -> Owner {
+    let mut ids = owner.split(':');
+    let uid = ids.next().and_then(|uid| uid.parse().ok());
+    let gid = ids.next().and_then(|gid| gid.parse().ok());
+
+    match (uid, gid) {
+        (Some(uid), Some(gid)) => Owner { uid, gid },
+        _ => panic!("invalid owner, expected UID:GID"),
+    }
+}
+
+fn parse_id_mapping(ids: &str) -> (u32, u32) {
+    let mut ids = ids.split(':');
+    let local = ids.next().and_then(|id| id.parse().ok());
+    let stored = ids.next().and_then(|id| id.parse().ok());
+
+    match (local, stored) {
+        (Some(local), Some(stored)) => (local, stored),
+        _ => panic!("invalid id mapping, expected LOCAL:STORED"),
+    }
+}
+
+fn parse_separator(separator: &str) -> char {
+    let mut chars = separator.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(separator), None) => separator,
+        _ => panic!("invalid name separator, expected a single character"),
+    }
+}
@@ -0,0 +1,106 @@
+use crate::view::View;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Mount parameters read from a TOML file, every one of them is optional
+/// and overridden by the matching command line flag, e.g:
+///
+/// ```toml
+/// antidote = ["127.0.0.1:8101", "127.0.0.1:8102"]
+/// fallback_dcs = [["10.0.1.1:8101"]]
+/// bucket = 0
+/// view = 1
+/// locks = "full"
+/// page_size = 65536
+/// compression = "lz4"
+/// log_level = "info"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub antidote: Option<Vec<String>>,
+    pub fallback_dcs: Option<Vec<Vec<String>>>,
+    pub bucket: Option<u32>,
+    pub view: Option<View>,
+    pub view_file: Option<String>,
+    pub snapshot: Option<String>,
+    pub root_path: Option<String>,
+    pub locks: Option<Locks>,
+    pub consistency: Option<String>,
+    pub page_size: Option<u64>,
+    pub page_conflicts: Option<String>,
+    pub compression: Option<String>,
+    pub key_file: Option<String>,
+    pub coarse_timestamps: Option<bool>,
+    pub squash_owner: Option<String>,
+    pub squash: Option<String>,
+    pub anonymous_owner: Option<String>,
+    pub uid_map: Option<Vec<String>>,
+    pub gid_map: Option<Vec<String>>,
+    pub round_trip_budget: Option<u64>,
+    pub strict_round_trip_budget: Option<bool>,
+    pub default_permissions: Option<bool>,
+    pub flush_on_close: Option<bool>,
+    pub big_writes: Option<bool>,
+    pub max_write: Option<u32>,
+    pub max_readahead: Option<u32>,
+    pub allow_other: Option<bool>,
+    pub auto_unmount: Option<bool>,
+    pub fuse_threads: Option<usize>,
+    pub retries: Option<u32>,
+    pub pool_size: Option<usize>,
+    pub pool_timeout: Option<f64>,
+    pub request_timeout: Option<f64>,
+    pub max_metadata_ops: Option<usize>,
+    pub max_read_ops: Option<usize>,
+    pub max_write_ops: Option<usize>,
+    pub max_other_ops: Option<usize>,
+    pub metrics: Option<String>,
+    pub otlp_endpoint: Option<String>,
+    pub log_level: Option<String>,
+    pub name_separator: Option<char>,
+    pub show_local_view: Option<bool>,
+    pub atime: Option<String>,
+    pub attr_timeout: Option<f64>,
+    pub entry_timeout: Option<f64>,
+    pub mount: Option<Vec<MountSection>>,
+}
+
+/// A mount of its own, the process then serving one per section, every
+/// other parameter being shared, e.g:
+///
+/// ```toml
+/// [[mount]]
+/// mountpoint = "/mnt/projects"
+/// bucket = 1
+/// view_file = "/var/lib/elmerfs/projects.view"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MountSection {
+    pub mountpoint: String,
+    pub bucket: Option<u32>,
+    pub view: Option<View>,
+    pub view_file: Option<String>,
+    pub snapshot: Option<String>,
+    pub root_path: Option<String>,
+}
+
+/// Either a lock policy, or whether to take locks at all as `locks` used to
+/// be.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Locks {
+    Enabled(bool),
+    Policy(String),
+}
+
+impl FileConfig {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+
+        toml::from_str(&content).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
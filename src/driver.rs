@@ -1,39 +1,131 @@
+mod access;
+mod admission;
+mod atime;
+mod attrs;
+mod batch;
+pub(crate) mod budget;
+mod buffers;
+mod changes;
+mod clock;
+mod coalesce;
+mod control;
+mod dirty;
+mod dispatch;
+mod handle;
+mod idmap;
 mod ino;
+mod interrupt;
+mod kernel;
+mod links;
 mod lock;
+pub(crate) mod metrics;
+mod negative;
 mod page;
 mod pool;
-
-pub use self::pool::AddressBook;
-
+mod prefetch;
+pub(crate) mod runtime;
+mod shape;
+mod tasks;
+
+pub use self::admission::Concurrency;
+pub use self::atime::AtimePolicy;
+pub use self::budget::RoundTripBudget;
+pub use self::check::{Problem, Report};
+pub use self::clock::{Clock, ManualClock, SystemClock};
+pub use self::idmap::{IdMap, Squash, DEFAULT_ANONYMOUS_OWNER};
+pub use self::page::{Compression, EncryptionKey, PageConflicts};
+pub use self::pool::{AddressBook, SharedPool};
+pub use self::shape::AttrShape;
+pub use self::stats::Stats;
+
+use self::admission::Admission;
+use self::atime::AtimeBatch;
+use self::attrs::{AttrCache, EntryAttr};
+use self::batch::{AttrBatcher, ReadRole};
+use self::buffers::{Buffer, BufferPool};
+use self::changes::ChangeWatch;
+use self::coalesce::{Role, WriteCoalescer};
+use self::control::{Conflicts, Node};
+use self::dirty::{Dirty, DirtyAttrs};
+use self::dispatch::Dispatcher;
+use self::flock::HeldLocks;
+use self::handle::HandleTable;
 use self::ino::InoGenerator;
+use self::interrupt::Interrupts;
+use self::jobs::{Job, Jobs};
+use self::kernel::KernelCache;
+use self::links::LinkCache;
 use self::lock::PageLocks;
+use self::metrics::Metrics;
+use self::negative::NegativeCache;
 use self::page::PageWriter;
 use self::pool::ConnectionPool;
+use self::prefetch::Prefetcher;
+use self::tasks::Tasks;
 use crate::key::Bucket;
+use crate::model::snapshot::Snapshot;
+use crate::model::views::ViewRecord;
 use crate::model::{
-    dir,
-    inode::{self, Inode, Kind, Owner},
+    dir::{self, DirView, EntryRef},
+    inode::{self, CreationToken, Inode, Kind, Owner},
+    journal::{self, Intent},
+    lock::{FileLock, LockKind},
+    orphan,
+    superblock::{self, Superblock},
     symlink,
 };
-use crate::view::{NameRef, View};
-use antidotec::{self, Connection, Transaction, TransactionLocks};
+use crate::view::{NameRef, Naming, View};
+use crate::FuseOptions;
+use antidotec::{
+    self, AntidoteError, Connection, Pipeline, RawIdent, Timestamp, Transaction, TransactionLocks,
+    UpdateQuery,
+};
 use async_std::sync::Arc;
-use async_std::task;
-use fuse::*;
+use fuser::*;
 use nix::errno::Errno;
+use nix::libc;
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
 use std::fmt::Debug;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::Range;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 use thiserror::Error;
 
-const ROOT_INO: u64 = 1;
-const MAX_CONNECTIONS: usize = 32;
-const PAGE_SIZE: u64 = 64 * 1024;
-
-const ENOENT: Error = Error::Sys(Errno::ENOENT);
+pub const ROOT_INO: u64 = 1;
+pub const DEFAULT_PAGE_SIZE: u64 = 64 * 1024;
+pub const DEFAULT_RETRIES: u32 = 3;
+pub const DEFAULT_POOL_SIZE: usize = 32;
+pub const DEFAULT_POOL_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+const MAINTENANCE_CONNECTIONS: usize = 2;
+const CREATION_RETRIES: u32 = 3;
+const CREATION_TOKEN_TTL: Duration = Duration::from_secs(30);
+const COPY_CHUNK_PAGES: u64 = 64;
+const READDIR_BATCH: usize = 512;
+const LOCK_POLL_MIN: Duration = Duration::from_millis(10);
+const LOCK_POLL_MAX: Duration = Duration::from_secs(1);
+const LOCK_WAIT_MAX: Duration = Duration::from_secs(60);
+const RETRY_BACKOFF_MIN: Duration = Duration::from_millis(5);
+const RETRY_BACKOFF_MAX: Duration = Duration::from_millis(500);
+
+/// Pages of a file holding several versions, with a versioned bucket.
+pub const PAGE_VERSIONS_XATTR: &str = "user.elmerfs.page_versions";
+/// Names, entries and pages of an inode written concurrently by several views.
+pub const CONFLICTS_XATTR: &str = "user.elmerfs.conflicts";
+
+const RENAME_NOREPLACE: u32 = libc::RENAME_NOREPLACE as u32;
+const RENAME_EXCHANGE: u32 = libc::RENAME_EXCHANGE as u32;
 
 macro_rules! transaction {
     ($cfg:expr, $connection:expr) => {
-        transaction!($cfg, { shared: [], exclusive: [] })
+        transaction!($cfg, $connection, { shared: [], exclusive: [] })
     };
 
     ($cfg:expr, $connection:expr, { shared: [$($shared:expr),*] }) => {
@@ -44,14 +136,26 @@ macro_rules! transaction {
         transaction!($cfg, $connection, { shared: [], exclusive: [$($excl),*] })
     };
 
-    ($cfg:expr, $connection:expr, { shared: [$($shared:expr),*], exclusive: [$($excl:expr),*] }) => {{
-        if $cfg.locks {
-            $connection.transaction_with_locks(TransactionLocks {
+    ($cfg:expr, $connection:expr, { shared: [$($shared:expr),*], exclusive: [$($excl:expr),*] }) => {
+        transaction!($cfg, $connection, $crate::driver::TxClass::Other, {
+            shared: [$($shared),*],
+            exclusive: [$($excl),*]
+        })
+    };
+
+    ($cfg:expr, $connection:expr, $class:expr, { exclusive: [$($excl:expr),*] }) => {
+        transaction!($cfg, $connection, $class, { shared: [], exclusive: [$($excl),*] })
+    };
+
+    /* Nothing ever writes a snapshot, reading it needs no lock. */
+    ($cfg:expr, $connection:expr, $class:expr, { shared: [$($shared:expr),*], exclusive: [$($excl:expr),*] }) => {{
+        if $cfg.locks.covers($class) && $cfg.snapshot.is_none() {
+            $connection.transaction_at($cfg.snapshot.as_ref(), TransactionLocks {
                 shared: vec![$($shared.into()),*],
                 exclusive: vec![$($excl.into()),*]
             })
         } else {
-            $connection.transaction_with_locks(TransactionLocks {
+            $connection.transaction_at($cfg.snapshot.as_ref(), TransactionLocks {
                 shared: vec![],
                 exclusive: vec![]
             })
@@ -59,22 +163,271 @@ macro_rules! transaction {
     }};
 }
 
+/* Failures are told apart for the logs and the callers of the driver, the
+kernel only ever gets the errno of `Error::errno`. */
 #[derive(Error, Debug)]
 pub(crate) enum Error {
     #[error("driver replied with: {0}")]
     Sys(Errno),
 
+    #[error("no such file or directory")]
+    NotFound,
+
+    #[error("file exists")]
+    Exists,
+
+    #[error("directory not empty")]
+    NotEmpty,
+
+    #[error("no inode number left to the view")]
+    QuotaExceeded,
+
+    #[error("transaction aborted by a concurrent one")]
+    Conflict,
+
+    #[error("antidote didn't reply in time")]
+    Timeout,
+
+    #[error("no connection to antidote available")]
+    PoolExhausted,
+
     #[error("io error with antidote: {0}")]
-    Antidote(#[from] antidotec::Error),
+    Antidote(#[source] antidotec::Error),
+
+    #[error("directories stored in format {0}, which this version can't mount")]
+    Format(u64),
 }
 pub(crate) type Result<T> = std::result::Result<T, Error>;
 
+mod check;
+mod flock;
+mod gc;
+mod jobs;
+mod migrate;
+mod recovery;
+mod snapshot;
+mod stats;
+mod tree;
+mod views;
+
+impl Error {
+    pub(crate) fn errno(&self) -> Errno {
+        match self {
+            Error::Sys(errno) => *errno,
+            Error::NotFound => Errno::ENOENT,
+            Error::Exists => Errno::EEXIST,
+            Error::NotEmpty => Errno::ENOTEMPTY,
+            Error::QuotaExceeded => Errno::EDQUOT,
+            Error::Conflict => Errno::EIO,
+            Error::Timeout => Errno::EIO,
+            Error::PoolExhausted => Errno::EBUSY,
+            Error::Antidote(_) => Errno::EIO,
+            Error::Format(_) => Errno::EPROTO,
+        }
+    }
+
+    /// Whether talking to Antidote failed, as opposed to the operation.
+    pub(crate) fn is_antidote(&self) -> bool {
+        matches!(
+            self,
+            Error::Conflict | Error::Timeout | Error::PoolExhausted | Error::Antidote(_)
+        )
+    }
+}
+
+impl From<antidotec::Error> for Error {
+    fn from(error: antidotec::Error) -> Self {
+        match error {
+            antidotec::Error::Antidote(AntidoteError::Aborted)
+            | antidotec::Error::AntidoteErrResp(AntidoteError::Aborted, _) => Error::Conflict,
+            antidotec::Error::Io(ref io) if io.kind() == std::io::ErrorKind::TimedOut => {
+                Error::Timeout
+            }
+            antidotec::Error::Interrupted => Error::Sys(Errno::EINTR),
+            antidotec::Error::Io(ref io) if io.kind() == std::io::ErrorKind::Interrupted => {
+                Error::Sys(Errno::EINTR)
+            }
+            error => Error::Antidote(error),
+        }
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Antidote(error) => std::io::Error::new(std::io::ErrorKind::Other, error),
+            error => std::io::Error::from_raw_os_error(error.errno() as i32),
+        }
+    }
+}
+
+/// Which transactions take Antidote locks on what they read and update.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LockPolicy {
+    /// None of them, concurrent updates are merged.
+    None,
+    /// Those changing the tree, e.g rename or rmdir, reads and writes of
+    /// files and attributes run without locks.
+    Namespace,
+    Full,
+}
+
+impl Default for LockPolicy {
+    fn default() -> Self {
+        LockPolicy::Full
+    }
+}
+
+impl FromStr for LockPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(LockPolicy::None),
+            "namespace" => Ok(LockPolicy::Namespace),
+            "full" => Ok(LockPolicy::Full),
+            _ => Err(format!("unknown lock policy: {}", s)),
+        }
+    }
+}
+
+impl LockPolicy {
+    pub(crate) fn covers(self, class: TxClass) -> bool {
+        match self {
+            LockPolicy::None => false,
+            LockPolicy::Namespace => class == TxClass::Namespace,
+            LockPolicy::Full => true,
+        }
+    }
+}
+
+/// How the operations changing the tree behave along with those of other
+/// views, each mode standing for a lock policy.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Consistency {
+    /// Operations are locked, as with `LockPolicy::Full`: a view never sees
+    /// the anomalies of concurrent renames and removals.
+    Strong,
+    /// Nothing is locked, as with `LockPolicy::None`: concurrent operations
+    /// never wait for each other and are merged.
+    Eventual,
+}
+
+impl FromStr for Consistency {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "strong" => Ok(Consistency::Strong),
+            "eventual" => Ok(Consistency::Eventual),
+            _ => Err(format!("unknown consistency mode: {}", s)),
+        }
+    }
+}
+
+impl Consistency {
+    pub fn locks(self) -> LockPolicy {
+        match self {
+            Consistency::Strong => LockPolicy::Full,
+            Consistency::Eventual => LockPolicy::None,
+        }
+    }
+}
+
+/* Transactions creating, linking, renaming or removing names, and leasing
+the inode numbers they need, are told apart from the others. */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum TxClass {
+    Namespace,
+    Other,
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub view: View,
     pub bucket: Bucket,
     pub addresses: Arc<AddressBook>,
-    pub locks: bool,
+    pub locks: LockPolicy,
+    pub page_size: u64,
+    /// How the pages written by the mount are compressed.
+    pub compression: Compression,
+    /// Encrypts the pages written by the mount, pages can't be read
+    /// without it once encrypted.
+    pub encryption_key: Option<EncryptionKey>,
+    /// What pages hold once written concurrently, for buckets created by
+    /// the mount. Others keep the policy they were created with.
+    pub page_conflicts: PageConflicts,
+    pub attr_shape: AttrShape,
+    pub idmap: Arc<IdMap>,
+    pub round_trip_budget: Option<RoundTripBudget>,
+    pub clock: Arc<dyn Clock>,
+    pub default_permissions: bool,
+    /// Stores what writes left pending on every close of a file, failing the
+    /// close when it can't be.
+    pub flush_on_close: bool,
+    pub fuse: FuseOptions,
+    pub retries: u32,
+    /// Connections to Antidote used at once, at most.
+    pub pool_size: usize,
+    /// How long an operation waits for a connection before failing with
+    /// `EBUSY`.
+    pub pool_timeout: Duration,
+    /// How long a request to Antidote, e.g beginning a transaction or
+    /// committing it, is waited for before failing the operation with
+    /// `EIO`. Requests are waited for as long as it takes when `None`.
+    pub request_timeout: Option<Duration>,
+    /// Fuse operations served at once, past which the next requests are
+    /// left to wait in the kernel.
+    pub concurrency: Concurrency,
+    pub metrics_address: Option<String>,
+    pub naming: Naming,
+    pub atime: AtimePolicy,
+    /// How long the kernel, and the driver, keep the attributes of an inode
+    /// before asking for them again.
+    pub attr_ttl: Duration,
+    /// How long the kernel keeps a name it looked up.
+    pub entry_ttl: Duration,
+    /// Reads the bucket as it was at this time, which is then read-only.
+    pub snapshot: Option<Timestamp>,
+    /// Roots the mount at this directory of the bucket, e.g `/teams/a`,
+    /// nothing out of it is reachable.
+    pub root_path: Option<PathBuf>,
+}
+
+impl Config {
+    /// The config of a mount with the defaults of the command line, to be
+    /// changed field by field.
+    pub fn new(view: View, bucket: Bucket, addresses: Arc<AddressBook>) -> Self {
+        Self {
+            view,
+            bucket,
+            addresses,
+            locks: LockPolicy::default(),
+            page_size: DEFAULT_PAGE_SIZE,
+            compression: Compression::default(),
+            encryption_key: None,
+            page_conflicts: PageConflicts::default(),
+            attr_shape: AttrShape::exact(),
+            idmap: Arc::new(IdMap::identity()),
+            round_trip_budget: None,
+            clock: Arc::new(SystemClock::new()),
+            default_permissions: false,
+            flush_on_close: false,
+            fuse: FuseOptions::default(),
+            retries: DEFAULT_RETRIES,
+            pool_size: DEFAULT_POOL_SIZE,
+            pool_timeout: DEFAULT_POOL_TIMEOUT,
+            request_timeout: Some(DEFAULT_REQUEST_TIMEOUT),
+            concurrency: Concurrency::default(),
+            metrics_address: None,
+            naming: Naming::default(),
+            atime: AtimePolicy::default(),
+            attr_ttl: Duration::from_secs(0),
+            entry_ttl: Duration::from_secs(0),
+            snapshot: None,
+            root_path: None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -82,29 +435,420 @@ pub(crate) struct Driver {
     cfg: Config,
     ino_counter: Arc<InoGenerator>,
     pool: Arc<ConnectionPool>,
+    /* Background work and lease renewals go through connections of their
+    own, so that they neither wait behind operations nor hold them up. */
+    maintenance: Arc<ConnectionPool>,
     pages: PageWriter,
     page_locks: PageLocks,
+    handles: Arc<HandleTable>,
+    prefetch: Arc<Prefetcher>,
+    metrics: Arc<Metrics>,
+    atimes: Arc<AtimeBatch>,
+    dirty: Arc<DirtyAttrs>,
+    changes: Arc<ChangeWatch>,
+    kernel: Arc<KernelCache>,
+    held_locks: HeldLocks,
+    negative: NegativeCache,
+    links: LinkCache,
+    attrs: Arc<AttrCache>,
+    tasks: Arc<Tasks>,
+    dispatcher: Arc<Dispatcher>,
+    interrupts: Arc<Interrupts>,
+    jobs: Arc<Jobs>,
+    conflicts: Conflicts,
+    mounted_at: Duration,
+    writes: WriteCoalescer,
+    attr_reads: AttrBatcher,
+    buffers: Arc<BufferPool>,
+    root: u64,
 }
 
 impl Driver {
     pub async fn new(cfg: Config) -> Result<Self> {
-        let pages = PageWriter::new(cfg.bucket, PAGE_SIZE);
-        let pool = ConnectionPool::with_capacity(cfg.addresses.clone(), MAX_CONNECTIONS);
+        let pool = SharedPool::new(&cfg);
+        Self::with_pool(cfg, pool).await
+    }
+
+    /// A driver going through connections shared with other drivers, to
+    /// buckets of their own.
+    pub async fn with_pool(mut cfg: Config, pool: SharedPool) -> Result<Self> {
+        if !cfg.page_size.is_power_of_two() {
+            return Err(Error::Sys(Errno::EINVAL));
+        }
+
+        /* A snapshot is only read: access times are not updated, orphans and
+        lock leases are left to the mounts of the live filesystem. */
+        let read_only = cfg.snapshot.is_some();
+        if read_only {
+            cfg.atime = AtimePolicy::Noatime;
+        }
+
+        let pool = pool.0;
         let ino_counter = {
             let mut connection = pool.acquire().await?;
-            Self::make_root(&cfg, &mut connection).await?;
-            let ino_counter = Self::load_ino_counter(&cfg, &mut connection).await?;
+            let superblock = Self::load_superblock(&cfg, &mut connection).await?;
+            cfg.page_size = superblock.page_size;
+            cfg.page_conflicts = superblock.page_conflicts;
+
+            /* A snapshot is read as it was stored, its directories can't be
+            moved to their shards. */
+            if superblock.dir_format < superblock::DIR_FORMAT {
+                if read_only {
+                    return Err(Error::Format(superblock.dir_format));
+                }
+
+                migrate::shard_dirs(&cfg, &pool).await?;
+                Self::store_dir_format(&cfg, &mut connection).await?;
+            }
 
-            ino_counter
+            if read_only {
+                InoGenerator::read_only(cfg.view)
+            } else {
+                Self::make_root(&cfg, &mut connection).await?;
+                Self::load_ino_counter(&cfg, &mut connection).await?
+            }
         };
 
-        Ok(Self {
-            cfg,
-            ino_counter: Arc::new(ino_counter),
+        /* The journal stays as is when it can't be replayed, for the next
+        mount to try again. */
+        if !read_only {
+            match recovery::replay(&cfg, &pool).await {
+                Ok(0) => {}
+                Ok(replayed) => tracing::info!(replayed, "journal replayed"),
+                Err(error) => tracing::warn!(?error, "failed to replay the journal"),
+            }
+        }
+
+        let maintenance = Arc::new(
+            ConnectionPool::with_capacity(
+                cfg.addresses.clone(),
+                MAINTENANCE_CONNECTIONS,
+                cfg.pool_timeout,
+            )
+            .with_request_timeout(cfg.request_timeout),
+        );
+        let pages = PageWriter::new(&cfg);
+        let handles = Arc::new(HandleTable::new());
+        let tasks = Arc::new(Tasks::new());
+        if !read_only {
+            runtime::spawn(gc::run(
+                cfg.clone(),
+                maintenance.clone(),
+                pages.clone(),
+                handles.clone(),
+                tasks.clone(),
+            ));
+            runtime::spawn(flock::run(cfg.clone(), maintenance.clone(), tasks.clone()));
+        }
+
+        let metrics = Arc::new(Metrics::new());
+        if let Some(address) = cfg.metrics_address.clone() {
+            runtime::spawn(metrics::serve(
+                address,
+                metrics.clone(),
+                pool.clone(),
+                maintenance.clone(),
+            ));
+        }
+
+        let ino_counter = Arc::new(ino_counter);
+        let jobs = Arc::new(Jobs::new(
+            cfg.clone(),
+            maintenance.clone(),
+            pages.clone(),
+            metrics.clone(),
+            tasks.clone(),
+        ));
+
+        let prefetch = Arc::new(Prefetcher::new());
+        let attrs = Arc::new(AttrCache::new(cfg.attr_ttl));
+        let atimes = Arc::new(AtimeBatch::new());
+        if cfg.atime != AtimePolicy::Noatime {
+            runtime::spawn(atime::run(
+                cfg.clone(),
+                maintenance.clone(),
+                prefetch.clone(),
+                attrs.clone(),
+                atimes.clone(),
+                tasks.clone(),
+            ));
+        }
+        let dirty = Arc::new(DirtyAttrs::new());
+        runtime::spawn(dirty::run(
+            cfg.clone(),
+            maintenance.clone(),
+            prefetch.clone(),
+            attrs.clone(),
+            dirty.clone(),
+            tasks.clone(),
+        ));
+        let changes = Arc::new(ChangeWatch::new(cfg.view));
+        let kernel = Arc::new(KernelCache::new(cfg.attr_ttl.max(cfg.entry_ttl)));
+        if cfg.snapshot.is_none() {
+            runtime::spawn(changes::run(
+                cfg.clone(),
+                maintenance.clone(),
+                changes.clone(),
+                kernel.clone(),
+                tasks.clone(),
+            ));
+        }
+
+        let mut driver = Self {
+            ino_counter,
             pages,
-            pool: Arc::new(pool),
-            page_locks: PageLocks::new(PAGE_SIZE),
-        })
+            pool,
+            maintenance,
+            page_locks: PageLocks::new(cfg.page_size),
+            handles,
+            prefetch,
+            metrics,
+            atimes,
+            dirty,
+            changes,
+            kernel,
+            held_locks: HeldLocks::new(),
+            negative: NegativeCache::new(),
+            links: LinkCache::new(),
+            attrs,
+            dispatcher: Arc::new(Dispatcher::new(
+                Admission::new(cfg.concurrency, tasks.clone()),
+                cfg.fuse.threads,
+            )),
+            interrupts: Arc::new(Interrupts::new()),
+            tasks,
+            jobs,
+            conflicts: Conflicts::new(),
+            mounted_at: now(&cfg),
+            writes: WriteCoalescer::new(),
+            attr_reads: AttrBatcher::new(),
+            buffers: Arc::new(BufferPool::new()),
+            root: ROOT_INO,
+            cfg,
+        };
+        if let Some(path) = driver.cfg.root_path.clone() {
+            driver.root = driver.resolve(ROOT_INO, &path).await?;
+        }
+
+        Ok(driver)
+    }
+
+    /* Each component is looked up from `from`, the control directory being
+    no place to root a mount at. */
+    async fn resolve(&self, from: u64, path: &Path) -> Result<u64> {
+        let mut ino = from;
+        for component in path.components() {
+            let name = match component {
+                Component::RootDir | Component::CurDir => continue,
+                Component::Normal(name) => name,
+                _ => return Err(Error::Sys(Errno::EINVAL)),
+            };
+            let name = self
+                .cfg
+                .naming
+                .parse(name.as_bytes())
+                .map_err(|_| Error::Sys(Errno::EINVAL))?;
+
+            let entry = self.lookup(Owner::ROOT, ino, name).await?;
+            if entry.attr.kind != FileType::Directory || Node::from_ino(entry.attr.ino).is_some() {
+                return Err(Error::Sys(Errno::ENOTDIR));
+            }
+            ino = entry.attr.ino;
+        }
+
+        Ok(ino)
+    }
+
+    /// The directory the mount is rooted at.
+    pub(crate) fn root(&self) -> u64 {
+        self.root
+    }
+
+    /// The inode the kernel refers to by `ino`, its root being the root of
+    /// the mount.
+    pub(crate) fn stored_ino(&self, ino: u64) -> u64 {
+        if ino == ROOT_INO {
+            self.root
+        } else {
+            ino
+        }
+    }
+
+    /// Once mounted, lets the driver notify the kernel through the session.
+    pub(crate) fn set_notifier(&self, notifier: Notifier) {
+        self.kernel.set_notifier(notifier);
+    }
+
+    /// Records a name replied to the kernel, and the inode it names.
+    pub(crate) fn entry_replied(&self, parent_ino: u64, name: &OsStr, ino: u64) {
+        if name != "." && name != ".." {
+            self.kernel
+                .entry(self.kernel_ino(parent_ino), name, self.kernel_ino(ino));
+        }
+    }
+
+    /// Records the attributes of an inode replied to the kernel.
+    pub(crate) fn attr_replied(&self, ino: u64) {
+        self.kernel.attr(self.kernel_ino(ino));
+    }
+
+    fn kernel_ino(&self, ino: u64) -> u64 {
+        if ino == self.root {
+            ROOT_INO
+        } else {
+            ino
+        }
+    }
+
+    pub(crate) fn round_trip_budget(&self) -> Option<RoundTripBudget> {
+        self.cfg.round_trip_budget
+    }
+
+    pub(crate) fn attr_ttl(&self) -> Duration {
+        self.cfg.attr_ttl
+    }
+
+    pub(crate) fn entry_ttl(&self) -> Duration {
+        self.cfg.entry_ttl
+    }
+
+    /* A snapshot is only ever read. */
+    fn check_writable(&self) -> Result<()> {
+        if self.cfg.snapshot.is_some() {
+            Err(Error::Sys(Errno::EROFS))
+        } else {
+            Ok(())
+        }
+    }
+
+    /* Must be called once a local update is committed. */
+    fn invalidate_caches(&self) {
+        self.prefetch.invalidate();
+        self.attrs.clear();
+    }
+
+    /* Names and attributes cached by this view are dropped once another
+    view changed the namespace or a file, rather than served until they
+    expire. */
+    fn forget_stale(&self) {
+        if self.changes.take_stale() {
+            self.invalidate_caches();
+            self.negative.clear();
+        }
+    }
+
+    /// Copies the data of a write out of the fuse session, into a buffer
+    /// reused once the write is done.
+    pub(crate) fn buffer(&self, data: &[u8]) -> Buffer {
+        self.buffers.copy(data)
+    }
+
+    pub(crate) fn dispatcher(&self) -> Arc<Dispatcher> {
+        self.dispatcher.clone()
+    }
+
+    pub(crate) fn interrupts(&self) -> Arc<Interrupts> {
+        self.interrupts.clone()
+    }
+
+    /* Waits for in-flight operations and the work they spawned, then writes
+    what is only kept in memory. The filesystem must be unmounted already. */
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn shutdown(&self) {
+        self.dispatcher.close();
+        self.tasks.close().await;
+
+        if let Err(error) = atime::flush_batch(&self.cfg, &self.maintenance, &self.atimes).await {
+            tracing::error!(?error, "failed to flush access times");
+        }
+        if let Err(error) =
+            dirty::flush_batch(&self.cfg, &self.maintenance, &self.dirty, None).await
+        {
+            tracing::error!(?error, "failed to flush dirty attributes");
+        }
+    }
+
+    /// Flags to open an inode with, control files are never cached.
+    pub(crate) fn open_flags(&self, ino: u64) -> u32 {
+        if Node::from_ino(ino).is_some() {
+            consts::FOPEN_DIRECT_IO
+        } else {
+            0
+        }
+    }
+
+    pub(crate) fn idmap(&self) -> Arc<IdMap> {
+        self.cfg.idmap.clone()
+    }
+
+    pub(crate) fn naming(&self) -> &Naming {
+        &self.cfg.naming
+    }
+
+    pub(crate) fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    #[tracing::instrument(skip(connection))]
+    pub(crate) async fn load_superblock(
+        cfg: &Config,
+        connection: &mut Connection,
+    ) -> Result<Superblock> {
+        let mut tx = transaction!(cfg, connection, { exclusive: [superblock::key()] }).await?;
+
+        let mut reply = tx.read(cfg.bucket, vec![superblock::read()]).await?;
+        /* Once stored, the superblock takes precedence over the configuration
+        so that remounts keep reading data the way it was written. */
+        let superblock = match superblock::decode(&mut reply, 0) {
+            Some(superblock) if superblock.dir_format > superblock::DIR_FORMAT => {
+                return Err(Error::Format(superblock.dir_format));
+            }
+            Some(superblock) => {
+                if superblock.page_conflicts != cfg.page_conflicts {
+                    tracing::warn!(
+                        stored = ?superblock.page_conflicts,
+                        requested = ?cfg.page_conflicts,
+                        "page conflict policy differs from the stored one, using the stored one"
+                    );
+                }
+                if superblock.page_size != cfg.page_size {
+                    tracing::warn!(
+                        stored = superblock.page_size,
+                        requested = cfg.page_size,
+                        "page size differs from the stored one, using the stored one"
+                    );
+                }
+
+                superblock
+            }
+            None if cfg.snapshot.is_some() => return Err(Error::NotFound),
+            None => {
+                let superblock = Superblock {
+                    page_size: cfg.page_size,
+                    page_conflicts: cfg.page_conflicts,
+                    dir_format: superblock::DIR_FORMAT,
+                };
+                tx.update(cfg.bucket, vec![superblock::create(&superblock)])
+                    .await?;
+
+                superblock
+            }
+        };
+
+        tx.commit().await?;
+        Ok(superblock)
+    }
+
+    async fn store_dir_format(cfg: &Config, connection: &mut Connection) -> Result<()> {
+        let mut tx = transaction!(cfg, connection, { exclusive: [superblock::key()] }).await?;
+        tx.update(
+            cfg.bucket,
+            vec![superblock::set_dir_format(superblock::DIR_FORMAT)],
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
     }
 
     #[tracing::instrument(skip(connection))]
@@ -112,7 +856,10 @@ impl Driver {
         cfg: &Config,
         connection: &mut Connection,
     ) -> Result<InoGenerator> {
-        let mut tx = transaction!(cfg, connection, { exclusive: [ino::key(cfg.view)] }).await?;
+        let mut tx = transaction!(cfg, connection, TxClass::Namespace, {
+            exclusive: [ino::key(cfg.view)]
+        })
+        .await?;
 
         let counter = InoGenerator::load(&mut tx, cfg.view, cfg.bucket).await?;
 
@@ -122,18 +869,21 @@ impl Driver {
 
     #[tracing::instrument(skip(connection))]
     pub(crate) async fn make_root(cfg: &Config, connection: &mut Connection) -> Result<()> {
-        let mut tx = transaction!(cfg, connection, { exclusive: [inode::key(ROOT_INO)] }).await?;
+        let mut tx = transaction!(cfg, connection, TxClass::Namespace, {
+            exclusive: [inode::key(ROOT_INO)]
+        })
+        .await?;
 
         match Self::attr_of(cfg, &mut tx, ROOT_INO).await {
             Ok(_) => {
                 tx.commit().await?;
                 return Ok(());
             }
-            Err(Error::Sys(Errno::ENOENT)) => {}
+            Err(Error::NotFound) => {}
             Err(error) => return Err(error),
         };
 
-        let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let t = now(cfg);
         let root_inode = Inode {
             ino: ROOT_INO,
             kind: inode::Kind::Directory,
@@ -141,20 +891,20 @@ impl Driver {
             atime: t,
             ctime: t,
             mtime: t,
+            crtime: t,
             owner: Owner { uid: 0, gid: 0 },
             mode: 0o777,
             size: 0,
-            nlink: 3,
+            nlink: 2,
+            rdev: 0,
+            creation_token: None,
+            extents: true,
+            generation: 0,
         };
 
-        tx.update(
-            cfg.bucket,
-            vec![
-                inode::create(&root_inode),
-                dir::create(cfg.view, ROOT_INO, ROOT_INO),
-            ],
-        )
-        .await?;
+        let updates = std::iter::once(inode::create(&root_inode))
+            .chain(dir::create(cfg.view, ROOT_INO, ROOT_INO));
+        tx.update(cfg.bucket, updates).await?;
         tx.commit().await?;
 
         Ok(())
@@ -162,8 +912,81 @@ impl Driver {
 
     #[tracing::instrument(skip(self))]
     pub(crate) async fn getattr(&self, ino: u64) -> Result<FileAttr> {
+        Ok(self.entry(ino).await?.attr)
+    }
+
+    async fn entry(&self, ino: u64) -> Result<EntryAttr> {
+        if let Some(node) = Node::from_ino(ino) {
+            return Ok(control_entry(node, self.mounted_at));
+        }
+
+        self.record_access(ino);
+        self.forget_stale();
+        if let Some(inode) = self.prefetch.inode(ino) {
+            return Ok(self.with_dirty(entry_attr(&self.cfg, &inode)));
+        }
+        if let Some(entry) = self.attrs.get(ino) {
+            return Ok(self.with_dirty(entry));
+        }
+
+        let entry = match self.attr_reads.join(ino).await {
+            ReadRole::Leader(id) => {
+                runtime::sleep(batch::READ_WINDOW).await;
+                let (inos, followers) = self.attr_reads.take().await;
+                tracing::debug!(followers, len = inos.len(), "reading batch");
+
+                let result = self.with_retry(|| self.getattr_batch(&inos)).await;
+                self.attr_reads.finish(id, followers, &result).await;
+                result?.get(&ino).copied().ok_or(Error::NotFound)?
+            }
+            ReadRole::Follower(id) => self.attr_reads.wait(id, ino).await?,
+            ReadRole::Alone => {
+                let result = self.with_retry(|| self.getattr_once(ino)).await;
+                self.attr_reads.done().await;
+                result?
+            }
+        };
+        self.attrs.insert(entry);
+        Ok(self.with_dirty(entry))
+    }
+
+    /* What writes changed and is not stored yet is served on top of what
+    was read. */
+    fn with_dirty(&self, entry: EntryAttr) -> EntryAttr {
+        self.dirty.apply_entry(&self.cfg.attr_shape, entry)
+    }
+
+    fn apply_dirty(&self, inode: &mut Inode) {
+        if let Some(dirty) = self.dirty.get(inode.ino) {
+            dirty.apply(inode);
+        }
+    }
+
+    /* Done before anything that could shrink the inode, a later flush would
+    otherwise grow it back. */
+    async fn flush_dirty(&self, ino: u64) -> Result<()> {
+        let written = dirty::flush_batch(&self.cfg, &self.pool, &self.dirty, Some(ino)).await?;
+        if written > 0 {
+            self.invalidate_caches();
+        }
+        Ok(())
+    }
+
+    /* Unless it has to wait for the writers locking the inode, a single
+    object is read in a static transaction, without the round trips to
+    begin and commit one. */
+    async fn getattr_once(&self, ino: u64) -> Result<EntryAttr> {
         let mut connection = self.pool.acquire().await?;
 
+        if !self.cfg.locks.covers(TxClass::Other) || self.cfg.snapshot.is_some() {
+            let snapshot = self.cfg.snapshot.as_ref();
+            let mut reply = connection
+                .read_static(snapshot, self.cfg.bucket, vec![inode::read(ino)])
+                .await?;
+            let inode = inode::decode(ino, &mut reply, 0).ok_or(Error::NotFound)?;
+            return Ok(entry_attr(&self.cfg, &inode));
+        }
+
         let mut tx = transaction!(self.cfg, connection, { shared: [inode::key(ino)] }).await?;
 
         let attrs = Self::attr_of(&self.cfg, &mut tx, ino).await?;
@@ -172,17 +995,64 @@ impl Driver {
         Ok(attrs)
     }
 
+    /* The inodes missing from the reply are left out. */
+    async fn getattr_batch(&self, inos: &[u64]) -> Result<HashMap<u64, EntryAttr>> {
+        let mut connection = self.pool.acquire().await?;
+        let queries: Vec<_> = inos.iter().map(|ino| inode::read(*ino)).collect();
+
+        let mut reply = if !self.cfg.locks.covers(TxClass::Other) || self.cfg.snapshot.is_some() {
+            let snapshot = self.cfg.snapshot.as_ref();
+            connection
+                .read_static(snapshot, self.cfg.bucket, queries)
+                .await?
+        } else {
+            let locks = TransactionLocks {
+                shared: inos.iter().map(|ino| inode::key(*ino).into()).collect(),
+                exclusive: vec![],
+            };
+            let mut tx = connection.transaction_at(None, locks).await?;
+            let reply = tx.read(self.cfg.bucket, queries).await?;
+            tx.commit().await?;
+            reply
+        };
+
+        let entries = inos
+            .iter()
+            .enumerate()
+            .filter_map(|(index, ino)| {
+                let inode = inode::decode(*ino, &mut reply, index)?;
+                Some((*ino, entry_attr(&self.cfg, &inode)))
+            })
+            .collect();
+        Ok(entries)
+    }
+
     #[tracing::instrument(skip(self))]
     pub(crate) async fn setattr(
         &self,
+        caller: Owner,
         ino: u64,
         mode: Option<u32>,
         uid: Option<u32>,
         gid: Option<u32>,
         size: Option<u64>,
-        atime: Option<Duration>,
-        mtime: Option<Duration>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
     ) -> Result<FileAttr> {
+        /* Only the truncation of a control file opening it for writing is
+        let through, there is nothing to truncate. */
+        if let Some(node) = Node::from_ino(ino) {
+            let times = atime.is_some() || mtime.is_some();
+            if mode.is_some() || uid.is_some() || gid.is_some() || times {
+                return Err(Error::Sys(Errno::EPERM));
+            }
+
+            return Ok(node.attr(self.mounted_at));
+        }
+
+        self.check_writable()?;
+        self.flush_dirty(ino).await?;
+
         macro_rules! update {
             ($target:expr, $v:ident) => {
                 $target = $v.unwrap_or($target);
@@ -200,15 +1070,64 @@ impl Driver {
 
         let inode = {
             let mut reply = tx.read(self.cfg.bucket, vec![inode::read(ino)]).await?;
-            let mut inode = inode::decode(ino, &mut reply, 0).ok_or(ENOENT)?;
+            let mut inode = inode::decode(ino, &mut reply, 0).ok_or(Error::NotFound)?;
+
+            /* The mode of a symlink is never used, changing it is ignored
+            while its owner can be changed as with lchown(2). */
+            let mode = match inode.kind {
+                inode::Kind::Symlink => None,
+                _ => mode,
+            };
+            if !self.cfg.default_permissions {
+                let attr = self.cfg.attr_shape.attr(&inode);
+                let times = match (atime, mtime) {
+                    (Some(TimeOrNow::SpecificTime(_)), _) => access::Times::Given,
+                    (_, Some(TimeOrNow::SpecificTime(_))) => access::Times::Given,
+                    (None, None) => access::Times::Unchanged,
+                    _ => access::Times::Now,
+                };
+                access::check_setattr(&attr, caller, mode, uid, gid, size, times)?;
+            }
+
+            /* Every inode is owned by the squashed owner, which it can't be
+            given away from. */
+            let shape = &self.cfg.attr_shape;
+            let (uid, gid) = match shape.squash_owner {
+                Some(owner) => {
+                    if matches!(uid, Some(uid) if uid != owner.uid)
+                        || matches!(gid, Some(gid) if gid != owner.gid)
+                    {
+                        return Err(Error::Sys(Errno::EPERM));
+                    }
+                    (None, None)
+                }
+                None => (uid, gid),
+            };
 
+            /* Given times are set as they are, even back in time as touch
+            and tar do, the current time never moves them back. */
+            let t = now(&self.cfg);
+            let stamp = |stored: Duration, time: TimeOrNow| match time {
+                TimeOrNow::SpecificTime(time) => {
+                    shape.timestamp(time.duration_since(UNIX_EPOCH).unwrap_or_default())
+                }
+                TimeOrNow::Now => inode::later(stored, t),
+            };
+            let atime = atime.map(|time| stamp(inode.atime, time));
+            let mtime = mtime.map(|time| stamp(inode.mtime, time));
+
+            if uid.is_some() || gid.is_some() {
+                inode.mode = access::chown_mode(inode.kind, inode.mode);
+            }
             update!(inode.mode, mode);
             update!(inode.owner.uid, uid);
             update!(inode.owner.gid, gid);
             update!(inode.atime, atime);
             update!(inode.mtime, mtime);
+            inode.changed(t);
 
             let update = if let Some(new_size) = size {
+                check_regular(&inode)?;
                 if new_size < inode.size {
                     tracing::debug!("truncate DOWN from 0x{:x} to 0x{:x}", inode.size, new_size);
 
@@ -224,81 +1143,645 @@ impl Driver {
                 inode::update_stats(&inode)
             };
 
-            tx.update(self.cfg.bucket, std::iter::once(update)).await?;
+            tx.update(self.cfg.bucket, vec![update, self.changes.bump()])
+                .await?;
 
             inode
         };
 
         tx.commit().await?;
-        Ok(inode.attr())
+        self.invalidate_caches();
+        Ok(self.cfg.attr_shape.attr(&inode))
     }
 
+    /* `.` and `..` are resolved from the inode, never from the entries
+    stored in the directory. */
     #[tracing::instrument(skip(self))]
-    pub(crate) async fn lookup(&self, parent_ino: u64, name: NameRef) -> Result<FileAttr> {
-        let mut connection = self.pool.acquire().await?;
-        let mut tx = transaction!(self.cfg, connection, { shared: [dir::key(parent_ino)] }).await?;
+    pub(crate) async fn lookup(
+        &self,
+        caller: Owner,
+        parent_ino: u64,
+        name: NameRef,
+    ) -> Result<EntryAttr> {
+        /* Names can only be resolved in directories the caller can search. */
+        if !self.cfg.default_permissions {
+            let parent = self.getattr(parent_ino).await?;
+            access::check(&parent, caller, access::X_OK)?;
+        }
 
-        let entries = {
-            let mut reply = tx
-                .read(self.cfg.bucket, vec![dir::read(parent_ino)])
-                .await?;
+        if let NameRef::Partial(prefix) = &name {
+            match prefix.as_slice() {
+                b"." => return self.entry(parent_ino).await,
+                b".." if parent_ino == self.root => return self.entry(parent_ino).await,
+                b".." => {
+                    let grand_parent_ino = self.parent_of(parent_ino).await?;
+                    return self.entry(grand_parent_ino).await;
+                }
+                _ => {}
+            }
+        }
 
-            dir::decode(self.cfg.view, &mut reply, 0).ok_or(ENOENT)?
-        };
+        if let Some(node) = control::lookup_entry(self.root, parent_ino, &name) {
+            return Ok(control_entry(node?, self.mounted_at));
+        }
+        let name = self.disambiguate(parent_ino, name).await?;
 
-        let attrs = match entries.get(&name) {
-            Some(entry) => Self::attr_of(&self.cfg, &mut tx, entry.ino).await,
-            None => Err(Error::Sys(Errno::ENOENT)),
-        };
+        self.forget_stale();
+        if self.negative.contains(parent_ino, &name) {
+            return Err(Error::NotFound);
+        }
+
+        let result = self
+            .with_retry(|| self.lookup_once(parent_ino, name.clone()))
+            .await;
+        if let Err(Error::NotFound) = result {
+            self.negative.insert(parent_ino, name);
+        }
+
+        result.map(|entry| self.with_dirty(entry))
+    }
+
+    /* The control directory is in the root of the mount. */
+    async fn parent_of(&self, ino: u64) -> Result<u64> {
+        if Node::from_ino(ino).is_some() {
+            return Ok(self.root);
+        }
+        if let Some(inode) = self.prefetch.inode(ino) {
+            return Ok(inode.parent);
+        }
+
+        self.with_retry(|| async {
+            let mut connection = self.pool.acquire().await?;
+            let mut tx = transaction!(self.cfg, connection).await?;
+
+            let mut reply = tx.read(self.cfg.bucket, vec![inode::read(ino)]).await?;
+            tx.commit().await?;
+
+            let inode = inode::decode(ino, &mut reply, 0).ok_or(Error::NotFound)?;
+            Ok(inode.parent)
+        })
+        .await
+    }
+
+    async fn lookup_once(&self, parent_ino: u64, name: NameRef) -> Result<EntryAttr> {
+        let mut connection = self.pool.acquire().await?;
+        let mut tx = transaction!(self.cfg, connection, { shared: [dir::key(parent_ino)] }).await?;
+
+        let entries = match self.prefetch.dir(parent_ino) {
+            Some(entries) => entries,
+            None => {
+                let mut reply = tx
+                    .read(self.cfg.bucket, vec![dir::read(parent_ino, name.prefix())])
+                    .await?;
+
+                Arc::new(dir::decode(self.cfg.view, &mut reply, 0))
+            }
+        };
+
+        let attrs = match entries.get(&name) {
+            Some(entry) => {
+                self.record_access(entry.ino);
+                match (self.prefetch.inode(entry.ino), self.attrs.get(entry.ino)) {
+                    (Some(inode), _) => Ok(entry_attr(&self.cfg, &inode)),
+                    (None, Some(cached)) => Ok(cached),
+                    (None, None) => {
+                        let read = Self::attr_of(&self.cfg, &mut tx, entry.ino).await?;
+                        self.attrs.insert(read);
+                        Ok(read)
+                    }
+                }
+            }
+            None => Err(Error::NotFound),
+        };
 
         tx.commit().await?;
         attrs
     }
 
-    async fn attr_of(cfg: &Config, tx: &mut Transaction<'_>, ino: u64) -> Result<FileAttr> {
+    /* A name that also reads as a decorated one is resolved ahead of the
+    operation, the entries of its readings being in shards of their own. */
+    async fn disambiguate(&self, parent_ino: u64, name: NameRef) -> Result<NameRef> {
+        let name = match name {
+            NameRef::Ambiguous(name) => name,
+            name => return Ok(name),
+        };
+
+        let mut readings = self.cfg.naming.readings(&name);
+        let found = self
+            .with_retry(|| async {
+                let mut connection = self.pool.acquire().await?;
+                let mut tx =
+                    transaction!(self.cfg, connection, { shared: [dir::key(parent_ino)] }).await?;
+
+                let found = Self::reading_of(&self.cfg, &mut tx, parent_ino, &readings).await?;
+                tx.commit().await?;
+                Ok(found)
+            })
+            .await?;
+
+        Ok(match found {
+            Some((index, _)) => readings.swap_remove(index),
+            None => NameRef::Partial(name),
+        })
+    }
+
+    /* The first reading of a name found in the directory, and its inode. */
+    async fn reading_of(
+        cfg: &Config,
+        tx: &mut Transaction<'_>,
+        parent_ino: u64,
+        readings: &[NameRef],
+    ) -> Result<Option<(usize, u64)>> {
+        let reads = readings
+            .iter()
+            .map(|reading| dir::read(parent_ino, reading.prefix()));
+        let mut reply = tx.read(cfg.bucket, reads).await?;
+
+        for (index, reading) in readings.iter().enumerate() {
+            let entries = dir::decode(cfg.view, &mut reply, index);
+            if let Some(entry) = entries.get(reading) {
+                return Ok(Some((index, entry.ino)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /* A name listed as is that also reads as a decorated one, which only
+    happens when it holds the separator, is listed along with its view and
+    inode unless it names its entry back. */
+    async fn listed<'a>(
+        &'a self,
+        tx: &mut Transaction<'_>,
+        parent_ino: u64,
+        dir: &'a DirView,
+    ) -> Result<Vec<EntryRef<'a>>> {
+        let mut listed: Vec<_> = dir
+            .iter_from(0, &self.cfg.naming)
+            .filter(|entry| &*entry.name != b"." && &*entry.name != b"..")
+            .collect();
+
+        let separator = self.cfg.naming.separator.to_string();
+        for entry in &mut listed {
+            let holds_separator = entry
+                .prefix
+                .windows(separator.len())
+                .any(|w| w == separator.as_bytes());
+            if !holds_separator {
+                continue;
+            }
+
+            let readings = self.cfg.naming.readings(&entry.name);
+            let found = Self::reading_of(&self.cfg, tx, parent_ino, &readings).await?;
+            if found.map(|(_, ino)| ino) != Some(entry.ino) {
+                let name = self
+                    .cfg
+                    .naming
+                    .decorate(entry.prefix, entry.view, Some(entry.ino));
+                entry.name = Cow::Owned(name);
+            }
+        }
+
+        Ok(listed)
+    }
+
+    async fn attr_of(cfg: &Config, tx: &mut Transaction<'_>, ino: u64) -> Result<EntryAttr> {
+        let mut reply = tx.read(cfg.bucket, vec![inode::read(ino)]).await?;
+        let inode = inode::decode(ino, &mut reply, 0).ok_or(Error::NotFound)?;
+        Ok(entry_attr(cfg, &inode))
+    }
+
+    async fn attr_of_own_creation(
+        cfg: &Config,
+        tx: &mut Transaction<'_>,
+        ino: u64,
+        token: CreationToken,
+    ) -> Result<EntryAttr> {
         let mut reply = tx.read(cfg.bucket, vec![inode::read(ino)]).await?;
-        let inode = inode::decode(ino, &mut reply, 0).ok_or(ENOENT)?;
-        Ok(inode.attr())
+        let inode = inode::decode(ino, &mut reply, 0).ok_or(Error::NotFound)?;
+
+        let t = now(cfg);
+        let is_recent = t
+            .checked_sub(inode.ctime)
+            .map_or(true, |age| age < CREATION_TOKEN_TTL);
+
+        if inode.creation_token == Some(token) && is_recent {
+            tracing::debug!(ino, "creation already applied by a previous attempt");
+            Ok(entry_attr(cfg, &inode))
+        } else {
+            Err(Error::Exists)
+        }
+    }
+
+    /* A creation that failed while talking to Antidote might still have been
+    committed. Retrying it with the same inode and token is safe: if the
+    entry exists and carries our token, the creation is reported as done. */
+    async fn retry_creation<T, F, Fut>(&self, mut create: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match create().await {
+                Err(error) if error.is_antidote() && attempt < CREATION_RETRIES => {
+                    tracing::warn!(?error, attempt, "retrying creation");
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /* Transactions aborted by Antidote because of a concurrent one are
+    retried, waiting a random time bounded by an exponential backoff. Only
+    meant for operations that can be replayed, such as reads. */
+    async fn with_retry<T, F, Fut>(&self, mut op: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut backoff = RETRY_BACKOFF_MIN;
+        let mut attempt = 0;
+        loop {
+            match op().await {
+                Err(Error::Conflict) if attempt < self.cfg.retries => {
+                    let wait = jitter(backoff);
+                    tracing::debug!(attempt, ?wait, "retrying aborted transaction");
+                    self.metrics.retried();
+                    runtime::sleep(wait).await;
+
+                    backoff = (backoff * 2).min(RETRY_BACKOFF_MAX);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn access(&self, caller: Owner, ino: u64, mask: u32) -> Result<()> {
+        let attr = self.getattr(ino).await?;
+        if mask == access::F_OK {
+            return Ok(());
+        }
+
+        access::check(&attr, caller, mask)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn opendir(&self, caller: Owner, ino: u64, flags: u32) -> Result<u64> {
+        let attr = self.getattr(ino).await?;
+        if attr.kind != FileType::Directory {
+            return Err(Error::Sys(Errno::ENOTDIR));
+        }
+        if !self.cfg.default_permissions {
+            access::check(&attr, caller, access::R_OK)?;
+        }
+
+        let (fh, _) = self.handles.open(ino, flags).await;
+        Ok(fh)
     }
 
     #[tracing::instrument(skip(self))]
-    pub(crate) async fn opendir(&self, ino: u64) -> Result<()> {
-        // FIXME: For now we are stateless, meaning that we do not track open
-        // close calls. just perform a simple getattr as a dummy check.
-        self.getattr(ino).await.map(|_| ())
+    pub(crate) async fn releasedir(&self, ino: u64, fh: u64) -> Result<()> {
+        self.handles
+            .release(ino, fh)
+            .await
+            .ok_or(Error::Sys(Errno::EBADF))?;
+
+        Ok(())
     }
 
+    /* When the kernel asks for a plain listing, attributes are still read
+    with it when they are cached, so that the lookups and getattrs following
+    a listing are served without a round trip each. */
     #[tracing::instrument(skip(self))]
-    pub(crate) async fn releasedir(&self, ino: u64) -> Result<()> {
-        self.getattr(ino).await.map(|_| ())
+    pub(crate) async fn readdir(
+        &self,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> Result<Vec<ReadDirEntry>> {
+        if self.cfg.attr_ttl == Duration::from_secs(0) {
+            return self.list_entries(ino, fh, offset).await;
+        }
+
+        let entries = self.readdirplus(ino, fh, offset).await?;
+        Ok(entries.into_iter().map(|(entry, _)| entry).collect())
     }
 
+    /* Entries whose inode is gone, e.g removed by another view since the
+    listing, are left out. */
     #[tracing::instrument(skip(self))]
-    pub(crate) async fn readdir(&self, ino: u64, offset: i64) -> Result<Vec<ReadDirEntry>> {
+    pub(crate) async fn readdirplus(
+        &self,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> Result<Vec<(ReadDirEntry, EntryAttr)>> {
+        let entries = self.list_entries(ino, fh, offset).await?;
+        if Node::from_ino(ino).is_some() {
+            let mut entries_plus = Vec::with_capacity(entries.len());
+            for entry in entries {
+                let attr = self.entry(entry.ino).await?;
+                entries_plus.push((entry, attr));
+            }
+            return Ok(entries_plus);
+        }
+
+        let inos: Vec<u64> = entries.iter().map(|entry| entry.ino).collect();
+        let attrs = self.with_retry(|| self.attrs_of(&inos)).await?;
+
+        let entries = entries
+            .into_iter()
+            .filter_map(|entry| {
+                let attr = *attrs.get(&entry.ino)?;
+                self.attrs.insert(attr);
+                Some((entry, self.with_dirty(attr)))
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
+    /* Every inode is read at once, in a single round trip. */
+    async fn attrs_of(&self, inos: &[u64]) -> Result<HashMap<u64, EntryAttr>> {
+        let mut connection = self.pool.acquire().await?;
+        let mut tx = transaction!(self.cfg, connection).await?;
+
+        let reads = inos.iter().map(|ino| inode::read(*ino));
+        let mut reply = tx.read(self.cfg.bucket, reads).await?;
+        tx.commit().await?;
+
+        let mut attrs = HashMap::with_capacity(inos.len());
+        for (index, ino) in inos.iter().enumerate() {
+            if let Some(inode) = inode::decode(*ino, &mut reply, index) {
+                attrs.insert(*ino, entry_attr(&self.cfg, &inode));
+            }
+        }
+
+        Ok(attrs)
+    }
+
+    /* Offsets are made of the shard of an entry and the cookie of its name,
+    so that a listing goes through the directory shard after shard and
+    resumes where it stopped even though entries came and went since. */
+    async fn list_entries(&self, ino: u64, fh: u64, offset: i64) -> Result<Vec<ReadDirEntry>> {
         assert!(offset >= 0);
+        if Node::from_ino(ino).is_some() {
+            return Ok(control::entries(self.root, offset));
+        }
+
+        let handle = self.handles.get(fh).await.ok_or(Error::Sys(Errno::EBADF))?;
+
+        let mut shard = (offset >> 32) as u16;
+        match handle.dir_snapshot {
+            Some(batch) if offset > 0 && batch.shards.contains(&shard) => {
+                let rest = batch.entries_after(offset);
+                if !rest.is_empty() {
+                    return Ok(rest);
+                }
+                shard = batch.shards.end;
+            }
+            _ => {}
+        }
+
+        if shard >= dir::SHARDS {
+            return Ok(Vec::new());
+        }
+
+        let batch = Arc::new(self.read_dir_batch(ino, shard).await?);
+        self.handles.set_dir_snapshot(fh, batch.clone()).await;
+
+        Ok(batch.entries_after(offset))
+    }
+
+    async fn read_dir_batch(&self, ino: u64, shard: u16) -> Result<DirBatch> {
+        self.with_retry(|| self.read_dir_batch_once(ino, shard))
+            .await
+    }
+
+    /* Reads shards until READDIR_BATCH entries are found, as many at once
+    as the shards read so far tell should be enough. */
+    async fn read_dir_batch_once(&self, ino: u64, start: u16) -> Result<DirBatch> {
         let mut connection = self.pool.acquire().await?;
         let mut tx = transaction!(self.cfg, connection, { shared: [dir::key(ino)] }).await?;
 
-        let entries = {
-            let entries = {
-                let mut reply = tx.read(self.cfg.bucket, vec![dir::read(ino)]).await?;
-                dir::decode(self.cfg.view, &mut reply, 0).ok_or(ENOENT)?
-            };
+        let mut entries = Vec::new();
+        let mut children = Vec::new();
+        let (mut shard, mut group) = (start, 1);
+        while shard < dir::SHARDS && entries.len() < READDIR_BATCH {
+            /* The listing starts with `.` and `..`, made up from the inode
+            of the directory read along with the first shards. */
+            let head = shard == 0;
+            let shards = shard..(shard + group).min(dir::SHARDS);
+            let reads = shards.clone().map(|shard| dir::read_shard(ino, shard));
+            let reads = reads.chain(if head { Some(inode::read(ino)) } else { None });
+            let mut reply = tx.read(self.cfg.bucket, reads).await?;
 
-            let mut mapped_entries = Vec::with_capacity(entries.len());
-            for entry in entries.iter_from(offset as usize) {
-                mapped_entries.push(ReadDirEntry {
-                    name: entry.name.into_owned(),
-                    ino,
-                    kind: entry.kind.to_file_type(),
-                });
+            if head {
+                let inode = inode::decode(ino, &mut reply, shards.len()).ok_or(Error::NotFound)?;
+                /* Nothing is reachable above the root of the mount. */
+                let parent_ino = if ino == self.root { ino } else { inode.parent };
+                entries.extend(dot_entries(ino, parent_ino));
             }
 
-            mapped_entries
-        };
+            let mut read = 0;
+            for (index, shard) in shards.clone().enumerate() {
+                let dir = dir::decode(self.cfg.view, &mut reply, index);
+                read += dir.len();
+                self.conflicts.record(ino, dir.conflicts());
+
+                let mut listed: Vec<_> = self
+                    .listed(&mut tx, ino, &dir)
+                    .await?
+                    .into_iter()
+                    .map(|entry| (dir::cookie_of(&entry.name), entry))
+                    .collect();
+                listed.sort_by(|(a, x), (b, y)| (a, &x.name).cmp(&(b, &y.name)));
+
+                /* Colliding names take the next free cookies. */
+                let mut last = None;
+                for (cookie, entry) in listed {
+                    let cookie = match last {
+                        Some(last) if cookie <= last => last + 1,
+                        _ => cookie,
+                    };
+                    last = Some(cookie);
+                    children.push((entry.ino, entry.kind));
+
+                    entries.push(ReadDirEntry {
+                        name: OsString::from_vec(entry.name.into_owned()),
+                        ino: entry.ino,
+                        kind: entry.kind.to_file_type(),
+                        offset: ((shard as i64) << 32) | (cookie as i64 + DOT_ENTRIES + 1),
+                    });
+                }
+            }
+
+            let per_shard = (read / shards.len()).max(1);
+            let missing = READDIR_BATCH.saturating_sub(entries.len());
+            group = (missing / per_shard).max(1).min(dir::SHARDS as usize) as u16;
+            shard = shards.end;
+        }
 
         tx.commit().await?;
-        Ok(entries)
+
+        if start == 0 {
+            self.prefetch.record_listing(ino, children);
+        } else {
+            self.prefetch.extend_listing(ino, children);
+        }
+
+        Ok(DirBatch {
+            shards: start..shard,
+            entries,
+        })
+    }
+
+    /* Entries come in the order of readdir, the token being the offset of
+    the last entry of the previous page: only the shards from there on are
+    read, until the page is full. */
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn list_dir(
+        &self,
+        ino: u64,
+        after: Option<&ContinuationToken>,
+        limit: usize,
+    ) -> Result<DirPage> {
+        let after = after.map_or(0, |after| after.0);
+
+        let mut listed = Vec::new();
+        let mut shard = (after >> 32) as u16;
+        while listed.len() <= limit && shard < dir::SHARDS {
+            let batch = self.read_dir_batch(ino, shard).await?;
+            listed.extend(
+                batch
+                    .entries_after(after)
+                    .into_iter()
+                    .filter(|entry| entry.name != "." && entry.name != ".."),
+            );
+            shard = batch.shards.end;
+        }
+
+        let next = if listed.len() > limit {
+            listed.truncate(limit);
+            listed.last().map(|entry| ContinuationToken(entry.offset))
+        } else {
+            None
+        };
+        let entries = listed
+            .into_iter()
+            .map(|entry| DirEntry {
+                name: entry.name,
+                ino: entry.ino,
+                kind: entry.kind,
+            })
+            .collect();
+
+        Ok(DirPage { entries, next })
+    }
+
+    pub(crate) async fn check(&self, repair: bool) -> Result<Report> {
+        if repair {
+            self.check_writable()?;
+        }
+
+        check::run(&self.cfg, &self.pool, &self.pages, repair).await
+    }
+
+    pub(crate) async fn collect_orphans(&self) -> Result<usize> {
+        self.check_writable()?;
+        gc::collect_orphans_offline(&self.cfg, &self.pool, &self.pages).await
+    }
+
+    /* Entries are unlinked in batches, with neither permission nor sticky
+    checks: the control file is only writable by root, and tooling using the
+    driver directly has access to the whole bucket anyway. */
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn remove_tree(&self, path: &Path) -> Result<u64> {
+        self.check_writable()?;
+
+        let name = path.file_name().ok_or(Error::Sys(Errno::EINVAL))?;
+        let parent = path.parent().unwrap_or_else(|| Path::new(""));
+        let parent_ino = self.resolve(self.root, parent).await?;
+        let name = self
+            .cfg
+            .naming
+            .parse(name.as_bytes())
+            .map_err(|_| Error::Sys(Errno::EINVAL))?;
+        control::check_removal(self.root, parent_ino, &name)?;
+        let name = self.disambiguate(parent_ino, name).await?;
+
+        let removed = self
+            .with_retry(|| {
+                tree::remove(
+                    &self.cfg,
+                    &self.pool,
+                    &self.pages,
+                    &self.changes,
+                    parent_ino,
+                    &name,
+                )
+            })
+            .await;
+
+        self.invalidate_caches();
+        self.negative.clear();
+        removed
+    }
+
+    pub(crate) async fn stats(&self) -> Result<Stats> {
+        stats::collect(&self.cfg, &self.pool, &self.pages).await
+    }
+
+    pub(crate) async fn create_snapshot(&self, name: &str) -> Result<Snapshot> {
+        snapshot::create(&self.cfg, &self.pool, name).await
+    }
+
+    pub(crate) async fn snapshots(&self) -> Result<Vec<Snapshot>> {
+        snapshot::list(&self.cfg, &self.pool).await
+    }
+
+    /* Without a driver, whose background tasks would otherwise keep on
+    running along the mount of the snapshot. */
+    pub(crate) async fn find_snapshot(cfg: &Config, name: &str) -> Result<Snapshot> {
+        let pool = ConnectionPool::with_capacity(cfg.addresses.clone(), 1, cfg.pool_timeout)
+            .with_request_timeout(cfg.request_timeout);
+        snapshot::find(cfg, &pool, name).await
+    }
+
+    /// Allocates a view no other mount was given, for mounts that don't
+    /// pick their own.
+    pub(crate) async fn allocate_view(cfg: &Config) -> Result<View> {
+        let pool = ConnectionPool::with_capacity(cfg.addresses.clone(), 1, cfg.pool_timeout)
+            .with_request_timeout(cfg.request_timeout);
+        views::allocate(cfg, &pool).await
+    }
+
+    pub(crate) async fn delete_snapshot(&self, name: &str) -> Result<()> {
+        snapshot::delete(&self.cfg, &self.pool, name).await
+    }
+
+    /// Announces the view of the mount until it is shut down, a snapshot
+    /// is never announced.
+    pub(crate) fn announce(&self) {
+        if self.cfg.snapshot.is_none() {
+            runtime::spawn(views::run(
+                self.cfg.clone(),
+                self.maintenance.clone(),
+                self.tasks.clone(),
+            ));
+        }
+    }
+
+    pub(crate) async fn views(&self) -> Result<Vec<ViewRecord>> {
+        views::list(&self.cfg, &self.pool).await
+    }
+
+    pub(crate) async fn forget_view(&self, view: View) -> Result<()> {
+        views::forget(&self.cfg, &self.pool, view).await
     }
 
     #[tracing::instrument(skip(self))]
@@ -308,11 +1791,33 @@ impl Driver {
         mode: u32,
         parent_ino: u64,
         name: NameRef,
-    ) -> Result<FileAttr> {
-        let ino = self.next_ino()?;
+        unique: u64,
+    ) -> Result<EntryAttr> {
+        self.check_writable()?;
+        control::check_creation(self.root, parent_ino, &name)?;
+        let name = self.disambiguate(parent_ino, name).await?;
+
+        let ino = self.next_ino().await?;
+        let token = CreationToken::new(self.cfg.view, unique);
+
+        let name = &name;
+        self.retry_creation(move || {
+            self.mkdir_once(owner, mode, parent_ino, name.clone(), ino, token)
+        })
+        .await
+    }
 
+    async fn mkdir_once(
+        &self,
+        owner: Owner,
+        mode: u32,
+        parent_ino: u64,
+        name: NameRef,
+        ino: u64,
+        token: CreationToken,
+    ) -> Result<EntryAttr> {
         let mut connection = self.pool.acquire().await?;
-        let mut tx = transaction!(self.cfg, connection, {
+        let mut tx = transaction!(self.cfg, connection, TxClass::Namespace, {
             exclusive: [
                 inode::key(parent_ino),
                 dir::key(parent_ino)
@@ -320,21 +1825,35 @@ impl Driver {
         })
         .await?;
 
-        let attr = {
+        let (attr, updates) = {
             let mut reply = tx
                 .read(
                     self.cfg.bucket,
-                    vec![inode::read(parent_ino), dir::read(parent_ino)],
+                    vec![
+                        inode::read(parent_ino),
+                        dir::read(parent_ino, name.prefix()),
+                        inode::read_generation(ino),
+                    ],
                 )
                 .await?;
 
-            let mut parent_inode = inode::decode(parent_ino, &mut reply, 0).ok_or(ENOENT)?;
-            let entries = dir::decode(self.cfg.view, &mut reply, 1).ok_or(ENOENT)?;
-            if entries.contains_key(&name) {
-                return Err(Error::Sys(Errno::EEXIST));
+            let mut parent_inode =
+                inode::decode(parent_ino, &mut reply, 0).ok_or(Error::NotFound)?;
+            let entries = dir::decode(self.cfg.view, &mut reply, 1);
+            let generation = inode::decode_generation(&mut reply, 2);
+            if let Some(entry) = entries.get(&name) {
+                let attr = Self::attr_of_own_creation(&self.cfg, &mut tx, entry.ino, token).await?;
+
+                tx.commit().await?;
+                return Ok(attr);
             }
+            self.check_access(&parent_inode, owner, access::W_OK | access::X_OK)?;
+
+            let stored_owner = self.cfg.attr_shape.owner(owner);
+            let (stored_owner, mode) =
+                access::inherit(&parent_inode, owner, stored_owner, Kind::Directory, mode);
 
-            let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            let t = now(&self.cfg);
             let inode = Inode {
                 ino,
                 kind: inode::Kind::Directory,
@@ -342,40 +1861,55 @@ impl Driver {
                 atime: t,
                 ctime: t,
                 mtime: t,
-                owner,
+                crtime: t,
+                owner: stored_owner,
                 mode,
                 size: 0,
                 nlink: 2,
+                rdev: 0,
+                creation_token: Some(token),
+                extents: true,
+                generation,
             };
-            parent_inode.mtime = t;
-            parent_inode.atime = t;
+            parent_inode.modified(t);
+            parent_inode.accessed(t);
             parent_inode.size += 1;
 
-            let attr = inode.attr();
+            let attr = entry_attr(&self.cfg, &inode);
 
             let name = name.canonicalize(self.cfg.view);
-            tx.update(
-                self.cfg.bucket,
-                vec![
-                    dir::add_entry(parent_ino, &dir::Entry::new(name, ino, Kind::Directory)),
-                    dir::create(self.cfg.view, parent_ino, ino),
-                    inode::create(&inode),
-                    inode::update_stats_and_size(&parent_inode),
-                ],
-            )
-            .await?;
+            let mut updates = vec![
+                dir::add_entry(parent_ino, &dir::Entry::new(name, ino, Kind::Directory)),
+                inode::create(&inode),
+                inode::update_stats_and_size(&parent_inode),
+                inode::incr_link_count(parent_ino, 1),
+                self.changes.bump(),
+            ];
+            updates.extend(dir::create(self.cfg.view, parent_ino, ino));
 
-            attr
+            (attr, updates)
         };
 
-        tx.commit().await?;
+        tx.commit_pipeline(Pipeline::new().update(self.cfg.bucket, updates))
+            .await?;
+        self.invalidate_caches();
+        self.negative.forget(parent_ino);
         Ok(attr)
     }
 
     #[tracing::instrument(skip(self))]
-    pub(crate) async fn rmdir(self: Arc<Driver>, parent_ino: u64, name: NameRef) -> Result<()> {
+    pub(crate) async fn rmdir(
+        self: Arc<Driver>,
+        caller: Owner,
+        parent_ino: u64,
+        name: NameRef,
+    ) -> Result<()> {
+        self.check_writable()?;
+        control::check_removal(self.root, parent_ino, &name)?;
+        let name = self.disambiguate(parent_ino, name).await?;
+
         let mut connection = self.pool.acquire().await?;
-        let mut tx = transaction!(self.cfg, connection, {
+        let mut tx = transaction!(self.cfg, connection, TxClass::Namespace, {
             exclusive: [
                 inode::key(parent_ino),
                 dir::key(parent_ino)
@@ -387,17 +1921,35 @@ impl Driver {
             let mut reply = tx
                 .read(
                     self.cfg.bucket,
-                    vec![inode::read(parent_ino), dir::read(parent_ino)],
+                    vec![
+                        inode::read(parent_ino),
+                        dir::read(parent_ino, name.prefix()),
+                    ],
                 )
                 .await?;
 
-            let mut parent_inode = inode::decode(parent_ino, &mut reply, 0).ok_or(ENOENT)?;
-            let entries = dir::decode(self.cfg.view, &mut reply, 1).ok_or(ENOENT)?;
-            let entry = entries.get(&name).ok_or(ENOENT)?;
+            let mut parent_inode =
+                inode::decode(parent_ino, &mut reply, 0).ok_or(Error::NotFound)?;
+            let entries = dir::decode(self.cfg.view, &mut reply, 1);
+            let entry = entries.get(&name).ok_or(Error::NotFound)?;
+            self.check_access(&parent_inode, caller, access::W_OK | access::X_OK)?;
+            self.check_sticky(&mut tx, &parent_inode, entry.ino, caller)
+                .await?;
+            if entry.kind != Kind::Directory {
+                return Err(Error::Sys(Errno::ENOTDIR));
+            }
+
+            /* Read in the same transaction, entries created since then
+            conflict with the removal. */
+            let mut reply = tx.read(self.cfg.bucket, dir::read_all(entry.ino)).await?;
+            let children = dir::decode_all(self.cfg.view, &mut reply, 0);
+            if children.map_or(false, |children| children.has_children()) {
+                return Err(Error::NotEmpty);
+            }
 
-            let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-            parent_inode.atime = t;
-            parent_inode.mtime = t;
+            let t = now(&self.cfg);
+            parent_inode.accessed(t);
+            parent_inode.modified(t);
             parent_inode.size -= 1;
 
             let dentry = entry.into_dentry();
@@ -407,6 +1959,9 @@ impl Driver {
                     inode::decr_link_count(entry.ino, 1),
                     dir::remove_entry(parent_ino, &dentry),
                     inode::update_stats_and_size(&parent_inode),
+                    inode::decr_link_count(parent_ino, 1),
+                    orphan::add(entry.ino),
+                    self.changes.bump(),
                 ],
             )
             .await?;
@@ -415,6 +1970,8 @@ impl Driver {
         };
 
         tx.commit().await?;
+        self.invalidate_caches();
+        self.negative.forget(parent_ino);
         self.schedule_delete(ino);
         Ok(())
     }
@@ -426,12 +1983,85 @@ impl Driver {
         mode: u32,
         parent_ino: u64,
         name: NameRef,
-        _rdev: u32,
-    ) -> Result<FileAttr> {
-        let ino = self.next_ino()?;
+        rdev: u32,
+        unique: u64,
+    ) -> Result<EntryAttr> {
+        self.check_writable()?;
+        control::check_creation(self.root, parent_ino, &name)?;
+        let name = self.disambiguate(parent_ino, name).await?;
+
+        let kind = Kind::of_node(mode).ok_or(Error::Sys(Errno::EINVAL))?;
+        let rdev = match kind {
+            Kind::CharDevice | Kind::BlockDevice => rdev,
+            _ => 0,
+        };
+        let ino = self.next_ino().await?;
+        let token = CreationToken::new(self.cfg.view, unique);
 
+        let node = NewNode {
+            owner,
+            mode,
+            kind,
+            rdev,
+            name,
+        };
+        let node = &node;
+        self.retry_creation(move || self.mknod_once(node, parent_ino, ino, token, false))
+            .await
+    }
+
+    /* Unlike a mknod followed by an open, the inode is created and held
+    open by this view in a single transaction. */
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn create(
+        &self,
+        owner: Owner,
+        mode: u32,
+        flags: u32,
+        parent_ino: u64,
+        name: NameRef,
+        unique: u64,
+    ) -> Result<(EntryAttr, u64)> {
+        self.check_writable()?;
+        control::check_creation(self.root, parent_ino, &name)?;
+        let name = self.disambiguate(parent_ino, name).await?;
+
+        let ino = self.next_ino().await?;
+        let token = CreationToken::new(self.cfg.view, unique);
+
+        let node = NewNode {
+            owner,
+            mode,
+            kind: Kind::Regular,
+            rdev: 0,
+            name,
+        };
+        let node = &node;
+        let attr = self
+            .retry_creation(move || self.mknod_once(node, parent_ino, ino, token, true))
+            .await?;
+
+        let (fh, _) = self.handles.open(attr.attr.ino, flags).await;
+        Ok((attr, fh))
+    }
+
+    async fn mknod_once(
+        &self,
+        node: &NewNode,
+        parent_ino: u64,
+        ino: u64,
+        token: CreationToken,
+        hold: bool,
+    ) -> Result<EntryAttr> {
+        let NewNode {
+            owner,
+            mode,
+            kind,
+            rdev,
+            ref name,
+        } = *node;
         let mut connection = self.pool.acquire().await?;
-        let mut tx = transaction!(self.cfg, connection, {
+        let mut tx = transaction!(self.cfg, connection, TxClass::Namespace, {
             exclusive: [
                 inode::key(parent_ino),
                 dir::key(parent_ino)
@@ -439,60 +2069,84 @@ impl Driver {
         })
         .await?;
 
-        let attr = {
+        let (attr, updates) = {
             let mut reply = tx
                 .read(
                     self.cfg.bucket,
-                    vec![inode::read(parent_ino), dir::read(parent_ino)],
+                    vec![
+                        inode::read(parent_ino),
+                        dir::read(parent_ino, name.prefix()),
+                        inode::read_generation(ino),
+                    ],
                 )
                 .await?;
 
-            let mut parent = inode::decode(parent_ino, &mut reply, 0).ok_or(ENOENT)?;
-            let entries = dir::decode(self.cfg.view, &mut reply, 1).ok_or(ENOENT)?;
-            if entries.contains_key(&name) {
-                return Err(Error::Sys(Errno::EEXIST));
+            let mut parent = inode::decode(parent_ino, &mut reply, 0).ok_or(Error::NotFound)?;
+            let entries = dir::decode(self.cfg.view, &mut reply, 1);
+            let generation = inode::decode_generation(&mut reply, 2);
+            if let Some(entry) = entries.get(&name) {
+                let attr = Self::attr_of_own_creation(&self.cfg, &mut tx, entry.ino, token).await?;
+
+                tx.commit().await?;
+                return Ok(attr);
             }
+            self.check_access(&parent, owner, access::W_OK | access::X_OK)?;
 
-            let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            let stored_owner = self.cfg.attr_shape.owner(owner);
+            let (stored_owner, mode) = access::inherit(&parent, owner, stored_owner, kind, mode);
+
+            let t = now(&self.cfg);
             let inode = Inode {
                 ino,
-                kind: inode::Kind::Regular,
+                kind,
                 parent: parent_ino,
                 atime: t,
                 ctime: t,
                 mtime: t,
-                owner,
+                crtime: t,
+                owner: stored_owner,
                 mode,
                 size: 0,
                 nlink: 1,
+                rdev,
+                creation_token: Some(token),
+                extents: true,
+                generation,
             };
-            parent.mtime = t;
-            parent.ctime = t;
+            parent.modified(t);
+            parent.changed(t);
             parent.size += 1;
 
-            let attr = inode.attr();
-            let name = name.canonicalize(self.cfg.view);
-            tx.update(
-                self.cfg.bucket,
-                vec![
-                    inode::update_stats_and_size(&parent),
-                    dir::add_entry(parent_ino, &dir::Entry::new(name, ino, Kind::Regular)),
-                    inode::create(&inode),
-                ],
-            )
-            .await?;
+            let attr = entry_attr(&self.cfg, &inode);
+            let name = name.clone().canonicalize(self.cfg.view);
+            let mut updates = vec![
+                inode::update_stats_and_size(&parent),
+                dir::add_entry(parent_ino, &dir::Entry::new(name, ino, kind)),
+                inode::create(&inode),
+                self.changes.bump(),
+            ];
+            if hold {
+                updates.push(orphan::hold(ino, self.cfg.view));
+            }
 
-            attr
+            (attr, updates)
         };
 
-        tx.commit().await?;
+        tx.commit_pipeline(Pipeline::new().update(self.cfg.bucket, updates))
+            .await?;
+        self.invalidate_caches();
+        self.negative.forget(parent_ino);
         Ok(attr)
     }
 
     #[tracing::instrument(skip(self))]
-    pub(crate) async fn unlink(&self, parent_ino: u64, name: NameRef) -> Result<()> {
+    pub(crate) async fn unlink(&self, caller: Owner, parent_ino: u64, name: NameRef) -> Result<()> {
+        self.check_writable()?;
+        control::check_removal(self.root, parent_ino, &name)?;
+        let name = self.disambiguate(parent_ino, name).await?;
+
         let mut connection = self.pool.acquire().await?;
-        let mut tx = transaction!(self.cfg, connection, {
+        let mut tx = transaction!(self.cfg, connection, TxClass::Namespace, {
             exclusive: [
                 inode::key(parent_ino),
                 dir::key(parent_ino)
@@ -504,25 +2158,40 @@ impl Driver {
             let mut reply = tx
                 .read(
                     self.cfg.bucket,
-                    vec![inode::read(parent_ino), dir::read(parent_ino)],
+                    vec![
+                        inode::read(parent_ino),
+                        dir::read(parent_ino, name.prefix()),
+                    ],
                 )
                 .await?;
 
-            let mut parent_inode = inode::decode(parent_ino, &mut reply, 0).ok_or(ENOENT)?;
-            let entries = dir::decode(self.cfg.view, &mut reply, 1).ok_or(ENOENT)?;
-            let entry = entries.get(&name).ok_or(ENOENT)?;
+            let mut parent_inode =
+                inode::decode(parent_ino, &mut reply, 0).ok_or(Error::NotFound)?;
+            let entries = dir::decode(self.cfg.view, &mut reply, 1);
+            let entry = entries.get(&name).ok_or(Error::NotFound)?;
+            self.check_access(&parent_inode, caller, access::W_OK | access::X_OK)?;
+            self.check_sticky(&mut tx, &parent_inode, entry.ino, caller)
+                .await?;
+            if entry.kind == Kind::Directory {
+                return Err(Error::Sys(Errno::EISDIR));
+            }
 
-            let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-            parent_inode.mtime = t;
-            parent_inode.ctime = t;
+            let t = now(&self.cfg);
+            parent_inode.modified(t);
+            parent_inode.changed(t);
             parent_inode.size -= 1;
 
+            /* The inode is recorded as an orphan right away, a crash before
+            its removal would leak it otherwise. */
             let dentry = entry.into_dentry();
             tx.update(
                 self.cfg.bucket,
                 vec![
                     dir::remove_entry(parent_ino, &dentry),
+                    inode::update_stats_and_size(&parent_inode),
                     inode::decr_link_count(entry.ino, 1),
+                    orphan::add(entry.ino),
+                    self.changes.bump(),
                 ],
             )
             .await?;
@@ -531,120 +2200,766 @@ impl Driver {
         };
 
         tx.commit().await?;
+        self.invalidate_caches();
+        self.negative.forget(parent_ino);
         self.schedule_delete(ino);
         Ok(())
     }
 
     #[tracing::instrument(skip(self))]
-    pub(crate) async fn open(&self, ino: u64) -> Result<()> {
-        self.getattr(ino).await.map(|_| ())
+    pub(crate) async fn open(&self, caller: Owner, ino: u64, flags: u32) -> Result<u64> {
+        if access::open_mask(flags) & access::W_OK != 0 {
+            self.check_writable()?;
+        }
+
+        let attr = self.getattr(ino).await?;
+        if !self.cfg.default_permissions {
+            access::check(&attr, caller, access::open_mask(flags))?;
+        }
+
+        let (fh, first) = self.handles.open(ino, flags).await;
+        if first {
+            if let Err(error) = self.hold(ino).await {
+                self.handles.release(ino, fh).await;
+                return Err(error);
+            }
+        }
+
+        Ok(fh)
+    }
+
+    /* Called on each close of a file, unlike release its error is returned
+    by close. Writes are already committed, what they changed in the inode
+    is stored here rather than once the last handle is released. */
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn flush(&self, ino: u64, lock_owner: u64) -> Result<()> {
+        self.unlock_owner(ino, lock_owner).await?;
+        if !self.cfg.flush_on_close {
+            return Ok(());
+        }
+
+        self.fsync(ino).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn release(&self, ino: u64, fh: u64, lock_owner: Option<u64>) -> Result<()> {
+        let (_, last) = self
+            .handles
+            .release(ino, fh)
+            .await
+            .ok_or(Error::Sys(Errno::EBADF))?;
+        self.kernel.forget_poll(fh);
+        if let Some(owner) = lock_owner {
+            self.unlock_owner(ino, owner).await?;
+        }
+
+        if last {
+            self.flush_dirty(ino).await?;
+            self.unhold(ino).await?;
+        }
+
+        Ok(())
+    }
+
+    /* Files are always ready to be read from and written to. A poll the
+    kernel asks to be notified for is woken up once another view changed the
+    namespace or a file, e.g appended to it, the kernel then polls again. */
+    #[tracing::instrument(skip(self, ph))]
+    pub(crate) async fn poll(
+        &self,
+        ino: u64,
+        fh: u64,
+        ph: PollHandle,
+        events: u32,
+        flags: u32,
+    ) -> Result<u32> {
+        match self.handles.get(fh).await {
+            Some(handle) if handle.ino == ino => {}
+            _ => return Err(Error::Sys(Errno::EBADF)),
+        }
+
+        if flags & consts::FUSE_POLL_SCHEDULE_NOTIFY != 0 {
+            self.kernel.poll(fh, ph);
+        }
+        let ready = libc::POLLIN | libc::POLLRDNORM | libc::POLLOUT | libc::POLLWRNORM;
+        Ok(events & ready as u32)
     }
 
+    /* Writes are committed before they are replied to and inode numbers
+    are leased before they are handed out, only what writes changed in the
+    inode is left to store. */
     #[tracing::instrument(skip(self))]
-    pub(crate) async fn release(&self, ino: u64) -> Result<()> {
-        self.getattr(ino).await.map(|_| ())
+    pub(crate) async fn fsync(&self, ino: u64) -> Result<()> {
+        if Node::from_ino(ino).is_some() || self.cfg.snapshot.is_some() {
+            return Ok(());
+        }
+
+        self.flush_dirty(ino).await
+    }
+
+    /* Open inodes are advertised so that no view removes an inode that
+    another view is still using once unlinked. Nothing is ever removed from
+    a snapshot, nor from the control directory. */
+    async fn hold(&self, ino: u64) -> Result<()> {
+        if self.cfg.snapshot.is_some() || Node::from_ino(ino).is_some() {
+            return Ok(());
+        }
+
+        let mut connection = self.pool.acquire().await?;
+        let mut tx =
+            transaction!(self.cfg, connection, { exclusive: [orphan::holders_key(ino)] }).await?;
+
+        tx.update(self.cfg.bucket, vec![orphan::hold(ino, self.cfg.view)])
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn unhold(&self, ino: u64) -> Result<()> {
+        if self.cfg.snapshot.is_some() || Node::from_ino(ino).is_some() {
+            return Ok(());
+        }
+
+        let mut connection = self.pool.acquire().await?;
+        let mut tx = transaction!(self.cfg, connection, {
+            exclusive: [orphan::holders_key(ino)]
+        })
+        .await?;
+
+        tx.update(self.cfg.bucket, vec![orphan::unhold(ino, self.cfg.view)])
+            .await?;
+
+        let unlinked = {
+            let mut reply = tx.read(self.cfg.bucket, vec![inode::read(ino)]).await?;
+            inode::decode(ino, &mut reply, 0).map_or(false, |inode| inode.nlink == 0)
+        };
+
+        tx.commit().await?;
+        if unlinked {
+            self.schedule_delete(ino);
+        }
+
+        Ok(())
     }
 
     #[tracing::instrument(skip(self, bytes), fields(offset, len = bytes.len()))]
-    pub(crate) async fn write(&self, ino: u64, bytes: &[u8], offset: u64) -> Result<()> {
-        let byte_range = offset..(offset + bytes.len() as u64);
+    pub(crate) async fn write(&self, ino: u64, fh: u64, bytes: Buffer, offset: u64) -> Result<()> {
+        if let Some(node) = Node::from_ino(ino) {
+            return self.trigger(node, &bytes).await;
+        }
+
+        self.check_writable()?;
+
+        let append = self.handles.get(fh).await.map_or(false, |h| h.append());
+        if append {
+            return self.write_locked(ino, &bytes, offset, true).await;
+        }
+
+        match self.writes.join(ino, offset, bytes).await {
+            Role::Leader(id) => {
+                runtime::sleep(coalesce::WRITE_WINDOW).await;
+                let (offset, bytes, followers) = self.writes.take(ino).await;
+                tracing::debug!(followers, len = bytes.len(), "writing batch");
+
+                let result = self.write_locked(ino, &bytes, offset, false).await;
+                self.writes.finish(id, followers, &result).await;
+                result
+            }
+            Role::Follower(id) => self.writes.wait(id).await,
+            Role::Alone(bytes) => self.write_locked(ino, &bytes, offset, false).await,
+        }
+    }
+
+    /* The offset of an append is only known once the size is read inside
+    the transaction, the whole file is guarded meanwhile. */
+    async fn write_locked(&self, ino: u64, bytes: &[u8], offset: u64, append: bool) -> Result<()> {
+        let byte_range = if append {
+            0..u64::max_value()
+        } else {
+            offset..(offset + bytes.len() as u64)
+        };
         let lock = self.page_locks.lock(ino, byte_range).await;
 
-        let result = self.write_nolock(ino, bytes, offset).await;
+        let result = self.write_nolock(ino, bytes, offset, append).await;
+        if result.is_ok() {
+            self.metrics.written(bytes.len() as u64);
+        }
 
         self.page_locks.unlock(lock).await;
         result
     }
 
-    pub(crate) async fn write_nolock(&self, ino: u64, bytes: &[u8], offset: u64) -> Result<()> {
+    pub(crate) async fn write_nolock(
+        &self,
+        ino: u64,
+        bytes: &[u8],
+        offset: u64,
+        append: bool,
+    ) -> Result<()> {
         let mut connection = self.pool.acquire().await?;
         let mut tx = transaction!(self.cfg, connection, { exclusive: [inode::key(ino)] }).await?;
 
-        self.pages.write(&mut tx, ino, offset, bytes).await?;
+        let mut reply = tx.read(self.cfg.bucket, vec![inode::read(ino)]).await?;
+        let mut inode = inode::decode(ino, &mut reply, 0).ok_or(Error::NotFound)?;
+        check_regular(&inode)?;
+        self.apply_dirty(&mut inode);
+
+        let offset = if append { inode.size } else { offset };
+        let size = inode.size.max(offset + bytes.len() as u64);
+        let mtime = now(&self.cfg);
+
+        /* The new size is journaled along with the content, a mount crashing
+        before storing it would otherwise lose what was appended. */
+        let mut journaled = Vec::new();
+        if size > inode.size {
+            tracing::debug!(extended = size);
+            let intent = Intent::Extend { ino, size, mtime };
+            journaled.push(journal::record(self.cfg.view, &intent));
+        }
+        journaled.push(self.changes.bump());
+        self.pages
+            .write_with(&mut tx, ino, offset, bytes, journaled)
+            .await?;
+        tx.commit().await?;
+
+        /* Only once the content is stored, the inode is left as is. */
+        self.dirty.written(ino, Dirty { mtime, size });
+        self.invalidate_caches();
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn fallocate(&self, ino: u64, offset: u64, len: u64, mode: i32) -> Result<()> {
+        self.check_writable()?;
+
+        const SUPPORTED: i32 =
+            libc::FALLOC_FL_KEEP_SIZE | libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_ZERO_RANGE;
+
+        let punch_hole = mode & libc::FALLOC_FL_PUNCH_HOLE != 0;
+        let zero_range = mode & libc::FALLOC_FL_ZERO_RANGE != 0;
+        let keep_size = mode & libc::FALLOC_FL_KEEP_SIZE != 0;
+        if mode & !SUPPORTED != 0 || (punch_hole && (zero_range || !keep_size)) {
+            return Err(Error::Sys(Errno::EOPNOTSUPP));
+        }
+        if len == 0 {
+            return Err(Error::Sys(Errno::EINVAL));
+        }
+
+        let byte_range = offset..(offset + len);
+        let lock = self.page_locks.lock(ino, byte_range.clone()).await;
+
+        let result = self
+            .fallocate_nolock(ino, byte_range, punch_hole || zero_range, keep_size)
+            .await;
+
+        self.page_locks.unlock(lock).await;
+        result
+    }
+
+    /* Pages are never preallocated, a file is only extended and the new
+    region is read as a hole until written. */
+    async fn fallocate_nolock(
+        &self,
+        ino: u64,
+        byte_range: Range<u64>,
+        zero: bool,
+        keep_size: bool,
+    ) -> Result<()> {
+        let mut connection = self.pool.acquire().await?;
+        let mut tx = transaction!(self.cfg, connection, { exclusive: [inode::key(ino)] }).await?;
 
         let mut reply = tx.read(self.cfg.bucket, vec![inode::read(ino)]).await?;
-        let mut inode = inode::decode(ino, &mut reply, 0).ok_or(ENOENT)?;
+        let mut inode = inode::decode(ino, &mut reply, 0).ok_or(Error::NotFound)?;
+        if inode.kind != inode::Kind::Regular {
+            return Err(Error::Sys(Errno::ENODEV));
+        }
+        self.apply_dirty(&mut inode);
+
+        if zero && byte_range.start < inode.size {
+            let zeroed = byte_range.start..byte_range.end.min(inode.size);
+            self.pages.zero(&mut tx, ino, zeroed).await?;
+        }
+
+        let t = now(&self.cfg);
+        inode.modified(t);
+        inode.changed(t);
+
+        let update = if !keep_size && byte_range.end > inode.size {
+            inode.size = byte_range.end;
+            inode::update_stats_and_size(&inode)
+        } else {
+            inode::update_stats(&inode)
+        };
 
-        let wrote_above_size = (offset + bytes.len() as u64).saturating_sub(inode.size);
+        tx.update(self.cfg.bucket, std::iter::once(update)).await?;
+        tx.commit().await?;
+        self.invalidate_caches();
+        Ok(())
+    }
+
+    /* Data goes from Antidote to Antidote through the driver, never
+    through the kernel. The copy is split in transactions of a bounded
+    size, what was copied so far is kept if one of them fails. */
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn copy_file_range(
+        &self,
+        src_ino: u64,
+        src_offset: u64,
+        dst_ino: u64,
+        dst_offset: u64,
+        len: u64,
+    ) -> Result<u64> {
+        self.check_writable()?;
+
+        let overlaps = src_offset < dst_offset + len && dst_offset < src_offset + len;
+        if src_ino == dst_ino && overlaps {
+            return Err(Error::Sys(Errno::EINVAL));
+        }
+
+        let chunk_len = COPY_CHUNK_PAGES * self.cfg.page_size;
+        let mut copied = 0;
+        while copied < len {
+            let chunk = (len - copied).min(chunk_len);
+            let src_offset = src_offset + copied;
+            let dst_range = (dst_offset + copied)..(dst_offset + copied + chunk);
+
+            let lock = self.page_locks.lock(dst_ino, dst_range.clone()).await;
+            let result = self
+                .copy_chunk(src_ino, src_offset, dst_ino, dst_range.start, chunk)
+                .await;
+            self.page_locks.unlock(lock).await;
+
+            match result {
+                Ok(0) => break,
+                Ok(chunk_copied) => copied += chunk_copied,
+                Err(error) if copied == 0 => return Err(error),
+                Err(error) => {
+                    tracing::warn!(?error, copied, "partial copy");
+                    break;
+                }
+            }
+        }
+
+        Ok(copied)
+    }
+
+    async fn copy_chunk(
+        &self,
+        src_ino: u64,
+        src_offset: u64,
+        dst_ino: u64,
+        dst_offset: u64,
+        len: u64,
+    ) -> Result<u64> {
+        let mut connection = self.pool.acquire().await?;
+        let mut tx = transaction!(self.cfg, connection, {
+            shared: [inode::key(src_ino)],
+            exclusive: [inode::key(dst_ino)]
+        })
+        .await?;
+
+        let (mut src, mut dst) = {
+            let reads = vec![inode::read(src_ino), inode::read(dst_ino)];
+            let mut reply = tx.read(self.cfg.bucket, reads).await?;
+
+            (
+                inode::decode(src_ino, &mut reply, 0).ok_or(Error::NotFound)?,
+                inode::decode(dst_ino, &mut reply, 1).ok_or(Error::NotFound)?,
+            )
+        };
+        check_regular(&src)?;
+        check_regular(&dst)?;
+        self.apply_dirty(&mut src);
+        self.apply_dirty(&mut dst);
+
+        let len = len.min(src.size.saturating_sub(src_offset));
+        if len == 0 {
+            tx.commit().await?;
+            return Ok(0);
+        }
+
+        /* When both offsets are aligned, whole pages are copied as is. */
+        let page_size = self.cfg.page_size;
+        let whole_pages = if src_offset % page_size == 0 && dst_offset % page_size == 0 {
+            len / page_size
+        } else {
+            0
+        };
+
+        if whole_pages > 0 {
+            let src_first_page = src_offset / page_size;
+            let src_pages = src_first_page..(src_first_page + whole_pages);
+            let dst_first_page = dst_offset / page_size;
+            self.pages
+                .copy_pages(
+                    &mut tx,
+                    src_ino,
+                    src_pages,
+                    src.extents,
+                    dst_ino,
+                    dst_first_page,
+                )
+                .await?;
+        }
+
+        let copied_pages = whole_pages * page_size;
+        if copied_pages < len {
+            let (src_offset, dst_offset) = (src_offset + copied_pages, dst_offset + copied_pages);
+            let remaining = len - copied_pages;
+
+            let mut bytes = Vec::with_capacity(remaining as usize);
+            if src.extents {
+                self.pages
+                    .read_sparse(&mut tx, src_ino, src_offset, remaining, &mut bytes)
+                    .await?;
+            } else {
+                self.pages
+                    .read(&mut tx, src_ino, src_offset, remaining, &mut bytes)
+                    .await?;
+            }
+            self.pages
+                .write(&mut tx, dst_ino, dst_offset, &bytes)
+                .await?;
+        }
+
+        let t = now(&self.cfg);
+        dst.modified(t);
+        dst.changed(t);
+        let update = if dst_offset + len > dst.size {
+            dst.size = dst_offset + len;
+            inode::update_stats_and_size(&dst)
+        } else {
+            inode::update_stats(&dst)
+        };
+
+        tx.update(self.cfg.bucket, std::iter::once(update)).await?;
+        tx.commit().await?;
+        self.invalidate_caches();
+        Ok(len)
+    }
+
+    pub(crate) async fn read(&self, ino: u64, offset: u64, len: u32) -> Result<Vec<u8>> {
+        if let Some(node) = Node::from_ino(ino) {
+            return self.read_control(node, offset, len);
+        }
+
+        let byte_range = offset..(offset + len as u64);
+        let lock = self.page_locks.lock(ino, byte_range).await;
+
+        let result = self.with_retry(|| self.read_nolock(ino, offset, len)).await;
+        if let Ok(bytes) = &result {
+            self.metrics.read(bytes.len() as u64);
+        }
+
+        self.page_locks.unlock(lock).await;
+        result
+    }
+
+    /* Control files are generated anew for each read, they are opened in
+    direct I/O so that the kernel doesn't keep an old content around. */
+    fn read_control(&self, node: Node, offset: u64, len: u32) -> Result<Vec<u8>> {
+        let content = match node {
+            Node::Stats => self.metrics.render(&self.pool, &self.maintenance),
+            Node::Connections => {
+                let (idle, in_use) = self.pool.usage();
+                let (_, maintenance) = self.maintenance.usage();
+                format!(
+                    "idle {}\nin_use {}\ncapacity {}\nwaiting {}\nmaintenance_in_use {}\n",
+                    idle,
+                    in_use,
+                    self.pool.capacity(),
+                    self.pool.waiting(),
+                    maintenance
+                )
+            }
+            Node::View => format!("{}\n", self.cfg.view),
+            Node::Conflicts => self.conflicts.render(),
+            Node::Interrupt => self.interrupts.render(),
+            Node::Dir => return Err(Error::Sys(Errno::EISDIR)),
+            Node::DropCaches | Node::Gc | Node::Rm => return Err(Error::Sys(Errno::EACCES)),
+        };
+
+        Ok(control::slice(&content, offset, len))
+    }
+
+    /* Whatever is written to a trigger, the action runs once per write. */
+    async fn trigger(&self, node: Node, content: &[u8]) -> Result<()> {
+        match node {
+            Node::DropCaches => {
+                self.invalidate_caches();
+                self.negative.clear();
+                self.links.clear();
+                Ok(())
+            }
+            Node::Gc => {
+                self.check_writable()?;
+                let collected =
+                    gc::collect_orphans(&self.cfg, &self.pool, &self.pages, &self.handles).await?;
+                tracing::info!(collected, "orphans collected on demand");
+                Ok(())
+            }
+            Node::Rm => {
+                let path = content.strip_suffix(b"\n").unwrap_or(content);
+                self.remove_tree(Path::new(OsStr::from_bytes(path))).await?;
+                Ok(())
+            }
+            Node::Interrupt => {
+                let unique = std::str::from_utf8(content)
+                    .ok()
+                    .and_then(|unique| unique.trim().parse().ok())
+                    .ok_or(Error::Sys(Errno::EINVAL))?;
+                if !self.interrupts.interrupt(unique) {
+                    return Err(Error::Sys(Errno::ESRCH));
+                }
+                Ok(())
+            }
+            _ => Err(Error::Sys(Errno::EACCES)),
+        }
+    }
+
+    async fn read_nolock(&self, ino: u64, offset: u64, len: u32) -> Result<Vec<u8>> {
+        let len = len as usize;
+        let mut connection = self.pool.acquire().await?;
+        let mut tx = transaction!(self.cfg, connection, { shared: [inode::key(ino)] }).await?;
+
+        let mut reply = tx.read(self.cfg.bucket, vec![inode::read(ino)]).await?;
+        let mut inode = inode::decode(ino, &mut reply, 0).ok_or(Error::NotFound)?;
+        check_regular(&inode)?;
+        self.apply_dirty(&mut inode);
+
+        let mut bytes = Vec::with_capacity(len);
+        let read_end = (offset + len as u64).min(inode.size);
+
+        if offset > inode.size {
+            return Err(Error::Sys(Errno::EINVAL));
+        }
+
+        let truncated_len = read_end - offset;
+        if inode.extents {
+            self.pages
+                .read_sparse(&mut tx, ino, offset, truncated_len, &mut bytes)
+                .await?;
+        } else {
+            self.pages
+                .read(&mut tx, ino, offset, truncated_len, &mut bytes)
+                .await?;
+        }
+
+        let padding = len.saturating_sub(bytes.len());
+        tracing::debug!(?padding, output_len = bytes.len());
+        bytes.resize(bytes.len() + padding, 0);
+        assert!(bytes.len() == len);
+
+        tx.commit().await?;
+        self.atimes.accessed(self.cfg.atime, &inode, now(&self.cfg));
+        Ok(bytes)
+    }
+
+    /* Locks are described as in fcntl(2), `end` being inclusive. When no
+    lock prevents the given one, it is returned unlocked. */
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn getlk(
+        &self,
+        ino: u64,
+        owner: u64,
+        start: u64,
+        end: u64,
+        typ: u32,
+        pid: u32,
+    ) -> Result<(u64, u64, u32, u32)> {
+        let probe = self.file_lock(owner, start, end, typ, pid)?;
+
+        match flock::conflicting(&self.cfg, &self.pool, ino, probe).await? {
+            Some(lock) => {
+                let typ = match lock.kind {
+                    LockKind::Read => libc::F_RDLCK,
+                    LockKind::Write => libc::F_WRLCK,
+                };
+                Ok((lock.start, lock.end, typ as u32, lock.pid))
+            }
+            None => Ok((start, end, libc::F_UNLCK as u32, pid)),
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn setlk(
+        &self,
+        ino: u64,
+        owner: u64,
+        start: u64,
+        end: u64,
+        typ: u32,
+        pid: u32,
+        sleep: bool,
+    ) -> Result<()> {
+        /* Locks are shared with other views through the bucket. */
+        if self.cfg.snapshot.is_some() {
+            return Err(Error::Sys(Errno::ENOLCK));
+        }
+
+        let unlock = typ == libc::F_UNLCK as u32;
+        let lock = if unlock {
+            self.file_lock(owner, start, end, libc::F_WRLCK as u32, pid)?
+        } else {
+            self.file_lock(owner, start, end, typ, pid)?
+        };
+
+        /* Waiting is done by polling, there is no way to be notified when
+        a lock held by another view is released. It is given up as if
+        interrupted after a while, as it holds a slot of the operations
+        served at once and the kernel's interrupts never reach it. */
+        let deadline = Instant::now() + LOCK_WAIT_MAX;
+        let mut backoff = LOCK_POLL_MIN;
+        while !flock::set(&self.cfg, &self.pool, ino, lock, unlock).await? {
+            if !sleep {
+                return Err(Error::Sys(Errno::EAGAIN));
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::Sys(Errno::EINTR));
+            }
+
+            runtime::sleep(backoff).await;
+            backoff = (backoff * 2).min(LOCK_POLL_MAX);
+        }
 
-        let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-        inode.atime = t;
-        inode.mtime = t;
+        if !unlock {
+            self.held_locks.taken(ino, owner);
+        }
+        Ok(())
+    }
 
-        let update = if wrote_above_size > 0 {
-            inode.size += wrote_above_size;
+    /* POSIX locks go away with any close of the file by their owner, flock
+    ones with the release of the open file they were taken through. */
+    async fn unlock_owner(&self, ino: u64, owner: u64) -> Result<()> {
+        if !self.held_locks.take(ino, owner) {
+            return Ok(());
+        }
 
-            tracing::debug!(extended = inode.size);
-            inode::update_stats_and_size(&inode)
-        } else {
-            inode::update_stats(&inode)
+        let all = FileLock {
+            view: self.cfg.view,
+            owner,
+            pid: 0,
+            kind: LockKind::Write,
+            start: 0,
+            end: u64::MAX,
         };
-
-        tx.update(self.cfg.bucket, std::iter::once(update)).await?;
-        tx.commit().await?;
+        if let Err(error) = flock::set(&self.cfg, &self.pool, ino, all, true).await {
+            self.held_locks.taken(ino, owner);
+            return Err(error);
+        }
         Ok(())
     }
 
-    pub(crate) async fn read(&self, ino: u64, offset: u64, len: u32) -> Result<Vec<u8>> {
-        let byte_range = offset..(offset + len as u64);
-        let lock = self.page_locks.lock(ino, byte_range).await;
+    fn file_lock(&self, owner: u64, start: u64, end: u64, typ: u32, pid: u32) -> Result<FileLock> {
+        let kind = match typ as i32 {
+            libc::F_RDLCK => LockKind::Read,
+            libc::F_WRLCK => LockKind::Write,
+            _ => return Err(Error::Sys(Errno::EINVAL)),
+        };
+        if start > end {
+            return Err(Error::Sys(Errno::EINVAL));
+        }
 
-        let result = self.read_nolock(ino, offset, len).await;
+        Ok(FileLock {
+            view: self.cfg.view,
+            owner,
+            pid,
+            kind,
+            start,
+            end,
+        })
+    }
 
-        self.page_locks.unlock(lock).await;
-        result
+    /* Only SEEK_DATA and SEEK_HOLE need the filesystem, other whences are
+    resolved by the kernel. Files without extents are reported as a single
+    data region. */
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn lseek(&self, ino: u64, offset: u64, whence: i32) -> Result<u64> {
+        self.with_retry(|| self.lseek_once(ino, offset, whence))
+            .await
     }
 
-    async fn read_nolock(&self, ino: u64, offset: u64, len: u32) -> Result<Vec<u8>> {
-        let len = len as usize;
+    async fn lseek_once(&self, ino: u64, offset: u64, whence: i32) -> Result<u64> {
         let mut connection = self.pool.acquire().await?;
         let mut tx = transaction!(self.cfg, connection, { shared: [inode::key(ino)] }).await?;
 
         let mut reply = tx.read(self.cfg.bucket, vec![inode::read(ino)]).await?;
-        let inode = inode::decode(ino, &mut reply, 0).ok_or(ENOENT)?;
-
-        let mut bytes = Vec::with_capacity(len);
-        let read_end = (offset + len as u64).min(inode.size);
-
-        if offset > inode.size {
-            return Err(Error::Sys(Errno::EINVAL));
+        let mut inode = inode::decode(ino, &mut reply, 0).ok_or(Error::NotFound)?;
+        self.apply_dirty(&mut inode);
+        if offset >= inode.size {
+            return Err(Error::Sys(Errno::ENXIO));
         }
 
-        let truncated_len = read_end - offset;
-        self.pages
-            .read(&mut tx, ino, offset, truncated_len, &mut bytes)
-            .await?;
-
-        let padding = len.saturating_sub(bytes.len());
-        tracing::debug!(?padding, output_len = bytes.len());
-        bytes.resize(bytes.len() + padding, 0);
-        assert!(bytes.len() == len);
-
+        let written = if inode.extents {
+            let pages = self.pages.covering_pages(&(offset..inode.size));
+            Some((
+                pages.clone(),
+                self.pages.written_pages(&mut tx, ino, pages).await?,
+            ))
+        } else {
+            None
+        };
         tx.commit().await?;
-        Ok(bytes)
+
+        let page_size = self.cfg.page_size;
+        match (whence, written) {
+            (libc::SEEK_DATA, None) => Ok(offset),
+            (libc::SEEK_HOLE, None) => Ok(inode.size),
+            (libc::SEEK_DATA, Some((_, written))) => {
+                let page = written.iter().next().ok_or(Error::Sys(Errno::ENXIO))?;
+                Ok(offset.max(page * page_size))
+            }
+            (libc::SEEK_HOLE, Some((mut pages, written))) => {
+                let hole = pages.find(|page| !written.contains(page));
+                let hole = hole.map_or(inode.size, |page| offset.max(page * page_size));
+                Ok(hole.min(inode.size))
+            }
+            _ => Err(Error::Sys(Errno::EINVAL)),
+        }
     }
 
     #[tracing::instrument(skip(self))]
     pub(crate) async fn rename(
         &self,
+        caller: Owner,
         parent_ino: u64,
         name: NameRef,
         new_parent_ino: u64,
         new_name: NameRef,
+        flags: u32,
     ) -> Result<()> {
-        let parents_to_lock = self
-            .up_until_common_ancestor(parent_ino, new_parent_ino)
-            .await?;
-        tracing::trace!(?parents_to_lock);
+        self.check_writable()?;
+        control::check_removal(self.root, parent_ino, &name)?;
+        let name = self.disambiguate(parent_ino, name).await?;
+        control::check_creation(self.root, new_parent_ino, &new_name)?;
+        let new_name = self.disambiguate(new_parent_ino, new_name).await?;
+
+        let noreplace = flags & RENAME_NOREPLACE != 0;
+        let exchange = flags & RENAME_EXCHANGE != 0;
+        if flags & !(RENAME_NOREPLACE | RENAME_EXCHANGE) != 0 || (noreplace && exchange) {
+            return Err(Error::Sys(Errno::EINVAL));
+        }
+
+        /* Moves across directories are serialized through the superblock,
+        as the kernel does with its rename mutex. Otherwise two concurrent
+        renames could each create one half of a loop. */
+        let mut exclusive: Vec<RawIdent> =
+            vec![inode::key(parent_ino).into(), dir::key(parent_ino).into()];
+        if parent_ino != new_parent_ino {
+            exclusive.push(superblock::key().into());
+            exclusive.push(inode::key(new_parent_ino).into());
+            exclusive.push(dir::key(new_parent_ino).into());
+        }
+        if !self.cfg.locks.covers(TxClass::Namespace) {
+            exclusive.clear();
+        }
 
         let mut connection = self.pool.acquire().await?;
         let mut tx = connection
             .transaction_with_locks(TransactionLocks {
                 shared: vec![],
-                exclusive: parents_to_lock
-                    .into_iter()
-                    .map(|ino| dir::key(ino).into())
-                    .collect(),
+                exclusive,
             })
             .await?;
 
@@ -655,21 +2970,21 @@ impl Driver {
                     vec![
                         inode::read(parent_ino),
                         inode::read(new_parent_ino),
-                        dir::read(parent_ino),
-                        dir::read(new_parent_ino),
+                        dir::read(parent_ino, name.prefix()),
+                        dir::read(new_parent_ino, new_name.prefix()),
                     ],
                 )
                 .await?;
 
             (
-                inode::decode(parent_ino, &mut reply, 0).ok_or(ENOENT)?,
-                inode::decode(new_parent_ino, &mut reply, 1).ok_or(ENOENT)?,
-                dir::decode(self.cfg.view, &mut reply, 2).ok_or(ENOENT)?,
-                dir::decode(self.cfg.view, &mut reply, 3).ok_or(ENOENT)?,
+                inode::decode(parent_ino, &mut reply, 0).ok_or(Error::NotFound)?,
+                inode::decode(new_parent_ino, &mut reply, 1).ok_or(Error::NotFound)?,
+                dir::decode(self.cfg.view, &mut reply, 2),
+                dir::decode(self.cfg.view, &mut reply, 3),
             )
         };
 
-        let entry = parent_entries.get(&name).ok_or(ENOENT)?;
+        let entry = parent_entries.get(&name).ok_or(Error::NotFound)?;
         let target_entry = new_parent_entries.get(&new_name);
 
         let (mut inode, target) = {
@@ -679,89 +2994,238 @@ impl Driver {
             };
             let mut reply = tx.read(self.cfg.bucket, reads).await?;
 
-            let inode = inode::decode(entry.ino, &mut reply, 0).ok_or(ENOENT)?;
+            let inode = inode::decode(entry.ino, &mut reply, 0).ok_or(Error::NotFound)?;
             let target = target_entry.and_then(|e| inode::decode(e.ino, &mut reply, 1));
 
             (inode, target)
         };
 
+        if inode.kind == inode::Kind::Directory && parent_ino != new_parent_ino {
+            Self::check_not_ancestor(&self.cfg, &mut tx, inode.ino, new_parent_ino).await?;
+        }
+
+        if !self.cfg.default_permissions {
+            let shape = &self.cfg.attr_shape;
+            let (parent_attr, new_parent_attr) = (shape.attr(&parent), shape.attr(&new_parent));
+            let attr = shape.attr(&inode);
+
+            access::check(&parent_attr, caller, access::W_OK | access::X_OK)?;
+            access::check(&new_parent_attr, caller, access::W_OK | access::X_OK)?;
+            access::check_sticky(&parent_attr, &attr, caller)?;
+            if let Some(target) = &target {
+                access::check_sticky(&new_parent_attr, &shape.attr(target), caller)?;
+            }
+
+            /* Its ".." entry is updated when a directory changes of parent. */
+            if inode.kind == inode::Kind::Directory && parent_ino != new_parent_ino {
+                access::check(&attr, caller, access::W_OK)?;
+            }
+        }
+
+        /* Renaming a file onto another link to itself does nothing. */
+        if target_entry.map_or(false, |target_entry| target_entry.ino == entry.ino) {
+            tx.commit().await?;
+            return Ok(());
+        }
+
+        if noreplace && target.is_some() {
+            return Err(Error::Exists);
+        }
+
+        if exchange {
+            let mut target = target.ok_or(Error::NotFound)?;
+            let target_entry = target_entry.unwrap();
+            if target.kind == inode::Kind::Directory && parent_ino != new_parent_ino {
+                Self::check_not_ancestor(&self.cfg, &mut tx, target.ino, parent_ino).await?;
+            }
+
+            let t = now(&self.cfg);
+            parent.accessed(t);
+            parent.modified(t);
+            new_parent.accessed(t);
+            new_parent.modified(t);
+            inode.accessed(t);
+            inode.parent = new_parent_ino;
+            target.accessed(t);
+            target.parent = parent_ino;
+
+            /* Each name is kept and now designates the other inode. */
+            let dentry = entry.into_dentry();
+            let target_dentry = target_entry.into_dentry();
+            let swapped = dir::Entry::new(dentry.name.clone(), target.ino, target.kind);
+            let new_swapped = dir::Entry::new(target_dentry.name.clone(), inode.ino, inode.kind);
+
+            let mut updates = vec![
+                dir::remove_entry(parent_ino, &dentry),
+                dir::remove_entry(new_parent_ino, &target_dentry),
+                dir::add_entry(parent_ino, &swapped),
+                dir::add_entry(new_parent_ino, &new_swapped),
+                inode::update_stats(&inode),
+                inode::update_stats(&target),
+                inode::update_stats(&parent),
+                self.changes.bump(),
+            ];
+            if parent_ino != new_parent_ino {
+                updates.push(inode::update_stats(&new_parent));
+            }
+
+            /* The ".." of a directory links to its parent. */
+            let is_dir = |inode: &Inode| inode.kind == inode::Kind::Directory;
+            if parent_ino != new_parent_ino && is_dir(&inode) != is_dir(&target) {
+                let (from, to) = if is_dir(&inode) {
+                    (parent_ino, new_parent_ino)
+                } else {
+                    (new_parent_ino, parent_ino)
+                };
+                updates.push(inode::decr_link_count(from, 1));
+                updates.push(inode::incr_link_count(to, 1));
+            }
+            if parent_ino != new_parent_ino {
+                if is_dir(&inode) {
+                    updates.extend(
+                        Self::set_parent(&self.cfg, &mut tx, inode.ino, new_parent_ino).await?,
+                    );
+                }
+                if is_dir(&target) {
+                    updates.extend(
+                        Self::set_parent(&self.cfg, &mut tx, target.ino, parent_ino).await?,
+                    );
+                }
+            }
+
+            tx.commit_pipeline(Pipeline::new().update(self.cfg.bucket, updates))
+                .await?;
+            self.invalidate_caches();
+            self.negative.forget(parent_ino);
+            self.negative.forget(new_parent_ino);
+            return Ok(());
+        }
+
+        /* A directory only replaces an empty directory, and a file only
+        replaces a file. */
+        if let Some(target) = &target {
+            let is_dir = inode.kind == inode::Kind::Directory;
+            match target.kind {
+                inode::Kind::Directory if !is_dir => return Err(Error::Sys(Errno::EISDIR)),
+                inode::Kind::Directory if target.size > 0 => return Err(Error::NotEmpty),
+                inode::Kind::Directory => {}
+                _ if is_dir => return Err(Error::Sys(Errno::ENOTDIR)),
+                _ => {}
+            }
+        }
+
         /* Checks if target is a dir and empty. If it is the case, we have
-        to delete it */
+        to delete it. Its removal is sent along with the rename itself. */
+        let mut pipeline = Pipeline::new();
+        let mut replaced = None;
         match &target {
             Some(target) if target.kind == inode::Kind::Directory && target.size == 0 => {
                 let target_entry = target_entry.unwrap();
                 let target_dentry = target_entry.into_dentry();
 
-                tx.update(
-                    self.cfg.bucket,
-                    vec![
-                        inode::remove(target_entry.ino),
-                        dir::remove(target_entry.ino),
-                        dir::remove_entry(new_parent_ino, &target_dentry),
-                    ],
-                )
-                .await?;
+                let mut updates = vec![
+                    inode::remove(target_entry.ino),
+                    inode::next_generation(target_entry.ino),
+                    dir::remove_entry(new_parent_ino, &target_dentry),
+                    inode::decr_link_count(new_parent_ino, 1),
+                ];
+                updates.extend(dir::remove(target_entry.ino));
+                pipeline = pipeline.update(self.cfg.bucket, updates);
+                new_parent.size -= 1;
             }
-            Some(target) if target.nlink == 1 => {
+            Some(target) if target.kind != inode::Kind::Directory => {
                 let target_entry = target_entry.unwrap();
                 let target_dentry = target_entry.into_dentry();
 
-                tx.update(
+                /* Goes through the same path as unlink, so that the target
+                content is reclaimed once nobody holds it anymore. */
+                pipeline = pipeline.update(
                     self.cfg.bucket,
                     vec![
-                        inode::remove(target.ino),
                         dir::remove_entry(new_parent_ino, &target_dentry),
-                        symlink::remove(target.ino),
+                        inode::decr_link_count(target.ino, 1),
+                        orphan::add(target.ino),
                     ],
-                )
-                .await?;
+                );
+                new_parent.size -= 1;
+                replaced = Some(target.ino);
             }
             _ => {}
         }
 
         /* At this point we are sure that target does not exists
         and we are ready to perform the rename */
-        let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let t = now(&self.cfg);
         new_parent.size += 1;
-        new_parent.atime = t;
-        new_parent.mtime = t;
+        new_parent.accessed(t);
+        new_parent.modified(t);
 
         parent.size -= 1;
-        parent.atime = t;
-        parent.mtime = t;
+        parent.accessed(t);
+        parent.modified(t);
 
-        inode.atime = t;
+        inode.accessed(t);
+        inode.parent = new_parent_ino;
 
         let ino = entry.ino;
         let dentry_to_remove = entry.into_dentry();
         let new_name = new_name.canonicalize(self.cfg.view);
         let new_dentry = &dir::Entry::new(new_name, ino, inode.kind);
 
-        tx.update(
-            self.cfg.bucket,
-            vec![
-                inode::update_stats_and_size(&parent),
-                inode::update_stats_and_size(&new_parent),
-                inode::update_stats(&inode),
-                dir::remove_entry(parent_ino, &dentry_to_remove),
-                dir::add_entry(new_parent_ino, new_dentry),
-            ],
-        )
-        .await?;
+        let mut updates = vec![
+            inode::update_stats_and_size(&parent),
+            inode::update_stats_and_size(&new_parent),
+            inode::update_stats(&inode),
+            dir::remove_entry(parent_ino, &dentry_to_remove),
+            dir::add_entry(new_parent_ino, new_dentry),
+            self.changes.bump(),
+        ];
+        if inode.kind == inode::Kind::Directory && parent_ino != new_parent_ino {
+            updates.push(inode::decr_link_count(parent_ino, 1));
+            updates.push(inode::incr_link_count(new_parent_ino, 1));
+            updates.extend(Self::set_parent(&self.cfg, &mut tx, ino, new_parent_ino).await?);
+        }
+
+        tx.commit_pipeline(pipeline.update(self.cfg.bucket, updates))
+            .await?;
+        self.invalidate_caches();
+        self.negative.forget(parent_ino);
+        self.negative.forget(new_parent_ino);
+        if let Some(replaced) = replaced {
+            self.schedule_delete(replaced);
+        }
 
-        tx.commit().await?;
         Ok(())
     }
 
+    /* The ".." a directory stores is rewritten once it changed of parent,
+    whichever views stored it. */
+    async fn set_parent(
+        cfg: &Config,
+        tx: &mut Transaction<'_>,
+        ino: u64,
+        parent_ino: u64,
+    ) -> Result<Vec<UpdateQuery>> {
+        let mut reply = tx.read(cfg.bucket, vec![dir::read(ino, b"..")]).await?;
+        let entries = dir::decode(cfg.view, &mut reply, 0);
+
+        Ok(dir::set_parent(cfg.view, ino, &entries, parent_ino))
+    }
+
     #[tracing::instrument(skip(self))]
     pub(crate) async fn link(
         &self,
+        caller: Owner,
         ino: u64,
         new_parent_ino: u64,
         new_name: NameRef,
-    ) -> Result<FileAttr> {
+    ) -> Result<EntryAttr> {
+        self.check_writable()?;
+        control::check_creation(self.root, new_parent_ino, &new_name)?;
+        let new_name = self.disambiguate(new_parent_ino, new_name).await?;
+
         let mut connection = self.pool.acquire().await?;
-        let mut tx = transaction!(self.cfg, connection, {
+        let mut tx = transaction!(self.cfg, connection, TxClass::Namespace, {
             exclusive: [
                 inode::key(ino),
                 inode::key(new_parent_ino),
@@ -777,25 +3241,26 @@ impl Driver {
                     vec![
                         inode::read(ino),
                         inode::read(new_parent_ino),
-                        dir::read(new_parent_ino),
+                        dir::read(new_parent_ino, new_name.prefix()),
                     ],
                 )
                 .await?;
 
-            let inode = inode::decode(ino, &mut reply, 0).ok_or(ENOENT)?;
-            let parent = inode::decode(new_parent_ino, &mut reply, 1).ok_or(ENOENT)?;
-            let entries = dir::decode(self.cfg.view, &mut reply, 2).ok_or(ENOENT)?;
+            let inode = inode::decode(ino, &mut reply, 0).ok_or(Error::NotFound)?;
+            let parent = inode::decode(new_parent_ino, &mut reply, 1).ok_or(Error::NotFound)?;
+            let entries = dir::decode(self.cfg.view, &mut reply, 2);
 
             (inode, parent, entries)
         };
 
         if entries.get(&new_name).is_some() {
-            return Err(Error::Sys(Errno::EEXIST));
+            return Err(Error::Exists);
         }
+        self.check_access(&parent, caller, access::W_OK | access::X_OK)?;
 
-        let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-        parent.mtime = t;
-        parent.atime = t;
+        let t = now(&self.cfg);
+        parent.modified(t);
+        parent.accessed(t);
         parent.size += 1;
 
         let new_name = new_name.canonicalize(self.cfg.view);
@@ -803,33 +3268,175 @@ impl Driver {
             self.cfg.bucket,
             vec![
                 inode::update_stats_and_size(&parent),
-                dir::add_entry(
-                    new_parent_ino,
-                    &dir::Entry::new(new_name, ino, Kind::Regular),
-                ),
+                dir::add_entry(new_parent_ino, &dir::Entry::new(new_name, ino, inode.kind)),
                 inode::incr_link_count(ino, 1),
+                self.changes.bump(),
             ],
         )
         .await?;
 
         inode.nlink += 1;
         tx.commit().await?;
-        Ok(inode.attr())
+        self.invalidate_caches();
+        self.negative.forget(new_parent_ino);
+        Ok(entry_attr(&self.cfg, &inode))
     }
 
     #[tracing::instrument(skip(self))]
     pub(crate) async fn read_link(&self, ino: u64) -> Result<String> {
+        if let Some(link) = self.links.get(ino) {
+            return Ok(link);
+        }
+
+        let link = self.with_retry(|| self.read_link_once(ino)).await?;
+        self.links.insert(ino, link.clone());
+        Ok(link)
+    }
+
+    async fn read_link_once(&self, ino: u64) -> Result<String> {
         let mut connection = self.pool.acquire().await?;
         let mut tx = transaction!(self.cfg, connection, { shared: [symlink::key(ino)] }).await?;
 
         let mut reply = tx.read(self.cfg.bucket, vec![symlink::read(ino)]).await?;
 
-        let link = symlink::decode(&mut reply, 0).ok_or(ENOENT)?;
+        let link = symlink::decode(&mut reply, 0).ok_or(Error::NotFound)?;
 
         tx.commit().await?;
         Ok(link)
     }
 
+    /* Attributes are computed when read, none can be set. */
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn get_xattr(&self, ino: u64, name: &[u8]) -> Result<Vec<u8>> {
+        if name == CONFLICTS_XATTR.as_bytes() && Node::from_ino(ino).is_none() {
+            return self.with_retry(|| self.conflicts_of(ino)).await;
+        }
+
+        if name != PAGE_VERSIONS_XATTR.as_bytes() || !self.lists_page_versions(ino).await? {
+            return Err(Error::Sys(Errno::ENODATA));
+        }
+
+        self.with_retry(|| self.page_versions(ino)).await
+    }
+
+    pub(crate) async fn list_xattr(&self, ino: u64) -> Result<Vec<u8>> {
+        let mut names = Vec::new();
+        if Node::from_ino(ino).is_none() {
+            names.extend_from_slice(CONFLICTS_XATTR.as_bytes());
+            names.push(0);
+        }
+
+        if self.lists_page_versions(ino).await? {
+            names.extend_from_slice(PAGE_VERSIONS_XATTR.as_bytes());
+            names.push(0);
+        }
+
+        Ok(names)
+    }
+
+    async fn lists_page_versions(&self, ino: u64) -> Result<bool> {
+        if Node::from_ino(ino).is_some() || self.cfg.page_conflicts != PageConflicts::Versioned {
+            return Ok(false);
+        }
+
+        let attr = self.getattr(ino).await?;
+        Ok(attr.kind == FileType::RegularFile)
+    }
+
+    /* One line per page written concurrently, listing its versions from
+    the one read to the oldest. */
+    async fn page_versions(&self, ino: u64) -> Result<Vec<u8>> {
+        let mut connection = self.pool.acquire().await?;
+        let mut tx = transaction!(self.cfg, connection, { shared: [inode::key(ino)] }).await?;
+
+        let mut reply = tx.read(self.cfg.bucket, vec![inode::read(ino)]).await?;
+        let inode = inode::decode(ino, &mut reply, 0).ok_or(Error::NotFound)?;
+        check_regular(&inode)?;
+
+        let rendered = self.render_page_versions(&mut tx, ino, &inode).await?;
+        tx.commit().await?;
+
+        Ok(rendered.into_bytes())
+    }
+
+    async fn render_page_versions(
+        &self,
+        tx: &mut Transaction<'_>,
+        ino: u64,
+        inode: &Inode,
+    ) -> Result<String> {
+        let pages = self.pages.covering_pages(&(0..inode.size));
+        let pages: Vec<u64> = if inode.extents {
+            self.pages
+                .written_pages(tx, ino, pages)
+                .await?
+                .into_iter()
+                .collect()
+        } else {
+            pages.collect()
+        };
+        let versions = self.pages.versions(tx, ino, pages).await?;
+
+        let mut rendered = String::new();
+        for (page, stamps) in versions {
+            let stamps: Vec<String> = stamps.iter().map(ToString::to_string).collect();
+            rendered.push_str(&format!("page {}: {}\n", page, stamps.join(", ")));
+        }
+        Ok(rendered)
+    }
+
+    /* One line per name of the inode held by entries of several views, then
+    per such name among the entries of a directory, then per page written
+    concurrently with a versioned bucket. Attributes are last writer wins
+    registers, they never keep concurrent versions. */
+    async fn conflicts_of(&self, ino: u64) -> Result<Vec<u8>> {
+        let mut connection = self.pool.acquire().await?;
+        let mut tx = transaction!(self.cfg, connection, { shared: [inode::key(ino)] }).await?;
+
+        let mut reply = tx.read(self.cfg.bucket, vec![inode::read(ino)]).await?;
+        let inode = inode::decode(ino, &mut reply, 0).ok_or(Error::NotFound)?;
+
+        let mut rendered = String::new();
+        if ino != self.root {
+            let mut reply = tx
+                .read(self.cfg.bucket, dir::read_all(inode.parent))
+                .await?;
+            if let Some(parent) = dir::decode_all(self.cfg.view, &mut reply, 0) {
+                let conflicts: HashSet<&[u8]> = parent.conflicts().collect();
+                let names: BTreeSet<&[u8]> = parent
+                    .entries()
+                    .iter()
+                    .filter(|entry| entry.ino == ino && conflicts.contains(&*entry.prefix))
+                    .map(|entry| &*entry.prefix)
+                    .collect();
+
+                for name in names {
+                    render_name_conflict(&mut rendered, "name", &parent, name);
+                }
+            }
+        }
+
+        match inode.kind {
+            Kind::Directory => {
+                let mut reply = tx.read(self.cfg.bucket, dir::read_all(ino)).await?;
+                if let Some(dir) = dir::decode_all(self.cfg.view, &mut reply, 0) {
+                    let names: BTreeSet<&[u8]> = dir.conflicts().collect();
+                    for name in names {
+                        render_name_conflict(&mut rendered, "entry", &dir, name);
+                    }
+                }
+            }
+            Kind::Regular if self.cfg.page_conflicts == PageConflicts::Versioned => {
+                let pages = self.render_page_versions(&mut tx, ino, &inode).await?;
+                rendered.push_str(&pages);
+            }
+            _ => {}
+        }
+
+        tx.commit().await?;
+        Ok(rendered.into_bytes())
+    }
+
     #[tracing::instrument(skip(self))]
     pub(crate) async fn symlink(
         &self,
@@ -837,11 +3444,18 @@ impl Driver {
         owner: Owner,
         name: NameRef,
         link: String,
-    ) -> Result<FileAttr> {
-        let ino = self.next_ino()?;
+    ) -> Result<EntryAttr> {
+        self.check_writable()?;
+        control::check_creation(self.root, parent_ino, &name)?;
+        let name = self.disambiguate(parent_ino, name).await?;
+        if link.len() >= libc::PATH_MAX as usize {
+            return Err(Error::Sys(Errno::ENAMETOOLONG));
+        }
+
+        let ino = self.next_ino().await?;
 
         let mut connection = self.pool.acquire().await?;
-        let mut tx = transaction!(self.cfg, connection, {
+        let mut tx = transaction!(self.cfg, connection, TxClass::Namespace, {
             exclusive: [
                 inode::key(parent_ino),
                 dir::key(parent_ino)
@@ -849,25 +3463,40 @@ impl Driver {
         })
         .await?;
 
-        let (mut parent, entries) = {
+        let (mut parent, entries, generation) = {
             let mut reply = tx
                 .read(
                     self.cfg.bucket,
-                    vec![inode::read(parent_ino), dir::read(parent_ino)],
+                    vec![
+                        inode::read(parent_ino),
+                        dir::read(parent_ino, name.prefix()),
+                        inode::read_generation(ino),
+                    ],
                 )
                 .await?;
 
-            let parent = inode::decode(parent_ino, &mut reply, 0).ok_or(ENOENT)?;
-            let entries = dir::decode(self.cfg.view, &mut reply, 1).ok_or(ENOENT)?;
+            let parent = inode::decode(parent_ino, &mut reply, 0).ok_or(Error::NotFound)?;
+            let entries = dir::decode(self.cfg.view, &mut reply, 1);
+            let generation = inode::decode_generation(&mut reply, 2);
 
-            (parent, entries)
+            (parent, entries, generation)
         };
 
         if entries.contains_key(&name) {
-            return Err(Error::Sys(Errno::EEXIST));
+            return Err(Error::Exists);
         }
+        self.check_access(&parent, owner, access::W_OK | access::X_OK)?;
+
+        let stored_owner = self.cfg.attr_shape.owner(owner);
+        let (stored_owner, _) = access::inherit(
+            &parent,
+            owner,
+            stored_owner,
+            Kind::Symlink,
+            inode::SYMLINK_MODE,
+        );
 
-        let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let t = now(&self.cfg);
         let inode = inode::Inode {
             ino,
             kind: inode::Kind::Symlink,
@@ -875,14 +3504,19 @@ impl Driver {
             atime: t,
             ctime: t,
             mtime: t,
-            owner,
-            mode: 0o644,
+            crtime: t,
+            owner: stored_owner,
+            mode: inode::SYMLINK_MODE,
             size: link.len() as u64,
             nlink: 1,
+            rdev: 0,
+            creation_token: None,
+            extents: true,
+            generation,
         };
         parent.size += 1;
-        parent.mtime = t;
-        parent.atime = t;
+        parent.modified(t);
+        parent.accessed(t);
 
         let name = name.canonicalize(self.cfg.view);
         tx.update(
@@ -891,125 +3525,231 @@ impl Driver {
                 inode::create(&inode),
                 inode::update_stats_and_size(&parent),
                 dir::add_entry(parent_ino, &dir::Entry::new(name, ino, Kind::Symlink)),
-                symlink::create(ino, link),
+                symlink::create(ino, link.clone()),
+                self.changes.bump(),
             ],
         )
         .await?;
 
         tx.commit().await?;
-        Ok(inode.attr())
+        self.links.insert(ino, link);
+        self.invalidate_caches();
+        self.negative.forget(parent_ino);
+        Ok(entry_attr(&self.cfg, &inode))
     }
 
-    fn schedule_delete(&self, ino: u64) {
-        #[tracing::instrument(skip(cfg, pool))]
-        async fn delete_later(
-            cfg: Config,
-            pool: Arc<ConnectionPool>,
-            pages: PageWriter,
-            ino: u64,
-        ) -> Result<bool> {
-            let mut connection = pool.acquire().await?;
-            let mut tx = transaction!(cfg, connection, { exclusive: [inode::key(ino)] }).await?;
+    /* With `default_permissions` the kernel already checked the request
+    against the attributes we reported. */
+    fn check_access(&self, inode: &Inode, caller: Owner, mask: u32) -> Result<()> {
+        if self.cfg.default_permissions {
+            return Ok(());
+        }
 
-            let inode = {
-                let mut reply = tx.read(cfg.bucket, vec![inode::read(ino)]).await?;
-                inode::decode(ino, &mut reply, 0).ok_or(ENOENT)?
-            };
+        access::check(&self.cfg.attr_shape.attr(inode), caller, mask)
+    }
 
-            let must_be_removed =
-                (inode.kind == inode::Kind::Directory && inode.nlink <= 1) || inode.nlink == 0;
+    async fn check_sticky(
+        &self,
+        tx: &mut Transaction<'_>,
+        dir: &Inode,
+        ino: u64,
+        caller: Owner,
+    ) -> Result<()> {
+        if self.cfg.default_permissions {
+            return Ok(());
+        }
 
-            if must_be_removed {
-                tx.update(
-                    cfg.bucket,
-                    vec![inode::remove(ino), dir::remove(ino), symlink::remove(ino)],
-                )
-                .await?;
+        let dir = self.cfg.attr_shape.attr(dir);
+        if !access::is_sticky_for(&dir, caller) {
+            return Ok(());
+        }
 
-                if inode.kind == inode::Kind::Regular {
-                    /* At this point we should be (locally) the only one
-                    seeing this file, don't bother locking up the pages */
-                    pages.remove(&mut tx, ino, 0..inode.size).await?;
-                }
-            }
+        let attr = Self::attr_of(&self.cfg, tx, ino).await?.attr;
+        access::check_sticky(&dir, &attr, caller)
+    }
 
-            tx.commit().await?;
-            Ok(must_be_removed)
+    /* Accessing most children of a freshly listed directory is what
+    recursive walks (find, du, ls -R) look like, fetch the remaining ones
+    in a single batch before they are asked for. */
+    fn record_access(&self, ino: u64) {
+        if let Some(batch) = self.prefetch.record_access(ino) {
+            let cfg = self.cfg.clone();
+            let pool = self.pool.clone();
+            let prefetch = self.prefetch.clone();
+            self.tasks
+                .spawn(prefetch::fetch(cfg, pool, prefetch, batch));
         }
+    }
 
-        let cfg = self.cfg.clone();
-        let pool = self.pool.clone();
-        let pages = self.pages;
-        task::spawn(delete_later(cfg, pool, pages, ino));
+    fn schedule_delete(&self, ino: u64) {
+        self.jobs.submit(Job::Delete(ino));
     }
 
+    /* Allocations are served from the lease of the view, renewed in a
+    transaction of its own once exhausted. */
     #[tracing::instrument(skip(self))]
-    pub(crate) fn next_ino(&self) -> Result<u64> {
-        #[tracing::instrument(skip(cfg, counter, pool))]
-        async fn checkpoint(
-            cfg: Config,
-            counter: Arc<InoGenerator>,
-            pool: Arc<ConnectionPool>,
-        ) -> Result<()> {
-            let mut connection = pool.acquire().await?;
-
-            let mut tx = transaction!(cfg, connection, { exclusive: [ino::key(cfg.view)] }).await?;
+    pub(crate) async fn next_ino(&self) -> Result<u64> {
+        let mut lease = self.ino_counter.current().await;
+        if lease.is_empty() {
+            let mut connection = self.maintenance.acquire().await?;
+            let mut tx = transaction!(self.cfg, connection, TxClass::Namespace, {
+                exclusive: [ino::key(self.cfg.view)]
+            })
+            .await?;
 
-            counter.checkpoint(&mut tx).await?;
+            let renewed = InoGenerator::lease(&mut tx, self.cfg.view, self.cfg.bucket).await?;
 
             tx.commit().await?;
-            Ok(())
+            *lease = renewed;
         }
 
-        let ino = self.ino_counter.next();
+        let next_ino = lease.next_back().ok_or(Error::QuotaExceeded)?;
+        Ok(self.ino_counter.ino(next_ino))
+    }
 
-        let counter = self.ino_counter.clone();
-        let pool = self.pool.clone();
-        let cfg = self.cfg.clone();
-        task::spawn(checkpoint(cfg, counter, pool));
+    /* A directory can't be moved inside its own subtree, which is checked
+    by walking up the parents of its destination. */
+    async fn check_not_ancestor(
+        cfg: &Config,
+        tx: &mut Transaction<'_>,
+        moved_ino: u64,
+        mut ino: u64,
+    ) -> Result<()> {
+        loop {
+            if ino == moved_ino {
+                return Err(Error::Sys(Errno::EINVAL));
+            }
+            if ino == ROOT_INO {
+                return Ok(());
+            }
 
-        Ok(ino)
+            let mut reply = tx.read(cfg.bucket, vec![inode::read(ino)]).await?;
+            ino = inode::decode(ino, &mut reply, 0)
+                .ok_or(Error::NotFound)?
+                .parent;
+        }
     }
+}
 
-    pub async fn up_until_common_ancestor(
-        &self,
-        mut lhs_parent: u64,
-        mut rhs_parent: u64,
-    ) -> Result<Vec<u64>> {
-        let mut connection = self.pool.acquire().await?;
-        let mut tx = connection.transaction().await?;
+fn now(cfg: &Config) -> Duration {
+    cfg.attr_shape.timestamp(cfg.clock.now())
+}
 
-        let dotdot = NameRef::Partial("..".into());
+fn render_name_conflict(rendered: &mut String, what: &str, dir: &dir::DirView, name: &[u8]) {
+    let holders: Vec<String> = dir
+        .entries()
+        .iter()
+        .filter(|entry| &*entry.prefix == name)
+        .map(|entry| format!("view {} inode {}", entry.view, entry.ino))
+        .collect();
+
+    rendered.push_str(&format!(
+        "{} {}: {}\n",
+        what,
+        String::from_utf8_lossy(name),
+        holders.join(", ")
+    ));
+}
 
-        let mut parents = Vec::with_capacity(1);
-        while lhs_parent != rhs_parent {
-            parents.push(lhs_parent);
-            parents.push(rhs_parent);
+/* Only regular files hold pages. */
+fn check_regular(inode: &Inode) -> Result<()> {
+    match inode.kind {
+        inode::Kind::Regular => Ok(()),
+        inode::Kind::Directory => Err(Error::Sys(Errno::EISDIR)),
+        _ => Err(Error::Sys(Errno::EINVAL)),
+    }
+}
 
-            let mut reply = tx
-                .read(
-                    self.cfg.bucket,
-                    vec![dir::read(lhs_parent), dir::read(rhs_parent)],
-                )
-                .await?;
+fn entry_attr(cfg: &Config, inode: &Inode) -> EntryAttr {
+    EntryAttr {
+        attr: cfg.attr_shape.attr(inode),
+        generation: inode.generation,
+    }
+}
 
-            let lhs_entries = dir::decode(self.cfg.view, &mut reply, 0).ok_or(ENOENT)?;
-            let rhs_entries = dir::decode(self.cfg.view, &mut reply, 1).ok_or(ENOENT)?;
+/* Control nodes are never removed, nor their number given to another. */
+fn control_entry(node: Node, mounted_at: Duration) -> EntryAttr {
+    EntryAttr {
+        attr: node.attr(mounted_at),
+        generation: 0,
+    }
+}
 
-            lhs_parent = lhs_entries.get(&dotdot).unwrap().ino;
-            rhs_parent = rhs_entries.get(&dotdot).unwrap().ino;
-        }
-        assert_eq!(lhs_parent, rhs_parent);
-        parents.push(lhs_parent);
+/* Somewhere between half and the whole backoff, so that transactions
+aborted together don't retry in lockstep. A fresh `RandomState` is seeded
+differently each time, which is random enough here. */
+fn jitter(backoff: Duration) -> Duration {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(backoff.subsec_nanos());
+    let ratio = (hasher.finish() % 1024) as u32;
 
-        tx.commit().await?;
-        Ok(parents)
-    }
+    backoff / 2 + backoff / 2 * ratio / 1024
 }
 
+/* What mknod and create add to a directory, on behalf of its owner. */
 #[derive(Debug)]
+struct NewNode {
+    owner: Owner,
+    mode: u32,
+    kind: Kind,
+    rdev: u32,
+    name: NameRef,
+}
+
+#[derive(Debug, Clone)]
 pub(crate) struct ReadDirEntry {
     pub(crate) ino: u64,
     pub(crate) kind: FileType,
-    pub(crate) name: String,
+    pub(crate) name: OsString,
+    pub(crate) offset: i64,
+}
+
+/* Offsets 1 and 2 of the first shard, the stored entries come after them. */
+const DOT_ENTRIES: i64 = 2;
+
+fn dot_entries(ino: u64, parent_ino: u64) -> Vec<ReadDirEntry> {
+    vec![(ino, "."), (parent_ino, "..")]
+        .into_iter()
+        .enumerate()
+        .map(|(index, (ino, name))| ReadDirEntry {
+            name: OsString::from(name),
+            ino,
+            kind: FileType::Directory,
+            offset: index as i64 + 1,
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub(crate) struct DirBatch {
+    shards: Range<u16>,
+    entries: Vec<ReadDirEntry>,
+}
+
+impl DirBatch {
+    fn entries_after(&self, offset: i64) -> Vec<ReadDirEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.offset > offset)
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: OsString,
+    pub ino: u64,
+    pub kind: FileType,
+}
+
+/// Where the next page of a listing starts, the shard and cookie of the
+/// last entry listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContinuationToken(i64);
+
+#[derive(Debug, Clone)]
+pub struct DirPage {
+    pub entries: Vec<DirEntry>,
+    pub next: Option<ContinuationToken>,
 }
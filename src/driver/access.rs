@@ -0,0 +1,170 @@
+use crate::driver::{Error, Result};
+use crate::model::inode::{Inode, Kind, Owner};
+use fuser::{FileAttr, FileType};
+use nix::errno::Errno;
+use nix::libc;
+
+pub const F_OK: u32 = libc::F_OK as u32;
+pub const X_OK: u32 = libc::X_OK as u32;
+pub const W_OK: u32 = libc::W_OK as u32;
+pub const R_OK: u32 = libc::R_OK as u32;
+
+const ROOT_UID: u32 = 0;
+const S_ISVTX: u16 = 0o1000;
+const S_ISUID: u32 = libc::S_ISUID;
+const S_ISGID: u32 = libc::S_ISGID;
+const S_IXGRP: u32 = libc::S_IXGRP;
+
+const EACCES: Error = Error::Sys(Errno::EACCES);
+const EPERM: Error = Error::Sys(Errno::EPERM);
+
+/* Mirrors what the kernel checks when mounted with `default_permissions`.
+Only the primary group of the caller is known, supplementary groups are
+not taken into account. */
+pub(crate) fn check(attr: &FileAttr, caller: Owner, mask: u32) -> Result<()> {
+    if caller.uid == ROOT_UID {
+        /* Root can execute a file only if anyone could. */
+        let executable = attr.kind == FileType::Directory || attr.perm & 0o111 != 0;
+        return if mask & X_OK == 0 || executable {
+            Ok(())
+        } else {
+            Err(EACCES)
+        };
+    }
+
+    let granted = if caller.uid == attr.uid {
+        attr.perm >> 6
+    } else if caller.gid == attr.gid {
+        attr.perm >> 3
+    } else {
+        attr.perm
+    };
+
+    let mask = mask & (R_OK | W_OK | X_OK);
+    if u32::from(granted) & mask == mask {
+        Ok(())
+    } else {
+        Err(EACCES)
+    }
+}
+
+pub(crate) fn open_mask(flags: u32) -> u32 {
+    let flags = flags as libc::c_int;
+
+    let mut mask = match flags & libc::O_ACCMODE {
+        libc::O_RDONLY => R_OK,
+        libc::O_WRONLY => W_OK,
+        _ => R_OK | W_OK,
+    };
+    if flags & libc::O_TRUNC != 0 {
+        mask |= W_OK;
+    }
+
+    mask
+}
+
+/* Entries of a sticky directory can only be removed or renamed by their
+owner or the owner of the directory. */
+pub(crate) fn check_sticky(dir: &FileAttr, entry: &FileAttr, caller: Owner) -> Result<()> {
+    if dir.perm & S_ISVTX == 0
+        || caller.uid == ROOT_UID
+        || caller.uid == dir.uid
+        || caller.uid == entry.uid
+    {
+        Ok(())
+    } else {
+        Err(EPERM)
+    }
+}
+
+pub(crate) fn is_sticky_for(dir: &FileAttr, caller: Owner) -> bool {
+    dir.perm & S_ISVTX != 0 && caller.uid != ROOT_UID && caller.uid != dir.uid
+}
+
+/// Times changed by a setattr.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Times {
+    Unchanged,
+    /// Only to the current time, as with `UTIME_NOW`.
+    Now,
+    Given,
+}
+
+/* chmod and utimes with explicit times are reserved to the owner, chown
+to root, and chgrp to the owner towards one of its groups. */
+pub(crate) fn check_setattr(
+    attr: &FileAttr,
+    caller: Owner,
+    mode: Option<u32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    size: Option<u64>,
+    times: Times,
+) -> Result<()> {
+    let root = caller.uid == ROOT_UID;
+    let owner = root || caller.uid == attr.uid;
+
+    if mode.is_some() && !owner {
+        return Err(EPERM);
+    }
+
+    if uid.map_or(false, |uid| uid != attr.uid) && !root {
+        return Err(EPERM);
+    }
+
+    let foreign_group = gid.map_or(false, |gid| gid != attr.gid && gid != caller.gid);
+    if gid.map_or(false, |gid| gid != attr.gid) && (!owner || (foreign_group && !root)) {
+        return Err(EPERM);
+    }
+
+    if size.is_some() {
+        check(attr, caller, W_OK)?;
+    }
+
+    match times {
+        Times::Given if !owner => return Err(EPERM),
+        Times::Now if !owner => check(attr, caller, W_OK)?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/* What is created in a setgid directory belongs to the group of the
+directory, and directories get the setgid bit themselves. Other files only
+keep a setgid bit asked for when created by root or a member of their
+group, of which only the primary group of the caller is known. */
+pub(crate) fn inherit(
+    parent: &Inode,
+    caller: Owner,
+    mut owner: Owner,
+    kind: Kind,
+    mut mode: u32,
+) -> (Owner, u32) {
+    if parent.mode & S_ISGID != 0 {
+        owner.gid = parent.owner.gid;
+        if kind == Kind::Directory {
+            mode |= S_ISGID;
+        }
+    }
+
+    if kind != Kind::Directory && caller.uid != ROOT_UID && caller.gid != owner.gid {
+        mode &= !S_ISGID;
+    }
+
+    (owner, mode)
+}
+
+/* A chown drops the setuid bit of anything but a directory, and its setgid
+bit when it goes along with the group execute one, as Linux does. */
+pub(crate) fn chown_mode(kind: Kind, mut mode: u32) -> u32 {
+    if kind == Kind::Directory {
+        return mode;
+    }
+
+    mode &= !S_ISUID;
+    if mode & S_IXGRP != 0 {
+        mode &= !S_ISGID;
+    }
+    mode
+}
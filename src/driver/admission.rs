@@ -0,0 +1,163 @@
+use crate::driver::tasks::Tasks;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+
+/* Operations of a kind waiting for room, past that the thread handing
+them in blocks until one of them is let in. */
+const MAX_QUEUED: usize = 4096;
+const CLASSES: usize = 4;
+
+pub(crate) type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// How many fuse operations of each kind are served at once, at most.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Concurrency {
+    /// Operations on metadata only, e.g `lookup`, `getattr` or `readdir`.
+    pub metadata: usize,
+    /// Operations reading file content, e.g `read`.
+    pub reads: usize,
+    /// Operations carrying or producing file content, e.g `write`.
+    pub writes: usize,
+    /// Every other operation, e.g `mkdir` or `setattr`.
+    pub others: usize,
+}
+
+impl Default for Concurrency {
+    fn default() -> Self {
+        Self {
+            metadata: 1024,
+            reads: 256,
+            writes: 64,
+            others: 256,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum OpClass {
+    Metadata = 0,
+    Read = 1,
+    Write = 2,
+    Other = 3,
+}
+
+impl OpClass {
+    /* Lock requests waiting for a conflicting lock are never held back, the
+    request releasing it would otherwise wait behind them. */
+    fn of(op: &str) -> Option<Self> {
+        match op {
+            "setlk" => None,
+            "getattr" | "access" | "opendir" | "readdir" | "readdirplus" | "lookup" | "getlk"
+            | "readlink" | "lseek" => Some(OpClass::Metadata),
+            "read" => Some(OpClass::Read),
+            "write" | "fallocate" | "copy_file_range" => Some(OpClass::Write),
+            _ => Some(OpClass::Other),
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    running: [usize; CLASSES],
+    queued: [VecDeque<Task>; CLASSES],
+}
+
+/* Each kind of operation is let in on its own: once a kind is full, its
+next operations are queued until one of its own is done, while the other
+kinds keep going. A flood of large writes then never holds back a lookup,
+which would otherwise wait for the writes ahead of it to be let in. */
+pub(crate) struct Admission {
+    limits: [usize; CLASSES],
+    tasks: Arc<Tasks>,
+    state: Mutex<State>,
+    room: Condvar,
+}
+
+impl std::fmt::Debug for Admission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Admission")
+            .field("limits", &self.limits)
+            .finish()
+    }
+}
+
+impl Admission {
+    pub(crate) fn new(concurrency: Concurrency, tasks: Arc<Tasks>) -> Self {
+        let limits = [
+            concurrency.metadata,
+            concurrency.reads,
+            concurrency.writes,
+            concurrency.others,
+        ];
+        assert!(limits.iter().all(|limit| *limit > 0));
+
+        Self {
+            limits,
+            tasks,
+            state: Mutex::new(State::default()),
+            room: Condvar::new(),
+        }
+    }
+
+    /// Spawns the task of `op` once its kind has room for it, blocking the
+    /// calling thread only while too many operations of that kind are
+    /// queued.
+    pub(crate) fn submit(self: &Arc<Self>, op: &str, task: Task) {
+        let class = match OpClass::of(op) {
+            Some(class) => class as usize,
+            None => return self.tasks.spawn(task),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if state.running[class] < self.limits[class] {
+            state.running[class] += 1;
+            drop(state);
+            return self.spawn(class, task);
+        }
+
+        while state.queued[class].len() >= MAX_QUEUED {
+            state = self.room.wait(state).unwrap();
+        }
+        state.queued[class].push_back(task);
+    }
+
+    fn spawn(self: &Arc<Self>, class: usize, task: Task) {
+        let permit = Permit {
+            admission: self.clone(),
+            class,
+        };
+
+        self.tasks.spawn(async move {
+            let _permit = permit;
+            task.await
+        });
+    }
+
+    /* The room left by an operation goes to the next one of its kind. */
+    fn done(self: &Arc<Self>, class: usize) {
+        let mut state = self.state.lock().unwrap();
+        let next = state.queued[class].pop_front();
+        if next.is_none() {
+            state.running[class] -= 1;
+        }
+        drop(state);
+
+        if let Some(task) = next {
+            self.room.notify_all();
+            self.spawn(class, task);
+        }
+    }
+}
+
+struct Permit {
+    admission: Arc<Admission>,
+    class: usize,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.admission.done(self.class);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::driver::attrs::AttrCache;
+use crate::driver::pool::ConnectionPool;
+use crate::driver::prefetch::Prefetcher;
+use crate::driver::runtime;
+use crate::driver::tasks::Tasks;
+use crate::driver::{Config, Result, TxClass};
+use crate::model::inode::{self, Inode};
+use antidotec::TransactionLocks;
+use async_std::sync::Arc;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const ATIME_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const RELATIME_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// When reading a file updates its access time, as the mount options of
+/// the same name.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AtimePolicy {
+    /// Only when the access time is older than the last modification or
+    /// change, or than a day.
+    Relatime,
+    Strictatime,
+    Noatime,
+}
+
+impl Default for AtimePolicy {
+    fn default() -> Self {
+        AtimePolicy::Relatime
+    }
+}
+
+impl FromStr for AtimePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "relatime" => Ok(AtimePolicy::Relatime),
+            "strictatime" => Ok(AtimePolicy::Strictatime),
+            "noatime" => Ok(AtimePolicy::Noatime),
+            _ => Err(format!("unknown atime policy: {}", s)),
+        }
+    }
+}
+
+impl AtimePolicy {
+    fn needs_update(self, inode: &Inode, t: Duration) -> bool {
+        match self {
+            AtimePolicy::Noatime => false,
+            AtimePolicy::Strictatime => inode.atime < t,
+            AtimePolicy::Relatime => {
+                inode.atime <= inode.mtime
+                    || inode.atime <= inode.ctime
+                    || t.checked_sub(inode.atime)
+                        .map_or(false, |age| age >= RELATIME_PERIOD)
+            }
+        }
+    }
+}
+
+/* Access times are not written by the reads themselves, which would then
+conflict with each other and with writers on the inode, but gathered here
+and written in a single transaction now and then. */
+#[derive(Debug, Default)]
+pub(crate) struct AtimeBatch {
+    pending: Mutex<HashMap<u64, Duration>>,
+}
+
+impl AtimeBatch {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn accessed(&self, policy: AtimePolicy, inode: &Inode, t: Duration) {
+        if !policy.needs_update(inode, t) {
+            return;
+        }
+
+        let mut pending = self.pending.lock().unwrap();
+        let atime = pending.entry(inode.ino).or_insert(t);
+        *atime = (*atime).max(t);
+    }
+
+    fn take(&self) -> HashMap<u64, Duration> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    /* Keeps what could not be written for the next flush, unless a later
+    access got recorded meanwhile. */
+    fn restore(&self, atimes: HashMap<u64, Duration>) {
+        let mut pending = self.pending.lock().unwrap();
+        for (ino, t) in atimes {
+            let atime = pending.entry(ino).or_insert(t);
+            *atime = (*atime).max(t);
+        }
+    }
+}
+
+pub(crate) async fn run(
+    cfg: Config,
+    pool: Arc<ConnectionPool>,
+    prefetch: Arc<Prefetcher>,
+    attrs: Arc<AttrCache>,
+    batch: Arc<AtimeBatch>,
+    tasks: Arc<Tasks>,
+) {
+    loop {
+        runtime::sleep(ATIME_FLUSH_INTERVAL).await;
+        let _work = match tasks.enter() {
+            Some(work) => work,
+            None => return,
+        };
+
+        match flush_batch(&cfg, &pool, &batch).await {
+            Ok(0) => {}
+            Ok(written) => {
+                tracing::debug!(written, "access times flushed");
+                prefetch.invalidate();
+                attrs.clear();
+            }
+            Err(error) => tracing::warn!(?error, "failed to flush access times"),
+        }
+    }
+}
+
+/* What could not be written is kept for the next flush. */
+pub(crate) async fn flush_batch(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    batch: &AtimeBatch,
+) -> Result<usize> {
+    let atimes = batch.take();
+    if atimes.is_empty() {
+        return Ok(0);
+    }
+
+    match flush(cfg, pool, &atimes).await {
+        Ok(written) => Ok(written),
+        Err(error) => {
+            batch.restore(atimes);
+            Err(error)
+        }
+    }
+}
+
+#[tracing::instrument(skip(cfg, pool, atimes))]
+async fn flush(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    atimes: &HashMap<u64, Duration>,
+) -> Result<usize> {
+    let inos: Vec<u64> = atimes.keys().copied().collect();
+
+    let mut connection = pool.acquire().await?;
+    let locks = if cfg.locks.covers(TxClass::Other) {
+        TransactionLocks {
+            shared: vec![],
+            exclusive: inos.iter().map(|ino| inode::key(*ino).into()).collect(),
+        }
+    } else {
+        TransactionLocks::new()
+    };
+    let mut tx = connection.transaction_with_locks(locks).await?;
+
+    let mut reply = tx
+        .read(cfg.bucket, inos.iter().map(|ino| inode::read(*ino)))
+        .await?;
+
+    /* Inodes removed since they were read are skipped, updating them would
+    bring back a partial inode. */
+    let mut updates = Vec::with_capacity(inos.len());
+    for (index, ino) in inos.iter().enumerate() {
+        let inode = match inode::decode(*ino, &mut reply, index) {
+            Some(inode) => inode,
+            None => continue,
+        };
+
+        let t = atimes[ino];
+        if inode.atime < t {
+            updates.push(inode::update_atime(*ino, t));
+        }
+    }
+
+    let written = updates.len();
+    if !updates.is_empty() {
+        tx.update(cfg.bucket, updates).await?;
+    }
+
+    tx.commit().await?;
+    Ok(written)
+}
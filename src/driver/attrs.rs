@@ -0,0 +1,62 @@
+use fuser::FileAttr;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const MAX_CACHED_ATTRS: usize = 64 * 1024;
+
+/// Attributes of an inode along with its generation, as replied to lookups
+/// and creations.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct EntryAttr {
+    pub(crate) attr: FileAttr,
+    pub(crate) generation: u64,
+}
+
+/* Attributes last read, served for as long as the kernel is told to keep
+them. Any local update drops the whole cache, as it may have changed more
+inodes than the one it was asked for, e.g link counts. */
+#[derive(Debug)]
+pub(crate) struct AttrCache {
+    ttl: Duration,
+    attrs: Mutex<HashMap<u64, (Instant, EntryAttr)>>,
+}
+
+impl AttrCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            attrs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn get(&self, ino: u64) -> Option<EntryAttr> {
+        let attrs = self.attrs.lock().unwrap();
+
+        match attrs.get(&ino) {
+            Some((at, entry)) if at.elapsed() < self.ttl => Some(*entry),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn insert(&self, entry: EntryAttr) {
+        if self.ttl == Duration::from_secs(0) {
+            return;
+        }
+
+        let mut attrs = self.attrs.lock().unwrap();
+        if attrs.len() >= MAX_CACHED_ATTRS {
+            let ttl = self.ttl;
+            attrs.retain(|_, (at, _)| at.elapsed() < ttl);
+        }
+        if attrs.len() >= MAX_CACHED_ATTRS {
+            attrs.clear();
+        }
+
+        attrs.insert(entry.attr.ino, (Instant::now(), entry));
+    }
+
+    pub(crate) fn clear(&self) {
+        self.attrs.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,152 @@
+use crate::driver::attrs::EntryAttr;
+use crate::driver::runtime::{Condvar, Mutex};
+use crate::driver::{Error, Result};
+use nix::errno::Errno;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// How long the first read of a batch waits for the next ones.
+pub(crate) const READ_WINDOW: Duration = Duration::from_micros(200);
+const MAX_BATCH_LEN: usize = 256;
+
+#[derive(Debug)]
+struct Batch {
+    id: u64,
+    inos: Vec<u64>,
+    followers: usize,
+}
+
+#[derive(Debug)]
+struct Outcome {
+    result: std::result::Result<HashMap<u64, EntryAttr>, Errno>,
+    waiting: usize,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    in_flight: usize,
+    open: Option<Batch>,
+    done: HashMap<u64, Outcome>,
+    next_id: u64,
+}
+
+/// What a read of attributes has to do once it joined the reads in flight.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum ReadRole {
+    /// Reads the batch it opened once the window is over.
+    Leader(u64),
+    /// Waits for the leader of the batch it was added to.
+    Follower(u64),
+    /// Reads on its own, nothing else is being read.
+    Alone,
+}
+
+/* Reads of attributes that miss the caches are batched only while others
+are in flight, an idle mount never waits for the window. A batch is read in
+a single transaction by the read that opened it, and every read of the
+batch is replied to once it is done. */
+#[derive(Debug, Default)]
+pub(crate) struct AttrBatcher {
+    state: Mutex<State>,
+    read: Condvar,
+}
+
+impl AttrBatcher {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) async fn join(&self, ino: u64) -> ReadRole {
+        let mut guard = self.state.lock().await;
+        let state = &mut *guard;
+
+        if state.in_flight == 0 {
+            state.in_flight += 1;
+            return ReadRole::Alone;
+        }
+
+        if let Some(batch) = &mut state.open {
+            if batch.inos.len() >= MAX_BATCH_LEN {
+                state.in_flight += 1;
+                return ReadRole::Alone;
+            }
+
+            if !batch.inos.contains(&ino) {
+                batch.inos.push(ino);
+            }
+            batch.followers += 1;
+            return ReadRole::Follower(batch.id);
+        }
+
+        state.next_id += 1;
+        state.in_flight += 1;
+        let id = state.next_id;
+        state.open = Some(Batch {
+            id,
+            inos: vec![ino],
+            followers: 0,
+        });
+
+        ReadRole::Leader(id)
+    }
+
+    /// Closes the batch of a leader, later reads open a new one.
+    pub(crate) async fn take(&self) -> (Vec<u64>, usize) {
+        let mut state = self.state.lock().await;
+        let batch = state.open.take().unwrap();
+
+        (batch.inos, batch.followers)
+    }
+
+    /// Ends a read done alone.
+    pub(crate) async fn done(&self) {
+        self.state.lock().await.in_flight -= 1;
+    }
+
+    pub(crate) async fn finish(
+        &self,
+        id: u64,
+        followers: usize,
+        result: &Result<HashMap<u64, EntryAttr>>,
+    ) {
+        let mut state = self.state.lock().await;
+        state.in_flight -= 1;
+        if followers == 0 {
+            return;
+        }
+
+        let result = match result {
+            Ok(entries) => Ok(entries.clone()),
+            Err(error) => Err(error.errno()),
+        };
+        state.done.insert(
+            id,
+            Outcome {
+                result,
+                waiting: followers,
+            },
+        );
+        self.read.notify_all();
+    }
+
+    pub(crate) async fn wait(&self, id: u64, ino: u64) -> Result<EntryAttr> {
+        let mut state = self.state.lock().await;
+
+        loop {
+            if let Some(outcome) = state.done.get_mut(&id) {
+                let result = match &outcome.result {
+                    Ok(entries) => entries.get(&ino).copied().ok_or(Error::NotFound),
+                    Err(errno) => Err(Error::Sys(*errno)),
+                };
+
+                outcome.waiting -= 1;
+                if outcome.waiting == 0 {
+                    state.done.remove(&id);
+                }
+                return result;
+            }
+
+            state = self.read.wait(state).await;
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use crate::driver::{Error, Result};
+use nix::errno::Errno;
+use std::cell::Cell;
+use std::future::Future;
+
+#[cfg(not(feature = "tokio-runtime"))]
+async_std::task_local! {
+    static ROUND_TRIPS: Cell<u64> = Cell::new(0);
+}
+
+#[cfg(feature = "tokio-runtime")]
+tokio::task_local! {
+    static ROUND_TRIPS: Cell<u64>;
+}
+
+/* Round trips to Antidote are the main cost of an operation. Each fuse
+operation runs in its own task, counting them per task gives the cost of
+the operation. */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RoundTripBudget {
+    pub limit: u64,
+    pub strict: bool,
+}
+
+pub(crate) fn charge(round_trips: u64) {
+    let _ = ROUND_TRIPS.try_with(|count| count.set(count.get() + round_trips));
+}
+
+/* Round trips made so far by the operation of the current task. */
+pub(crate) fn used() -> u64 {
+    ROUND_TRIPS.try_with(|count| count.get()).unwrap_or(0)
+}
+
+/* Tokio has no task local until a scope is entered, each task spawned by the
+driver runs in one. */
+#[cfg(feature = "tokio-runtime")]
+pub(crate) fn scope<F: Future>(f: F) -> impl Future<Output = F::Output> {
+    ROUND_TRIPS.scope(Cell::new(0), f)
+}
+
+pub(crate) async fn track<T>(
+    op: &'static str,
+    budget: Option<RoundTripBudget>,
+    f: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let result = f.await;
+
+    let budget = match budget {
+        Some(budget) => budget,
+        None => return result,
+    };
+
+    let used = used();
+    if used > budget.limit {
+        tracing::warn!(op, used, limit = budget.limit, "round trip budget exceeded");
+
+        if budget.strict {
+            return Err(Error::Sys(Errno::EIO));
+        }
+    }
+
+    result
+}
@@ -0,0 +1,69 @@
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+/* Enough for the largest writes the kernel sends, larger buffers are left
+to the allocator rather than kept around. */
+const MAX_POOLED_CAPACITY: usize = 2 * 1024 * 1024;
+const MAX_POOLED: usize = 64;
+
+/* Buffers taking the data of writes out of the fuse session, given back
+once the write is done so that the next ones don't allocate again. */
+#[derive(Debug, Default)]
+pub(crate) struct BufferPool {
+    free: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn copy(self: &Arc<Self>, data: &[u8]) -> Buffer {
+        let mut bytes = self.free.lock().unwrap().pop().unwrap_or_default();
+        bytes.extend_from_slice(data);
+
+        Buffer {
+            bytes,
+            pool: self.clone(),
+        }
+    }
+
+    fn give_back(&self, mut bytes: Vec<u8>) {
+        if bytes.capacity() > MAX_POOLED_CAPACITY {
+            return;
+        }
+
+        let mut free = self.free.lock().unwrap();
+        if free.len() < MAX_POOLED {
+            bytes.clear();
+            free.push(bytes);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Buffer {
+    bytes: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl Buffer {
+    pub(crate) fn extend_from_slice(&mut self, data: &[u8]) {
+        self.bytes.extend_from_slice(data);
+    }
+}
+
+impl Deref for Buffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        let bytes = std::mem::take(&mut self.bytes);
+        self.pool.give_back(bytes);
+    }
+}
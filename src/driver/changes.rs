@@ -0,0 +1,92 @@
+use crate::driver::kernel::KernelCache;
+use crate::driver::pool::ConnectionPool;
+use crate::driver::runtime;
+use crate::driver::tasks::Tasks;
+use crate::driver::{Config, Result};
+use crate::model::changes;
+use crate::view::View;
+use async_std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const CHANGES_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/* The changes of the namespace and of files, their content and attributes,
+are counted by Antidote, by view, along with the changes themselves. Once the count of the other views grew, what is
+cached might be stale: the caches of the driver are dropped by the next
+operation, those of the kernel right away. */
+#[derive(Debug)]
+pub(crate) struct ChangeWatch {
+    view: View,
+    seen: Mutex<Option<u64>>,
+    stale: AtomicBool,
+}
+
+impl ChangeWatch {
+    pub(crate) fn new(view: View) -> Self {
+        Self {
+            view,
+            seen: Mutex::new(None),
+            stale: AtomicBool::new(false),
+        }
+    }
+
+    /// Counts a change of this view, to be committed along with it.
+    pub(crate) fn bump(&self) -> antidotec::UpdateQuery {
+        changes::bump(self.view)
+    }
+
+    /// Whether other views changed anything since last asked.
+    pub(crate) fn take_stale(&self) -> bool {
+        self.stale.load(Ordering::Acquire) && self.stale.swap(false, Ordering::AcqRel)
+    }
+
+    fn observe(&self, count: u64) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+
+        let changed = matches!(*seen, Some(seen) if seen != count);
+        if changed {
+            tracing::debug!(count, "changes made by another view");
+            self.stale.store(true, Ordering::Release);
+        }
+        *seen = Some(count);
+        changed
+    }
+}
+
+pub(crate) async fn run(
+    cfg: Config,
+    pool: Arc<ConnectionPool>,
+    watch: Arc<ChangeWatch>,
+    kernel: Arc<KernelCache>,
+    tasks: Arc<Tasks>,
+) {
+    loop {
+        let work = match tasks.enter() {
+            Some(work) => work,
+            None => return,
+        };
+
+        match count(&cfg, &pool).await {
+            Ok(count) => {
+                if watch.observe(count) {
+                    kernel.invalidate();
+                }
+            }
+            Err(error) => tracing::warn!(?error, "failed to read the namespace changes"),
+        }
+
+        drop(work);
+        runtime::sleep(CHANGES_POLL_INTERVAL).await;
+    }
+}
+
+async fn count(cfg: &Config, pool: &ConnectionPool) -> Result<u64> {
+    let mut connection = pool.acquire().await?;
+    let mut reply = connection
+        .read_static(None, cfg.bucket, vec![changes::read()])
+        .await?;
+
+    Ok(changes::decode_others(cfg.view, &mut reply, 0))
+}
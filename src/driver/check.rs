@@ -0,0 +1,578 @@
+use crate::driver::page::PageWriter;
+use crate::driver::pool::ConnectionPool;
+use crate::driver::{Config, Result, ROOT_INO};
+use crate::model::{
+    dir::{self, EntryView},
+    extent,
+    inode::{self, Inode, Kind},
+    orphan, symlink,
+};
+use crate::view::Name;
+use antidotec::TransactionLocks;
+use fuser::FileType;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::{self, Display};
+
+/* Objects read at once when checking inodes found during the walk. */
+const READ_BATCH: usize = 256;
+
+/// An inconsistency found by walking the filesystem from its root. Most of
+/// them are left by a crash in the middle of an operation spanning several
+/// transactions, or by concurrent operations of views being merged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// A directory whose entries can't be read.
+    MissingDir { ino: u64 },
+    /// An entry pointing at an inode that doesn't exist.
+    DanglingEntry { parent: u64, name: String, ino: u64 },
+    /// An entry whose kind differs from the one of its inode.
+    KindMismatch {
+        parent: u64,
+        name: String,
+        ino: u64,
+        entry: FileType,
+        inode: FileType,
+    },
+    /// The stored link count of an inode differs from the links found to it.
+    LinkCount { ino: u64, stored: u64, found: u64 },
+    /// A directory reachable through more than one entry.
+    MultipleParents { ino: u64, parents: Vec<u64> },
+    /// The parent stored in a directory inode isn't the one holding it.
+    ParentPointer { ino: u64, stored: u64, found: u64 },
+    /// The `.` or `..` entries of a directory don't point where they should.
+    DotEntries { ino: u64, parent: u64 },
+    /// A symlink inode without a target.
+    MissingSymlink { ino: u64 },
+    /// Pages written past the end of a file.
+    PagesPastEnd { ino: u64, first: u64, last: u64 },
+    /// An orphan whose inode is gone, along with the objects it left.
+    StaleOrphan { ino: u64, symlink: bool, pages: u64 },
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::MissingDir { ino } => write!(f, "directory {} has no entries", ino),
+            Problem::DanglingEntry { parent, name, ino } => write!(
+                f,
+                "entry {} of directory {} points to missing inode {}",
+                name, parent, ino
+            ),
+            Problem::KindMismatch {
+                parent,
+                name,
+                ino,
+                entry,
+                inode,
+            } => write!(
+                f,
+                "entry {} of directory {} is a {:?} but inode {} is a {:?}",
+                name, parent, entry, ino, inode
+            ),
+            Problem::LinkCount { ino, stored, found } => write!(
+                f,
+                "inode {} has {} links but {} were found",
+                ino, stored, found
+            ),
+            Problem::MultipleParents { ino, parents } => write!(
+                f,
+                "directory {} is linked from directories {:?}",
+                ino, parents
+            ),
+            Problem::ParentPointer { ino, stored, found } => write!(
+                f,
+                "directory {} points to parent {} but is held by {}",
+                ino, stored, found
+            ),
+            Problem::DotEntries { ino, parent } => write!(
+                f,
+                "directory {} has . or .. entries not pointing to itself and {}",
+                ino, parent
+            ),
+            Problem::MissingSymlink { ino } => write!(f, "symlink {} has no target", ino),
+            Problem::PagesPastEnd { ino, first, last } => write!(
+                f,
+                "inode {} has pages {} to {} written past its end",
+                ino, first, last
+            ),
+            Problem::StaleOrphan {
+                ino,
+                symlink,
+                pages,
+            } => write!(
+                f,
+                "orphan {} is gone, leaving a symlink: {}, pages: {}",
+                ino, symlink, pages
+            ),
+        }
+    }
+}
+
+/// Outcome of a check, `repaired` counting the problems that were fixed.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub inodes: usize,
+    pub problems: Vec<Problem>,
+    pub repaired: usize,
+}
+
+/* Every inode reachable from the root, with what was found on the way. */
+#[derive(Debug, Default)]
+pub(super) struct Walk {
+    pub(super) inodes: HashMap<u64, Inode>,
+    links: HashMap<u64, u64>,
+    parents: BTreeMap<u64, Vec<u64>>,
+    dot_entries: HashMap<u64, Vec<EntryView>>,
+    problems: Vec<Problem>,
+}
+
+/* The walk isn't atomic, it is meant to run on a filesystem that no view
+mounts. */
+#[tracing::instrument(skip(cfg, pool, pages))]
+pub(crate) async fn run(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    pages: &PageWriter,
+    repair: bool,
+) -> Result<Report> {
+    let mut walk = walk(cfg, pool).await?;
+
+    check_dirs(&mut walk);
+    check_files(cfg, pool, pages, &mut walk).await?;
+    check_orphans(cfg, pool, &mut walk).await?;
+
+    let mut report = Report {
+        inodes: walk.inodes.len(),
+        problems: walk.problems,
+        repaired: 0,
+    };
+
+    if repair {
+        for problem in &report.problems {
+            if fix(cfg, pool, pages, problem).await? {
+                report.repaired += 1;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+pub(super) async fn walk(cfg: &Config, pool: &ConnectionPool) -> Result<Walk> {
+    let mut walk = Walk::default();
+    walk.parents.insert(ROOT_INO, vec![ROOT_INO]);
+
+    let root = {
+        let mut connection = pool.acquire().await?;
+        let mut tx = transaction!(cfg, connection).await?;
+        let mut reply = tx.read(cfg.bucket, vec![inode::read(ROOT_INO)]).await?;
+        tx.commit().await?;
+
+        inode::decode(ROOT_INO, &mut reply, 0)
+    };
+    if let Some(root) = root {
+        walk.inodes.insert(ROOT_INO, root);
+    }
+
+    let mut queue = VecDeque::new();
+    queue.push_back(ROOT_INO);
+    while let Some(ino) = queue.pop_front() {
+        walk_dir(cfg, pool, &mut walk, ino, &mut queue).await?;
+    }
+
+    Ok(walk)
+}
+
+async fn walk_dir(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    walk: &mut Walk,
+    ino: u64,
+    queue: &mut VecDeque<u64>,
+) -> Result<()> {
+    let mut connection = pool.acquire().await?;
+    let mut tx = transaction!(cfg, connection, { shared: [dir::key(ino)] }).await?;
+
+    let mut reply = tx.read(cfg.bucket, dir::read_all(ino)).await?;
+    let entries = match dir::decode_all(cfg.view, &mut reply, 0) {
+        Some(entries) => entries,
+        None => {
+            tx.commit().await?;
+            walk.problems.push(Problem::MissingDir { ino });
+            return Ok(());
+        }
+    };
+
+    let mut children = Vec::with_capacity(entries.len());
+    for entry in entries.entries() {
+        match &*entry.prefix {
+            b"." | b".." => walk.dot_entries.entry(ino).or_default().push(entry.clone()),
+            _ => children.push(entry.clone()),
+        }
+    }
+
+    for batch in children.chunks(READ_BATCH) {
+        let reads = batch.iter().map(|entry| inode::read(entry.ino));
+        let mut reply = tx.read(cfg.bucket, reads).await?;
+
+        for (index, entry) in batch.iter().enumerate() {
+            let name = entry.into_dentry().name.to_string();
+            let child = match inode::decode(entry.ino, &mut reply, index) {
+                Some(child) => child,
+                None => {
+                    walk.problems.push(Problem::DanglingEntry {
+                        parent: ino,
+                        name,
+                        ino: entry.ino,
+                    });
+                    continue;
+                }
+            };
+
+            if child.kind != entry.kind {
+                walk.problems.push(Problem::KindMismatch {
+                    parent: ino,
+                    name,
+                    ino: entry.ino,
+                    entry: entry.kind.to_file_type(),
+                    inode: child.kind.to_file_type(),
+                });
+            }
+
+            if child.kind == Kind::Directory {
+                let parents = walk.parents.entry(child.ino).or_default();
+                if parents.is_empty() {
+                    queue.push_back(child.ino);
+                }
+                parents.push(ino);
+            } else {
+                *walk.links.entry(child.ino).or_default() += 1;
+            }
+            walk.inodes.insert(child.ino, child);
+        }
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+fn check_dirs(walk: &mut Walk) {
+    /* A directory is linked by its entry, its "." and the ".." of each of
+    its subdirectories. */
+    let mut subdirs: HashMap<u64, u64> = HashMap::new();
+    for (&ino, parents) in &walk.parents {
+        if ino != ROOT_INO {
+            for &parent in parents {
+                *subdirs.entry(parent).or_default() += 1;
+            }
+        }
+    }
+
+    for (&ino, parents) in &walk.parents {
+        let parent = parents[0];
+        if parents.len() > 1 {
+            walk.problems.push(Problem::MultipleParents {
+                ino,
+                parents: parents.clone(),
+            });
+        }
+
+        let inode = match walk.inodes.get(&ino) {
+            Some(inode) => inode,
+            None => continue,
+        };
+        let links = 2 + subdirs.get(&ino).copied().unwrap_or(0);
+        if inode.nlink != links {
+            walk.problems.push(Problem::LinkCount {
+                ino,
+                stored: inode.nlink,
+                found: links,
+            });
+        }
+
+        if inode.parent != parent {
+            walk.problems.push(Problem::ParentPointer {
+                ino,
+                stored: inode.parent,
+                found: parent,
+            });
+        }
+
+        let dot_entries = walk.dot_entries.get(&ino).map_or(&[][..], |e| &e[..]);
+        if !dot_entries_agree(dot_entries, ino, parent) {
+            walk.problems.push(Problem::DotEntries { ino, parent });
+        }
+    }
+}
+
+fn dot_entries_agree(entries: &[EntryView], ino: u64, parent: u64) -> bool {
+    let dot = entries.iter().filter(|entry| &*entry.prefix == b".");
+    let dotdot = entries.iter().filter(|entry| &*entry.prefix == b"..");
+
+    dot.clone().count() > 0
+        && dotdot.clone().count() > 0
+        && dot.clone().all(|entry| entry.ino == ino)
+        && dotdot.clone().all(|entry| entry.ino == parent)
+}
+
+async fn check_files(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    pages: &PageWriter,
+    walk: &mut Walk,
+) -> Result<()> {
+    let mut links: Vec<_> = walk
+        .links
+        .iter()
+        .map(|(&ino, &found)| (ino, found))
+        .collect();
+    links.sort();
+    for (ino, found) in links {
+        let stored = walk.inodes[&ino].nlink;
+        if stored != found {
+            walk.problems
+                .push(Problem::LinkCount { ino, stored, found });
+        }
+    }
+
+    let mut symlinks: Vec<u64> = walk
+        .inodes
+        .values()
+        .filter(|inode| inode.kind == Kind::Symlink)
+        .map(|inode| inode.ino)
+        .collect();
+    symlinks.sort();
+
+    let mut files: Vec<(u64, u64)> = walk
+        .inodes
+        .values()
+        .filter(|inode| inode.kind == Kind::Regular && inode.extents)
+        .map(|inode| (inode.ino, inode.size))
+        .collect();
+    files.sort();
+
+    let mut connection = pool.acquire().await?;
+    let mut tx = transaction!(cfg, connection).await?;
+
+    for batch in symlinks.chunks(READ_BATCH) {
+        let reads = batch.iter().map(|&ino| symlink::read(ino));
+        let mut reply = tx.read(cfg.bucket, reads).await?;
+
+        for (index, &ino) in batch.iter().enumerate() {
+            if symlink::decode(&mut reply, index).is_none() {
+                walk.problems.push(Problem::MissingSymlink { ino });
+            }
+        }
+    }
+
+    /* Only the extent group holding the end of a file is looked at, pages
+    further away would have to be written by a bogus offset. */
+    for (ino, size) in files {
+        let first = pages.covering_pages(&(0..size)).end;
+        let group_end = (extent::group_of(first) + 1) * extent::PAGES_PER_GROUP;
+
+        let written = pages.written_pages(&mut tx, ino, first..group_end).await?;
+        if let (Some(&first), Some(&last)) = (written.iter().next(), written.iter().last()) {
+            walk.problems
+                .push(Problem::PagesPastEnd { ino, first, last });
+        }
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn check_orphans(cfg: &Config, pool: &ConnectionPool, walk: &mut Walk) -> Result<()> {
+    let mut connection = pool.acquire().await?;
+    let mut tx = transaction!(cfg, connection).await?;
+
+    let mut reply = tx.read(cfg.bucket, vec![orphan::read()]).await?;
+    let mut orphans = orphan::decode(&mut reply, 0);
+    orphans.sort();
+    orphans.retain(|ino| !walk.inodes.contains_key(ino));
+
+    for batch in orphans.chunks(READ_BATCH) {
+        let reads = batch
+            .iter()
+            .flat_map(|&ino| vec![inode::read(ino), symlink::read(ino), extent::read(ino, 0)]);
+        let mut reply = tx.read(cfg.bucket, reads).await?;
+
+        for (index, &ino) in batch.iter().enumerate() {
+            if inode::decode(ino, &mut reply, 3 * index).is_some() {
+                continue;
+            }
+
+            walk.problems.push(Problem::StaleOrphan {
+                ino,
+                symlink: symlink::decode(&mut reply, 3 * index + 1).is_some(),
+                pages: extent::decode(&mut reply, 3 * index + 2).len() as u64,
+            });
+        }
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/* Problems without an obvious fix, such as a directory held twice, are
+only reported. */
+async fn fix(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    pages: &PageWriter,
+    problem: &Problem,
+) -> Result<bool> {
+    let mut connection = pool.acquire().await?;
+
+    match *problem {
+        Problem::DanglingEntry {
+            parent, ref name, ..
+        }
+        | Problem::KindMismatch {
+            parent, ref name, ..
+        } => {
+            let mut tx = transaction!(cfg, connection, {
+                exclusive: [inode::key(parent), dir::key(parent)]
+            })
+            .await?;
+
+            let reads = std::iter::once(inode::read(parent)).chain(dir::read_all(parent));
+            let mut reply = tx.read(cfg.bucket, reads).await?;
+            let parent_inode = inode::decode(parent, &mut reply, 0);
+            let entries = dir::decode_all(cfg.view, &mut reply, 1);
+
+            let entry = entries.as_ref().and_then(|entries| {
+                entries
+                    .entries()
+                    .iter()
+                    .map(EntryView::into_dentry)
+                    .find(|entry| entry.name.to_string() == *name)
+            });
+            let (mut parent_inode, entry) = match (parent_inode, entry) {
+                (Some(parent_inode), Some(entry)) => (parent_inode, entry),
+                _ => {
+                    tx.commit().await?;
+                    return Ok(false);
+                }
+            };
+
+            let mut updates = vec![dir::remove_entry(parent, &entry)];
+            match problem {
+                Problem::KindMismatch { inode: kind, .. } => {
+                    let kind = kind_of(*kind);
+                    updates.push(dir::add_entry(
+                        parent,
+                        &dir::Entry::new(entry.name, entry.ino, kind),
+                    ));
+                }
+                _ => {
+                    parent_inode.size = parent_inode.size.saturating_sub(1);
+                    updates.push(inode::update_stats_and_size(&parent_inode));
+                }
+            }
+
+            tx.update(cfg.bucket, updates).await?;
+            tx.commit().await?;
+        }
+        Problem::LinkCount { ino, stored, found } => {
+            let mut tx = transaction!(cfg, connection, { exclusive: [inode::key(ino)] }).await?;
+
+            let update = if found > stored {
+                inode::incr_link_count(ino, (found - stored) as u32)
+            } else {
+                inode::decr_link_count(ino, (stored - found) as u32)
+            };
+
+            tx.update(cfg.bucket, vec![update]).await?;
+            tx.commit().await?;
+        }
+        Problem::ParentPointer { ino, found, .. } => {
+            let mut tx = transaction!(cfg, connection, { exclusive: [inode::key(ino)] }).await?;
+
+            let mut reply = tx.read(cfg.bucket, vec![inode::read(ino)]).await?;
+            let mut inode = match inode::decode(ino, &mut reply, 0) {
+                Some(inode) => inode,
+                None => {
+                    tx.commit().await?;
+                    return Ok(false);
+                }
+            };
+            inode.parent = found;
+
+            tx.update(cfg.bucket, vec![inode::update_stats(&inode)])
+                .await?;
+            tx.commit().await?;
+        }
+        Problem::DotEntries { ino, parent } => {
+            let mut tx = transaction!(cfg, connection, { exclusive: [dir::key(ino)] }).await?;
+
+            let mut reply = tx.read(cfg.bucket, dir::read_all(ino)).await?;
+            let entries = dir::decode_all(cfg.view, &mut reply, 0);
+            let dot_entries = entries
+                .iter()
+                .flat_map(|entries| entries.entries())
+                .filter(|entry| &*entry.prefix == b"." || &*entry.prefix == b"..");
+
+            let mut updates: Vec<_> = dot_entries
+                .map(EntryView::into_dentry)
+                .map(|entry| dir::remove_entry(ino, &entry))
+                .collect();
+            updates.push(dir::add_entry(
+                ino,
+                &dir::Entry::new(Name::new(".", cfg.view), ino, Kind::Directory),
+            ));
+            updates.push(dir::add_entry(
+                ino,
+                &dir::Entry::new(Name::new("..", cfg.view), parent, Kind::Directory),
+            ));
+
+            tx.update(cfg.bucket, updates).await?;
+            tx.commit().await?;
+        }
+        Problem::PagesPastEnd { ino, first, last } => {
+            let mut tx = transaction!(cfg, connection, { exclusive: [inode::key(ino)] }).await?;
+
+            let page_size = cfg.page_size;
+            pages
+                .remove_range(&mut tx, ino, (first * page_size)..(last * page_size))
+                .await?;
+            tx.commit().await?;
+        }
+        Problem::StaleOrphan {
+            ino,
+            symlink,
+            pages: written,
+        } => {
+            let mut tx = transaction!(cfg, connection, { exclusive: [inode::key(ino)] }).await?;
+
+            let mut updates = vec![orphan::remove(ino), orphan::remove_holders(ino)];
+            if symlink {
+                updates.push(symlink::remove(ino));
+            }
+            tx.update(cfg.bucket, updates).await?;
+
+            if written > 0 {
+                let end = extent::PAGES_PER_GROUP * cfg.page_size;
+                pages.remove_range(&mut tx, ino, 0..(end - 1)).await?;
+            }
+            tx.commit().await?;
+        }
+        Problem::MissingDir { .. }
+        | Problem::MultipleParents { .. }
+        | Problem::MissingSymlink { .. } => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+fn kind_of(file_type: FileType) -> Kind {
+    match file_type {
+        FileType::Directory => Kind::Directory,
+        FileType::Symlink => Kind::Symlink,
+        FileType::NamedPipe => Kind::Fifo,
+        FileType::Socket => Kind::Socket,
+        FileType::CharDevice => Kind::CharDevice,
+        FileType::BlockDevice => Kind::BlockDevice,
+        FileType::RegularFile => Kind::Regular,
+    }
+}
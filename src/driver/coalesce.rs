@@ -0,0 +1,136 @@
+use crate::driver::buffers::Buffer;
+use crate::driver::runtime::{Condvar, Mutex};
+use crate::driver::{Error, Result};
+use nix::errno::Errno;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// How long the first write of a batch waits for the next ones.
+pub(crate) const WRITE_WINDOW: Duration = Duration::from_millis(1);
+const MAX_BATCH_LEN: usize = 1024 * 1024;
+
+#[derive(Debug)]
+struct Batch {
+    id: u64,
+    offset: u64,
+    bytes: Buffer,
+    followers: usize,
+}
+
+#[derive(Debug)]
+struct Outcome {
+    result: std::result::Result<(), Errno>,
+    waiting: usize,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    open: HashMap<u64, Batch>,
+    done: HashMap<u64, Outcome>,
+    next_id: u64,
+}
+
+/// What a write has to do once it joined the writes in flight.
+#[derive(Debug)]
+pub(crate) enum Role {
+    /// Writes the batch it opened once the window is over.
+    Leader(u64),
+    /// Waits for the leader of the batch it was appended to.
+    Follower(u64),
+    /// Writes on its own, it doesn't follow the open batch of its inode.
+    Alone(Buffer),
+}
+
+/* The kernel splits large writes into back to back requests, each of them
+is appended to the batch of its inode when it starts where the batch ends.
+A batch is written in a single transaction by the write that opened it, and
+every write of the batch is replied to once it is committed. The batch is
+the buffer of the write that opened it, only the following ones are copied. */
+#[derive(Debug, Default)]
+pub(crate) struct WriteCoalescer {
+    state: Mutex<State>,
+    written: Condvar,
+}
+
+impl WriteCoalescer {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) async fn join(&self, ino: u64, offset: u64, bytes: Buffer) -> Role {
+        let mut guard = self.state.lock().await;
+        let state = &mut *guard;
+
+        if let Some(batch) = state.open.get_mut(&ino) {
+            let contiguous = batch.offset + batch.bytes.len() as u64 == offset;
+            if contiguous && batch.bytes.len() + bytes.len() <= MAX_BATCH_LEN {
+                batch.bytes.extend_from_slice(&bytes);
+                batch.followers += 1;
+                return Role::Follower(batch.id);
+            }
+
+            return Role::Alone(bytes);
+        }
+
+        state.next_id += 1;
+        let id = state.next_id;
+        state.open.insert(
+            ino,
+            Batch {
+                id,
+                offset,
+                bytes,
+                followers: 0,
+            },
+        );
+
+        Role::Leader(id)
+    }
+
+    /// Closes the batch of a leader, later writes open a new one.
+    pub(crate) async fn take(&self, ino: u64) -> (u64, Buffer, usize) {
+        let mut state = self.state.lock().await;
+        let batch = state.open.remove(&ino).unwrap();
+
+        (batch.offset, batch.bytes, batch.followers)
+    }
+
+    pub(crate) async fn finish(&self, id: u64, followers: usize, result: &Result<()>) {
+        if followers == 0 {
+            return;
+        }
+
+        let result = match result {
+            Ok(()) => Ok(()),
+            Err(error) => Err(error.errno()),
+        };
+
+        let mut state = self.state.lock().await;
+        state.done.insert(
+            id,
+            Outcome {
+                result,
+                waiting: followers,
+            },
+        );
+        self.written.notify_all();
+    }
+
+    pub(crate) async fn wait(&self, id: u64) -> Result<()> {
+        let mut state = self.state.lock().await;
+
+        loop {
+            if let Some(outcome) = state.done.get_mut(&id) {
+                let result = outcome.result.map_err(Error::Sys);
+
+                outcome.waiting -= 1;
+                if outcome.waiting == 0 {
+                    state.done.remove(&id);
+                }
+                return result;
+            }
+
+            state = self.written.wait(state).await;
+        }
+    }
+}
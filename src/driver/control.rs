@@ -0,0 +1,237 @@
+use crate::driver::{Error, ReadDirEntry, Result};
+use crate::view::NameRef;
+use fuser::{FileAttr, FileType};
+use nix::errno::Errno;
+use std::collections::{HashSet, VecDeque};
+use std::ffi::OsString;
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+pub(crate) const CONTROL_DIR_NAME: &str = ".elmerfs";
+
+/* Inode numbers allocated by views are made of a view in their upper 16
+bits and a 32 bits counter, this bit is never set by an allocation. */
+const CONTROL_INO_BASE: u64 = 1 << 47;
+const MAX_CONFLICTS: usize = 1024;
+
+/// The `.elmerfs` directory of the root and what it holds, generated by the
+/// driver and never stored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Node {
+    Dir,
+    Stats,
+    Connections,
+    View,
+    Conflicts,
+    DropCaches,
+    Gc,
+    /// Removes the directory whose path is written, relative to the root of
+    /// the mount, and everything under it.
+    Rm,
+    /// Lists the requests in flight, interrupts the one whose unique id is
+    /// written.
+    Interrupt,
+}
+
+const FILES: [Node; 8] = [
+    Node::Stats,
+    Node::Connections,
+    Node::View,
+    Node::Conflicts,
+    Node::DropCaches,
+    Node::Gc,
+    Node::Rm,
+    Node::Interrupt,
+];
+
+impl Node {
+    pub(crate) fn from_ino(ino: u64) -> Option<Self> {
+        if ino == Node::Dir.ino() {
+            return Some(Node::Dir);
+        }
+
+        FILES.iter().copied().find(|node| node.ino() == ino)
+    }
+
+    pub(crate) fn lookup(name: &[u8]) -> Option<Self> {
+        FILES
+            .iter()
+            .copied()
+            .find(|node| node.name().as_bytes() == name)
+    }
+
+    pub(crate) fn ino(self) -> u64 {
+        CONTROL_INO_BASE | self as u64
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Node::Dir => CONTROL_DIR_NAME,
+            Node::Stats => "stats",
+            Node::Connections => "connections",
+            Node::View => "view",
+            Node::Conflicts => "conflicts",
+            Node::DropCaches => "drop_caches",
+            Node::Gc => "gc",
+            Node::Rm => "rm",
+            Node::Interrupt => "interrupt",
+        }
+    }
+
+    pub(crate) fn kind(self) -> FileType {
+        match self {
+            Node::Dir => FileType::Directory,
+            _ => FileType::RegularFile,
+        }
+    }
+
+    /* Actions are only triggered by root, as /proc/sys/vm/drop_caches. The
+    size is unknown until read, files are opened in direct I/O. */
+    pub(crate) fn attr(self, mounted_at: Duration) -> FileAttr {
+        let perm = match self {
+            Node::Dir => 0o555,
+            Node::DropCaches | Node::Gc | Node::Rm => 0o200,
+            Node::Interrupt => 0o600,
+            _ => 0o444,
+        };
+        let t = UNIX_EPOCH + mounted_at;
+
+        FileAttr {
+            ino: self.ino(),
+            size: 0,
+            blocks: 0,
+            atime: t,
+            mtime: t,
+            ctime: t,
+            crtime: t,
+            kind: self.kind(),
+            perm,
+            nlink: if self == Node::Dir { 2 } else { 1 },
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: 0,
+            flags: 0,
+        }
+    }
+}
+
+/// The listing of the directory from `offset` on, `..` being the root of
+/// the mount.
+pub(crate) fn entries(root: u64, offset: i64) -> Vec<ReadDirEntry> {
+    let dots = vec![(Node::Dir.ino(), "."), (root, "..")];
+    let files = FILES.iter().map(|node| (node.ino(), node.name()));
+
+    dots.into_iter()
+        .chain(files)
+        .enumerate()
+        .skip(offset as usize)
+        .map(|(index, (ino, name))| ReadDirEntry {
+            ino,
+            kind: Node::from_ino(ino).map_or(FileType::Directory, Node::kind),
+            name: OsString::from(name),
+            offset: index as i64 + 1,
+        })
+        .collect()
+}
+
+/* The directory is looked up in the root before anything stored under the
+same name, which is then out of reach until renamed by name and view. */
+pub(crate) fn lookup_entry(root: u64, parent_ino: u64, name: &NameRef) -> Option<Result<Node>> {
+    if is_reserved(root, parent_ino, name) {
+        Some(Ok(Node::Dir))
+    } else if parent_ino == Node::Dir.ino() {
+        Some(Node::lookup(name.prefix()).ok_or(Error::NotFound))
+    } else {
+        None
+    }
+}
+
+/// Nothing is created in the directory, nor under its name in the root.
+pub(crate) fn check_creation(root: u64, parent_ino: u64, name: &NameRef) -> Result<()> {
+    if parent_ino == Node::Dir.ino() {
+        Err(Error::Sys(Errno::EPERM))
+    } else if is_reserved(root, parent_ino, name) {
+        Err(Error::Exists)
+    } else {
+        Ok(())
+    }
+}
+
+/// Neither the directory nor what it holds can be removed or renamed.
+pub(crate) fn check_removal(root: u64, parent_ino: u64, name: &NameRef) -> Result<()> {
+    if parent_ino == Node::Dir.ino() || is_reserved(root, parent_ino, name) {
+        Err(Error::Sys(Errno::EPERM))
+    } else {
+        Ok(())
+    }
+}
+
+fn is_reserved(root: u64, parent_ino: u64, name: &NameRef) -> bool {
+    match name {
+        NameRef::Partial(prefix) => {
+            parent_ino == root && prefix.as_slice() == CONTROL_DIR_NAME.as_bytes()
+        }
+        _ => false,
+    }
+}
+
+/// The part of a generated content a read at `offset` returns.
+pub(crate) fn slice(content: &str, offset: u64, len: u32) -> Vec<u8> {
+    content
+        .as_bytes()
+        .iter()
+        .skip(offset as usize)
+        .take(len as usize)
+        .copied()
+        .collect()
+}
+
+#[derive(Debug, Default)]
+struct State {
+    order: VecDeque<(u64, String)>,
+    seen: HashSet<(u64, String)>,
+}
+
+/* Names created concurrently by several views, as last seen by listings of
+this mount. */
+#[derive(Debug, Default)]
+pub(crate) struct Conflicts {
+    state: Mutex<State>,
+}
+
+impl Conflicts {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record<'a>(&self, parent_ino: u64, names: impl Iterator<Item = &'a [u8]>) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+
+        for name in names {
+            let conflict = (parent_ino, String::from_utf8_lossy(name).into_owned());
+            if state.seen.contains(&conflict) {
+                continue;
+            }
+
+            if state.order.len() >= MAX_CONFLICTS {
+                if let Some(oldest) = state.order.pop_front() {
+                    state.seen.remove(&oldest);
+                }
+            }
+            state.order.push_back(conflict.clone());
+            state.seen.insert(conflict);
+        }
+    }
+
+    pub(crate) fn render(&self) -> String {
+        let state = self.state.lock().unwrap();
+
+        state
+            .order
+            .iter()
+            .map(|(parent_ino, name)| format!("{} {}\n", parent_ino, name))
+            .collect()
+    }
+}
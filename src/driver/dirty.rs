@@ -0,0 +1,212 @@
+use crate::driver::attrs::{AttrCache, EntryAttr};
+use crate::driver::pool::ConnectionPool;
+use crate::driver::prefetch::Prefetcher;
+use crate::driver::runtime;
+use crate::driver::shape::AttrShape;
+use crate::driver::tasks::Tasks;
+use crate::driver::{Config, Result, TxClass};
+use crate::model::changes;
+use crate::model::inode::{self, Inode};
+use crate::model::journal::{self, Intent};
+use antidotec::TransactionLocks;
+use async_std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+const DIRTY_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What writes changed in an inode and is not stored yet.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Dirty {
+    pub(crate) mtime: Duration,
+    pub(crate) size: u64,
+}
+
+impl Dirty {
+    fn merge(&mut self, other: Dirty) {
+        self.mtime = self.mtime.max(other.mtime);
+        self.size = self.size.max(other.size);
+    }
+
+    /// Applies to an inode just read, sizes only ever grow.
+    pub(crate) fn apply(&self, inode: &mut Inode) {
+        inode.mtime = inode.mtime.max(self.mtime);
+        inode.atime = inode.atime.max(self.mtime);
+        inode.size = inode.size.max(self.size);
+    }
+
+    fn apply_entry(&self, shape: &AttrShape, entry: &mut EntryAttr) {
+        let t = UNIX_EPOCH + shape.timestamp(self.mtime);
+        entry.attr.mtime = entry.attr.mtime.max(t);
+        entry.attr.atime = entry.attr.atime.max(t);
+        entry.attr.size = entry.attr.size.max(self.size);
+    }
+}
+
+/* Writes don't update their inode themselves, each of them would otherwise
+rewrite all of its attributes. What they changed is merged here, served on
+top of what is read, and written now and then, on fsync and once the last
+handle on the inode is released. An inode is forgotten only once what was
+written of it is stored and it wasn't written to meanwhile, so that what
+is served never goes back in between. */
+#[derive(Debug, Default)]
+pub(crate) struct DirtyAttrs {
+    pending: Mutex<HashMap<u64, Dirty>>,
+}
+
+impl DirtyAttrs {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn written(&self, ino: u64, dirty: Dirty) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.entry(ino).or_insert(dirty).merge(dirty);
+    }
+
+    pub(crate) fn get(&self, ino: u64) -> Option<Dirty> {
+        self.pending.lock().unwrap().get(&ino).copied()
+    }
+
+    pub(crate) fn apply_entry(&self, shape: &AttrShape, mut entry: EntryAttr) -> EntryAttr {
+        if let Some(dirty) = self.get(entry.attr.ino) {
+            dirty.apply_entry(shape, &mut entry);
+        }
+        entry
+    }
+
+    fn snapshot(&self, ino: Option<u64>) -> HashMap<u64, Dirty> {
+        let pending = self.pending.lock().unwrap();
+        match ino {
+            Some(ino) => pending
+                .get(&ino)
+                .map(|dirty| (ino, *dirty))
+                .into_iter()
+                .collect(),
+            None => pending.clone(),
+        }
+    }
+
+    fn stored(&self, flushed: &HashMap<u64, Dirty>) {
+        let mut pending = self.pending.lock().unwrap();
+        for (ino, dirty) in flushed {
+            if pending.get(ino) == Some(dirty) {
+                pending.remove(ino);
+            }
+        }
+    }
+}
+
+pub(crate) async fn run(
+    cfg: Config,
+    pool: Arc<ConnectionPool>,
+    prefetch: Arc<Prefetcher>,
+    attrs: Arc<AttrCache>,
+    dirty: Arc<DirtyAttrs>,
+    tasks: Arc<Tasks>,
+) {
+    loop {
+        runtime::sleep(DIRTY_FLUSH_INTERVAL).await;
+        let _work = match tasks.enter() {
+            Some(work) => work,
+            None => return,
+        };
+
+        match flush_batch(&cfg, &pool, &dirty, None).await {
+            Ok(0) => {}
+            Ok(written) => {
+                tracing::debug!(written, "dirty attributes flushed");
+                prefetch.invalidate();
+                attrs.clear();
+            }
+            Err(error) => tracing::warn!(?error, "failed to flush dirty attributes"),
+        }
+    }
+}
+
+/// Stores what writes changed, in every inode or in `ino` only. What could
+/// not be written is kept for the next flush.
+pub(crate) async fn flush_batch(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    dirty: &DirtyAttrs,
+    ino: Option<u64>,
+) -> Result<usize> {
+    let pending = dirty.snapshot(ino);
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    let written = flush(cfg, pool, &pending).await?;
+    dirty.stored(&pending);
+    Ok(written)
+}
+
+#[tracing::instrument(skip(cfg, pool, pending))]
+async fn flush(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    pending: &HashMap<u64, Dirty>,
+) -> Result<usize> {
+    let inos: Vec<u64> = pending.keys().copied().collect();
+
+    let mut connection = pool.acquire().await?;
+    let locks = if cfg.locks.covers(TxClass::Other) {
+        TransactionLocks {
+            shared: vec![],
+            exclusive: inos.iter().map(|ino| inode::key(*ino).into()).collect(),
+        }
+    } else {
+        TransactionLocks::new()
+    };
+    let mut tx = connection.transaction_with_locks(locks).await?;
+
+    let reads = inos
+        .iter()
+        .map(|ino| inode::read(*ino))
+        .chain(std::iter::once(journal::read(cfg.view)));
+    let mut reply = tx.read(cfg.bucket, reads).await?;
+
+    /* Inodes removed since they were written are skipped, updating them
+    would bring back a partial inode. The size is only written when it
+    grows, through its merged register, as writes did before. */
+    let mut updates = Vec::with_capacity(inos.len());
+    for (index, ino) in inos.iter().enumerate() {
+        let mut inode = match inode::decode(*ino, &mut reply, index) {
+            Some(inode) => inode,
+            None => continue,
+        };
+
+        let dirty = pending[ino];
+        let extended = dirty.size > inode.size;
+        dirty.apply(&mut inode);
+
+        updates.push(if extended {
+            inode::update_stats_and_size(&inode)
+        } else {
+            inode::update_stats(&inode)
+        });
+    }
+
+    let written = updates.len();
+    if written > 0 {
+        updates.push(changes::bump(cfg.view));
+    }
+
+    /* Extensions are forgotten once a size at least as large is stored,
+    those of removed inodes along with the others. */
+    let stored = journal::decode(&mut reply, inos.len()).into_iter().filter(
+        |Intent::Extend { ino, size, .. }| {
+            matches!(pending.get(ino), Some(dirty) if *size <= dirty.size)
+        },
+    );
+    updates.extend(stored.map(|intent| journal::forget(cfg.view, &intent)));
+
+    if !updates.is_empty() {
+        tx.update(cfg.bucket, updates).await?;
+    }
+
+    tx.commit().await?;
+    Ok(written)
+}
@@ -0,0 +1,101 @@
+use crate::driver::admission::{Admission, Task};
+use async_std::sync::Arc;
+use crossbeam::channel::{self, Receiver, Sender};
+use std::future::Future;
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+
+/* Operations waiting for a dispatcher thread, per thread, past that the
+session blocks as it would without them. */
+const QUEUED_PER_THREAD: usize = 64;
+
+struct Dispatch {
+    op: &'static str,
+    task: Task,
+}
+
+/* Only the session thread reads requests from the kernel, fuser keeps its
+channel to itself, but letting operations in and spawning their task is
+fanned out to dispatcher threads. */
+#[derive(Debug)]
+pub(crate) struct Dispatcher {
+    admission: Arc<Admission>,
+    sender: Option<Sender<Option<Dispatch>>>,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Dispatcher {
+    /// Dispatches from the calling thread when `threads` is zero.
+    pub(crate) fn new(admission: Admission, threads: usize) -> Self {
+        let admission = Arc::new(admission);
+        if threads == 0 {
+            return Self {
+                admission,
+                sender: None,
+                threads: Mutex::new(Vec::new()),
+            };
+        }
+
+        let (sender, receiver) = channel::bounded(threads * QUEUED_PER_THREAD);
+        let threads = (0..threads)
+            .map(|id| {
+                let receiver = receiver.clone();
+                let admission = admission.clone();
+
+                thread::Builder::new()
+                    .name(format!("dispatch-{}", id))
+                    .spawn(move || run(receiver, &admission))
+                    .expect("failed to spawn a dispatcher thread")
+            })
+            .collect();
+
+        Self {
+            admission,
+            sender: Some(sender),
+            threads: Mutex::new(threads),
+        }
+    }
+
+    pub(crate) fn dispatch<F>(&self, op: &'static str, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let dispatch = Dispatch {
+            op,
+            task: Box::pin(task),
+        };
+
+        match &self.sender {
+            Some(sender) => {
+                if let Err(error) = sender.send(Some(dispatch)) {
+                    /* Only once closed, the session is over by then. */
+                    if let Some(dispatch) = error.into_inner() {
+                        self.admission.submit(dispatch.op, dispatch.task);
+                    }
+                }
+            }
+            None => self.admission.submit(dispatch.op, dispatch.task),
+        }
+    }
+
+    /// Waits for every operation handed to the dispatcher threads to be
+    /// let in or queued.
+    pub(crate) fn close(&self) {
+        let threads = std::mem::take(&mut *self.threads.lock().unwrap());
+
+        if let Some(sender) = &self.sender {
+            for _ in 0..threads.len() {
+                let _ = sender.send(None);
+            }
+        }
+        for thread in threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(receiver: Receiver<Option<Dispatch>>, admission: &Arc<Admission>) {
+    while let Ok(Some(dispatch)) = receiver.recv() {
+        admission.submit(dispatch.op, dispatch.task);
+    }
+}
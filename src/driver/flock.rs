@@ -0,0 +1,200 @@
+use crate::driver::pool::ConnectionPool;
+use crate::driver::runtime;
+use crate::driver::tasks::Tasks;
+use crate::driver::{Config, Result};
+use crate::model::lock::{self, FileLock};
+use crate::view::View;
+use antidotec::{Transaction, TransactionLocks};
+use async_std::sync::Arc;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/* Locks of a view that didn't renew its lease for this long are ignored,
+it is most likely gone without releasing them. */
+const LOCK_LEASE: Duration = Duration::from_secs(30);
+const LEASE_RENEWAL: Duration = Duration::from_secs(10);
+
+/* The owners that took locks on an inode through this view, so that closing
+a file only goes to Antidote when there may be locks to release. */
+#[derive(Debug, Default)]
+pub(crate) struct HeldLocks {
+    held: Mutex<HashSet<(u64, u64)>>,
+}
+
+impl HeldLocks {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn taken(&self, ino: u64, owner: u64) {
+        self.held.lock().unwrap().insert((ino, owner));
+    }
+
+    /// Whether `owner` may still hold locks on `ino`, which are forgotten.
+    pub(crate) fn take(&self, ino: u64, owner: u64) -> bool {
+        self.held.lock().unwrap().remove(&(ino, owner))
+    }
+}
+
+/* Returns the first lock that prevents `probe` from being taken. */
+#[tracing::instrument(skip(cfg, pool))]
+pub(crate) async fn conflicting(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    ino: u64,
+    probe: FileLock,
+) -> Result<Option<FileLock>> {
+    let mut connection = pool.acquire().await?;
+    let mut tx = transaction!(cfg, connection, { shared: [lock::key(ino)] }).await?;
+
+    let mut reply = tx.read(cfg.bucket, vec![lock::read(ino)]).await?;
+    let locks = lock::decode(&mut reply, 0);
+    let dead = dead_views(cfg, &mut tx, locks.iter().map(|lock| lock.view)).await?;
+
+    tx.commit().await?;
+    Ok(locks
+        .into_iter()
+        .find(|lock| !dead.contains(&lock.view) && lock.conflicts_with(&probe)))
+}
+
+/* Takes `lock`, or releases its range when no kind is given. Locks of the
+same owner overlapping that range are replaced, as POSIX locks are. Returns
+false when the lock is held by someone else. Whatever the lock policy, the
+locks of an inode are changed under an Antidote lock: otherwise two views
+could both find no conflict and both take the lock. */
+#[tracing::instrument(skip(cfg, pool))]
+pub(crate) async fn set(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    ino: u64,
+    lock: FileLock,
+    unlock: bool,
+) -> Result<bool> {
+    let mut connection = pool.acquire().await?;
+    let mut tx = connection
+        .transaction_with_locks(TransactionLocks {
+            shared: vec![],
+            exclusive: vec![lock::key(ino).into()],
+        })
+        .await?;
+
+    let mut reply = tx.read(cfg.bucket, vec![lock::read(ino)]).await?;
+    let locks = lock::decode(&mut reply, 0);
+    let dead = dead_views(cfg, &mut tx, locks.iter().map(|lock| lock.view)).await?;
+
+    let conflict = locks
+        .iter()
+        .any(|other| !dead.contains(&other.view) && other.conflicts_with(&lock));
+    if !unlock && conflict {
+        tx.commit().await?;
+        return Ok(false);
+    }
+
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    for other in &locks {
+        if dead.contains(&other.view) {
+            removed.push(*other);
+            continue;
+        }
+
+        if !other.is_owned_by(lock.view, lock.owner) || !other.overlaps(lock.start, lock.end) {
+            continue;
+        }
+
+        removed.push(*other);
+        if other.start < lock.start {
+            added.push(FileLock {
+                end: lock.start - 1,
+                ..*other
+            });
+        }
+        if other.end > lock.end {
+            added.push(FileLock {
+                start: lock.end + 1,
+                ..*other
+            });
+        }
+    }
+    if !unlock {
+        added.push(lock);
+    }
+
+    let mut updates = Vec::with_capacity(2);
+    if !removed.is_empty() {
+        updates.push(lock::remove(ino, &removed));
+    }
+    if !added.is_empty() {
+        updates.push(lock::add(ino, &added));
+    }
+    if !updates.is_empty() {
+        tx.update(cfg.bucket, updates).await?;
+    }
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+/* Among `views`, those other than this one whose lease ran out. Every
+mount of the live filesystem renews its lease, whether it takes locks or
+not. */
+pub(crate) async fn dead_views(
+    cfg: &Config,
+    tx: &mut Transaction<'_>,
+    views: impl IntoIterator<Item = View>,
+) -> Result<HashSet<View>> {
+    let views: Vec<View> = views
+        .into_iter()
+        .filter(|view| *view != cfg.view)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    if views.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let reads = views.iter().map(|view| lock::read_lease(*view));
+    let mut reply = tx.read(cfg.bucket, reads).await?;
+
+    let now = cfg.clock.now();
+    let mut dead = HashSet::new();
+    for (index, view) in views.into_iter().enumerate() {
+        let alive = lock::decode_lease(&mut reply, index)
+            .map_or(false, |renewed| renewed + LOCK_LEASE > now);
+        if !alive {
+            dead.insert(view);
+        }
+    }
+
+    Ok(dead)
+}
+
+async fn renew_lease(cfg: &Config, pool: &ConnectionPool) -> Result<()> {
+    let mut connection = pool.acquire().await?;
+    let mut tx = connection.transaction().await?;
+
+    tx.update(
+        cfg.bucket,
+        vec![lock::renew_lease(cfg.view, cfg.clock.now())],
+    )
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub(crate) async fn run(cfg: Config, pool: Arc<ConnectionPool>, tasks: Arc<Tasks>) {
+    loop {
+        let work = match tasks.enter() {
+            Some(work) => work,
+            None => return,
+        };
+        if let Err(error) = renew_lease(&cfg, &pool).await {
+            tracing::error!(?error, "failed to renew the lock lease");
+        }
+        drop(work);
+
+        runtime::sleep(LEASE_RENEWAL).await;
+    }
+}
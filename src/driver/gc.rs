@@ -0,0 +1,264 @@
+use crate::driver::flock;
+use crate::driver::handle::HandleTable;
+use crate::driver::page::PageWriter;
+use crate::driver::pool::ConnectionPool;
+use crate::driver::runtime;
+use crate::driver::tasks::Tasks;
+use crate::driver::{Config, Result};
+use crate::model::{dir, extent, inode, orphan, symlink};
+use antidotec::TransactionLocks;
+use async_std::sync::Arc;
+use std::time::Duration;
+
+const ORPHAN_GC_INTERVAL: Duration = Duration::from_secs(60);
+/* Pages removed by a single transaction, a whole group of extents so that
+each transaction resets the groups it empties and no other. */
+const DELETE_CHUNK_PAGES: u64 = extent::PAGES_PER_GROUP;
+
+/* Holds on an orphan which are known to be left over by views that no
+longer run, and can be dropped: those of this view when it has the orphan
+closed, or of views whose lock lease ran out. */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum StaleHolds {
+    None,
+    Dead,
+    All,
+}
+
+/* What a pass over an inode did to it. */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Removal {
+    Kept,
+    Removed,
+    /// Only the last pages of a large file were removed.
+    Shrunk,
+}
+
+#[tracing::instrument(skip(cfg, pool))]
+pub(crate) async fn delete_later(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    pages: &PageWriter,
+    ino: u64,
+) -> Result<bool> {
+    remove_if_unlinked(cfg, pool, pages, ino, StaleHolds::None).await
+}
+
+/* Removes every object of an inode once it is unlinked and no view
+holds it open anymore. Otherwise the inode is kept as an orphan, to be
+collected later on. Holds known to be left over by previous mounts are
+dropped. */
+async fn remove_if_unlinked(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    pages: &PageWriter,
+    ino: u64,
+    stale_holds: StaleHolds,
+) -> Result<bool> {
+    loop {
+        match remove_pass(cfg, pool, pages, ino, stale_holds).await? {
+            Removal::Kept => return Ok(false),
+            Removal::Removed => return Ok(true),
+            Removal::Shrunk => continue,
+        }
+    }
+}
+
+/* The pages of a large file don't fit in a single transaction, they are
+removed a chunk at a time from its end, each chunk along with the size of
+the inode shrunk below it. The inode stays an orphan meanwhile: a removal
+interrupted by a crash is resumed from that size by the next collection. */
+async fn remove_pass(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    pages: &PageWriter,
+    ino: u64,
+    stale_holds: StaleHolds,
+) -> Result<Removal> {
+    let mut connection = pool.acquire().await?;
+    let mut tx = transaction!(cfg, connection, { exclusive: [inode::key(ino)] }).await?;
+
+    let (inode, mut holders) = {
+        let mut reply = tx
+            .read(
+                cfg.bucket,
+                vec![inode::read(ino), orphan::read_holders(ino)],
+            )
+            .await?;
+
+        (
+            inode::decode(ino, &mut reply, 0),
+            orphan::decode_holders(&mut reply, 1),
+        )
+    };
+
+    let inode = match inode {
+        Some(inode) => inode,
+        None => {
+            tracing::debug!("already removed");
+            tx.update(
+                cfg.bucket,
+                vec![orphan::remove(ino), orphan::remove_holders(ino)],
+            )
+            .await?;
+
+            tx.commit().await?;
+            return Ok(Removal::Kept);
+        }
+    };
+
+    match stale_holds {
+        StaleHolds::Dead if !holders.is_empty() => {
+            let mut dead = flock::dead_views(cfg, &mut tx, holders.iter().copied()).await?;
+            if holders.contains(&cfg.view) {
+                dead.insert(cfg.view);
+            }
+
+            if !dead.is_empty() {
+                tracing::debug!(?dead, "dropping stale holds");
+                let unholds = dead.iter().map(|view| orphan::unhold(ino, *view));
+                tx.update(cfg.bucket, unholds).await?;
+                holders.retain(|view| !dead.contains(view));
+            }
+        }
+        StaleHolds::All if !holders.is_empty() => {
+            tracing::debug!(?holders, "dropping stale holds");
+            tx.update(cfg.bucket, vec![orphan::remove_holders(ino)])
+                .await?;
+            holders.clear();
+        }
+        _ => {}
+    }
+
+    let unlinked = (inode.kind == inode::Kind::Directory && inode.nlink <= 1) || inode.nlink == 0;
+    let must_be_removed = unlinked && holders.is_empty();
+
+    if !unlinked {
+        tx.update(cfg.bucket, vec![orphan::remove(ino)]).await?;
+    }
+
+    if unlinked && !must_be_removed {
+        tracing::debug!(?holders, "still open, orphaned");
+        tx.update(cfg.bucket, vec![orphan::add(ino)]).await?;
+    }
+
+    let chunk = DELETE_CHUNK_PAGES * cfg.page_size;
+    if must_be_removed && inode.kind == inode::Kind::Regular && inode.size > chunk {
+        let start = (inode.size - 1) / chunk * chunk;
+        tracing::debug!(size = inode.size, start, "removing the last pages");
+        pages.remove_range(&mut tx, ino, start..inode.size).await?;
+
+        let mut inode = inode;
+        inode.size = start;
+        tx.update(
+            cfg.bucket,
+            vec![inode::update_stats_and_size(&inode), orphan::add(ino)],
+        )
+        .await?;
+
+        tx.commit().await?;
+        return Ok(Removal::Shrunk);
+    }
+
+    if must_be_removed {
+        let mut updates = vec![
+            inode::remove(ino),
+            inode::next_generation(ino),
+            symlink::remove(ino),
+            orphan::remove(ino),
+            orphan::remove_holders(ino),
+        ];
+        if inode.kind == inode::Kind::Directory {
+            updates.extend(dir::remove(ino));
+        }
+        tx.update(cfg.bucket, updates).await?;
+
+        if inode.kind == inode::Kind::Regular {
+            /* At this point we should be (locally) the only one
+            seeing this file, don't bother locking up the pages */
+            pages.remove_range(&mut tx, ino, 0..inode.size).await?;
+        }
+    }
+
+    tx.commit().await?;
+    if must_be_removed {
+        Ok(Removal::Removed)
+    } else {
+        Ok(Removal::Kept)
+    }
+}
+
+#[tracing::instrument(skip(cfg, pool, handles))]
+pub(crate) async fn collect_orphans(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    pages: &PageWriter,
+    handles: &HandleTable,
+) -> Result<usize> {
+    let orphans = read_orphans(cfg, pool).await?;
+
+    let mut collected = 0;
+    for ino in orphans {
+        if handles.is_open(ino).await {
+            continue;
+        }
+
+        if remove_if_unlinked(cfg, pool, pages, ino, StaleHolds::Dead).await? {
+            collected += 1;
+        }
+    }
+
+    Ok(collected)
+}
+
+/* When no view mounts the filesystem, every hold is left over by a view
+that didn't close its files and every unlinked orphan can go. */
+#[tracing::instrument(skip(cfg, pool))]
+pub(crate) async fn collect_orphans_offline(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    pages: &PageWriter,
+) -> Result<usize> {
+    let orphans = read_orphans(cfg, pool).await?;
+
+    let mut collected = 0;
+    for ino in orphans {
+        if remove_if_unlinked(cfg, pool, pages, ino, StaleHolds::All).await? {
+            collected += 1;
+        }
+    }
+
+    Ok(collected)
+}
+
+async fn read_orphans(cfg: &Config, pool: &ConnectionPool) -> Result<Vec<u64>> {
+    let mut connection = pool.acquire().await?;
+    let mut tx = transaction!(cfg, connection, { shared: [orphan::key()] }).await?;
+
+    let mut reply = tx.read(cfg.bucket, vec![orphan::read()]).await?;
+    let orphans = orphan::decode(&mut reply, 0);
+
+    tx.commit().await?;
+    Ok(orphans)
+}
+
+pub(crate) async fn run(
+    cfg: Config,
+    pool: Arc<ConnectionPool>,
+    pages: PageWriter,
+    handles: Arc<HandleTable>,
+    tasks: Arc<Tasks>,
+) {
+    loop {
+        runtime::sleep(ORPHAN_GC_INTERVAL).await;
+        let _work = match tasks.enter() {
+            Some(work) => work,
+            None => return,
+        };
+
+        match collect_orphans(&cfg, &pool, &pages, &handles).await {
+            Ok(collected) => tracing::debug!(collected, "orphans collected"),
+            Err(error) => tracing::error!(?error, "failed to collect orphans"),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use crate::driver::runtime::Mutex;
+use crate::driver::DirBatch;
+use nix::fcntl::OFlag;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub struct Handle {
+    pub ino: u64,
+    pub flags: OFlag,
+    /* The last shards of the directory read by readdir, so that following
+    calls within them see the very same listing. */
+    pub dir_snapshot: Option<Arc<DirBatch>>,
+}
+
+impl Handle {
+    pub fn append(&self) -> bool {
+        self.flags.contains(OFlag::O_APPEND)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Handles {
+    by_fh: HashMap<u64, Handle>,
+    open_count: HashMap<u64, usize>,
+}
+
+#[derive(Debug)]
+pub struct HandleTable {
+    next_fh: AtomicU64,
+    handles: Mutex<Handles>,
+}
+
+impl HandleTable {
+    pub fn new() -> Self {
+        Self {
+            next_fh: AtomicU64::new(1),
+            handles: Mutex::new(Handles::default()),
+        }
+    }
+
+    /* Returns the new handle and whether it is the first one opened on
+    this inode by this mount. */
+    pub async fn open(&self, ino: u64, flags: u32) -> (u64, bool) {
+        let fh = self.next_fh.fetch_add(1, Ordering::Relaxed);
+        let handle = Handle {
+            ino,
+            flags: OFlag::from_bits_truncate(flags as i32),
+            dir_snapshot: None,
+        };
+
+        let mut handles = self.handles.lock().await;
+        handles.by_fh.insert(fh, handle);
+
+        let count = handles.open_count.entry(ino).or_insert(0);
+        *count += 1;
+
+        (fh, *count == 1)
+    }
+
+    pub async fn is_open(&self, ino: u64) -> bool {
+        self.handles.lock().await.open_count.contains_key(&ino)
+    }
+
+    pub async fn get(&self, fh: u64) -> Option<Handle> {
+        self.handles.lock().await.by_fh.get(&fh).cloned()
+    }
+
+    pub async fn set_dir_snapshot(&self, fh: u64, snapshot: Arc<DirBatch>) {
+        if let Some(handle) = self.handles.lock().await.by_fh.get_mut(&fh) {
+            handle.dir_snapshot = Some(snapshot);
+        }
+    }
+
+    /* Returns the released handle and whether it was the last one opened
+    on its inode by this mount, a handle opened on another inode is left
+    as is. */
+    pub async fn release(&self, ino: u64, fh: u64) -> Option<(Handle, bool)> {
+        let mut handles = self.handles.lock().await;
+        if handles.by_fh.get(&fh)?.ino != ino {
+            return None;
+        }
+        let handle = handles.by_fh.remove(&fh)?;
+
+        let count = handles.open_count.get_mut(&handle.ino).unwrap();
+        *count -= 1;
+
+        let last = *count == 0;
+        if last {
+            handles.open_count.remove(&handle.ino);
+        }
+
+        Some((handle, last))
+    }
+}
@@ -0,0 +1,118 @@
+use crate::model::inode::Owner;
+use fuser::FileAttr;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+pub const DEFAULT_ANONYMOUS_OWNER: Owner = Owner {
+    uid: 65534,
+    gid: 65534,
+};
+
+const ROOT_UID: u32 = 0;
+const ROOT_GID: u32 = 0;
+
+/// Which callers are handled as the anonymous owner, as the NFS export
+/// options of the same name.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Squash {
+    None,
+    Root,
+    All,
+}
+
+impl Default for Squash {
+    fn default() -> Self {
+        Squash::None
+    }
+}
+
+impl FromStr for Squash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Squash::None),
+            "root" => Ok(Squash::Root),
+            "all" => Ok(Squash::All),
+            _ => Err(format!("unknown squash policy: {}", s)),
+        }
+    }
+}
+
+/// Translates the users and groups of a mount into the ones stored in the
+/// bucket, for machines that don't share the same ids. Ids without mapping
+/// are stored as they are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdMap {
+    uids: HashMap<u32, u32>,
+    gids: HashMap<u32, u32>,
+    local_uids: HashMap<u32, u32>,
+    local_gids: HashMap<u32, u32>,
+    squash: Squash,
+    anonymous: Owner,
+}
+
+impl IdMap {
+    pub fn identity() -> Self {
+        Self::new(Squash::None, DEFAULT_ANONYMOUS_OWNER)
+    }
+
+    pub fn new(squash: Squash, anonymous: Owner) -> Self {
+        Self {
+            uids: HashMap::new(),
+            gids: HashMap::new(),
+            local_uids: HashMap::new(),
+            local_gids: HashMap::new(),
+            squash,
+            anonymous,
+        }
+    }
+
+    pub fn map_uid(&mut self, local: u32, stored: u32) {
+        self.uids.insert(local, stored);
+        self.local_uids.insert(stored, local);
+    }
+
+    pub fn map_gid(&mut self, local: u32, stored: u32) {
+        self.gids.insert(local, stored);
+        self.local_gids.insert(stored, local);
+    }
+
+    /// The identity a caller acts as, both when checking its permissions
+    /// and when owning what it creates.
+    pub fn caller(&self, caller: Owner) -> Owner {
+        Owner {
+            uid: self.stored_uid(caller.uid),
+            gid: self.stored_gid(caller.gid),
+        }
+    }
+
+    pub fn stored_uid(&self, uid: u32) -> u32 {
+        match self.squash {
+            Squash::All => self.anonymous.uid,
+            Squash::Root if uid == ROOT_UID => self.anonymous.uid,
+            _ => *self.uids.get(&uid).unwrap_or(&uid),
+        }
+    }
+
+    pub fn stored_gid(&self, gid: u32) -> u32 {
+        match self.squash {
+            Squash::All => self.anonymous.gid,
+            Squash::Root if gid == ROOT_GID => self.anonymous.gid,
+            _ => *self.gids.get(&gid).unwrap_or(&gid),
+        }
+    }
+
+    /// Attributes as reported to the mount.
+    pub fn local_attr(&self, mut attr: FileAttr) -> FileAttr {
+        attr.uid = *self.local_uids.get(&attr.uid).unwrap_or(&attr.uid);
+        attr.gid = *self.local_gids.get(&attr.gid).unwrap_or(&attr.gid);
+        attr
+    }
+}
+
+impl Default for IdMap {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
@@ -1,44 +1,95 @@
+use crate::driver::runtime::{Mutex, MutexGuard};
 use crate::key::{Bucket, KeyWriter, Ty};
 use crate::view::View;
 use antidotec::{counter, Error, RawIdent, Transaction};
 use std::mem;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::ops::Range;
 
+/// How many inode numbers a view leases at once.
+pub const LEASE_SIZE: u32 = 1024;
+
+/* The upper bits of an inode hold the view that allocated it, views then
+allocate from disjoint spaces. Counters are 32 bits and never hand out 0 nor
+1, the root being inode 1 of view 0. */
+const VIEW_SHIFT: u32 = 48;
+
+/* The counter of a view is only ever decremented by the view itself, by a
+whole lease at once. Numbers of a lease are handed out from memory, those
+left when the view stops are never used. */
 #[derive(Debug)]
 pub struct InoGenerator {
-    bucket: Bucket,
     view: View,
-    counter: AtomicU64,
+    lease: Mutex<Range<u64>>,
 }
 
 impl InoGenerator {
     pub async fn load(tx: &mut Transaction<'_>, view: View, bucket: Bucket) -> Result<Self, Error> {
-        let next_ino = Self::stored_ino(tx, view, bucket).await?;
+        let lease = Self::lease(tx, view, bucket).await?;
 
         Ok(Self {
             view,
-            bucket,
-            counter: AtomicU64::new(next_ino),
+            lease: Mutex::new(lease),
         })
     }
 
-    pub fn next(&self) -> u64 {
-        let next_ino = self.counter.fetch_sub(1, Ordering::Relaxed);
-        assert!(next_ino > 1 && next_ino < (1 << 48));
+    /// A generator for mounts that never create anything, e.g snapshots,
+    /// it has no lease to allocate from.
+    pub fn read_only(view: View) -> Self {
+        Self {
+            view,
+            lease: Mutex::new(0..0),
+        }
+    }
 
-        (next_ino << 16) | self.view as u64
+    /// The numbers left in the current lease, locked so that a single
+    /// caller renews it once exhausted.
+    pub async fn current(&self) -> MutexGuard<'_, Range<u64>> {
+        self.lease.lock().await
     }
 
-    pub async fn checkpoint(&self, tx: &mut Transaction<'_>) -> Result<(), Error> {
-        let key = key(self.view);
+    /// Leases the next block of numbers of the view, an empty one once the
+    /// view used them all. It must be committed before being handed out.
+    pub async fn lease(
+        tx: &mut Transaction<'_>,
+        view: View,
+        bucket: Bucket,
+    ) -> Result<Range<u64>, Error> {
+        let stored = Self::stored_ino(tx, view, bucket).await?;
+
+        /* Numbers 0 and 1 are never allocated, they would collide with the
+        root. */
+        let available = stored.saturating_sub(1).min(LEASE_SIZE as u64);
+        if available == 0 {
+            return Ok(0..0);
+        }
 
-        let stored = Self::stored_ino(tx, self.view, self.bucket).await?;
-        let current = self.counter.load(Ordering::Relaxed);
+        tx.update(bucket, vec![counter::inc(key(view), -(available as i32))])
+            .await?;
 
-        let inc = -(stored.checked_sub(current).unwrap() as i32);
-        tx.update(self.bucket, vec![counter::inc(key, inc)]).await?;
+        Ok((stored - available + 1)..(stored + 1))
+    }
 
-        Ok(())
+    /// The inode allocated from a number of a lease.
+    pub fn ino(&self, next_ino: u64) -> u64 {
+        encode(self.view, next_ino)
+    }
+
+    /// Next inode number the view would lease and how many are left,
+    /// without initializing the counter of a view that never allocated any.
+    pub async fn peek(
+        tx: &mut Transaction<'_>,
+        view: View,
+        bucket: Bucket,
+    ) -> Result<Option<(u64, u64)>, Error> {
+        let mut reply = tx.read(bucket, vec![counter::get(key(view))]).await?;
+
+        let offset = i32::max_value() as u32;
+        let next_ino = match reply.counter(0) {
+            0 => return Ok(None),
+            x => (x as u32 + offset) as u64,
+        };
+
+        Ok(Some((encode(view, next_ino), next_ino - 1)))
     }
 
     async fn stored_ino(
@@ -67,6 +118,12 @@ impl InoGenerator {
     }
 }
 
+fn encode(view: View, next_ino: u64) -> u64 {
+    assert!(next_ino > 1 && next_ino < (1 << 32));
+
+    ((view as u64) << VIEW_SHIFT) | next_ino
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Key(View);
 
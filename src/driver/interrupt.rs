@@ -0,0 +1,95 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+}
+
+/* Operations in flight, by the unique id of their request. An interrupted
+operation fails with EINTR at its next request to Antidote, its transaction
+being aborted. Its future is never dropped halfway, which would leave the
+connection it goes through out of sync. */
+#[derive(Debug, Default)]
+pub(crate) struct Interrupts {
+    flags: Mutex<BTreeMap<u64, Arc<AtomicBool>>>,
+}
+
+impl Interrupts {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `op` as the request `unique`, interruptible until done.
+    pub(crate) async fn run<F: Future>(&self, unique: u64, op: F) -> F::Output {
+        let flag = Arc::new(AtomicBool::new(false));
+        self.flags.lock().unwrap().insert(unique, flag.clone());
+        let _registered = Registered {
+            interrupts: self,
+            unique,
+        };
+
+        Scoped {
+            flag,
+            op: Box::pin(op),
+        }
+        .await
+    }
+
+    /// Whether `unique` was in flight.
+    pub(crate) fn interrupt(&self, unique: u64) -> bool {
+        match self.flags.lock().unwrap().get(&unique) {
+            Some(flag) => {
+                flag.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The requests in flight, one per line.
+    pub(crate) fn render(&self) -> String {
+        let flags = self.flags.lock().unwrap();
+        flags.keys().map(|unique| format!("{}\n", unique)).collect()
+    }
+}
+
+struct Registered<'a> {
+    interrupts: &'a Interrupts,
+    unique: u64,
+}
+
+impl Drop for Registered<'_> {
+    fn drop(&mut self) {
+        self.interrupts.flags.lock().unwrap().remove(&self.unique);
+    }
+}
+
+/* The flag of an operation is current while it is polled, for the
+connections it acquires to check it. Tasks it spawns are polled apart and
+run to completion. */
+struct Scoped<F> {
+    flag: Arc<AtomicBool>,
+    op: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for Scoped<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = self.get_mut();
+        let outer = CURRENT.with(|current| current.replace(Some(this.flag.clone())));
+        let poll = this.op.as_mut().poll(cx);
+        CURRENT.with(|current| *current.borrow_mut() = outer);
+        poll
+    }
+}
+
+/// The flag of the operation polled on this thread, if any.
+pub(crate) fn current() -> Option<Arc<AtomicBool>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
@@ -0,0 +1,150 @@
+use crate::driver::gc;
+use crate::driver::metrics::Metrics;
+use crate::driver::page::PageWriter;
+use crate::driver::pool::ConnectionPool;
+use crate::driver::runtime;
+use crate::driver::tasks::Tasks;
+use crate::driver::{Config, Result};
+use async_std::sync::Arc;
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const MAX_QUEUED_JOBS: usize = 4096;
+const MAX_WORKERS: usize = 4;
+const JOB_RETRIES: u32 = 5;
+const JOB_BACKOFF_MIN: Duration = Duration::from_millis(50);
+const JOB_BACKOFF_MAX: Duration = Duration::from_secs(5);
+
+/// Work left behind by an operation, done once it replied.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Job {
+    /// Removes an inode once it is unlinked and released.
+    Delete(u64),
+}
+
+#[derive(Debug, Default)]
+struct State {
+    queue: VecDeque<Job>,
+    queued: HashSet<Job>,
+    running: HashSet<Job>,
+    workers: usize,
+}
+
+/* Jobs read what they need when they run, so a job submitted while an
+identical one is still queued is covered by it. The same job never runs
+twice at once, two deletions of an inode would otherwise race. */
+#[derive(Debug)]
+pub(crate) struct Jobs {
+    cfg: Config,
+    pool: Arc<ConnectionPool>,
+    pages: PageWriter,
+    metrics: Arc<Metrics>,
+    tasks: Arc<Tasks>,
+    state: Mutex<State>,
+}
+
+impl Jobs {
+    pub(crate) fn new(
+        cfg: Config,
+        pool: Arc<ConnectionPool>,
+        pages: PageWriter,
+        metrics: Arc<Metrics>,
+        tasks: Arc<Tasks>,
+    ) -> Self {
+        Self {
+            cfg,
+            pool,
+            pages,
+            metrics,
+            tasks,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /* Once the queue is full, deletions are left to the orphan collection. */
+    pub(crate) fn submit(self: &Arc<Self>, job: Job) {
+        let mut state = self.state.lock().unwrap();
+
+        if state.queued.contains(&job) {
+            return;
+        }
+        if state.queue.len() >= MAX_QUEUED_JOBS {
+            tracing::warn!(?job, "background job queue full, job dropped");
+            self.metrics.job_dropped();
+            return;
+        }
+
+        state.queue.push_back(job);
+        state.queued.insert(job);
+
+        if state.workers < MAX_WORKERS {
+            state.workers += 1;
+            self.tasks.spawn(self.clone().work());
+        }
+    }
+
+    async fn work(self: Arc<Self>) {
+        while let Some(job) = self.next() {
+            if let Err(error) = self.run_with_retry(job).await {
+                tracing::error!(?job, ?error, "background job failed");
+                self.metrics.job_failed();
+            }
+
+            self.done(job);
+        }
+    }
+
+    /* Jobs that are already running are left in the queue for later. */
+    fn next(&self) -> Option<Job> {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+
+        let running = &state.running;
+        let position = state.queue.iter().position(|job| !running.contains(job));
+        let job = match position.and_then(|position| state.queue.remove(position)) {
+            Some(job) => job,
+            None => {
+                state.workers -= 1;
+                return None;
+            }
+        };
+
+        state.queued.remove(&job);
+        state.running.insert(job);
+        Some(job)
+    }
+
+    fn done(&self, job: Job) {
+        self.state.lock().unwrap().running.remove(&job);
+    }
+
+    /* Errors returned by the filesystem itself would only be returned
+    again, only those talking to Antidote are retried. */
+    async fn run_with_retry(&self, job: Job) -> Result<()> {
+        let mut backoff = JOB_BACKOFF_MIN;
+        let mut attempt = 0;
+        loop {
+            match self.run(job).await {
+                Err(error) if error.is_antidote() && attempt < JOB_RETRIES => {
+                    let wait = super::jitter(backoff);
+                    tracing::warn!(?job, ?error, attempt, ?wait, "retrying background job");
+                    runtime::sleep(wait).await;
+
+                    backoff = (backoff * 2).min(JOB_BACKOFF_MAX);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn run(&self, job: Job) -> Result<()> {
+        match job {
+            Job::Delete(ino) => {
+                gc::delete_later(&self.cfg, &self.pool, &self.pages, ino).await?;
+                Ok(())
+            }
+        }
+    }
+}
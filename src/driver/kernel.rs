@@ -0,0 +1,138 @@
+use fuser::{Notifier, PollHandle};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/* Past that many, what the kernel cached is only dropped once its ttl runs
+out. */
+const MAX_REPLIED: usize = 64 * 1024;
+
+#[derive(Debug, Default)]
+struct State {
+    entries: HashMap<(u64, OsString), Instant>,
+    inodes: HashMap<u64, Instant>,
+}
+
+impl State {
+    fn len(&self) -> usize {
+        self.entries.len() + self.inodes.len()
+    }
+}
+
+/* Names and attributes replied to the kernel stay in its caches until their
+ttl runs out, whatever this view learns in the meantime. Those replied for
+less than a ttl are kept, by inode as the kernel numbers them, so that the
+kernel can be told to drop them once another view changed something.
+Polls waiting on files are woken up then too, by file handle. */
+#[derive(Debug)]
+pub(crate) struct KernelCache {
+    ttl: Duration,
+    notifier: Mutex<Option<Notifier>>,
+    state: Mutex<State>,
+    polls: Mutex<HashMap<u64, PollHandle>>,
+}
+
+impl KernelCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            notifier: Mutex::new(None),
+            state: Mutex::new(State::default()),
+            polls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Once mounted, the session the kernel is notified through.
+    pub(crate) fn set_notifier(&self, notifier: Notifier) {
+        *self.notifier.lock().unwrap() = Some(notifier);
+    }
+
+    pub(crate) fn entry(&self, parent_ino: u64, name: &OsStr, ino: u64) {
+        if self.ttl == Duration::from_secs(0) {
+            return;
+        }
+
+        let mut state = self.prune();
+        let now = Instant::now();
+        state.entries.insert((parent_ino, name.to_os_string()), now);
+        state.inodes.insert(ino, now);
+    }
+
+    pub(crate) fn attr(&self, ino: u64) {
+        if self.ttl == Duration::from_secs(0) {
+            return;
+        }
+
+        let mut state = self.prune();
+        state.inodes.insert(ino, Instant::now());
+    }
+
+    /// A poll on `fh` to wake up on the next change of another view.
+    pub(crate) fn poll(&self, fh: u64, ph: PollHandle) {
+        self.polls.lock().unwrap().insert(fh, ph);
+    }
+
+    pub(crate) fn forget_poll(&self, fh: u64) {
+        self.polls.lock().unwrap().remove(&fh);
+    }
+
+    fn prune(&self) -> MutexGuard<'_, State> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.len() >= MAX_REPLIED {
+            let ttl = self.ttl;
+            state.entries.retain(|_, at| at.elapsed() < ttl);
+            state.inodes.retain(|_, at| at.elapsed() < ttl);
+        }
+        if state.len() >= MAX_REPLIED {
+            *state = State::default();
+        }
+        state
+    }
+
+    /* The kernel takes the lock of a directory to drop one of its names,
+    which a request in flight may hold until it is replied to: notifications
+    are sent from a thread of their own, not to hold up the runtime. Names
+    and inodes the kernel already dropped are answered with ENOENT. */
+    pub(crate) fn invalidate(&self) {
+        let notifier = match &*self.notifier.lock().unwrap() {
+            Some(notifier) => notifier.clone(),
+            None => return,
+        };
+        let state = std::mem::take(&mut *self.state.lock().unwrap());
+        let polls = std::mem::take(&mut *self.polls.lock().unwrap());
+        if state.len() == 0 && polls.is_empty() {
+            return;
+        }
+
+        let ttl = self.ttl;
+        let spawned = thread::Builder::new()
+            .name(String::from("invalidate"))
+            .spawn(move || {
+                for ((parent_ino, name), at) in state.entries {
+                    if at.elapsed() < ttl {
+                        if let Err(error) = notifier.inval_entry(parent_ino, &name) {
+                            tracing::trace!(parent_ino, ?name, ?error, "entry not invalidated");
+                        }
+                    }
+                }
+                for (ino, at) in state.inodes {
+                    if at.elapsed() < ttl {
+                        if let Err(error) = notifier.inval_inode(ino, -1, 0) {
+                            tracing::trace!(ino, ?error, "attributes not invalidated");
+                        }
+                    }
+                }
+                for (fh, ph) in polls {
+                    if let Err(error) = ph.notify() {
+                        tracing::trace!(fh, ?error, "poll not woken up");
+                    }
+                }
+            });
+        if let Err(error) = spawned {
+            tracing::warn!(?error, "failed to invalidate the kernel caches");
+        }
+    }
+}
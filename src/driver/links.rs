@@ -0,0 +1,35 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const MAX_LINKS: usize = 16 * 1024;
+
+/* The target of a symlink never changes once created, and inode numbers are
+never handed out twice, so targets are kept until the caches are dropped.
+Once full, the cache starts over rather than tracking what was used last. */
+#[derive(Debug, Default)]
+pub(crate) struct LinkCache {
+    targets: Mutex<HashMap<u64, String>>,
+}
+
+impl LinkCache {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn get(&self, ino: u64) -> Option<String> {
+        self.targets.lock().unwrap().get(&ino).cloned()
+    }
+
+    pub(crate) fn insert(&self, ino: u64, target: String) {
+        let mut targets = self.targets.lock().unwrap();
+        if targets.len() >= MAX_LINKS {
+            targets.clear();
+        }
+
+        targets.insert(ino, target);
+    }
+
+    pub(crate) fn clear(&self) {
+        self.targets.lock().unwrap().clear();
+    }
+}
@@ -1,4 +1,4 @@
-use async_std::sync::{Condvar, Mutex};
+use crate::driver::runtime::{Condvar, Mutex};
 use std::collections::HashMap;
 use std::ops::Range;
 use std::sync::Arc;
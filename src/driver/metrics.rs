@@ -0,0 +1,279 @@
+use crate::driver::pool::ConnectionPool;
+use crate::driver::{budget, Error, Result};
+use async_std::io::prelude::*;
+use async_std::net::{TcpListener, TcpStream};
+use async_std::sync::Arc;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/* Upper bounds of the latency buckets, in seconds. */
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0,
+];
+
+#[derive(Debug, Default)]
+struct OpStats {
+    count: u64,
+    errors: u64,
+    aborts: u64,
+    round_trips: u64,
+    latency_sum: Duration,
+    latency_buckets: [u64; LATENCY_BUCKETS.len()],
+}
+
+/// Counters of a mount, rendered in the Prometheus text format.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    ops: Mutex<BTreeMap<&'static str, OpStats>>,
+    retries: AtomicU64,
+    jobs_failed: AtomicU64,
+    jobs_dropped: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn retried(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn job_failed(&self) {
+        self.jobs_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn job_dropped(&self) {
+        self.jobs_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn read(&self, len: u64) {
+        self.bytes_read.fetch_add(len, Ordering::Relaxed);
+    }
+
+    pub(crate) fn written(&self, len: u64) {
+        self.bytes_written.fetch_add(len, Ordering::Relaxed);
+    }
+
+    fn record<T>(&self, op: &'static str, elapsed: Duration, result: &Result<T>) {
+        let mut ops = self.ops.lock().unwrap();
+        let stats = ops.entry(op).or_default();
+
+        stats.count += 1;
+        stats.round_trips += budget::used();
+        stats.latency_sum += elapsed;
+
+        let seconds = elapsed.as_secs_f64();
+        for (count, bound) in stats.latency_buckets.iter_mut().zip(&LATENCY_BUCKETS) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+
+        match result {
+            Ok(_) => {}
+            Err(Error::Conflict) => {
+                stats.errors += 1;
+                stats.aborts += 1;
+            }
+            Err(_) => stats.errors += 1,
+        }
+    }
+
+    pub(crate) fn render(&self, pool: &ConnectionPool, maintenance: &ConnectionPool) -> String {
+        let mut out = String::new();
+        let ops = self.ops.lock().unwrap();
+
+        /* Writing into a String can't fail. */
+        let _ = writeln!(out, "# TYPE elmerfs_op_duration_seconds histogram");
+        for (op, stats) in ops.iter() {
+            for (count, bound) in stats.latency_buckets.iter().zip(&LATENCY_BUCKETS) {
+                let _ = writeln!(
+                    out,
+                    "elmerfs_op_duration_seconds_bucket{{op=\"{}\",le=\"{}\"}} {}",
+                    op, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "elmerfs_op_duration_seconds_bucket{{op=\"{}\",le=\"+Inf\"}} {}",
+                op, stats.count
+            );
+            let _ = writeln!(
+                out,
+                "elmerfs_op_duration_seconds_sum{{op=\"{}\"}} {}",
+                op,
+                stats.latency_sum.as_secs_f64()
+            );
+            let _ = writeln!(
+                out,
+                "elmerfs_op_duration_seconds_count{{op=\"{}\"}} {}",
+                op, stats.count
+            );
+        }
+
+        let per_op: [(&str, fn(&OpStats) -> u64); 3] = [
+            ("elmerfs_op_errors_total", |stats| stats.errors),
+            ("elmerfs_op_aborts_total", |stats| stats.aborts),
+            ("elmerfs_op_round_trips_total", |stats| stats.round_trips),
+        ];
+        for (name, value) in per_op.iter() {
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            for (op, stats) in ops.iter() {
+                let _ = writeln!(out, "{}{{op=\"{}\"}} {}", name, op, value(stats));
+            }
+        }
+
+        let counters = [
+            ("elmerfs_transaction_retries_total", &self.retries),
+            ("elmerfs_failed_jobs_total", &self.jobs_failed),
+            ("elmerfs_dropped_jobs_total", &self.jobs_dropped),
+            ("elmerfs_read_bytes_total", &self.bytes_read),
+            ("elmerfs_written_bytes_total", &self.bytes_written),
+        ];
+        for (name, counter) in counters.iter() {
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed));
+        }
+
+        /* Background work has connections of its own, each pool is labeled. */
+        let pools = [("foreground", pool), ("maintenance", maintenance)];
+
+        let _ = writeln!(out, "# TYPE elmerfs_pool_connections gauge");
+        for (name, pool) in pools.iter() {
+            let (idle, in_use) = pool.usage();
+            let _ = writeln!(
+                out,
+                "elmerfs_pool_connections{{pool=\"{}\",state=\"idle\"}} {}",
+                name, idle
+            );
+            let _ = writeln!(
+                out,
+                "elmerfs_pool_connections{{pool=\"{}\",state=\"in_use\"}} {}",
+                name, in_use
+            );
+        }
+
+        let _ = writeln!(out, "# TYPE elmerfs_pool_waiting gauge");
+        for (name, pool) in pools.iter() {
+            let _ = writeln!(
+                out,
+                "elmerfs_pool_waiting{{pool=\"{}\"}} {}",
+                name,
+                pool.waiting()
+            );
+        }
+
+        let _ = writeln!(out, "# TYPE elmerfs_pool_wait_seconds summary");
+        for (name, pool) in pools.iter() {
+            let waits = pool.wait_stats();
+            let _ = writeln!(
+                out,
+                "elmerfs_pool_wait_seconds_sum{{pool=\"{}\"}} {}",
+                name,
+                waits.wait_time.as_secs_f64()
+            );
+            let _ = writeln!(
+                out,
+                "elmerfs_pool_wait_seconds_count{{pool=\"{}\"}} {}",
+                name, waits.waits
+            );
+        }
+
+        let _ = writeln!(out, "# TYPE elmerfs_pool_timeouts_total counter");
+        for (name, pool) in pools.iter() {
+            let timeouts = pool.wait_stats().timeouts;
+            let _ = writeln!(
+                out,
+                "elmerfs_pool_timeouts_total{{pool=\"{}\"}} {}",
+                name, timeouts
+            );
+        }
+
+        let _ = writeln!(out, "# TYPE elmerfs_pool_rejected_total counter");
+        for (name, pool) in pools.iter() {
+            let rejected = pool.wait_stats().rejected;
+            let _ = writeln!(
+                out,
+                "elmerfs_pool_rejected_total{{pool=\"{}\"}} {}",
+                name, rejected
+            );
+        }
+
+        out
+    }
+}
+
+pub(crate) async fn track<T>(
+    metrics: &Metrics,
+    op: &'static str,
+    f: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let start = Instant::now();
+    let result = f.await;
+
+    metrics.record(op, start.elapsed(), &result);
+    result
+}
+
+/* A bare HTTP server answering every request with the metrics, which is
+all a Prometheus scraper needs. */
+#[tracing::instrument(skip(metrics, pool, maintenance))]
+pub(crate) async fn serve(
+    address: String,
+    metrics: Arc<Metrics>,
+    pool: Arc<ConnectionPool>,
+    maintenance: Arc<ConnectionPool>,
+) {
+    let listener = match TcpListener::bind(&address).await {
+        Ok(listener) => listener,
+        Err(error) => {
+            tracing::error!(?error, "failed to bind the metrics endpoint");
+            return;
+        }
+    };
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(error) => {
+                tracing::warn!(?error, "failed to accept a metrics scraper");
+                continue;
+            }
+        };
+
+        if let Err(error) = respond(stream, &metrics, &pool, &maintenance).await {
+            tracing::debug!(?error, "failed to answer a metrics scraper");
+        }
+    }
+}
+
+async fn respond(
+    mut stream: TcpStream,
+    metrics: &Metrics,
+    pool: &ConnectionPool,
+    maintenance: &ConnectionPool,
+) -> std::io::Result<()> {
+    /* The request itself is ignored, only wait for its first bytes. */
+    let mut request = [0; 1024];
+    let _ = stream.read(&mut request).await?;
+
+    let body = metrics.render(pool, maintenance);
+    let header = format!(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        body.len()
+    );
+
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use crate::driver::pool::ConnectionPool;
+use crate::driver::{Config, Error, Result, TxClass, ROOT_INO};
+use crate::model::dir;
+use crate::model::inode::Kind;
+use antidotec::TransactionLocks;
+use std::collections::HashSet;
+
+/* Directories were stored as a single set under the key of the directory,
+before their entries were spread across shards. Each directory found from
+the root has its entries moved to their shard in a transaction of its own,
+so that a migration interrupted midway is taken up by the next mount.
+Directories already sharded are left as they are. Returns the number of
+entries moved. */
+pub(crate) async fn shard_dirs(cfg: &Config, pool: &ConnectionPool) -> Result<u64> {
+    let mut moved = 0;
+    let mut seen = HashSet::new();
+    let mut pending = vec![ROOT_INO];
+    while let Some(ino) = pending.pop() {
+        if !seen.insert(ino) {
+            continue;
+        }
+
+        let mut attempt = 0;
+        let (count, subdirs) = loop {
+            match shard_dir(cfg, pool, ino).await {
+                Err(Error::Conflict) if attempt < cfg.retries => attempt += 1,
+                result => break result?,
+            }
+        };
+        moved += count;
+        pending.extend(subdirs);
+    }
+
+    tracing::info!(moved, directories = seen.len(), "directories sharded");
+    Ok(moved)
+}
+
+/* Returns the number of entries moved and the subdirectories found. */
+async fn shard_dir(cfg: &Config, pool: &ConnectionPool, ino: u64) -> Result<(u64, Vec<u64>)> {
+    let mut connection = pool.acquire().await?;
+    let mut tx = transaction!(cfg, connection, TxClass::Namespace, {
+        exclusive: [dir::key(ino)]
+    })
+    .await?;
+
+    let reads = std::iter::once(dir::read_unsharded(ino)).chain(dir::read_all(ino));
+    let mut reply = tx.read(cfg.bucket, reads).await?;
+    let unsharded = dir::decode_unsharded(&mut reply, 0);
+    let sharded = dir::decode_all(cfg.view, &mut reply, 1);
+
+    let subdirs = unsharded
+        .iter()
+        .map(|entry| (&*entry.name.prefix, entry.ino, entry.kind))
+        .chain(
+            sharded
+                .iter()
+                .flat_map(|dir| dir.entries())
+                .map(|entry| (&*entry.prefix, entry.ino, entry.kind)),
+        )
+        .filter(|(name, _, kind)| *kind == Kind::Directory && *name != b"." && *name != b"..")
+        .map(|(_, ino, _)| ino)
+        .collect();
+
+    if !unsharded.is_empty() {
+        let updates = unsharded
+            .iter()
+            .map(|entry| dir::add_entry(ino, entry))
+            .chain(std::iter::once(dir::remove_unsharded(ino)));
+        tx.update(cfg.bucket, updates).await?;
+    }
+
+    tx.commit().await?;
+    Ok((unsharded.len() as u64, subdirs))
+}
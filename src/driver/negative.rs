@@ -0,0 +1,79 @@
+use crate::view::NameRef;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/* Misses are only trusted for a short while, names created by other views
+show up once it is over. */
+const NEGATIVE_TTL: Duration = Duration::from_secs(1);
+const MAX_NEGATIVE_ENTRIES: usize = 16 * 1024;
+
+#[derive(Debug, Default)]
+struct State {
+    misses: HashMap<u64, HashMap<NameRef, Instant>>,
+    len: usize,
+}
+
+/* Lookups known to fail, by parent directory. Local operations adding a
+name to a directory forget about it, so that a miss never hides a name
+created by this view. */
+#[derive(Debug, Default)]
+pub(crate) struct NegativeCache {
+    state: Mutex<State>,
+}
+
+impl NegativeCache {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn contains(&self, parent_ino: u64, name: &NameRef) -> bool {
+        let state = self.state.lock().unwrap();
+
+        match state
+            .misses
+            .get(&parent_ino)
+            .and_then(|names| names.get(name))
+        {
+            Some(at) => at.elapsed() < NEGATIVE_TTL,
+            None => false,
+        }
+    }
+
+    pub(crate) fn insert(&self, parent_ino: u64, name: NameRef) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+
+        if state.len >= MAX_NEGATIVE_ENTRIES {
+            state
+                .misses
+                .values_mut()
+                .for_each(|names| names.retain(|_, at| at.elapsed() < NEGATIVE_TTL));
+            state.misses.retain(|_, names| !names.is_empty());
+            state.len = state.misses.values().map(HashMap::len).sum();
+        }
+        if state.len >= MAX_NEGATIVE_ENTRIES {
+            state.misses.clear();
+            state.len = 0;
+        }
+
+        let names = state.misses.entry(parent_ino).or_default();
+        if names.insert(name, Instant::now()).is_none() {
+            state.len += 1;
+        }
+    }
+
+    pub(crate) fn forget(&self, parent_ino: u64) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(names) = state.misses.remove(&parent_ino) {
+            state.len -= names.len();
+        }
+    }
+    pub(crate) fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        state.misses.clear();
+        state.len = 0;
+    }
+}
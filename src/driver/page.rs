@@ -1,17 +1,343 @@
-use crate::driver::Result;
+use crate::driver::{Clock, Config, Error, Result};
 use crate::key::{Bucket, KeyWriter, Ty};
-use antidotec::{lwwreg, RawIdent, Transaction};
+use crate::model::extent;
+use crate::view::View;
+use antidotec::{lwwreg, mvreg, RawIdent, ReadQuery, ReadReply, Transaction, UpdateQuery};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use nix::errno::Errno;
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
 use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
-#[derive(Debug, Copy, Clone)]
+const ZSTD_LEVEL: i32 = 3;
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+const CHECKSUM_LEN: usize = 4;
+
+/* Pages start with a header telling their codec and their length once
+decoded, codecs wrapping each other. Pages stored before headers existed
+have none, a page is only taken as having one when it starts with the
+magic and decompresses back to the length it tells. Uncompressed content
+starting with the magic is stored behind a header too, it is never
+mistaken. */
+const MAGIC: [u8; 4] = *b"\xe1mfs";
+const HEADER_LEN: usize = MAGIC.len() + 1 + 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+enum Codec {
+    Raw = 0,
+    Lz4 = 1,
+    Zstd = 2,
+    /// Another page, compressed or not, encrypted along with where it is
+    /// stored.
+    Sealed = 3,
+    /// Another page, compressed or not, after its CRC32C.
+    Checked = 4,
+    /// Another page, stored as is, after the view writing it and when.
+    Stamped = 5,
+}
+
+impl Codec {
+    fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Codec::Raw),
+            1 => Some(Codec::Lz4),
+            2 => Some(Codec::Zstd),
+            3 => Some(Codec::Sealed),
+            4 => Some(Codec::Checked),
+            5 => Some(Codec::Stamped),
+            _ => None,
+        }
+    }
+}
+
+/// How the pages written by a mount are compressed. Pages are read back
+/// whatever they were written with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::None
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!("unknown compression: {}", s)),
+        }
+    }
+}
+
+/// What a page holds once several views wrote it concurrently, chosen when
+/// the bucket is created.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum PageConflicts {
+    /// The write antidote commits last wins, the others are lost.
+    #[default]
+    LastWriter,
+    /// Every concurrent write is kept until the page is written again, the
+    /// latest one by the clock of its view being read.
+    Versioned,
+}
+
+impl PageConflicts {
+    pub(crate) fn to_u64(self) -> u64 {
+        match self {
+            PageConflicts::LastWriter => 0,
+            PageConflicts::Versioned => 1,
+        }
+    }
+
+    pub(crate) fn from_u64(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(PageConflicts::LastWriter),
+            1 => Some(PageConflicts::Versioned),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for PageConflicts {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "last-writer" => Ok(PageConflicts::LastWriter),
+            "versioned" => Ok(PageConflicts::Versioned),
+            _ => Err(format!("unknown page conflict policy: {}", s)),
+        }
+    }
+}
+
+/// Who wrote a version of a page, and when by its own clock.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Stamp {
+    pub(crate) written: Duration,
+    pub(crate) view: View,
+}
+
+const STAMP_LEN: usize = mem::size_of::<View>() + 2 * mem::size_of::<u64>();
+
+impl Stamp {
+    fn to_bytes(self) -> [u8; STAMP_LEN] {
+        let mut bytes = [0u8; STAMP_LEN];
+        bytes[..2].copy_from_slice(&self.view.to_le_bytes());
+        bytes[2..10].copy_from_slice(&self.written.as_secs().to_le_bytes());
+        bytes[10..].copy_from_slice(&u64::from(self.written.subsec_nanos()).to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < STAMP_LEN {
+            return None;
+        }
+
+        let view = View::from_le_bytes(bytes[..2].try_into().unwrap());
+        let secs = u64::from_le_bytes(bytes[2..10].try_into().unwrap());
+        let nanos = u64::from_le_bytes(bytes[10..STAMP_LEN].try_into().unwrap());
+        Some(Self {
+            written: Duration::new(secs, nanos as u32),
+            view,
+        })
+    }
+}
+
+impl fmt::Display for Stamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "view {} at {}.{:09}",
+            self.view,
+            self.written.as_secs(),
+            self.written.subsec_nanos()
+        )
+    }
+}
+
+/// The key the pages written by a mount are encrypted with, using
+/// XChaCha20-Poly1305.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; KEY_LEN]);
+
+impl EncryptionKey {
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut key = [0u8; KEY_LEN];
+        if bytes.len() != KEY_LEN {
+            return None;
+        }
+
+        key.copy_from_slice(bytes);
+        Some(Self(key))
+    }
+
+    /// A key written as 64 hexadecimal digits.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.trim();
+        if hex.len() != 2 * KEY_LEN || !hex.is_ascii() {
+            return None;
+        }
+
+        let mut key = [0u8; KEY_LEN];
+        for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).ok()?;
+            *byte = u8::from_str_radix(digits, 16).ok()?;
+        }
+
+        Some(Self(key))
+    }
+
+    /// Reads a key file, holding either the 32 bytes of the key or their
+    /// hexadecimal form.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+
+        Self::from_bytes(&bytes)
+            .or_else(|| Self::from_hex(std::str::from_utf8(&bytes).ok()?))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid encryption key"))
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&self.0.into())
+    }
+}
+
+/* Never shows up in logs. */
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+#[derive(Debug, Clone)]
 pub(crate) struct PageWriter {
     bucket: Bucket,
     page_size: u64,
+    compression: Compression,
+    encryption_key: Option<EncryptionKey>,
+    conflicts: PageConflicts,
+    view: View,
+    clock: Arc<dyn Clock>,
 }
 
 impl PageWriter {
-    pub fn new(bucket: Bucket, page_size: u64) -> Self {
-        Self { bucket, page_size }
+    pub fn new(cfg: &Config) -> Self {
+        assert!(cfg.page_size.is_power_of_two());
+
+        Self {
+            bucket: cfg.bucket,
+            page_size: cfg.page_size,
+            compression: cfg.compression,
+            encryption_key: cfg.encryption_key,
+            conflicts: cfg.page_conflicts,
+            view: cfg.view,
+            clock: cfg.clock.clone(),
+        }
+    }
+
+    /* Versioned pages are multi-value registers, every write concurrent to
+    the others being kept. */
+    fn get(&self, key: Key) -> ReadQuery {
+        match self.conflicts {
+            PageConflicts::LastWriter => lwwreg::get(key),
+            PageConflicts::Versioned => mvreg::get(key),
+        }
+    }
+
+    fn set(&self, key: Key, stored: Vec<u8>) -> UpdateQuery {
+        match self.conflicts {
+            PageConflicts::LastWriter => lwwreg::set(key, stored),
+            PageConflicts::Versioned => mvreg::set(key, self.stamp(stored)),
+        }
+    }
+
+    /* Holes stay empty, they are never told apart. */
+    fn stamp(&self, stored: Vec<u8>) -> Vec<u8> {
+        if stored.is_empty() {
+            return stored;
+        }
+
+        let stamp = Stamp {
+            written: self.clock.now(),
+            view: self.view,
+        };
+        let stamped: Vec<u8> = stamp.to_bytes().iter().chain(&stored).copied().collect();
+        with_header(Codec::Stamped, stored.len(), &stamped)
+    }
+
+    /* The page stored at `index` of a reply as it was written, the latest
+    of its versions for a versioned page. */
+    fn stored(&self, reply: &mut ReadReply, index: usize) -> Vec<u8> {
+        match self.conflicts {
+            PageConflicts::LastWriter => reply.lwwreg(index).unwrap_or_default(),
+            PageConflicts::Versioned => {
+                let versions = reply.mvreg(index).unwrap_or_default();
+                versions
+                    .into_iter()
+                    .map(|version| unstamp(&version))
+                    .max_by_key(|(stamp, _)| *stamp)
+                    .map(|(_, stored)| stored)
+                    .unwrap_or_default()
+            }
+        }
+    }
+
+    /// Pages among the given ones holding several versions, written
+    /// concurrently, along with who wrote each of them, latest first.
+    pub async fn versions(
+        &self,
+        tx: &mut Transaction<'_>,
+        ino: u64,
+        pages: impl IntoIterator<Item = u64>,
+    ) -> Result<Vec<(u64, Vec<Stamp>)>> {
+        if self.conflicts != PageConflicts::Versioned {
+            return Ok(Vec::new());
+        }
+
+        let pages: Vec<u64> = pages.into_iter().collect();
+        if pages.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let reads = pages.iter().map(|page| mvreg::get(Key::new(ino, *page)));
+        let mut reply = tx.read(self.bucket, reads).await?;
+
+        let mut conflicting = Vec::new();
+        for (index, page) in pages.into_iter().enumerate() {
+            let versions = reply.mvreg(index).unwrap_or_default();
+            if versions.len() < 2 {
+                continue;
+            }
+
+            let mut stamps: Vec<Stamp> = versions
+                .iter()
+                .filter_map(|version| unstamp(version).0)
+                .collect();
+            stamps.sort_by(|a, b| b.cmp(a));
+            conflicting.push((page, stamps));
+        }
+
+        Ok(conflicting)
     }
 
     pub async fn write(
@@ -20,6 +346,18 @@ impl PageWriter {
         ino: u64,
         offset: u64,
         content: &[u8],
+    ) -> Result<()> {
+        self.write_with(tx, ino, offset, content, Vec::new()).await
+    }
+
+    /// As `write`, sending `updates` along with the first page.
+    pub async fn write_with(
+        &self,
+        tx: &mut Transaction<'_>,
+        ino: u64,
+        offset: u64,
+        content: &[u8],
+        updates: Vec<UpdateQuery>,
     ) -> Result<()> {
         let byte_range = offset..(offset + content.len() as u64);
 
@@ -31,7 +369,10 @@ impl PageWriter {
         let head_len = (self.page_size - offset).min(content.len() as u64);
         let (head, remaining) = content.split_at(head_len as usize);
 
-        self.write_page(tx, ino, pages.start, offset, head).await?;
+        let mut written = extent::add(ino, self.covering_pages(&byte_range));
+        written.extend(updates);
+        self.write_page(tx, ino, pages.start, offset, head, written)
+            .await?;
 
         if remaining.len() > 0 {
             self.write_extent(tx, ino, remaining_pages.start, remaining)
@@ -48,6 +389,7 @@ impl PageWriter {
         page: u64,
         offset: u64,
         content: &[u8],
+        extents: Vec<UpdateQuery>,
     ) -> Result<()> {
         assert!(content.len() as u64 <= self.page_size);
         let write_range = offset..(offset + content.len() as u64);
@@ -55,8 +397,8 @@ impl PageWriter {
 
         let page = Key::new(ino, page);
         let mut page_content = {
-            let mut reply = tx.read(self.bucket, vec![lwwreg::get(page)]).await?;
-            reply.lwwreg(0).unwrap_or_default()
+            let mut reply = tx.read(self.bucket, vec![self.get(page)]).await?;
+            self.content(&mut reply, 0, page)?
         };
 
         let previous_len = page_content.len();
@@ -69,8 +411,9 @@ impl PageWriter {
         }
 
         page_content[write_range.start as usize..write_range.end as usize].copy_from_slice(content);
-        tx.update(self.bucket, vec![lwwreg::set(page, page_content)])
-            .await?;
+        let page_content = self.encode(page, page_content);
+        let updates = std::iter::once(self.set(page, page_content)).chain(extents);
+        tx.update(self.bucket, updates).await?;
 
         Ok(())
     }
@@ -85,7 +428,8 @@ impl PageWriter {
         let mut page = extent_start;
         let writes = content.chunks_exact(self.page_size as usize).map(|chunk| {
             assert!(chunk.len() == self.page_size as usize);
-            let write = lwwreg::set(Key::new(ino, page), chunk.into());
+            let key = Key::new(ino, page);
+            let write = self.set(key, self.encode(key, chunk.into()));
             page += 1;
 
             write
@@ -96,7 +440,8 @@ impl PageWriter {
         let remaining = content.chunks_exact(self.page_size as usize).remainder();
 
         if remaining.len() > 0 {
-            self.write_page(tx, ino, page, 0, remaining).await?;
+            self.write_page(tx, ino, page, 0, remaining, Vec::new())
+                .await?;
         }
 
         Ok(())
@@ -131,6 +476,80 @@ impl PageWriter {
         Ok(())
     }
 
+    /* Only pages known to hold data are fetched, holes are read as zeros
+    without a round trip to Antidote. */
+    pub async fn read_sparse(
+        &self,
+        tx: &mut Transaction<'_>,
+        ino: u64,
+        offset: u64,
+        len: u64,
+        output: &mut Vec<u8>,
+    ) -> Result<()> {
+        let byte_range = offset..(offset + len);
+        let pages = self.covering_pages(&byte_range);
+        let written = self.written_pages(tx, ino, pages.clone()).await?;
+        tracing::debug!(?byte_range, ?pages, written = written.len());
+
+        let mut reply = if written.is_empty() {
+            None
+        } else {
+            let reads = written.iter().map(|page| self.get(Key::new(ino, *page)));
+            Some(tx.read(self.bucket, reads).await?)
+        };
+
+        let mut index = 0;
+        for page in pages {
+            let page_start = page * self.page_size;
+            let read = (byte_range.start.max(page_start) - page_start)
+                ..(byte_range.end.min(page_start + self.page_size) - page_start);
+
+            let content = match &mut reply {
+                Some(reply) if written.contains(&page) => {
+                    index += 1;
+                    self.content(reply, index - 1, Key::new(ino, page))?
+                }
+                _ => Vec::new(),
+            };
+
+            let overlapping = intersect_range(0..content.len() as u64, read.clone());
+            output
+                .extend_from_slice(&content[overlapping.start as usize..overlapping.end as usize]);
+
+            let padding = (read.end - read.start) - (overlapping.end - overlapping.start);
+            output.resize(output.len() + padding as usize, 0);
+        }
+
+        Ok(())
+    }
+
+    /* Pages among the given ones which hold data. */
+    pub async fn written_pages(
+        &self,
+        tx: &mut Transaction<'_>,
+        ino: u64,
+        pages: Range<u64>,
+    ) -> Result<BTreeSet<u64>> {
+        if pages.start >= pages.end {
+            return Ok(BTreeSet::new());
+        }
+
+        let groups = extent::group_of(pages.start)..(extent::group_of(pages.end - 1) + 1);
+        let reads = groups.clone().map(|group| extent::read(ino, group));
+        let mut reply = tx.read(self.bucket, reads).await?;
+
+        let mut written = BTreeSet::new();
+        for index in 0..groups.count() {
+            written.extend(
+                extent::decode(&mut reply, index)
+                    .into_iter()
+                    .filter(|page| pages.contains(page)),
+            );
+        }
+
+        Ok(written)
+    }
+
     async fn read_page(
         &self,
         tx: &mut Transaction<'_>,
@@ -145,8 +564,8 @@ impl PageWriter {
 
         let page = Key::new(ino, page);
         let page_content = {
-            let mut reply = tx.read(self.bucket, vec![lwwreg::get(page)]).await?;
-            reply.lwwreg(0).unwrap_or_default()
+            let mut reply = tx.read(self.bucket, vec![self.get(page)]).await?;
+            self.content(&mut reply, 0, page)?
         };
 
         if page_content.is_empty() {
@@ -176,13 +595,14 @@ impl PageWriter {
         len: u64,
         output: &mut Vec<u8>,
     ) -> Result<()> {
-        let reads = pages.clone().map(|page| lwwreg::get(Key::new(ino, page)));
+        let reads = pages.clone().map(|page| self.get(Key::new(ino, page)));
         let mut reply = tx.read(self.bucket, reads).await?;
 
         let mut page_index = 0;
         let mut remaining = len;
         while remaining >= self.page_size {
-            let content = reply.lwwreg(page_index as usize).unwrap_or_default();
+            let key = Key::new(ino, pages.start + page_index);
+            let content = self.content(&mut reply, page_index as usize, key)?;
             if content.is_empty() {
                 output.resize(output.len() + self.page_size as usize, 0);
                 remaining -= self.page_size;
@@ -198,7 +618,8 @@ impl PageWriter {
         }
 
         if remaining > 0 {
-            let content = reply.lwwreg(page_index as usize).unwrap_or_default();
+            let key = Key::new(ino, pages.start + page_index);
+            let content = self.content(&mut reply, page_index as usize, key)?;
             output.extend_from_slice(&content[..remaining.min(content.len() as u64) as usize]);
         }
 
@@ -219,28 +640,283 @@ impl PageWriter {
 
         let content_tail = {
             let page_key = Key::new(ino, pages.start);
-            let mut reply = tx.read(self.bucket, vec![lwwreg::get(page_key)]).await?;
-            let mut content = reply.lwwreg(0).unwrap_or_default();
+            let mut reply = tx.read(self.bucket, vec![self.get(page_key)]).await?;
+            let mut content = self.content(&mut reply, 0, page_key)?;
 
             content.truncate(offset as usize);
-            lwwreg::set(page_key, content)
+            self.set(page_key, self.encode(page_key, content))
+        };
+
+        let cleared = if offset == 0 {
+            pages.clone()
+        } else {
+            remaining_pages.clone()
+        };
+        let removes = remaining_pages.map(|p| self.set(Key::new(ino, p), Vec::new()));
+
+        let updates = std::iter::once(content_tail)
+            .chain(removes)
+            .chain(extent::remove(ino, cleared));
+        tx.update(self.bucket, updates).await?;
+
+        Ok(())
+    }
+
+    /* Copies whole page objects, holes of the source are kept as holes in
+    the destination. Without extents every source page is considered
+    written. Pages are copied as stored, compressed or not, only sealed
+    ones are sealed anew for their destination. */
+    #[tracing::instrument(skip(self, tx))]
+    pub async fn copy_pages(
+        &self,
+        tx: &mut Transaction<'_>,
+        src_ino: u64,
+        src_pages: Range<u64>,
+        src_extents: bool,
+        dst_ino: u64,
+        dst_first_page: u64,
+    ) -> Result<()> {
+        let written: BTreeSet<u64> = if src_extents {
+            self.written_pages(tx, src_ino, src_pages.clone()).await?
+        } else {
+            src_pages.clone().collect()
+        };
+
+        let mut reply = if written.is_empty() {
+            None
+        } else {
+            let reads = written
+                .iter()
+                .map(|page| self.get(Key::new(src_ino, *page)));
+            Some(tx.read(self.bucket, reads).await?)
         };
 
-        let removes = remaining_pages.map(|p| lwwreg::set(Key::new(ino, p), Vec::new()));
+        let mut updates = Vec::with_capacity((src_pages.end - src_pages.start) as usize + 2);
+        let mut index = 0;
+        for page in src_pages.clone() {
+            let stored = match &mut reply {
+                Some(reply) if written.contains(&page) => {
+                    index += 1;
+                    self.stored(reply, index - 1)
+                }
+                _ => Vec::new(),
+            };
+
+            let dst_page = Key::new(dst_ino, dst_first_page + (page - src_pages.start));
+            let stored = self.reseal(Key::new(src_ino, page), dst_page, stored)?;
+            updates.push(self.set(dst_page, stored));
+        }
+
+        let to_dst = |page: u64| dst_first_page + (page - src_pages.start);
+        let holes = src_pages.clone().filter(|page| !written.contains(page));
+        updates.extend(extent::remove(dst_ino, holes.map(to_dst)));
+        updates.extend(extent::add(dst_ino, written.iter().copied().map(to_dst)));
 
-        let updates = std::iter::once(content_tail).chain(removes);
+        tx.update(self.bucket, updates).await?;
+        Ok(())
+    }
+
+    /* Pages entirely covered by the range are dropped, the partially
+    covered ones at its edges are overwritten with zeros. */
+    #[tracing::instrument(skip(self, tx, ino))]
+    pub async fn zero(
+        &self,
+        tx: &mut Transaction<'_>,
+        ino: u64,
+        byte_range: Range<u64>,
+    ) -> Result<()> {
+        let shift = self.page_size.trailing_zeros();
+        let first_full = (byte_range.start + self.page_size - 1) >> shift;
+        let end_full = byte_range.end >> shift;
+
+        if first_full >= end_full {
+            let zeros = vec![0; (byte_range.end - byte_range.start) as usize];
+            return self.write(tx, ino, byte_range.start, &zeros).await;
+        }
+
+        let full = first_full..end_full;
+        let head = byte_range.start..(full.start << shift);
+        let tail = (full.end << shift)..byte_range.end;
+        tracing::debug!(?head, ?full, ?tail);
+
+        for edge in vec![head, tail].into_iter().filter(|r| r.start < r.end) {
+            let zeros = vec![0; (edge.end - edge.start) as usize];
+            self.write(tx, ino, edge.start, &zeros).await?;
+        }
+
+        let removes = full.clone().map(|p| self.set(Key::new(ino, p), Vec::new()));
+        let updates = removes.chain(extent::remove(ino, full));
         tx.update(self.bucket, updates).await?;
 
         Ok(())
     }
 
+    /* Unlike `remove`, pages are dropped whole without being read back,
+    this is meant to reclaim the content of a file being deleted. */
+    #[tracing::instrument(skip(self, tx, ino))]
+    pub async fn remove_range(
+        &self,
+        tx: &mut Transaction<'_>,
+        ino: u64,
+        byte_range: Range<u64>,
+    ) -> Result<()> {
+        let pages = self.page_range(&byte_range);
+        let removes = pages
+            .clone()
+            .map(|p| self.set(Key::new(ino, p), Vec::new()));
+
+        let updates = removes.chain(extent::reset(ino, pages));
+        tx.update(self.bucket, updates).await?;
+        Ok(())
+    }
+
+    /* The content of a page read at `index` of a reply, empty for a hole. */
+    fn content(&self, reply: &mut ReadReply, index: usize, key: Key) -> Result<Vec<u8>> {
+        let stored = self.stored(reply, index);
+
+        self.open(key, stored)
+    }
+
+    /* Sealed pages are decrypted first, checksums are then verified and
+    what they cover decoded as any other page. Pages that can't be
+    decrypted, without a key or with another one, that don't match their
+    checksum or can't be decoded are never returned as they are. Only pages
+    stored without a header, before there were any, are. */
+    fn open(&self, key: Key, stored: Vec<u8>) -> Result<Vec<u8>> {
+        let stored = match header(&stored) {
+            Some((Codec::Sealed, _, payload)) => self.unseal(key, payload)?,
+            _ => stored,
+        };
+        let stored = match header(&stored) {
+            Some((Codec::Checked, len, payload)) => verify(key, len, payload)?,
+            _ => stored,
+        };
+        if header(&stored).is_none() {
+            return Ok(stored);
+        }
+
+        self.decode(&stored).ok_or_else(|| {
+            tracing::error!(?key, "failed to decode page");
+            Error::Sys(Errno::EIO)
+        })
+    }
+
+    fn unseal(&self, key: Key, payload: &[u8]) -> Result<Vec<u8>> {
+        let encryption_key = self.encryption_key.ok_or(Error::Sys(Errno::EIO))?;
+        if payload.len() < NONCE_LEN {
+            return Err(Error::Sys(Errno::EIO));
+        }
+
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &key.aad(),
+        };
+
+        encryption_key
+            .cipher()
+            .decrypt(XNonce::from_slice(nonce), payload)
+            .map_err(|_| {
+                tracing::error!(?key, "failed to decrypt page");
+                Error::Sys(Errno::EIO)
+            })
+    }
+
+    fn decode(&self, stored: &[u8]) -> Option<Vec<u8>> {
+        let (codec, len, payload) = header(stored)?;
+        if len as u64 > self.page_size {
+            return None;
+        }
+
+        let content = match codec {
+            Codec::Raw => payload.to_vec(),
+            Codec::Lz4 => lz4_flex::block::decompress(payload, len).ok()?,
+            Codec::Zstd => zstd::bulk::decompress(payload, len).ok()?,
+            Codec::Sealed | Codec::Checked | Codec::Stamped => return None,
+        };
+
+        if content.len() == len {
+            Some(content)
+        } else {
+            None
+        }
+    }
+
+    /* Holes stay empty, encrypted or not. */
+    fn encode(&self, key: Key, content: Vec<u8>) -> Vec<u8> {
+        let stored = checksum(self.compress(content));
+
+        match self.encryption_key {
+            Some(encryption_key) if !stored.is_empty() => {
+                let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+                let payload = Payload {
+                    msg: &stored,
+                    aad: &key.aad(),
+                };
+                let ciphertext = encryption_key
+                    .cipher()
+                    .encrypt(&nonce, payload)
+                    .expect("pages are small enough to be encrypted");
+
+                let sealed: Vec<u8> = nonce.into_iter().chain(ciphertext).collect();
+                with_header(Codec::Sealed, stored.len(), &sealed)
+            }
+            _ => stored,
+        }
+    }
+
+    /* Content that doesn't shrink once compressed is stored as is. */
+    fn compress(&self, content: Vec<u8>) -> Vec<u8> {
+        if content.is_empty() {
+            return content;
+        }
+
+        let compressed = match self.compression {
+            Compression::None => None,
+            Compression::Lz4 => Some((Codec::Lz4, lz4_flex::block::compress(&content))),
+            Compression::Zstd => zstd::bulk::compress(&content, ZSTD_LEVEL)
+                .ok()
+                .map(|payload| (Codec::Zstd, payload)),
+        };
+
+        match compressed {
+            Some((codec, payload)) if HEADER_LEN + payload.len() < content.len() => {
+                with_header(codec, content.len(), &payload)
+            }
+            _ if content.starts_with(&MAGIC) => with_header(Codec::Raw, content.len(), &content),
+            _ => content,
+        }
+    }
+
+    /* Sealed pages are bound to where they are stored. */
+    fn reseal(&self, src: Key, dst: Key, stored: Vec<u8>) -> Result<Vec<u8>> {
+        match header(&stored) {
+            Some((Codec::Sealed, _, _)) => {
+                let content = self.open(src, stored)?;
+                Ok(self.encode(dst, content))
+            }
+            _ => Ok(stored),
+        }
+    }
+
     fn page_range(&self, byte_range: &Range<u64>) -> Range<u64> {
-        let first = byte_range.start / self.page_size;
-        let last = byte_range.end / self.page_size;
+        let shift = self.page_size.trailing_zeros();
+        let first = byte_range.start >> shift;
+        let last = byte_range.end >> shift;
         tracing::debug!(first, last);
 
         first..(last + 1)
     }
+
+    /* Unlike `page_range`, a range ending on a page boundary doesn't
+    include the next page. */
+    pub fn covering_pages(&self, byte_range: &Range<u64>) -> Range<u64> {
+        let shift = self.page_size.trailing_zeros();
+        let first = byte_range.start >> shift;
+        let end = (byte_range.end + self.page_size - 1) >> shift;
+
+        first..end.max(first)
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -257,6 +933,15 @@ impl Key {
     const fn byte_len() -> usize {
         2 * std::mem::size_of::<u64>()
     }
+
+    /* What a sealed page is encrypted along with, it can't be moved to
+    another page or inode unnoticed. */
+    fn aad(self) -> [u8; 16] {
+        let mut aad = [0u8; 16];
+        aad[..8].copy_from_slice(&self.ino.to_le_bytes());
+        aad[8..].copy_from_slice(&self.page.to_le_bytes());
+        aad
+    }
 }
 
 impl Into<RawIdent> for Key {
@@ -268,6 +953,74 @@ impl Into<RawIdent> for Key {
     }
 }
 
+fn header(stored: &[u8]) -> Option<(Codec, usize, &[u8])> {
+    if stored.len() < HEADER_LEN || !stored.starts_with(&MAGIC) {
+        return None;
+    }
+
+    let codec = Codec::from_u8(stored[MAGIC.len()])?;
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&stored[MAGIC.len() + 1..HEADER_LEN]);
+
+    Some((
+        codec,
+        u32::from_le_bytes(len) as usize,
+        &stored[HEADER_LEN..],
+    ))
+}
+
+/* Versions without a stamp, which no mount writes, sort before the
+others. */
+fn unstamp(stored: &[u8]) -> (Option<Stamp>, Vec<u8>) {
+    if let Some((Codec::Stamped, len, payload)) = header(stored) {
+        if payload.len() == STAMP_LEN + len {
+            let (stamp, stored) = payload.split_at(STAMP_LEN);
+            return (Stamp::from_bytes(stamp), stored.to_vec());
+        }
+    }
+
+    (None, stored.to_vec())
+}
+
+fn checksum(stored: Vec<u8>) -> Vec<u8> {
+    if stored.is_empty() {
+        return stored;
+    }
+
+    let checked: Vec<u8> = crc32c::crc32c(&stored)
+        .to_le_bytes()
+        .iter()
+        .chain(&stored)
+        .copied()
+        .collect();
+    with_header(Codec::Checked, stored.len(), &checked)
+}
+
+fn verify(key: Key, len: usize, payload: &[u8]) -> Result<Vec<u8>> {
+    if payload.len() == CHECKSUM_LEN + len {
+        let (checksum, stored) = payload.split_at(CHECKSUM_LEN);
+        let mut expected = [0u8; CHECKSUM_LEN];
+        expected.copy_from_slice(checksum);
+
+        if crc32c::crc32c(stored) == u32::from_le_bytes(expected) {
+            return Ok(stored.to_vec());
+        }
+    }
+
+    tracing::error!(?key, "page doesn't match its checksum");
+    Err(Error::Sys(Errno::EIO))
+}
+
+fn with_header(codec: Codec, len: usize, payload: &[u8]) -> Vec<u8> {
+    let mut stored = Vec::with_capacity(HEADER_LEN + payload.len());
+    stored.extend_from_slice(&MAGIC);
+    stored.push(codec as u8);
+    stored.extend_from_slice(&(len as u32).to_le_bytes());
+    stored.extend_from_slice(payload);
+
+    stored
+}
+
 fn intersect_range(lhs: Range<u64>, rhs: Range<u64>) -> Range<u64> {
     if lhs.end < rhs.start || rhs.end < lhs.start {
         return 0..0;
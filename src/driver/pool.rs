@@ -1,32 +1,80 @@
-use antidotec::{Connection, Error};
+use crate::driver::{budget, interrupt, runtime, Config, Error, Result};
+use antidotec::Connection;
+#[cfg(feature = "fault-injection")]
+use antidotec::Faults;
 use crossbeam::queue::SegQueue;
+use std::collections::{HashSet, VecDeque};
+use std::future;
 use std::ops::{Deref, DerefMut};
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
 use std::time::{Duration, Instant};
 use tracing::*;
 
 const CONNECTION_TIMEOUT_S: u64 = 180;
+const LOCAL_DC: usize = 0;
 
+/* Operations asking for a connection past this many waiting are turned
+away right away, they would only time out behind the others. */
+const MAX_WAITING: usize = 1024;
+
+/* Connections left idle longer than this are pinged before being handed
+out, the node behind them may have gone away in the meantime. */
+const PING_AFTER: Duration = Duration::from_secs(10);
+
+/* Reconnection attempts, waiting exponentially longer between each. */
+const CONNECT_ATTEMPTS: u32 = 6;
+const CONNECT_BACKOFF_MIN: Duration = Duration::from_millis(50);
+const CONNECT_BACKOFF_MAX: Duration = Duration::from_secs(2);
+
+/* Once the local datacenter is found unreachable, connections go to the
+fallback ones for this long before the local one is tried again. */
+const LOCAL_DC_RETRY: Duration = Duration::from_secs(30);
+
+/* Faults injected into every connection of builds with fault injection,
+as parsed by `Faults`: `seed=1,latency=5ms@0.1,drop=0.01,abort=0.05`. */
+#[cfg(feature = "fault-injection")]
+const FAULTS_ENV: &str = "ELMERFS_FAULTS";
+
+/// Addresses of Antidote nodes grouped by datacenter, the first one being
+/// local to the mount and the others only used when it is unreachable.
 #[derive(Debug)]
 pub struct AddressBook {
-    addresses: Vec<String>,
+    datacenters: Vec<Vec<String>>,
     next: AtomicUsize,
 }
 
 impl AddressBook {
     pub fn with_addresses(addresses: Vec<String>) -> Self {
-        assert_ne!(addresses.len(), 0);
+        Self::with_datacenters(addresses, Vec::new())
+    }
+
+    pub fn with_datacenters(local: Vec<String>, fallbacks: Vec<Vec<String>>) -> Self {
+        assert_ne!(local.len(), 0);
+        assert!(fallbacks.iter().all(|addresses| !addresses.is_empty()));
+
+        let mut datacenters = Vec::with_capacity(1 + fallbacks.len());
+        datacenters.push(local);
+        datacenters.extend(fallbacks);
 
         Self {
-            addresses,
+            datacenters,
             next: AtomicUsize::new(0),
         }
     }
 
-    pub fn next(&self) -> &str {
+    fn datacenters(&self) -> usize {
+        self.datacenters.len()
+    }
+
+    /* Every address of a datacenter, starting from a different one each
+    time to spread connections among its nodes. */
+    fn addresses(&self, dc: usize) -> impl Iterator<Item = &str> {
+        let addresses = &self.datacenters[dc];
         let next = self.next.fetch_add(1, Ordering::Relaxed);
-        &self.addresses[next % self.addresses.len()]
+
+        (0..addresses.len()).map(move |i| &addresses[(next + i) % addresses.len()] as &str)
     }
 }
 
@@ -34,6 +82,49 @@ impl AddressBook {
 struct AvailableConnection {
     pushed_at: Instant,
     connection: Connection,
+    dc: usize,
+}
+
+#[derive(Debug, Default)]
+struct Admission {
+    in_use: usize,
+    waiting: VecDeque<(u64, Option<Waker>)>,
+    granted: HashSet<u64>,
+    next_ticket: u64,
+}
+
+/// How long operations waited for a connection, and how many gave up.
+#[derive(Debug, Default)]
+pub struct WaitStats {
+    pub waits: u64,
+    pub wait_time: Duration,
+    pub timeouts: u64,
+    pub rejected: u64,
+}
+
+#[derive(Debug, Default)]
+struct WaitCounters {
+    waits: AtomicU64,
+    wait_micros: AtomicU64,
+    timeouts: AtomicU64,
+    rejected: AtomicU64,
+}
+
+/// Connections to Antidote shared by several mounts of a process, each
+/// mount otherwise opening its own. Background work still goes through a
+/// few connections of each mount.
+#[derive(Debug, Clone)]
+pub struct SharedPool(pub(crate) Arc<ConnectionPool>);
+
+impl SharedPool {
+    /// Connects to the addresses of `cfg`, sized after its `pool_size` and
+    /// `pool_timeout`, those of the mounts sharing it are ignored.
+    pub fn new(cfg: &Config) -> Self {
+        Self(Arc::new(
+            ConnectionPool::with_capacity(cfg.addresses.clone(), cfg.pool_size, cfg.pool_timeout)
+                .with_request_timeout(cfg.request_timeout),
+        ))
+    }
 }
 
 #[derive(Debug)]
@@ -42,59 +133,341 @@ pub struct ConnectionPool {
     available: SegQueue<AvailableConnection>,
     capacity: usize,
     timeout: Duration,
+    wait_timeout: Duration,
+    request_timeout: Option<Duration>,
+    local_down_until: Mutex<Option<Instant>>,
+    admission: Mutex<Admission>,
+    waits: WaitCounters,
+    #[cfg(feature = "fault-injection")]
+    faults: Option<Faults>,
 }
 
 impl ConnectionPool {
-    pub fn with_capacity(addresses: Arc<AddressBook>, capacity: usize) -> Self {
+    /// A pool handing out at most `capacity` connections at once, operations
+    /// asking for one fail after `wait_timeout` if none was released.
+    pub fn with_capacity(
+        addresses: Arc<AddressBook>,
+        capacity: usize,
+        wait_timeout: Duration,
+    ) -> Self {
+        assert_ne!(capacity, 0);
+
         ConnectionPool {
             addresses,
             available: SegQueue::new(),
             capacity,
             timeout: Duration::from_secs(CONNECTION_TIMEOUT_S),
+            wait_timeout,
+            request_timeout: None,
+            local_down_until: Mutex::new(None),
+            admission: Mutex::new(Admission::default()),
+            waits: WaitCounters::default(),
+            #[cfg(feature = "fault-injection")]
+            faults: faults_from_env(),
+        }
+    }
+
+    /// Connections of the pool give up on a request to Antidote after
+    /// `timeout`, failing the operation with `Error::Timeout`.
+    pub fn with_request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Connections waiting in the pool and connections currently acquired.
+    pub fn usage(&self) -> (usize, usize) {
+        let in_use = self.admission.lock().unwrap().in_use;
+        (self.available.len(), in_use)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Operations currently waiting for a connection.
+    pub fn waiting(&self) -> usize {
+        self.admission.lock().unwrap().waiting.len()
+    }
+
+    pub fn wait_stats(&self) -> WaitStats {
+        WaitStats {
+            waits: self.waits.waits.load(Ordering::Relaxed),
+            wait_time: Duration::from_micros(self.waits.wait_micros.load(Ordering::Relaxed)),
+            timeouts: self.waits.timeouts.load(Ordering::Relaxed),
+            rejected: self.waits.rejected.load(Ordering::Relaxed),
         }
     }
 
     #[instrument(skip(self))]
-    pub async fn acquire(&self) -> Result<PoolGuard<'_>, Error> {
+    pub async fn acquire(&self) -> Result<PoolGuard<'_>> {
+        self.admit().await?;
+
+        match self.connection().await {
+            Ok((connection, dc)) => Ok(PoolGuard::new(self, connection, dc)),
+            Err(error) => {
+                self.release();
+                Err(error.into())
+            }
+        }
+    }
+
+    /* Connections are handed out in the order they were asked for: once
+    every one is in use, a released one goes to the oldest waiting
+    operation rather than to whoever asks next. */
+    async fn admit(&self) -> Result<()> {
+        let ticket = {
+            let mut admission = self.admission.lock().unwrap();
+            if admission.waiting.is_empty() && admission.in_use < self.capacity {
+                admission.in_use += 1;
+                return Ok(());
+            }
+
+            if admission.waiting.len() >= MAX_WAITING {
+                warn!(
+                    waiting = admission.waiting.len(),
+                    "connection pool exhausted"
+                );
+                self.waits.rejected.fetch_add(1, Ordering::Relaxed);
+                return Err(Error::PoolExhausted);
+            }
+
+            admission.next_ticket += 1;
+            let ticket = admission.next_ticket;
+            admission.waiting.push_back((ticket, None));
+
+            ticket
+        };
+
+        let mut waiter = Waiter {
+            pool: self,
+            ticket,
+            granted: false,
+        };
+
+        let started = Instant::now();
+        let granted = runtime::timeout(self.wait_timeout, waiter.wait()).await;
+
+        let waited = started.elapsed().as_micros() as u64;
+        self.waits.waits.fetch_add(1, Ordering::Relaxed);
+        self.waits.wait_micros.fetch_add(waited, Ordering::Relaxed);
+
+        match granted {
+            Some(()) => {
+                waiter.granted = true;
+                Ok(())
+            }
+            None => {
+                warn!(timeout = ?self.wait_timeout, "no connection released in time");
+                self.waits.timeouts.fetch_add(1, Ordering::Relaxed);
+                Err(Error::PoolExhausted)
+            }
+        }
+    }
+
+    fn release(&self) {
+        let mut admission = self.admission.lock().unwrap();
+        Self::hand_over(&mut admission);
+    }
+
+    fn hand_over(admission: &mut Admission) {
+        match admission.waiting.pop_front() {
+            Some((ticket, waker)) => {
+                admission.granted.insert(ticket);
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }
+            None => admission.in_use -= 1,
+        }
+    }
+
+    async fn connection(&self) -> std::result::Result<(Connection, usize), antidotec::Error> {
         while self.available.len() > self.capacity {
             match self.available.pop() {
-                Ok(mut available) => available.connection.close().await?,
+                Ok(available) => Self::evict(available).await,
                 Err(_) => break,
             }
         }
 
-        if let Ok(available) = self.available.pop() {
-            if available.pushed_at.elapsed() < self.timeout {
-                return Ok(PoolGuard::new(self, available.connection));
+        while let Ok(mut available) = self.available.pop() {
+            let failing_back = available.dc != LOCAL_DC && !self.is_local_down();
+            if failing_back || available.pushed_at.elapsed() >= self.timeout {
+                Self::evict(available).await;
+                continue;
             }
+
+            if available.pushed_at.elapsed() >= PING_AFTER {
+                if let Err(error) = available.connection.ping().await {
+                    warn!(dc = available.dc, ?error, "evicting dead connection");
+                    continue;
+                }
+            }
+
+            return Ok((available.connection, available.dc));
         }
 
-        let connection = Connection::new(self.addresses.next()).await?;
-        Ok(PoolGuard::new(self, connection))
+        self.reconnect().await
+    }
+
+    async fn evict(mut available: AvailableConnection) {
+        if let Err(error) = available.connection.close().await {
+            debug!(dc = available.dc, ?error, "closing evicted connection");
+        }
+    }
+
+    async fn reconnect(&self) -> std::result::Result<(Connection, usize), antidotec::Error> {
+        let mut backoff = CONNECT_BACKOFF_MIN;
+        let mut attempt = 1;
+
+        loop {
+            match self.connect().await {
+                Ok(connected) => return Ok(connected),
+                Err(error) if attempt < CONNECT_ATTEMPTS => {
+                    warn!(
+                        attempt,
+                        ?backoff,
+                        ?error,
+                        "no antidote node reachable, retrying"
+                    );
+                    runtime::sleep(backoff).await;
+
+                    backoff = (backoff * 2).min(CONNECT_BACKOFF_MAX);
+                    attempt += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    async fn connect(&self) -> std::result::Result<(Connection, usize), antidotec::Error> {
+        let mut datacenters: Vec<usize> = (0..self.addresses.datacenters()).collect();
+        if self.is_local_down() {
+            datacenters.rotate_left(1);
+        }
+
+        let mut last_error = None;
+        for dc in datacenters {
+            for address in self.addresses.addresses(dc) {
+                match Connection::with_timeout(address, self.request_timeout).await {
+                    Ok(connection) => {
+                        #[cfg(feature = "fault-injection")]
+                        let connection = match &self.faults {
+                            Some(faults) => connection.with_faults(faults),
+                            None => connection,
+                        };
+
+                        if dc == LOCAL_DC {
+                            *self.local_down_until.lock().unwrap() = None;
+                        }
+
+                        return Ok((connection, dc));
+                    }
+                    Err(error) => {
+                        warn!(dc, address, ?error, "antidote node unreachable");
+                        last_error = Some(error);
+                    }
+                }
+            }
+
+            if dc == LOCAL_DC && self.addresses.datacenters() > 1 {
+                warn!("local datacenter unreachable, failing over");
+                *self.local_down_until.lock().unwrap() = Some(Instant::now() + LOCAL_DC_RETRY);
+            }
+        }
+
+        Err(last_error.unwrap())
+    }
+
+    fn is_local_down(&self) -> bool {
+        let local_down_until = self.local_down_until.lock().unwrap();
+        local_down_until.map_or(false, |until| Instant::now() < until)
     }
 
     #[instrument(skip(self))]
-    fn push(&self, connection: Connection) {
+    fn push(&self, connection: Connection, dc: usize) {
         let pushed_at = Instant::now();
         let entry = AvailableConnection {
             pushed_at,
             connection,
+            dc,
         };
 
         self.available.push(entry);
     }
 }
 
+#[cfg(feature = "fault-injection")]
+fn faults_from_env() -> Option<Faults> {
+    let faults = std::env::var(FAULTS_ENV).ok()?;
+    let faults = faults
+        .parse()
+        .unwrap_or_else(|error| panic!("invalid {}: {}", FAULTS_ENV, error));
+
+    warn!(?faults, "injecting faults into connections to antidote");
+    Some(faults)
+}
+
+/* Gives its place back when the wait is cut short, by a timeout or by the
+operation being dropped, including a connection granted meanwhile. */
+struct Waiter<'p> {
+    pool: &'p ConnectionPool,
+    ticket: u64,
+    granted: bool,
+}
+
+impl Waiter<'_> {
+    async fn wait(&self) {
+        future::poll_fn(|cx| {
+            let mut admission = self.pool.admission.lock().unwrap();
+            if admission.granted.contains(&self.ticket) {
+                return Poll::Ready(());
+            }
+
+            if let Some((_, waker)) = admission
+                .waiting
+                .iter_mut()
+                .find(|(ticket, _)| *ticket == self.ticket)
+            {
+                *waker = Some(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        let mut admission = self.pool.admission.lock().unwrap();
+        if admission.granted.remove(&self.ticket) {
+            if !self.granted {
+                ConnectionPool::hand_over(&mut admission);
+            }
+        } else {
+            let ticket = self.ticket;
+            admission.waiting.retain(|(waiting, _)| *waiting != ticket);
+        }
+    }
+}
+
 pub struct PoolGuard<'p> {
     connection: Option<Connection>,
     pool: &'p ConnectionPool,
+    round_trips: u64,
+    dc: usize,
 }
 
 impl<'p> PoolGuard<'p> {
-    pub fn new(pool: &'p ConnectionPool, connection: Connection) -> Self {
+    /* The connection refuses its requests once the operation acquiring it
+    is interrupted. */
+    fn new(pool: &'p ConnectionPool, mut connection: Connection, dc: usize) -> Self {
+        connection.set_interrupt(interrupt::current());
+
         Self {
+            round_trips: connection.round_trips(),
             connection: Some(connection),
             pool,
+            dc,
         }
     }
 }
@@ -115,7 +488,16 @@ impl DerefMut for PoolGuard<'_> {
 
 impl Drop for PoolGuard<'_> {
     fn drop(&mut self) {
-        let connection = self.connection.take().unwrap();
-        self.pool.push(connection);
+        let mut connection = self.connection.take().unwrap();
+        budget::charge(connection.round_trips() - self.round_trips);
+        connection.set_interrupt(None);
+
+        /* Back in the pool before the next waiting operation is let in. */
+        if connection.is_broken() {
+            warn!(dc = self.dc, "dropping broken connection");
+        } else {
+            self.pool.push(connection, self.dc);
+        }
+        self.pool.release();
     }
 }
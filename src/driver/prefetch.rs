@@ -0,0 +1,230 @@
+use crate::driver::pool::ConnectionPool;
+use crate::driver::{Config, Result};
+use crate::model::dir::{self, DirView};
+use crate::model::inode::{self, Inode, Kind};
+use antidotec::TransactionLocks;
+use async_std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/* Prefetched objects are only served for a short while, long enough for
+a tree walk to consume them but not to hide updates from other views. */
+const PREFETCH_TTL: Duration = Duration::from_secs(1);
+const LISTING_TTL: Duration = Duration::from_secs(10);
+const MAX_LISTINGS: usize = 1024;
+
+/* A walk is detected once this ratio of a listed directory's children were
+accessed, and at least MIN_WALK_ACCESSES of them. */
+const MIN_WALK_ACCESSES: usize = 4;
+const WALK_RATIO: usize = 2;
+
+#[derive(Debug)]
+struct Listing {
+    at: Instant,
+    children: Vec<(u64, Kind)>,
+    accessed: HashSet<u64>,
+    triggered: bool,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    generation: u64,
+    listings: HashMap<u64, Listing>,
+    parents: HashMap<u64, u64>,
+    inodes: HashMap<u64, (Instant, Inode)>,
+    dirs: HashMap<u64, (Instant, Arc<DirView>)>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Prefetch {
+    pub generation: u64,
+    pub targets: Vec<(u64, Kind)>,
+}
+
+#[derive(Debug, Default)]
+pub struct Prefetcher {
+    state: Mutex<State>,
+}
+
+impl Prefetcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_listing(&self, dir: u64, children: Vec<(u64, Kind)>) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+
+        if state.listings.len() >= MAX_LISTINGS {
+            state
+                .listings
+                .retain(|_, listing| listing.at.elapsed() < LISTING_TTL);
+            let listings = &state.listings;
+            state
+                .parents
+                .retain(|_, parent| listings.contains_key(parent));
+        }
+
+        for (child, _) in &children {
+            state.parents.insert(*child, dir);
+        }
+
+        state.listings.insert(
+            dir,
+            Listing {
+                at: Instant::now(),
+                children,
+                accessed: HashSet::new(),
+                triggered: false,
+            },
+        );
+    }
+
+    /* Adds the children found in the next shards of a directory being
+    listed. */
+    pub fn extend_listing(&self, dir: u64, children: Vec<(u64, Kind)>) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+
+        let listing = match state.listings.get_mut(&dir) {
+            Some(listing) => listing,
+            None => return,
+        };
+
+        for (child, _) in &children {
+            state.parents.insert(*child, dir);
+        }
+        listing.children.extend(children);
+    }
+
+    /* Returns what should be fetched ahead of time when this access makes
+    the parent directory look like it is being walked. */
+    pub(crate) fn record_access(&self, ino: u64) -> Option<Prefetch> {
+        let mut state = self.state.lock().unwrap();
+        let generation = state.generation;
+
+        let parent = *state.parents.get(&ino)?;
+        let listing = state.listings.get_mut(&parent)?;
+        if listing.triggered || listing.at.elapsed() > LISTING_TTL {
+            return None;
+        }
+
+        listing.accessed.insert(ino);
+        let accessed = listing.accessed.len();
+        if accessed < MIN_WALK_ACCESSES || accessed * WALK_RATIO < listing.children.len() {
+            return None;
+        }
+
+        listing.triggered = true;
+        let targets = listing
+            .children
+            .iter()
+            .filter(|(child, _)| !listing.accessed.contains(child))
+            .copied()
+            .collect();
+
+        Some(Prefetch {
+            generation,
+            targets,
+        })
+    }
+
+    pub fn inode(&self, ino: u64) -> Option<Inode> {
+        let state = self.state.lock().unwrap();
+
+        match state.inodes.get(&ino) {
+            Some((at, inode)) if at.elapsed() < PREFETCH_TTL => Some(inode.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn dir(&self, ino: u64) -> Option<Arc<DirView>> {
+        let state = self.state.lock().unwrap();
+
+        match state.dirs.get(&ino) {
+            Some((at, dir)) if at.elapsed() < PREFETCH_TTL => Some(dir.clone()),
+            _ => None,
+        }
+    }
+
+    /* Must be called once a local update is committed, prefetches still in
+    flight are discarded as they might have read the previous state. */
+    pub fn invalidate(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        state.generation += 1;
+        state.inodes.clear();
+        state.dirs.clear();
+    }
+
+    fn fill(&self, generation: u64, inodes: Vec<Inode>, dirs: Vec<(u64, DirView)>) {
+        let mut state = self.state.lock().unwrap();
+        if state.generation != generation {
+            return;
+        }
+
+        let now = Instant::now();
+        state
+            .inodes
+            .retain(|_, (at, _)| at.elapsed() < PREFETCH_TTL);
+        state.dirs.retain(|_, (at, _)| at.elapsed() < PREFETCH_TTL);
+
+        for inode in inodes {
+            state.inodes.insert(inode.ino, (now, inode));
+        }
+
+        for (ino, dir) in dirs {
+            state.dirs.insert(ino, (now, Arc::new(dir)));
+        }
+    }
+}
+
+/* Every inode and child directory is read in a single request so that the
+whole batch costs one round trip. */
+#[tracing::instrument(skip(cfg, pool, prefetcher, prefetch), fields(targets = prefetch.targets.len()))]
+pub(crate) async fn fetch(
+    cfg: Config,
+    pool: Arc<ConnectionPool>,
+    prefetcher: Arc<Prefetcher>,
+    prefetch: Prefetch,
+) -> Result<()> {
+    let mut connection = pool.acquire().await?;
+    let mut tx = connection
+        .transaction_at(cfg.snapshot.as_ref(), TransactionLocks::new())
+        .await?;
+
+    let dirs: Vec<u64> = prefetch
+        .targets
+        .iter()
+        .filter(|(_, kind)| *kind == Kind::Directory)
+        .map(|(ino, _)| *ino)
+        .collect();
+
+    let reads = prefetch
+        .targets
+        .iter()
+        .map(|(ino, _)| inode::read(*ino))
+        .chain(dirs.iter().flat_map(|ino| dir::read_all(*ino)));
+    let mut reply = tx.read(cfg.bucket, reads).await?;
+    tx.commit().await?;
+
+    let mut inodes = Vec::with_capacity(prefetch.targets.len());
+    for (index, (ino, _)) in prefetch.targets.iter().enumerate() {
+        if let Some(inode) = inode::decode(*ino, &mut reply, index) {
+            inodes.push(inode);
+        }
+    }
+
+    let mut dir_views = Vec::with_capacity(dirs.len());
+    for (index, ino) in dirs.iter().enumerate() {
+        let index = prefetch.targets.len() + index * dir::SHARDS as usize;
+        if let Some(dir) = dir::decode_all(cfg.view, &mut reply, index) {
+            dir_views.push((*ino, dir));
+        }
+    }
+
+    tracing::debug!(inodes = inodes.len(), dirs = dir_views.len());
+    prefetcher.fill(prefetch.generation, inodes, dir_views);
+    Ok(())
+}
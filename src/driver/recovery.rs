@@ -0,0 +1,82 @@
+use crate::driver::pool::ConnectionPool;
+use crate::driver::{Config, Result, TxClass};
+use crate::model::inode::{self, Inode};
+use crate::model::journal::{self, Intent};
+use antidotec::TransactionLocks;
+use std::collections::{BTreeMap, BTreeSet};
+
+/* What a crashed mount of the view left half done is finished on the next
+one, before any operation runs. An inode changed since the intent was
+recorded, by another view or once removed, is left as it is: what changed
+it is more recent. Intents are forgotten either way. */
+#[tracing::instrument(skip(cfg, pool))]
+pub(crate) async fn replay(cfg: &Config, pool: &ConnectionPool) -> Result<usize> {
+    let mut connection = pool.acquire().await?;
+
+    let intents = {
+        let mut tx = connection.transaction().await?;
+        let mut reply = tx.read(cfg.bucket, vec![journal::read(cfg.view)]).await?;
+        tx.commit().await?;
+
+        journal::decode(&mut reply, 0)
+    };
+    if intents.is_empty() {
+        return Ok(0);
+    }
+
+    let inos: Vec<u64> = intents
+        .iter()
+        .map(|Intent::Extend { ino, .. }| *ino)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+
+    let locks = if cfg.locks.covers(TxClass::Other) {
+        TransactionLocks {
+            shared: vec![],
+            exclusive: inos.iter().map(|ino| inode::key(*ino).into()).collect(),
+        }
+    } else {
+        TransactionLocks::new()
+    };
+    let mut tx = connection.transaction_with_locks(locks).await?;
+
+    let mut reply = tx
+        .read(cfg.bucket, inos.iter().map(|ino| inode::read(*ino)))
+        .await?;
+    let mut inodes: BTreeMap<u64, (Inode, bool)> = BTreeMap::new();
+    for (index, ino) in inos.iter().enumerate() {
+        if let Some(inode) = inode::decode(*ino, &mut reply, index) {
+            inodes.insert(*ino, (inode, false));
+        }
+    }
+
+    let mut updates = Vec::with_capacity(inos.len() + intents.len());
+    for intent in &intents {
+        let Intent::Extend { ino, size, mtime } = *intent;
+        updates.push(journal::forget(cfg.view, intent));
+
+        let (inode, extended) = match inodes.get_mut(&ino) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        if inode.mtime < mtime && inode.size < size {
+            inode.size = size;
+            inode.mtime = mtime;
+            *extended = true;
+        }
+    }
+
+    let mut replayed = 0;
+    for (inode, extended) in inodes.values() {
+        if *extended {
+            tracing::info!(ino = inode.ino, size = inode.size, "extension replayed");
+            updates.push(inode::update_stats_and_size(inode));
+            replayed += 1;
+        }
+    }
+
+    tx.update(cfg.bucket, updates).await?;
+    tx.commit().await?;
+    Ok(replayed)
+}
@@ -0,0 +1,105 @@
+use std::future::Future;
+use std::time::Duration;
+
+/* The driver only needs to spawn detached tasks, sleep and block on its
+entry points. The async-std synchronization primitives don't depend on its
+executor and sockets are driven by a reactor thread of their own, both are
+kept whichever runtime is picked. */
+pub(crate) use async_std::sync::{Condvar, Mutex, MutexGuard};
+
+#[cfg(not(feature = "tokio-runtime"))]
+mod imp {
+    use async_std::task;
+    use std::future::Future;
+    use std::time::Duration;
+
+    pub(super) fn spawn<F>(f: F)
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        task::spawn(f);
+    }
+
+    pub(super) async fn sleep(duration: Duration) {
+        task::sleep(duration).await
+    }
+
+    pub(super) async fn timeout<F: Future>(duration: Duration, f: F) -> Option<F::Output> {
+        async_std::future::timeout(duration, f).await.ok()
+    }
+
+    pub(super) fn block_on<F: Future>(f: F) -> F::Output {
+        task::block_on(f)
+    }
+}
+
+/* The runtime of the caller is reused when the driver is first entered from
+one of its threads, one is started otherwise. Blocking entry points, e.g
+mounting or the client, must be called outside of its tasks, for instance
+with `spawn_blocking`. */
+#[cfg(feature = "tokio-runtime")]
+mod imp {
+    use crate::driver::budget;
+    use std::future::Future;
+    use std::sync::OnceLock;
+    use std::time::Duration;
+    use tokio::runtime::{Handle, Runtime};
+
+    static OWN_RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    static HANDLE: OnceLock<Handle> = OnceLock::new();
+
+    fn handle() -> &'static Handle {
+        HANDLE.get_or_init(|| {
+            Handle::try_current().unwrap_or_else(|_| {
+                let runtime = OWN_RUNTIME
+                    .get_or_init(|| Runtime::new().expect("failed to start the tokio runtime"));
+                runtime.handle().clone()
+            })
+        })
+    }
+
+    /* Task locals are scoped explicitly with tokio. */
+    pub(super) fn spawn<F>(f: F)
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        handle().spawn(budget::scope(f));
+    }
+
+    pub(super) async fn sleep(duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+
+    pub(super) async fn timeout<F: Future>(duration: Duration, f: F) -> Option<F::Output> {
+        tokio::time::timeout(duration, f).await.ok()
+    }
+
+    pub(super) fn block_on<F: Future>(f: F) -> F::Output {
+        handle().block_on(budget::scope(f))
+    }
+}
+
+/// Runs `f` in a task of its own, without waiting for it.
+pub(crate) fn spawn<F>(f: F)
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    imp::spawn(f)
+}
+
+pub(crate) async fn sleep(duration: Duration) {
+    imp::sleep(duration).await
+}
+
+/// Waits for `f` for at most `duration`, `None` once it is over.
+pub(crate) async fn timeout<F: Future>(duration: Duration, f: F) -> Option<F::Output> {
+    imp::timeout(duration, f).await
+}
+
+/// Blocks the current thread until `f` is done.
+pub(crate) fn block_on<F: Future>(f: F) -> F::Output {
+    imp::block_on(f)
+}
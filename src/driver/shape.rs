@@ -0,0 +1,63 @@
+use crate::model::inode::{Inode, Owner};
+use fuser::FileAttr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/* Metadata fidelity can be traded for less inode churn: two updates that
+end up writing the same coarsened value don't conflict with each other. */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AttrShape {
+    pub subsec_timestamps: bool,
+    pub squash_owner: Option<Owner>,
+}
+
+impl AttrShape {
+    pub const fn exact() -> Self {
+        Self {
+            subsec_timestamps: true,
+            squash_owner: None,
+        }
+    }
+
+    pub fn timestamp(&self, t: Duration) -> Duration {
+        if self.subsec_timestamps {
+            t
+        } else {
+            Duration::from_secs(t.as_secs())
+        }
+    }
+
+    pub fn owner(&self, owner: Owner) -> Owner {
+        self.squash_owner.unwrap_or(owner)
+    }
+
+    pub fn attr(&self, inode: &Inode) -> FileAttr {
+        let mut attr = inode.attr();
+
+        if !self.subsec_timestamps {
+            attr.atime = whole_secs(attr.atime);
+            attr.mtime = whole_secs(attr.mtime);
+            attr.ctime = whole_secs(attr.ctime);
+            attr.crtime = whole_secs(attr.crtime);
+        }
+
+        if let Some(owner) = self.squash_owner {
+            attr.uid = owner.uid;
+            attr.gid = owner.gid;
+        }
+
+        attr
+    }
+}
+
+fn whole_secs(t: SystemTime) -> SystemTime {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs()),
+        Err(_) => t,
+    }
+}
+
+impl Default for AttrShape {
+    fn default() -> Self {
+        Self::exact()
+    }
+}
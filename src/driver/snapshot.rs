@@ -0,0 +1,88 @@
+use crate::driver::pool::ConnectionPool;
+use crate::driver::{Config, Error, Result};
+use crate::model::snapshot::{self, Snapshot};
+use antidotec::TransactionLocks;
+use nix::errno::Errno;
+
+const MAX_NAME_LEN: usize = 255;
+
+/* The time of a snapshot is the one of a transaction that only read,
+anything committed before it is part of the snapshot. */
+#[tracing::instrument(skip(cfg, pool))]
+pub(crate) async fn create(cfg: &Config, pool: &ConnectionPool, name: &str) -> Result<Snapshot> {
+    if cfg.snapshot.is_some() {
+        return Err(Error::Sys(Errno::EROFS));
+    }
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(Error::Sys(Errno::EINVAL));
+    }
+
+    let mut connection = pool.acquire().await?;
+    let timestamp = connection
+        .transaction_at(None, TransactionLocks::new())
+        .await?
+        .commit_at()
+        .await?;
+
+    let mut tx = transaction!(cfg, connection, { exclusive: [snapshot::key()] }).await?;
+    let mut reply = tx.read(cfg.bucket, vec![snapshot::read()]).await?;
+    let snapshots = snapshot::decode(&mut reply, 0);
+    if snapshots.iter().any(|snapshot| snapshot.name == name) {
+        tx.commit().await?;
+        return Err(Error::Exists);
+    }
+
+    let snapshot = Snapshot {
+        name: String::from(name),
+        timestamp,
+    };
+    tx.update(cfg.bucket, vec![snapshot::add(&snapshot)])
+        .await?;
+    tx.commit().await?;
+
+    Ok(snapshot)
+}
+
+pub(crate) async fn list(cfg: &Config, pool: &ConnectionPool) -> Result<Vec<Snapshot>> {
+    let mut connection = pool.acquire().await?;
+    let mut tx = transaction!(cfg, connection, { shared: [snapshot::key()] }).await?;
+
+    let mut reply = tx.read(cfg.bucket, vec![snapshot::read()]).await?;
+    let snapshots = snapshot::decode(&mut reply, 0);
+
+    tx.commit().await?;
+    Ok(snapshots)
+}
+
+pub(crate) async fn find(cfg: &Config, pool: &ConnectionPool, name: &str) -> Result<Snapshot> {
+    list(cfg, pool)
+        .await?
+        .into_iter()
+        .find(|snapshot| snapshot.name == name)
+        .ok_or(Error::NotFound)
+}
+
+#[tracing::instrument(skip(cfg, pool))]
+pub(crate) async fn delete(cfg: &Config, pool: &ConnectionPool, name: &str) -> Result<()> {
+    if cfg.snapshot.is_some() {
+        return Err(Error::Sys(Errno::EROFS));
+    }
+
+    let mut connection = pool.acquire().await?;
+    let mut tx = transaction!(cfg, connection, { exclusive: [snapshot::key()] }).await?;
+
+    let mut reply = tx.read(cfg.bucket, vec![snapshot::read()]).await?;
+    let removed: Vec<_> = snapshot::decode(&mut reply, 0)
+        .into_iter()
+        .filter(|snapshot| snapshot.name == name)
+        .collect();
+    if removed.is_empty() {
+        tx.commit().await?;
+        return Err(Error::NotFound);
+    }
+
+    tx.update(cfg.bucket, removed.iter().map(snapshot::remove))
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use crate::driver::check;
+use crate::driver::ino::InoGenerator;
+use crate::driver::page::PageWriter;
+use crate::driver::pool::ConnectionPool;
+use crate::driver::{Config, Result};
+use crate::model::{inode::Kind, orphan};
+use antidotec::TransactionLocks;
+
+/// What a bucket holds, as found by walking it from the root.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub directories: u64,
+    pub regular_files: u64,
+    pub symlinks: u64,
+    pub special_files: u64,
+    pub orphans: u64,
+    /// Sum of the sizes of regular files.
+    pub file_bytes: u64,
+    /// Bytes taken by written pages, holes aside.
+    pub stored_bytes: u64,
+    pub page_size: u64,
+    /// Next inode of the view and how many it can still allocate, unset when
+    /// the view never created any.
+    pub ino_counter: Option<(u64, u64)>,
+}
+
+#[tracing::instrument(skip(cfg, pool, pages))]
+pub(crate) async fn collect(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    pages: &PageWriter,
+) -> Result<Stats> {
+    let walk = check::walk(cfg, pool).await?;
+
+    let mut stats = Stats {
+        page_size: cfg.page_size,
+        ..Stats::default()
+    };
+
+    let mut connection = pool.acquire().await?;
+    let mut tx = transaction!(cfg, connection).await?;
+
+    for inode in walk.inodes.values() {
+        match inode.kind {
+            Kind::Directory => stats.directories += 1,
+            Kind::Symlink => stats.symlinks += 1,
+            Kind::Regular => stats.regular_files += 1,
+            _ => stats.special_files += 1,
+        }
+
+        if inode.kind != Kind::Regular {
+            continue;
+        }
+        stats.file_bytes += inode.size;
+
+        let covering = pages.covering_pages(&(0..inode.size));
+        let written = if inode.extents {
+            pages
+                .written_pages(&mut tx, inode.ino, covering)
+                .await?
+                .len() as u64
+        } else {
+            covering.end - covering.start
+        };
+        stats.stored_bytes += written * cfg.page_size;
+    }
+
+    let mut reply = tx.read(cfg.bucket, vec![orphan::read()]).await?;
+    stats.orphans = orphan::decode(&mut reply, 0).len() as u64;
+    stats.ino_counter = InoGenerator::peek(&mut tx, cfg.view, cfg.bucket).await?;
+
+    tx.commit().await?;
+    Ok(stats)
+}
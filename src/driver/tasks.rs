@@ -0,0 +1,72 @@
+use crate::driver::runtime;
+use async_std::sync::Arc;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+const DRAIN_POLL: Duration = Duration::from_millis(10);
+
+/* Work that must not be cut short by a shutdown, e.g in the middle of a
+transaction. Once closing, background loops stop taking new work but
+what is spawned still runs, as in-flight operations might need it to
+finish, e.g to remove an unlinked inode. */
+#[derive(Debug, Default)]
+pub(crate) struct Tasks {
+    in_flight: AtomicUsize,
+    closing: AtomicBool,
+}
+
+pub(crate) struct Work {
+    tasks: Arc<Tasks>,
+}
+
+impl Drop for Work {
+    fn drop(&mut self) {
+        self.tasks.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl Tasks {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a piece of work of a background loop, `None` once closing.
+    pub(crate) fn enter(self: &Arc<Self>) -> Option<Work> {
+        if self.closing.load(Ordering::Acquire) {
+            return None;
+        }
+
+        Some(self.work())
+    }
+
+    pub(crate) fn spawn<F>(self: &Arc<Self>, f: F)
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let work = self.work();
+        runtime::spawn(async move {
+            let output = f.await;
+            drop(work);
+            output
+        });
+    }
+
+    /// Waits for every piece of work to be done, no new one is started by
+    /// background loops past this point.
+    pub(crate) async fn close(&self) {
+        self.closing.store(true, Ordering::Release);
+
+        while self.in_flight.load(Ordering::Acquire) > 0 {
+            runtime::sleep(DRAIN_POLL).await;
+        }
+    }
+
+    fn work(self: &Arc<Self>) -> Work {
+        self.in_flight.fetch_add(1, Ordering::AcqRel);
+        Work {
+            tasks: self.clone(),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use crate::driver::changes::ChangeWatch;
+use crate::driver::page::PageWriter;
+use crate::driver::pool::ConnectionPool;
+use crate::driver::{gc, now, Config, Error, Result, TxClass};
+use crate::model::dir::{self, Entry};
+use crate::model::inode::{self, Kind};
+use crate::model::orphan;
+use crate::view::NameRef;
+use antidotec::TransactionLocks;
+use nix::errno::Errno;
+
+const REMOVE_BATCH: usize = 256;
+
+/* A directory being emptied, along with its entry in its parent. */
+#[derive(Debug, Clone)]
+struct Level {
+    parent_ino: u64,
+    entry: Entry,
+}
+
+/* What a pass over a directory did. */
+#[derive(Debug)]
+enum Pass {
+    /// Entries other than directories were unlinked.
+    Unlinked(Vec<u64>),
+    /// A directory that still holds entries was found.
+    Descend(Entry),
+    /// The directory was empty and got unlinked from its parent.
+    Removed(u64),
+}
+
+/* Removes the directory named `name` in `parent_ino` and everything under
+it, depth first. Each pass unlinks a batch of entries of a directory in a
+single transaction, then the inodes unlinked are removed as orphans are.
+Returns the number of inodes unlinked. */
+pub(crate) async fn remove(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    pages: &PageWriter,
+    changes: &ChangeWatch,
+    parent_ino: u64,
+    name: &NameRef,
+) -> Result<u64> {
+    let entry = {
+        let mut connection = pool.acquire().await?;
+        let mut tx = transaction!(cfg, connection, { shared: [dir::key(parent_ino)] }).await?;
+
+        let mut reply = tx
+            .read(cfg.bucket, vec![dir::read(parent_ino, name.prefix())])
+            .await?;
+        let entries = dir::decode(cfg.view, &mut reply, 0);
+        let entry = entries.get(name).ok_or(Error::NotFound)?.into_dentry();
+
+        tx.commit().await?;
+        entry
+    };
+    if entry.kind != Kind::Directory {
+        return Err(Error::Sys(Errno::ENOTDIR));
+    }
+
+    let mut unlinked = 0;
+    let mut stack = vec![Level { parent_ino, entry }];
+    while let Some(level) = stack.last().cloned() {
+        match pass(cfg, pool, changes, &level).await? {
+            Pass::Unlinked(inos) => {
+                unlinked += inos.len() as u64;
+                for ino in inos {
+                    gc::delete_later(cfg, pool, pages, ino).await?;
+                }
+            }
+            Pass::Descend(entry) => {
+                stack.push(Level {
+                    parent_ino: level.entry.ino,
+                    entry,
+                });
+            }
+            Pass::Removed(ino) => {
+                unlinked += 1;
+                stack.pop();
+                gc::delete_later(cfg, pool, pages, ino).await?;
+            }
+        }
+    }
+
+    tracing::info!(unlinked, "tree removed");
+    Ok(unlinked)
+}
+
+/* The entries of the directory are read in the transaction that unlinks
+them, those created since then conflict with it. Once empty, the directory
+is unlinked from its parent as rmdir does. */
+async fn pass(
+    cfg: &Config,
+    pool: &ConnectionPool,
+    changes: &ChangeWatch,
+    level: &Level,
+) -> Result<Pass> {
+    let (parent_ino, ino) = (level.parent_ino, level.entry.ino);
+
+    let mut connection = pool.acquire().await?;
+    let mut tx = transaction!(cfg, connection, TxClass::Namespace, {
+        exclusive: [
+            inode::key(parent_ino),
+            dir::key(parent_ino),
+            inode::key(ino),
+            dir::key(ino)
+        ]
+    })
+    .await?;
+
+    let shards = dir::SHARDS as usize;
+    let reads = dir::read_all(ino)
+        .chain(Some(inode::read(ino)))
+        .chain(Some(inode::read(parent_ino)));
+    let mut reply = tx.read(cfg.bucket, reads).await?;
+
+    let children = dir::decode_all(cfg.view, &mut reply, 0);
+    let mut dir_inode = inode::decode(ino, &mut reply, shards).ok_or(Error::NotFound)?;
+    let mut parent = inode::decode(parent_ino, &mut reply, shards + 1).ok_or(Error::NotFound)?;
+
+    let children: Vec<Entry> = children
+        .iter()
+        .flat_map(|children| children.entries())
+        .filter(|entry| &*entry.prefix != b"." && &*entry.prefix != b"..")
+        .map(|entry| entry.into_dentry())
+        .collect();
+
+    let t = now(cfg);
+    let files: Vec<&Entry> = children
+        .iter()
+        .filter(|entry| entry.kind != Kind::Directory)
+        .take(REMOVE_BATCH)
+        .collect();
+    if !files.is_empty() {
+        dir_inode.modified(t);
+        dir_inode.changed(t);
+        dir_inode.size = dir_inode.size.saturating_sub(files.len() as u64);
+
+        let mut updates = Vec::with_capacity(3 * files.len() + 2);
+        for entry in &files {
+            updates.push(dir::remove_entry(ino, entry));
+            updates.push(inode::decr_link_count(entry.ino, 1));
+            updates.push(orphan::add(entry.ino));
+        }
+        updates.push(inode::update_stats_and_size(&dir_inode));
+        updates.push(changes.bump());
+        tx.update(cfg.bucket, updates).await?;
+
+        tx.commit().await?;
+        return Ok(Pass::Unlinked(
+            files.iter().map(|entry| entry.ino).collect(),
+        ));
+    }
+
+    if let Some(subdir) = children.into_iter().next() {
+        tx.commit().await?;
+        return Ok(Pass::Descend(subdir));
+    }
+
+    parent.accessed(t);
+    parent.modified(t);
+    parent.size = parent.size.saturating_sub(1);
+    tx.update(
+        cfg.bucket,
+        vec![
+            inode::decr_link_count(ino, 1),
+            dir::remove_entry(parent_ino, &level.entry),
+            inode::update_stats_and_size(&parent),
+            inode::decr_link_count(parent_ino, 1),
+            orphan::add(ino),
+            changes.bump(),
+        ],
+    )
+    .await?;
+
+    tx.commit().await?;
+    Ok(Pass::Removed(ino))
+}
@@ -0,0 +1,135 @@
+use crate::driver::pool::ConnectionPool;
+use crate::driver::tasks::Tasks;
+use crate::driver::{now, runtime, Config, Error, Result};
+use crate::model::views::{self, ViewRecord};
+use crate::view::View;
+use antidotec::TransactionLocks;
+use async_std::sync::Arc;
+use nix::errno::Errno;
+use nix::unistd;
+use std::time::Duration;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/* A mount announces its view, and where it runs, when it starts and then
+now and then, so that views can be told apart from the ones gone for long.
+Records never conflict: each view only ever writes its own. */
+pub(crate) async fn run(cfg: Config, pool: Arc<ConnectionPool>, tasks: Arc<Tasks>) {
+    let hostname = hostname();
+
+    loop {
+        let work = match tasks.enter() {
+            Some(work) => work,
+            None => return,
+        };
+
+        let record = ViewRecord {
+            view: cfg.view,
+            hostname: hostname.clone(),
+            last_seen: now(&cfg),
+        };
+        if let Err(error) = announce(&cfg, &pool, &record).await {
+            tracing::warn!(?error, "failed to announce the view");
+        }
+
+        drop(work);
+        runtime::sleep(HEARTBEAT_INTERVAL).await;
+    }
+}
+
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    match unistd::gethostname(&mut buffer) {
+        Ok(hostname) => hostname.to_string_lossy().into_owned(),
+        Err(_) => String::new(),
+    }
+}
+
+async fn announce(cfg: &Config, pool: &ConnectionPool, record: &ViewRecord) -> Result<()> {
+    let mut connection = pool.acquire().await?;
+    let mut tx = connection
+        .transaction_with_locks(TransactionLocks::new())
+        .await?;
+
+    tx.update(cfg.bucket, vec![views::seen(record)]).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub(crate) async fn list(cfg: &Config, pool: &ConnectionPool) -> Result<Vec<ViewRecord>> {
+    let mut connection = pool.acquire().await?;
+    let mut reply = connection
+        .read_static(cfg.snapshot.as_ref(), cfg.bucket, vec![views::read()])
+        .await?;
+
+    Ok(views::decode(&mut reply, 0))
+}
+
+/* A view still mounted announces itself again with its next heartbeat. */
+#[tracing::instrument(skip(cfg, pool))]
+pub(crate) async fn forget(cfg: &Config, pool: &ConnectionPool, view: View) -> Result<()> {
+    if cfg.snapshot.is_some() {
+        return Err(Error::Sys(Errno::EROFS));
+    }
+
+    let mut connection = pool.acquire().await?;
+    let mut tx = transaction!(cfg, connection, { exclusive: [views::key()] }).await?;
+
+    let mut reply = tx.read(cfg.bucket, vec![views::read()]).await?;
+    if !views::decode(&mut reply, 0)
+        .iter()
+        .any(|record| record.view == view)
+    {
+        tx.commit().await?;
+        return Err(Error::NotFound);
+    }
+
+    tx.update(cfg.bucket, vec![views::forget(view)]).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/* Views are handed out in order from 1, view 0 being left to mounts picking
+their own, skipping the ones already announced. Locks are taken whatever
+the policy, two mounts given the same view would corrupt each other. */
+#[tracing::instrument(skip(cfg, pool))]
+pub(crate) async fn allocate(cfg: &Config, pool: &ConnectionPool) -> Result<View> {
+    let mut connection = pool.acquire().await?;
+    let mut tx = connection
+        .transaction_with_locks(TransactionLocks {
+            shared: vec![],
+            exclusive: vec![views::counter_key().into(), views::key().into()],
+        })
+        .await?;
+
+    let mut reply = tx
+        .read(cfg.bucket, vec![views::read_allocated(), views::read()])
+        .await?;
+    let last = views::decode_allocated(&mut reply, 0);
+    let known = views::decode(&mut reply, 1);
+
+    let view = last.checked_add(1).and_then(|first| {
+        (first..=View::MAX).find(|view| !known.iter().any(|record| record.view == *view))
+    });
+    let view = match view {
+        Some(view) => view,
+        None => {
+            tx.commit().await?;
+            return Err(Error::Sys(Errno::ENOSPC));
+        }
+    };
+
+    let record = ViewRecord {
+        view,
+        hostname: hostname(),
+        last_seen: now(cfg),
+    };
+    tx.update(
+        cfg.bucket,
+        vec![views::allocated(last, view), views::seen(&record)],
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(view)
+}
@@ -1,11 +1,16 @@
-use crate::driver::Driver;
+use crate::driver::{
+    budget, metrics, Driver, IdMap, CONFLICTS_XATTR, PAGE_VERSIONS_XATTR, ROOT_INO,
+};
 use crate::model::inode::Owner;
-use async_std::{sync::Arc, task};
-use fuse::{Filesystem, *};
+use crate::view::NameRefParseError;
+use crate::FuseOptions;
+use async_std::sync::Arc;
+use fuser::{consts, Filesystem, *};
 use nix::{errno::Errno, libc};
 use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
-use time::Timespec;
+use std::time::SystemTime;
 use tracing_futures::Instrument;
 
 macro_rules! function {
@@ -31,13 +36,16 @@ macro_rules! check_utf8 {
     };
 }
 
+/* Names are taken as they are, whatever their encoding. */
 macro_rules! check_name {
-    ($reply:expr, $str:ident) => {{
-        let n = check_utf8!($reply, $str);
-
-        match n.parse() {
+    ($driver:expr, $reply:expr, $str:ident) => {{
+        match $driver.naming().parse($str.as_bytes()) {
             Ok(name) => name,
-            Err(_) => {
+            Err(NameRefParseError::TooLong) => {
+                $reply.error(Errno::ENAMETOOLONG as libc::c_int);
+                return;
+            }
+            Err(NameRefParseError::Invalid) => {
                 $reply.error(Errno::EINVAL as libc::c_int);
                 return;
             }
@@ -45,24 +53,54 @@ macro_rules! check_name {
     }};
 }
 
-fn ttl() -> time::Timespec {
-    time::Timespec::new(0, 0)
+macro_rules! check_offset {
+    ($reply:expr, $offset:ident) => {
+        if $offset < 0 {
+            $reply.error(Errno::EINVAL as libc::c_int);
+            return;
+        }
+        let $offset = $offset as u64;
+    };
+}
+
+/* A size of zero only asks for the size of the value. */
+fn reply_xattr(reply: ReplyXattr, size: u32, value: &[u8]) {
+    if size == 0 {
+        reply.size(value.len() as u32);
+    } else if value.len() > size as usize {
+        reply.error(libc::ERANGE);
+    } else {
+        reply.data(value);
+    }
+}
+
+fn caller(driver: &Driver, req: &Request) -> Owner {
+    driver.idmap().caller(Owner {
+        gid: req.gid(),
+        uid: req.uid(),
+    })
 }
 
 macro_rules! session {
-    ($req:expr, $reply:ident, $op:expr, $ok:ident => $resp:block) => {
+    ($req:expr, $reply:ident, $driver:ident.$method:ident($($arg:expr),*), $ok:ident => $resp:block) => {
         let unique = $req.unique();
         let (uid, gid) = ($req.uid(), $req.gid());
+        let op = function!();
+        let budget = $driver.round_trip_budget();
+        let metrics = $driver.metrics();
+        let dispatcher = $driver.dispatcher();
+        let interrupts = $driver.interrupts();
 
         let task = async move {
-            let result = $op.await;
+            let result = budget::track(op, budget, $driver.$method($($arg),*));
+            let result = interrupts.run(unique, metrics::track(&metrics, op, result)).await;
 
             if result.is_ok() {
                 let result: Result<_, ()> = Ok(()); /* omit the content */
                 tracing::debug!(?result);
             } else {
                 match &result {
-                    Err(crate::driver::Error::Sys(Errno::ENOENT)) => {}
+                    Err(crate::driver::Error::NotFound) => {}
                     result => {
                         tracing::error!(?result);
                     }
@@ -74,77 +112,191 @@ macro_rules! session {
                     $resp
                 }
                 Err(error) => {
-                    match error {
-                        crate::driver::Error::Sys(errno) => {
-                            $reply.error(errno as libc::c_int);
-                        },
-                        crate::driver::Error::Antidote(_) => {
-                            $reply.error(Errno::EIO as libc::c_int);
-                        }
-                    }
+                    $reply.error(error.errno() as libc::c_int);
                 }
             }
         };
         let task = task.instrument(
-            tracing::trace_span!("session", op = function!(), id = unique, uid, gid)
+            tracing::trace_span!("session", op, id = unique, uid, gid)
         );
 
-        task::spawn(task);
+        dispatcher.dispatch(op, task);
     };
 
-    ($req:expr, $reply:ident, $op:expr, _ => $resp:block) => {
-        session!($req, $reply, $op, _r => $resp);
+    ($req:expr, $reply:ident, $driver:ident.$method:ident($($arg:expr),*), _ => $resp:block) => {
+        session!($req, $reply, $driver.$method($($arg),*), _r => $resp);
     };
 }
 
+/* Replies carry owners as they are on this host and the root of the mount
+as the kernel numbers it, whatever the directory it is rooted at. */
+struct Local {
+    idmap: Arc<IdMap>,
+    root: u64,
+}
+
+impl Local {
+    fn new(driver: &Driver) -> Self {
+        Self {
+            idmap: driver.idmap(),
+            root: driver.root(),
+        }
+    }
+
+    fn attr(&self, attr: FileAttr) -> FileAttr {
+        let mut attr = self.idmap.local_attr(attr);
+        attr.ino = self.ino(attr.ino);
+        attr
+    }
+
+    fn ino(&self, ino: u64) -> u64 {
+        if ino == self.root {
+            ROOT_INO
+        } else {
+            ino
+        }
+    }
+}
+
 pub struct Elmerfs {
     pub(crate) driver: Arc<Driver>,
+    pub(crate) options: FuseOptions,
 }
 
+/* There is no interrupt callback: fuser answers FUSE_INTERRUPT itself with
+ENOSYS, after which the kernel stops sending them for the session. Each
+operation is still interruptible by the unique id of its request, through
+`.elmerfs/interrupt`: it then fails with EINTR at its next request to
+Antidote, which aborts its transaction. */
 impl Filesystem for Elmerfs {
+    /* Listings carry the attributes of their entries, the kernel asks for
+    them only when it is about to look the entries up. fcntl and flock locks
+    are both handled here, to be shared with other views. */
+    fn init(&mut self, _req: &Request, config: &mut KernelConfig) -> Result<(), libc::c_int> {
+        let mut capabilities = consts::FUSE_DO_READDIRPLUS
+            | consts::FUSE_READDIRPLUS_AUTO
+            | consts::FUSE_POSIX_LOCKS
+            | consts::FUSE_FLOCK_LOCKS;
+        if self.options.big_writes {
+            capabilities |= consts::FUSE_BIG_WRITES;
+        }
+        if let Err(unsupported) = config.add_capabilities(capabilities) {
+            tracing::warn!(unsupported, "capabilities not supported by the kernel");
+        }
+
+        if let Some(max_write) = self.options.max_write {
+            if let Err(nearest) = config.set_max_write(max_write) {
+                tracing::warn!(max_write, nearest, "max_write out of bounds");
+                let _ = config.set_max_write(nearest);
+            }
+        }
+        if let Some(max_readahead) = self.options.max_readahead {
+            if let Err(nearest) = config.set_max_readahead(max_readahead) {
+                tracing::warn!(max_readahead, nearest, "max_readahead out of bounds");
+                let _ = config.set_max_readahead(nearest);
+            }
+        }
+
+        Ok(())
+    }
+
     fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
+        let ino = self.driver.stored_ino(ino);
         let driver = self.driver.clone();
 
+        let attr_ttl = driver.attr_ttl();
+        let local = Local::new(&driver);
         session!(req, reply, driver.getattr(ino), attrs => {
-            reply.attr(&ttl(), &attrs);
+            driver.attr_replied(attrs.ino);
+            reply.attr(&attr_ttl, &local.attr(attrs));
         });
     }
 
-    fn opendir(&mut self, req: &Request, ino: u64, _flags: u32, reply: ReplyOpen) {
+    fn access(&mut self, req: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
+        let ino = self.driver.stored_ino(ino);
+        let caller = caller(&self.driver, req);
         let driver = self.driver.clone();
 
-        session!(req, reply, driver.opendir(ino), _ => {
+        session!(req, reply, driver.access(caller, ino, mask as u32), _ => {
+            reply.ok();
+        });
+    }
+
+    fn opendir(&mut self, req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        let ino = self.driver.stored_ino(ino);
+        let caller = caller(&self.driver, req);
+        let driver = self.driver.clone();
+
+        session!(req, reply, driver.opendir(caller, ino, flags as u32), fh => {
             let flags = 0;
-            reply.opened(ino, flags);
+            reply.opened(fh, flags);
         });
     }
 
-    fn releasedir(&mut self, req: &Request, ino: u64, _fh: u64, _flags: u32, reply: ReplyEmpty) {
+    fn releasedir(&mut self, req: &Request, ino: u64, fh: u64, _flags: i32, reply: ReplyEmpty) {
+        let ino = self.driver.stored_ino(ino);
         let driver = self.driver.clone();
 
-        session!(req, reply, driver.opendir(ino), _ => {
+        session!(req, reply, driver.releasedir(ino, fh), _ => {
             reply.ok()
         });
     }
 
+    fn fsyncdir(&mut self, req: &Request, ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        let ino = self.driver.stored_ino(ino);
+        let driver = self.driver.clone();
+
+        session!(req, reply, driver.fsync(ino), _ => {
+            reply.ok();
+        });
+    }
+
     fn readdir(
         &mut self,
         req: &Request,
         ino: u64,
-        _fh: u64,
+        fh: u64,
         offset: i64,
         mut reply: ReplyDirectory,
     ) {
+        let ino = self.driver.stored_ino(ino);
         let driver = self.driver.clone();
 
-        session!(req, reply, driver.readdir(ino, offset), entries => {
-            for (i, entry) in entries.into_iter().enumerate() {
-                let offset = offset + i as i64 + 1;
+        let local = Local::new(&driver);
+        session!(req, reply, driver.readdir(ino, fh, offset), entries => {
+            for entry in entries {
+                let full = reply.add(local.ino(entry.ino), entry.offset, entry.kind, entry.name);
+                if full {
+                    break;
+                }
+            }
+
+            reply.ok();
+        });
+    }
 
-                let full = reply.add(entry.ino, offset, entry.kind, entry.name);
+    fn readdirplus(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectoryPlus,
+    ) {
+        let ino = self.driver.stored_ino(ino);
+        let driver = self.driver.clone();
+
+        let entry_ttl = driver.entry_ttl();
+        let local = Local::new(&driver);
+        session!(req, reply, driver.readdirplus(ino, fh, offset), entries => {
+            for (entry, attrs) in entries {
+                let generation = attrs.generation;
+                let attrs = local.attr(attrs.attr);
+                let full = reply.add(local.ino(entry.ino), entry.offset, &entry.name, &entry_ttl, &attrs, generation);
                 if full {
                     break;
                 }
+                driver.entry_replied(ino, &entry.name, entry.ino);
             }
 
             reply.ok();
@@ -152,12 +304,17 @@ impl Filesystem for Elmerfs {
     }
 
     fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        let name = check_name!(reply, name);
+        let parent = self.driver.stored_ino(parent);
+        let replied = name.to_os_string();
+        let name = check_name!(self.driver, reply, name);
+        let caller = caller(&self.driver, req);
         let driver = self.driver.clone();
 
-        session!(req, reply, driver.lookup(parent, name), attrs => {
-            let generation = 0;
-            reply.entry(&ttl(), &attrs, generation);
+        let entry_ttl = driver.entry_ttl();
+        let local = Local::new(&driver);
+        session!(req, reply, driver.lookup(caller, parent, name), attrs => {
+            driver.entry_replied(parent, &replied, attrs.attr.ino);
+            reply.entry(&entry_ttl, &local.attr(attrs.attr), attrs.generation);
         });
     }
 
@@ -167,26 +324,34 @@ impl Filesystem for Elmerfs {
         parent_ino: u64,
         name: &OsStr,
         mode: u32,
+        umask: u32,
         reply: ReplyEntry,
     ) {
-        let owner = Owner {
-            gid: req.gid(),
-            uid: req.uid(),
-        };
-        let name = check_name!(reply, name);
+        let parent_ino = self.driver.stored_ino(parent_ino);
+        let owner = caller(&self.driver, req);
+        let replied = name.to_os_string();
+        let name = check_name!(self.driver, reply, name);
+        let unique = req.unique();
+        /* The kernel masks modes itself unless asked not to, doing so again
+        changes nothing. */
+        let mode = mode & !umask;
         let driver = self.driver.clone();
 
-        session!(req, reply, driver.mkdir(owner, mode, parent_ino, name), attrs => {
-            let generation = 0;
-            reply.entry(&ttl(), &attrs, generation);
+        let entry_ttl = driver.entry_ttl();
+        let local = Local::new(&driver);
+        session!(req, reply, driver.mkdir(owner, mode, parent_ino, name, unique), attrs => {
+            driver.entry_replied(parent_ino, &replied, attrs.attr.ino);
+            reply.entry(&entry_ttl, &local.attr(attrs.attr), attrs.generation);
         });
     }
 
     fn rmdir(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
-        let name = check_name!(reply, name);
+        let parent = self.driver.stored_ino(parent);
+        let name = check_name!(self.driver, reply, name);
+        let caller = caller(&self.driver, req);
         let driver = self.driver.clone();
 
-        session!(req, reply, driver.rmdir(parent, name), _ => {
+        session!(req, reply, driver.rmdir(caller, parent, name), _ => {
             reply.ok();
         });
     }
@@ -197,27 +362,60 @@ impl Filesystem for Elmerfs {
         parent: u64,
         name: &OsStr,
         mode: u32,
+        umask: u32,
         rdev: u32,
         reply: ReplyEntry,
     ) {
-        let name = check_name!(reply, name);
-        let owner = Owner {
-            gid: req.gid(),
-            uid: req.uid(),
-        };
+        let parent = self.driver.stored_ino(parent);
+        let replied = name.to_os_string();
+        let name = check_name!(self.driver, reply, name);
+        let owner = caller(&self.driver, req);
+        let unique = req.unique();
+        let mode = mode & !umask;
         let driver = self.driver.clone();
 
-        session!(req, reply, driver.mknod(owner, mode, parent, name, rdev), attrs => {
-            let generation = 0;
-            reply.entry(&ttl(), &attrs, generation);
+        let entry_ttl = driver.entry_ttl();
+        let local = Local::new(&driver);
+        session!(req, reply, driver.mknod(owner, mode, parent, name, rdev, unique), attrs => {
+            driver.entry_replied(parent, &replied, attrs.attr.ino);
+            reply.entry(&entry_ttl, &local.attr(attrs.attr), attrs.generation);
+        });
+    }
+
+    fn create(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        flags: i32,
+        reply: ReplyCreate,
+    ) {
+        let parent = self.driver.stored_ino(parent);
+        let replied = name.to_os_string();
+        let name = check_name!(self.driver, reply, name);
+        let owner = caller(&self.driver, req);
+        let unique = req.unique();
+        let mode = mode & !umask;
+        let driver = self.driver.clone();
+
+        let entry_ttl = driver.entry_ttl();
+        let local = Local::new(&driver);
+        session!(req, reply, driver.create(owner, mode, flags as u32, parent, name, unique), created => {
+            let (attrs, fh) = created;
+            driver.entry_replied(parent, &replied, attrs.attr.ino);
+            reply.created(&entry_ttl, &local.attr(attrs.attr), attrs.generation, fh, 0);
         });
     }
 
     fn unlink(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
-        let name = check_name!(reply, name);
+        let parent = self.driver.stored_ino(parent);
+        let name = check_name!(self.driver, reply, name);
+        let caller = caller(&self.driver, req);
         let driver = self.driver.clone();
 
-        session!(req, reply, driver.unlink(parent, name), _ => {
+        session!(req, reply, driver.unlink(caller, parent, name), _ => {
             reply.ok();
         });
     }
@@ -230,36 +428,52 @@ impl Filesystem for Elmerfs {
         uid: Option<u32>,
         gid: Option<u32>,
         size: Option<u64>,
-        atime: Option<Timespec>,
-        mtime: Option<Timespec>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
         _fh: Option<u64>,
-        _crtime: Option<Timespec>,
-        _chgtime: Option<Timespec>,
-        _bkuptime: Option<Timespec>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
         _flags: Option<u32>,
         reply: ReplyAttr,
     ) {
-        let t2d = |t: time::Timespec| std::time::Duration::new(t.sec as u64, t.nsec as u32);
-        let atime = atime.map(t2d);
-        let mtime = mtime.map(t2d);
+        let ino = self.driver.stored_ino(ino);
+        let caller = caller(&self.driver, req);
         let driver = self.driver.clone();
+        let uid = uid.map(|uid| driver.idmap().stored_uid(uid));
+        let gid = gid.map(|gid| driver.idmap().stored_gid(gid));
 
+        let attr_ttl = driver.attr_ttl();
+        let local = Local::new(&driver);
         session!(
             req,
             reply,
-            driver.setattr(ino, mode, uid, gid, size, atime, mtime),
+            driver.setattr(caller, ino, mode, uid, gid, size, atime, mtime),
             attrs => {
-                reply.attr(&ttl(), &attrs);
+                driver.attr_replied(attrs.ino);
+                reply.attr(&attr_ttl, &local.attr(attrs));
             }
         );
     }
 
-    fn open(&mut self, req: &Request, ino: u64, _flags: u32, reply: ReplyOpen) {
+    fn open(&mut self, req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        let ino = self.driver.stored_ino(ino);
+        let caller = caller(&self.driver, req);
         let driver = self.driver.clone();
+        let open_flags = driver.open_flags(ino);
 
-        session!(req, reply, driver.open(ino), _ => {
-            let flags = 0;
-            reply.opened(ino, flags);
+        session!(req, reply, driver.open(caller, ino, flags as u32), fh => {
+            reply.opened(fh, open_flags);
+        });
+    }
+
+    fn flush(&mut self, req: &Request, ino: u64, _fh: u64, lock_owner: u64, reply: ReplyEmpty) {
+        let ino = self.driver.stored_ino(ino);
+        let driver = self.driver.clone();
+
+        session!(req, reply, driver.flush(ino, lock_owner), _ => {
+            reply.ok();
         });
     }
 
@@ -267,15 +481,25 @@ impl Filesystem for Elmerfs {
         &mut self,
         req: &Request,
         ino: u64,
-        _fh: u64,
-        _flags: u32,
-        _lock_owner: u64,
+        fh: u64,
+        _flags: i32,
+        lock_owner: Option<u64>,
         _flush: bool,
         reply: ReplyEmpty,
     ) {
+        let ino = self.driver.stored_ino(ino);
         let driver = self.driver.clone();
 
-        session!(req, reply, driver.release(ino), _ => {
+        session!(req, reply, driver.release(ino, fh, lock_owner), _ => {
+            reply.ok();
+        });
+    }
+
+    fn fsync(&mut self, req: &Request, ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        let ino = self.driver.stored_ino(ino);
+        let driver = self.driver.clone();
+
+        session!(req, reply, driver.fsync(ino), _ => {
             reply.ok();
         });
     }
@@ -284,22 +508,22 @@ impl Filesystem for Elmerfs {
         &mut self,
         req: &Request,
         ino: u64,
-        _fh: u64,
+        fh: u64,
         offset: i64,
         data: &[u8],
-        _flags: u32,
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
         reply: ReplyWrite,
     ) {
-        if offset < 0 {
-            reply.error(Errno::EINVAL as libc::c_int);
-            return;
-        }
-        let offset = offset as u64;
+        let ino = self.driver.stored_ino(ino);
+        check_offset!(reply, offset);
         let driver = self.driver.clone();
-        let data = Vec::from(data);
+        let len = data.len() as u32;
+        let data = driver.buffer(data);
 
-        session!(req, reply, driver.write(ino, &data, offset), _ => {
-            reply.written(data.len() as u32);
+        session!(req, reply, driver.write(ino, fh, data, offset), _ => {
+            reply.written(len);
         });
     }
 
@@ -310,13 +534,12 @@ impl Filesystem for Elmerfs {
         _fh: u64,
         offset: i64,
         size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
         reply: ReplyData,
     ) {
-        if offset < 0 {
-            reply.error(Errno::EINVAL as libc::c_int);
-            return;
-        }
-        let offset = offset as u64;
+        let ino = self.driver.stored_ino(ino);
+        check_offset!(reply, offset);
         let driver = self.driver.clone();
 
         session!(req, reply, driver.read(ino, offset, size), data => {
@@ -331,13 +554,17 @@ impl Filesystem for Elmerfs {
         name: &OsStr,
         newparent: u64,
         newname: &OsStr,
+        flags: u32,
         reply: ReplyEmpty,
     ) {
-        let name = check_name!(reply, name);
-        let newname = check_name!(reply, newname);
+        let parent = self.driver.stored_ino(parent);
+        let newparent = self.driver.stored_ino(newparent);
+        let name = check_name!(self.driver, reply, name);
+        let newname = check_name!(self.driver, reply, newname);
+        let caller = caller(&self.driver, req);
         let driver = self.driver.clone();
 
-        session!(req, reply, driver.rename(parent, name, newparent, newname), _ => {
+        session!(req, reply, driver.rename(caller, parent, name, newparent, newname, flags), _ => {
             reply.ok();
         });
     }
@@ -350,12 +577,18 @@ impl Filesystem for Elmerfs {
         newname: &OsStr,
         reply: ReplyEntry,
     ) {
-        let newname = check_name!(reply, newname);
+        let ino = self.driver.stored_ino(ino);
+        let newparent = self.driver.stored_ino(newparent);
+        let replied = newname.to_os_string();
+        let newname = check_name!(self.driver, reply, newname);
+        let caller = caller(&self.driver, req);
         let driver = self.driver.clone();
 
-        session!(req, reply, driver.link(ino, newparent, newname), attrs => {
-            let generation = 0;
-            reply.entry(&ttl(), &attrs, generation);
+        let entry_ttl = driver.entry_ttl();
+        let local = Local::new(&driver);
+        session!(req, reply, driver.link(caller, ino, newparent, newname), attrs => {
+            driver.entry_replied(newparent, &replied, attrs.attr.ino);
+            reply.entry(&entry_ttl, &local.attr(attrs.attr), attrs.generation);
         });
     }
 
@@ -367,26 +600,193 @@ impl Filesystem for Elmerfs {
         link: &Path,
         reply: ReplyEntry,
     ) {
+        let parent = self.driver.stored_ino(parent);
         let link = link.as_os_str();
         let link = check_utf8!(reply, link);
-        let name = check_name!(reply, name);
-        let owner = Owner {
-            gid: req.gid(),
-            uid: req.uid(),
-        };
+        let replied = name.to_os_string();
+        let name = check_name!(self.driver, reply, name);
+        let owner = caller(&self.driver, req);
         let driver = self.driver.clone();
 
+        let entry_ttl = driver.entry_ttl();
+        let local = Local::new(&driver);
         session!(req, reply, driver.symlink(parent, owner, name, link), attrs => {
-            let generation = 0;
-            reply.entry(&ttl(), &attrs, generation);
+            driver.entry_replied(parent, &replied, attrs.attr.ino);
+            reply.entry(&entry_ttl, &local.attr(attrs.attr), attrs.generation);
         });
     }
 
+    fn getlk(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        _fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+        reply: ReplyLock,
+    ) {
+        let ino = self.driver.stored_ino(ino);
+        let driver = self.driver.clone();
+
+        session!(req, reply, driver.getlk(ino, lock_owner, start, end, typ as u32, pid), lock => {
+            let (start, end, typ, pid) = lock;
+            reply.locked(start, end, typ as i32, pid);
+        });
+    }
+
+    /* flock(2) locks come here too, as whole file write or read locks owned
+    by the open file, and are released with it. */
+    fn setlk(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        _fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+        sleep: bool,
+        reply: ReplyEmpty,
+    ) {
+        let ino = self.driver.stored_ino(ino);
+        let driver = self.driver.clone();
+
+        session!(
+            req,
+            reply,
+            driver.setlk(ino, lock_owner, start, end, typ as u32, pid, sleep),
+            _ => {
+                reply.ok();
+            }
+        );
+    }
+
     fn readlink(&mut self, req: &Request, ino: u64, reply: ReplyData) {
+        let ino = self.driver.stored_ino(ino);
         let driver = self.driver.clone();
 
         session!(req, reply, driver.read_link(ino), path => {
             reply.data(path.as_bytes());
         });
     }
+
+    fn getxattr(&mut self, req: &Request, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        /* The kernel asks for security attributes before every write, none
+        of them ever exists. */
+        let computed = [CONFLICTS_XATTR, PAGE_VERSIONS_XATTR];
+        if !computed
+            .iter()
+            .any(|computed| computed.as_bytes() == name.as_bytes())
+        {
+            reply.error(libc::ENODATA);
+            return;
+        }
+
+        let ino = self.driver.stored_ino(ino);
+        let name = name.as_bytes().to_vec();
+        let driver = self.driver.clone();
+
+        session!(req, reply, driver.get_xattr(ino, &name), value => {
+            reply_xattr(reply, size, &value);
+        });
+    }
+
+    fn listxattr(&mut self, req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        let ino = self.driver.stored_ino(ino);
+        let driver = self.driver.clone();
+
+        session!(req, reply, driver.list_xattr(ino), names => {
+            reply_xattr(reply, size, &names);
+        });
+    }
+
+    fn fallocate(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        let ino = self.driver.stored_ino(ino);
+        check_offset!(reply, offset);
+        check_offset!(reply, length);
+        let driver = self.driver.clone();
+
+        session!(req, reply, driver.fallocate(ino, offset, length, mode), _ => {
+            reply.ok();
+        });
+    }
+
+    fn lseek(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        whence: i32,
+        reply: ReplyLseek,
+    ) {
+        let ino = self.driver.stored_ino(ino);
+        check_offset!(reply, offset);
+        let driver = self.driver.clone();
+
+        session!(req, reply, driver.lseek(ino, offset, whence), offset => {
+            reply.offset(offset as i64);
+        });
+    }
+
+    fn poll(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        ph: PollHandle,
+        events: u32,
+        flags: u32,
+        reply: ReplyPoll,
+    ) {
+        let ino = self.driver.stored_ino(ino);
+        let driver = self.driver.clone();
+
+        session!(req, reply, driver.poll(ino, fh, ph, events, flags), revents => {
+            reply.poll(revents);
+        });
+    }
+
+    fn copy_file_range(
+        &mut self,
+        req: &Request,
+        ino_in: u64,
+        _fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        _fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        let ino_in = self.driver.stored_ino(ino_in);
+        let ino_out = self.driver.stored_ino(ino_out);
+        check_offset!(reply, offset_in);
+        check_offset!(reply, offset_out);
+        let driver = self.driver.clone();
+
+        session!(
+            req,
+            reply,
+            driver.copy_file_range(ino_in, offset_in, ino_out, offset_out, len),
+            copied => {
+                /* The kernel never asks for more than a reply can count. */
+                reply.written(copied as u32);
+            }
+        );
+    }
 }
@@ -10,6 +10,18 @@ pub enum Ty {
     Page = 3,
     Dir = 4,
     Symlink = 5,
+    Superblock = 6,
+    Orphans = 7,
+    Holders = 8,
+    Extent = 9,
+    FileLocks = 10,
+    LockLease = 11,
+    Snapshots = 12,
+    Generation = 13,
+    Changes = 14,
+    Views = 15,
+    ViewCounter = 16,
+    Journal = 17,
 }
 
 pub struct KeyWriter {
@@ -1,69 +1,393 @@
+pub mod bench;
+pub mod config;
 mod driver;
 mod fs;
 mod key;
 mod model;
+mod shutdown;
+pub mod telemetry;
 mod view;
 
+use crate::driver::runtime;
 use crate::driver::Driver;
 use crate::fs::Elmerfs;
-use async_std::{sync::Arc, task};
-use std::ffi::{OsStr, OsString};
+use crate::view::NameRefParseError;
+use async_std::sync::Arc;
+use fuser::MountOption;
+use nix::libc;
+use std::ffi::OsStr;
 use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::thread::{self, JoinHandle};
 use tracing::*;
 
-pub use crate::driver::{AddressBook, Config};
+pub use crate::driver::{
+    AddressBook, AtimePolicy, AttrShape, Clock, Compression, Concurrency, Config, Consistency,
+    ContinuationToken, DirEntry, DirPage, EncryptionKey, IdMap, LockPolicy, ManualClock,
+    PageConflicts, Problem, Report, RoundTripBudget, SharedPool, Squash, Stats, SystemClock,
+    CONFLICTS_XATTR, DEFAULT_ANONYMOUS_OWNER, DEFAULT_PAGE_SIZE, DEFAULT_POOL_SIZE,
+    DEFAULT_POOL_TIMEOUT, DEFAULT_REQUEST_TIMEOUT, DEFAULT_RETRIES, PAGE_VERSIONS_XATTR, ROOT_INO,
+};
 pub use crate::key::Bucket;
-pub use crate::view::View;
+pub use crate::model::inode::Owner;
+pub use crate::model::snapshot::Snapshot;
+pub use crate::model::views::ViewRecord;
+pub use crate::view::{Naming, View};
+pub use antidotec::Timestamp;
+
+/// Options of the FUSE session, handed to the kernel when mounting or when
+/// the session starts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FuseOptions {
+    /// Lets the kernel send writes larger than a page.
+    pub big_writes: bool,
+    /// Largest write the kernel sends at once, in bytes.
+    pub max_write: Option<u32>,
+    /// Largest read ahead the kernel does, in bytes.
+    pub max_readahead: Option<u32>,
+    /// Lets other users than the one mounting access the filesystem.
+    pub allow_other: bool,
+    /// Unmounts the filesystem when the process goes away.
+    pub auto_unmount: bool,
+    /// Threads letting operations in and spawning them, the session thread
+    /// does it itself when zero.
+    pub threads: usize,
+}
+
+impl FuseOptions {
+    /* Sizes and big writes are negotiated once the session starts. */
+    fn mount_options(&self) -> Vec<MountOption> {
+        let mut options = Vec::new();
+        if self.allow_other {
+            options.push(MountOption::AllowOther);
+        }
+        if self.auto_unmount {
+            options.push(MountOption::AutoUnmount);
+        }
+
+        options
+    }
+}
 
 /// There is two main thread of execution to follow:
 ///
 /// The first one is dedicated to fuse whom sole purpose is to perform
 /// argument format validation (e.g are name given valid utf8 strings ?) and
-/// send those requests to whoever might be interested.
+/// hand every request, as a task, to the dispatcher.
+///
+/// The second one, the dispatcher, lets those tasks in per kind of operation
+/// and spawns them, calling into the root of the filesystem, the Rp driver.
+/// It runs on the fuse thread itself unless `FuseOptions::threads` gives it
+/// threads of its own.
 ///
-/// The second one, the dispatcher thread, it takes fuse request and dispatch
-/// them into asynchronous tasks calling into the root of the filesystem,
-/// the Rp driver.
+/// On SIGINT or SIGTERM, the filesystem is unmounted and this returns once
+/// every operation in flight and the work they left behind are done.
 pub fn run(cfg: Config, mountpoint: &OsStr) {
-    const RETRIES: u32 = 5;
+    run_all(vec![(cfg, PathBuf::from(mountpoint))]);
+}
 
-    let driver = task::block_on(Driver::new(cfg)).expect("driver init");
+/// Like `run`, for several mounts, e.g of different buckets, served by the
+/// same process. They share the connections of the first one.
+pub fn run_all(mounts: Vec<(Config, PathBuf)>) {
+    let pool = match mounts.first() {
+        Some((cfg, _)) => SharedPool::new(cfg),
+        None => return,
+    };
 
-    let driver = Arc::new(driver);
-    let options = ["-o", "fsname=rpfs"]
+    let mounts: Vec<Mount> = mounts
+        .into_iter()
+        .map(|(cfg, mountpoint)| mount_with_pool(cfg, mountpoint, &pool).expect("failed to mount"))
+        .collect();
+
+    /* Lazily, as a busy filesystem can't be unmounted otherwise, the
+    operations in flight are still served until the session ends. */
+    let mountpoints: Vec<PathBuf> = mounts
         .iter()
-        .map(|o| o.as_ref())
-        .collect::<Vec<&OsStr>>();
+        .map(|mount| mount.mountpoint().to_path_buf())
+        .collect();
+    shutdown::on_shutdown_signal(move || {
+        for mountpoint in &mountpoints {
+            if let Err(error) = fusermount_unmount(mountpoint, true) {
+                error!(?error, ?mountpoint, "failed to unmount");
+            }
+        }
+    })
+    .expect("failed to handle shutdown signals");
+
+    for mount in mounts {
+        if let Err(error) = mount.join() {
+            error!("{:?}", error);
+        }
+    }
+}
+
+/// Mounts the filesystem and serves it from a thread of its own. The
+/// filesystem is mounted once this returns.
+pub fn mount(cfg: Config, mountpoint: impl AsRef<Path>) -> io::Result<Mount> {
+    let pool = SharedPool::new(&cfg);
+    mount_with_pool(cfg, mountpoint, &pool)
+}
+
+/// Like `mount`, going through connections shared with other mounts.
+pub fn mount_with_pool(
+    cfg: Config,
+    mountpoint: impl AsRef<Path>,
+    pool: &SharedPool,
+) -> io::Result<Mount> {
+    const RETRIES: u32 = 5;
 
-    for _ in 0..RETRIES {
-        let _umount = UmountOnDrop(mountpoint.to_os_string());
+    let mountpoint = mountpoint.as_ref().to_path_buf();
+    let mut options = vec![MountOption::FSName(String::from("rpfs"))];
+    if cfg.default_permissions {
+        options.push(MountOption::DefaultPermissions);
+    }
+    if cfg.snapshot.is_some() {
+        options.push(MountOption::RO);
+    }
+    options.extend(cfg.fuse.mount_options());
+    let fuse_options = cfg.fuse.clone();
+
+    let driver = Arc::new(runtime::block_on(Driver::with_pool(cfg, pool.clone()))?);
+    driver.announce();
 
+    /* A previous mount that went away without being unmounted leaves the
+    mountpoint disconnected. */
+    let mut attempts = 0;
+    let mut session = loop {
         let fs = Elmerfs {
             driver: driver.clone(),
+            options: fuse_options.clone(),
         };
-        match fuse::mount(fs, &mountpoint, &options) {
-            Ok(()) => break,
-            Err(error) if error.kind() == io::ErrorKind::NotConnected => {
-                continue;
+
+        match fuser::Session::new(fs, &mountpoint, &options) {
+            Ok(session) => break session,
+            Err(error) if error.kind() == io::ErrorKind::NotConnected && attempts < RETRIES => {
+                attempts += 1;
+                let _ = fusermount_unmount(&mountpoint, false);
             }
-            Err(error) => {
-                error!("{:?}", error);
+            Err(error) => return Err(error),
+        }
+    };
+    driver.set_notifier(session.notifier());
+
+    let session = thread::Builder::new()
+        .name(String::from("fuse"))
+        .spawn(move || session.run())?;
+
+    Ok(Mount {
+        mountpoint,
+        driver,
+        session: Some(session),
+    })
+}
+
+/// Finds a snapshot by name, e.g to fill `Config::snapshot` before mounting.
+pub fn find_snapshot(cfg: &Config, name: &str) -> io::Result<Snapshot> {
+    Ok(runtime::block_on(Driver::find_snapshot(cfg, name))?)
+}
+
+/// Allocates a view no other mount of the bucket was given, once and for
+/// all, e.g to fill `Config::view` before mounting for the first time.
+/// `Config::view` itself is ignored.
+pub fn allocate_view(cfg: &Config) -> io::Result<View> {
+    Ok(runtime::block_on(Driver::allocate_view(cfg))?)
+}
+
+/// A mounted filesystem, unmounted when dropped.
+#[derive(Debug)]
+pub struct Mount {
+    mountpoint: PathBuf,
+    driver: Arc<Driver>,
+    session: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Mount {
+    pub fn mountpoint(&self) -> &Path {
+        &self.mountpoint
+    }
+
+    /// Unmounts the filesystem and waits for its session to end. It fails
+    /// while the filesystem is busy, the mount is then left as is.
+    pub fn unmount(mut self) -> io::Result<()> {
+        fusermount_unmount(&self.mountpoint, false)?;
+        self.join_session()
+    }
+
+    /// Waits for the filesystem to be unmounted, e.g with `fusermount -u`.
+    pub fn join(mut self) -> io::Result<()> {
+        self.join_session()
+    }
+
+    /* Once the session ends, the work left behind by the last operations is
+    waited for, so that none of it is cut short when the process exits. */
+    fn join_session(&mut self) -> io::Result<()> {
+        let result = match self.session.take() {
+            Some(session) => session.join().unwrap_or_else(|_| {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "fuse session panicked",
+                ))
+            }),
+            None => return Ok(()),
+        };
+
+        runtime::block_on(self.driver.shutdown());
+        result
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        if self.session.is_none() {
+            return;
+        }
+
+        match fusermount_unmount(&self.mountpoint, false) {
+            Ok(()) => {
+                let _ = self.join_session();
             }
+            Err(error) => error!(?error, mountpoint = ?self.mountpoint, "failed to unmount"),
         }
     }
 }
 
-struct UmountOnDrop(OsString);
+/// Direct access to a bucket for tooling that can't afford to go through
+/// a mount, e.g to enumerate huge directories page by page.
+pub struct Client {
+    driver: Driver,
+}
 
-impl Drop for UmountOnDrop {
-    fn drop(&mut self) {
-        Command::new("fusermount")
-            .arg("-u")
-            .arg(&self.0)
-            .stderr(Stdio::piped())
-            .stdout(Stdio::piped())
-            .status()
-            .expect("failed to umount test dir");
+impl Client {
+    pub fn connect(cfg: Config) -> io::Result<Self> {
+        let driver = runtime::block_on(Driver::new(cfg))?;
+        Ok(Self { driver })
+    }
+
+    pub fn lookup(&self, parent: u64, name: &OsStr) -> io::Result<u64> {
+        let name = self
+            .driver
+            .naming()
+            .parse(name.as_bytes())
+            .map_err(|error| match error {
+                NameRefParseError::TooLong => io::Error::from_raw_os_error(libc::ENAMETOOLONG),
+                NameRefParseError::Invalid => {
+                    io::Error::new(io::ErrorKind::InvalidInput, "invalid name")
+                }
+            })?;
+
+        let entry = runtime::block_on(self.driver.lookup(Owner::ROOT, parent, name))?;
+        Ok(entry.attr.ino)
+    }
+
+    /// Finds the next data region or hole of a file, with `SEEK_DATA` or
+    /// `SEEK_HOLE` as `whence`, e.g to copy sparse files.
+    pub fn lseek(&self, ino: u64, offset: u64, whence: i32) -> io::Result<u64> {
+        Ok(runtime::block_on(self.driver.lseek(ino, offset, whence))?)
+    }
+
+    /// Preallocates, punches a hole in or zeroes a range of a file, `mode`
+    /// takes the `FALLOC_FL_*` flags of fallocate(2).
+    pub fn fallocate(&self, ino: u64, offset: u64, len: u64, mode: i32) -> io::Result<()> {
+        Ok(runtime::block_on(
+            self.driver.fallocate(ino, offset, len, mode),
+        )?)
+    }
+
+    /// Copies a range of a file into another one without the data leaving
+    /// the driver, returns the number of bytes copied.
+    pub fn copy_file_range(
+        &self,
+        src_ino: u64,
+        src_offset: u64,
+        dst_ino: u64,
+        dst_offset: u64,
+        len: u64,
+    ) -> io::Result<u64> {
+        let copy = self
+            .driver
+            .copy_file_range(src_ino, src_offset, dst_ino, dst_offset, len);
+        Ok(runtime::block_on(copy)?)
+    }
+
+    pub fn list_dir(
+        &self,
+        ino: u64,
+        after: Option<&ContinuationToken>,
+        limit: usize,
+    ) -> io::Result<DirPage> {
+        Ok(runtime::block_on(self.driver.list_dir(ino, after, limit))?)
+    }
+
+    /// Walks the whole filesystem looking for inconsistencies, fixing the
+    /// ones that can be when `repair` is set. No view should be mounted
+    /// meanwhile.
+    pub fn check(&self, repair: bool) -> io::Result<Report> {
+        Ok(runtime::block_on(self.driver.check(repair))?)
+    }
+
+    /// Removes every unlinked orphan, including the ones still held open by
+    /// views that went away. Returns the number of inodes removed.
+    pub fn collect_orphans(&self) -> io::Result<usize> {
+        Ok(runtime::block_on(self.driver.collect_orphans())?)
+    }
+
+    /// Removes the directory at `path`, relative to the root of the bucket,
+    /// and everything under it. Returns the number of inodes removed.
+    pub fn remove_tree(&self, path: &Path) -> io::Result<u64> {
+        Ok(runtime::block_on(self.driver.remove_tree(path))?)
+    }
+
+    pub fn stats(&self) -> io::Result<Stats> {
+        Ok(runtime::block_on(self.driver.stats())?)
+    }
+
+    /// Names the current state of the bucket, to be mounted read-only later
+    /// on with `Config::snapshot`.
+    pub fn create_snapshot(&self, name: &str) -> io::Result<Snapshot> {
+        Ok(runtime::block_on(self.driver.create_snapshot(name))?)
+    }
+
+    pub fn snapshots(&self) -> io::Result<Vec<Snapshot>> {
+        Ok(runtime::block_on(self.driver.snapshots())?)
+    }
+
+    pub fn delete_snapshot(&self, name: &str) -> io::Result<()> {
+        Ok(runtime::block_on(self.driver.delete_snapshot(name))?)
+    }
+
+    /// Every view that mounted the bucket, with where it last ran and when.
+    pub fn views(&self) -> io::Result<Vec<ViewRecord>> {
+        Ok(runtime::block_on(self.driver.views())?)
+    }
+
+    /// Forgets about a view gone for good, a view still mounted shows up
+    /// again once it announces itself.
+    pub fn forget_view(&self, view: View) -> io::Result<()> {
+        Ok(runtime::block_on(self.driver.forget_view(view))?)
+    }
+}
+
+fn fusermount_unmount(mountpoint: &Path, lazy: bool) -> io::Result<()> {
+    let mut command = Command::new("fusermount");
+    command.arg("-u");
+    if lazy {
+        command.arg("-z");
+    }
+
+    let status = command
+        .arg(mountpoint)
+        .stderr(Stdio::piped())
+        .stdout(Stdio::piped())
+        .status()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("fusermount -u failed with {}", status),
+        ))
     }
 }
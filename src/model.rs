@@ -1,3 +1,11 @@
+pub mod changes;
 pub mod dir;
+pub mod extent;
 pub mod inode;
+pub mod journal;
+pub mod lock;
+pub mod orphan;
+pub mod snapshot;
+pub mod superblock;
 pub mod symlink;
+pub mod views;
@@ -0,0 +1,54 @@
+use crate::key::{KeyWriter, Ty};
+use crate::view::View;
+use antidotec::RawIdent;
+
+/* The changes of the namespace, counted by the view making them, so that
+views can tell when another one changed what they cached. Being part of the
+transaction of the change, a count goes away with it when it aborts. */
+#[derive(Debug, Copy, Clone)]
+pub struct Key;
+
+pub fn key() -> Key {
+    Key
+}
+
+impl From<Key> for RawIdent {
+    fn from(_: Key) -> RawIdent {
+        KeyWriter::with_capacity(Ty::Changes, 0).into()
+    }
+}
+
+fn field(view: View) -> RawIdent {
+    view.to_le_bytes().to_vec()
+}
+
+pub use ops::*;
+
+mod ops {
+    use super::{field, key};
+    use crate::view::View;
+    use antidotec::{counter, rrmap, ReadQuery, ReadReply, UpdateQuery};
+
+    pub fn read() -> ReadQuery {
+        rrmap::get(key())
+    }
+
+    pub fn bump(view: View) -> UpdateQuery {
+        rrmap::update(key())
+            .push(counter::inc(field(view), 1))
+            .build()
+    }
+
+    /// The changes made by views other than `view`.
+    pub fn decode_others(view: View, reply: &mut ReadReply, index: usize) -> u64 {
+        let own = field(view);
+
+        reply
+            .rrmap(index)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(field, _)| *field != own)
+            .map(|(_, count)| count.into_counter() as u64)
+            .sum()
+    }
+}
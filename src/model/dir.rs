@@ -1,6 +1,6 @@
 use crate::key::{KeyWriter, Ty};
 use crate::model::inode::Kind;
-use crate::view::{Name, NameRef, View};
+use crate::view::{Name, NameRef, Naming, View};
 use antidotec::RawIdent;
 use std::borrow::Cow;
 use std::collections::HashMap;
@@ -9,14 +9,29 @@ use std::fmt::{self, Display};
 use std::mem::size_of;
 use std::sync::Arc;
 
+/// Number of sets the entries of a directory are spread across, by the
+/// hash of their name, so that a lookup only reads the entries sharing its
+/// shard and a listing can go through the directory a few shards at a time.
+pub const SHARDS: u16 = 16;
+
+/* The key of the directory as a whole, used to lock it. Its entries are
+stored under the key of their shard. */
 #[derive(Debug, Copy, Clone)]
 pub struct Key {
     ino: u64,
+    shard: Option<u16>,
 }
 
 impl Key {
     fn new(ino: u64) -> Self {
-        Self { ino }
+        Self { ino, shard: None }
+    }
+
+    fn shard(ino: u64, shard: u16) -> Self {
+        Self {
+            ino,
+            shard: Some(shard),
+        }
     }
 }
 
@@ -26,12 +41,33 @@ pub fn key(ino: u64) -> Key {
 
 impl Into<RawIdent> for Key {
     fn into(self) -> RawIdent {
-        KeyWriter::with_capacity(Ty::Dir, size_of::<u64>())
-            .write_u64(self.ino)
-            .into()
+        let writer = KeyWriter::with_capacity(Ty::Dir, size_of::<u64>() + size_of::<u16>())
+            .write_u64(self.ino);
+
+        match self.shard {
+            Some(shard) => writer.write_u16(shard).into(),
+            None => writer.into(),
+        }
     }
 }
 
+/* FNV-1a, shards must be the same for every view and every build. */
+fn hash(name: &[u8]) -> u64 {
+    name.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn shard_of(prefix: &[u8]) -> u16 {
+    (hash(prefix) % SHARDS as u64) as u16
+}
+
+/// Where an entry stands in the listing of its shard. It only depends on
+/// its name, so that it doesn't move as other entries come and go.
+pub fn cookie_of(name: &[u8]) -> u32 {
+    (hash(name) >> 33) as u32
+}
+
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct Entry {
     pub name: Name,
@@ -56,7 +92,7 @@ impl Entry {
         content.extend_from_slice(&self.ino.to_le_bytes()[..]);
         content.push(self.kind as u8);
         content.extend_from_slice(&self.name.view.to_le_bytes()[..]);
-        content.extend_from_slice(&self.name.prefix.as_bytes());
+        content.extend_from_slice(&self.name.prefix);
     }
 
     fn from_bytes(bytes: &[u8]) -> Self {
@@ -70,7 +106,7 @@ impl Entry {
         let mut view_bytes = [0; size_of::<View>()];
         view_bytes.copy_from_slice(view);
 
-        let prefix = String::from_utf8(prefix.into()).expect("valid utf8");
+        let prefix = prefix.to_vec();
         let kind = Kind::try_from(kind[0]).unwrap();
 
         Self {
@@ -90,92 +126,165 @@ impl Entry {
 
 impl Display for Name {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}@{}", self.prefix, self.view)
+        write!(f, "{}@{}", String::from_utf8_lossy(&self.prefix), self.view)
     }
 }
 
 pub use ops::*;
 
 mod ops {
-    use super::{DirView, Entry, EntryList, EntryView, Key};
+    use super::{shard_of, DirView, Entry, EntryList, EntryView, Key, SHARDS};
     use crate::model::inode::Kind;
     use crate::view::{Name, View};
     use antidotec::{rwset, ReadQuery, ReadReply, UpdateQuery};
     use std::collections::HashMap;
     use std::sync::Arc;
 
-    pub fn read(ino: u64) -> ReadQuery {
-        rwset::get(Key::new(ino))
+    /// Reads the shard where entries named `prefix` are, whatever their view.
+    pub fn read(ino: u64, prefix: &[u8]) -> ReadQuery {
+        read_shard(ino, shard_of(prefix))
     }
 
-    pub fn decode(view: View, reply: &mut ReadReply, index: usize) -> Option<DirView> {
-        use std::collections::hash_map::Entry as HashEntry;
+    pub fn read_shard(ino: u64, shard: u16) -> ReadQuery {
+        rwset::get(Key::shard(ino, shard))
+    }
 
-        let set = reply.rwset(index)?;
+    /// Reads every shard, to be decoded with `decode_all`.
+    pub fn read_all(ino: u64) -> impl Iterator<Item = ReadQuery> {
+        (0..SHARDS).map(move |shard| read_shard(ino, shard))
+    }
 
-        let mut entries = Vec::with_capacity(set.len());
-        let mut by_name: HashMap<_, EntryList> = HashMap::with_capacity(set.len());
-        for encoded_entry in set {
-            let entry = Entry::from_bytes(&encoded_entry);
-            let prefix: Arc<str> = Arc::from(entry.name.prefix);
+    /* A shard without any entry reads the same as a missing directory,
+    callers tell them apart with the inode of the directory. */
+    pub fn decode(view: View, reply: &mut ReadReply, index: usize) -> DirView {
+        let entries = reply
+            .rwset(index)
+            .into_iter()
+            .flatten()
+            .map(|encoded_entry| Entry::from_bytes(&encoded_entry));
 
-            entries.push(EntryView {
-                ino: entry.ino,
-                prefix: prefix.clone(),
-                view: entry.name.view,
-                kind: entry.kind,
-                next: None,
-            });
-        }
-        entries.sort();
+        DirView::from_entries(view, entries)
+    }
 
-        for idx in 0..entries.len() {
-            let prefix = entries[idx].prefix.clone();
+    /// Decodes the shards read by `read_all` from `index` onward, `None`
+    /// when none of them has an entry, as a directory always has `.`.
+    pub fn decode_all(view: View, reply: &mut ReadReply, index: usize) -> Option<DirView> {
+        let mut entries = Vec::new();
+        for shard in 0..SHARDS as usize {
+            let set = reply.rwset(index + shard).into_iter().flatten();
+            entries.extend(set.map(|encoded_entry| Entry::from_bytes(&encoded_entry)));
+        }
 
-            match by_name.entry(prefix) {
-                HashEntry::Occupied(mut entry) => {
-                    let entry_list = entry.get_mut();
-                    entries[entry_list.tail].next = Some(idx);
-                    entry_list.tail = idx;
-                }
-                HashEntry::Vacant(entry) => {
-                    entry.insert(EntryList {
-                        head: idx,
-                        tail: idx,
-                    });
-                }
-            }
+        if entries.is_empty() {
+            return None;
         }
 
-        Some(DirView {
-            view,
-            entries,
-            by_name,
-        })
+        Some(DirView::from_entries(view, entries))
+    }
+
+    /// Reads the single set a directory was stored as before it had shards,
+    /// see `superblock::DIR_FORMAT`.
+    pub fn read_unsharded(ino: u64) -> ReadQuery {
+        rwset::get(Key::new(ino))
     }
 
-    pub fn create(view: View, parent_ino: u64, ino: u64) -> UpdateQuery {
+    pub fn decode_unsharded(reply: &mut ReadReply, index: usize) -> Vec<Entry> {
+        reply
+            .rwset(index)
+            .into_iter()
+            .flatten()
+            .map(|encoded_entry| Entry::from_bytes(&encoded_entry))
+            .collect()
+    }
+
+    pub fn remove_unsharded(ino: u64) -> UpdateQuery {
+        rwset::reset(Key::new(ino))
+    }
+
+    pub fn create(view: View, parent_ino: u64, ino: u64) -> impl Iterator<Item = UpdateQuery> {
         let dot = Entry::new(Name::new(".", view), ino, Kind::Directory);
         let dotdot = Entry::new(Name::new("..", view), parent_ino, Kind::Directory);
 
-        rwset::insert(Key::new(ino))
-            .add(dot.into_bytes())
-            .add(dotdot.into_bytes())
-            .build()
+        vec![add_entry(ino, &dot), add_entry(ino, &dotdot)].into_iter()
     }
 
-    pub fn remove(ino: u64) -> UpdateQuery {
-        rwset::reset(Key::new(ino))
+    /// Points the ".." of a directory to its new parent, in place of the
+    /// ones found in `entries`, as read with `read(ino, b"..")`.
+    pub fn set_parent(
+        view: View,
+        ino: u64,
+        entries: &DirView,
+        parent_ino: u64,
+    ) -> Vec<UpdateQuery> {
+        let mut updates: Vec<_> = entries
+            .entries()
+            .iter()
+            .filter(|entry| &*entry.prefix == b"..")
+            .map(|entry| remove_entry(ino, &entry.into_dentry()))
+            .collect();
+
+        let dotdot = Entry::new(Name::new("..", view), parent_ino, Kind::Directory);
+        updates.push(add_entry(ino, &dotdot));
+        updates
+    }
+
+    pub fn remove(ino: u64) -> impl Iterator<Item = UpdateQuery> {
+        (0..SHARDS).map(move |shard| rwset::reset(Key::shard(ino, shard)))
     }
 
     pub fn add_entry(ino: u64, entry: &Entry) -> UpdateQuery {
-        rwset::insert(Key::new(ino)).add(entry.into_bytes()).build()
+        let key = Key::shard(ino, shard_of(&entry.name.prefix));
+
+        rwset::insert(key).add(entry.into_bytes()).build()
     }
 
     pub fn remove_entry(ino: u64, entry: &Entry) -> UpdateQuery {
-        rwset::remove(Key::new(ino))
-            .remove(entry.into_bytes())
-            .build()
+        let key = Key::shard(ino, shard_of(&entry.name.prefix));
+
+        rwset::remove(key).remove(entry.into_bytes()).build()
+    }
+
+    impl DirView {
+        fn from_entries(view: View, entries: impl IntoIterator<Item = Entry>) -> Self {
+            use std::collections::hash_map::Entry as HashEntry;
+
+            let mut entries: Vec<_> = entries
+                .into_iter()
+                .map(|entry| EntryView {
+                    ino: entry.ino,
+                    prefix: Arc::from(entry.name.prefix),
+                    view: entry.name.view,
+                    kind: entry.kind,
+                    next: None,
+                })
+                .collect();
+            entries.sort();
+
+            let mut by_name: HashMap<_, EntryList> = HashMap::with_capacity(entries.len());
+            for idx in 0..entries.len() {
+                let prefix = entries[idx].prefix.clone();
+
+                match by_name.entry(prefix) {
+                    HashEntry::Occupied(mut entry) => {
+                        let entry_list = entry.get_mut();
+                        entries[entry_list.tail].next = Some(idx);
+                        entry_list.tail = idx;
+                    }
+                    HashEntry::Vacant(entry) => {
+                        entry.insert(EntryList {
+                            head: idx,
+                            tail: idx,
+                        });
+                    }
+                }
+            }
+
+            DirView {
+                view,
+                entries,
+                by_name,
+            }
+        }
     }
 }
 
@@ -184,7 +293,7 @@ pub struct EntryView {
     pub ino: u64,
     pub view: View,
     pub kind: Kind,
-    pub prefix: Arc<str>,
+    pub prefix: Arc<[u8]>,
     next: Option<usize>,
 }
 
@@ -193,7 +302,7 @@ impl EntryView {
         Entry {
             ino: self.ino,
             name: Name {
-                prefix: String::from(&*self.prefix as &str),
+                prefix: self.prefix.to_vec(),
                 view: self.view,
             },
             kind: self.kind,
@@ -211,7 +320,7 @@ struct EntryList {
 pub struct DirView {
     view: View,
     entries: Vec<EntryView>,
-    by_name: HashMap<Arc<str>, EntryList>,
+    by_name: HashMap<Arc<[u8]>, EntryList>,
 }
 
 impl DirView {
@@ -227,37 +336,88 @@ impl DirView {
         self.get(name).is_some()
     }
 
-    pub fn iter_from(&self, offset: usize) -> impl Iterator<Item = EntryRef<'_>> {
+    /// Names held by more than one entry, e.g created concurrently by
+    /// several views.
+    pub fn conflicts(&self) -> impl Iterator<Item = &[u8]> {
+        self.by_name
+            .iter()
+            .filter(|(_, entry_list)| entry_list.head != entry_list.tail)
+            .map(|(name, _)| name as &[u8])
+    }
+
+    /// Whether it holds anything else than `.` and `..`.
+    pub fn has_children(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| &*entry.prefix != b"." && &*entry.prefix != b"..")
+    }
+
+    /// Every entry as stored, including `.` and `..`, whatever their view.
+    pub fn entries(&self) -> &[EntryView] {
+        &self.entries
+    }
+
+    pub fn iter_from<'a>(
+        &'a self,
+        offset: usize,
+        naming: &'a Naming,
+    ) -> impl Iterator<Item = EntryRef<'a>> {
         let start = offset.min(self.entries.len());
         Iter {
-            entries: self.entries[start..].iter(),
-            by_name: &self.by_name,
-            view: self.view,
+            dir: self,
+            naming,
+            position: start,
         }
     }
 
     fn position(&self, name: &NameRef) -> Option<usize> {
         match name {
             NameRef::Exact(name) => {
-                let entry_list = self.by_name.get(&name.prefix as &str)?;
+                let entry_list = self.by_name.get(&name.prefix as &[u8])?;
                 self.resolve_by_view(&entry_list, name.view)
             }
-            NameRef::Partial(prefix) => {
+            NameRef::Partial(prefix) | NameRef::Ambiguous(prefix) => {
                 /* This is simple algorithm to resolve conflicts (multiple entry with
                 the same prefix). If there is only one entry for a given prefix
                 there is no conflict so we can simply entry. Otherwise, try to
                 fetch the exact entry by using our current view */
 
-                let entry_list = self.by_name.get(prefix as &str)?;
+                let entry_list = self.by_name.get(prefix as &[u8])?;
                 if entry_list.head == entry_list.tail {
                     return Some(entry_list.head);
                 }
 
                 self.resolve_by_view(&entry_list, self.view)
             }
+            NameRef::Sibling(name, ino) => {
+                let entry_list = self.by_name.get(&name.prefix as &[u8])?;
+                self.list(entry_list).find(|&idx| {
+                    self.entries[idx].view == name.view && self.entries[idx].ino == *ino
+                })
+            }
         }
     }
 
+    /* When a view created the same name more than once, e.g concurrently on
+    two replicas, each entry is shown with its inode rather than letting the
+    first one shadow the others. */
+    fn has_siblings(&self, idx: usize) -> bool {
+        let entry = &self.entries[idx];
+        let entry_list = &self.by_name[&entry.prefix];
+
+        self.list(entry_list)
+            .any(|other| other != idx && self.entries[other].view == entry.view)
+    }
+
+    fn list<'a>(&'a self, entry_list: &EntryList) -> impl Iterator<Item = usize> + 'a {
+        let mut current = Some(entry_list.head);
+        std::iter::from_fn(move || {
+            let idx = current?;
+            current = self.entries[idx].next;
+            Some(idx)
+        })
+    }
+
     fn resolve_by_view(&self, entry_list: &EntryList, view: View) -> Option<usize> {
         let mut current = Some(entry_list.head);
         while let Some(idx) = current {
@@ -275,49 +435,49 @@ impl DirView {
 
 #[derive(Debug)]
 pub struct EntryRef<'a> {
-    pub name: Cow<'a, str>,
+    pub name: Cow<'a, [u8]>,
     pub ino: u64,
     pub kind: Kind,
+    /// The name and view the entry is stored under.
+    pub prefix: &'a [u8],
+    pub view: View,
 }
 
 pub struct Iter<'a> {
-    entries: std::slice::Iter<'a, EntryView>,
-    by_name: &'a HashMap<Arc<str>, EntryList>,
-    view: View,
+    dir: &'a DirView,
+    naming: &'a Naming,
+    position: usize,
 }
 
 impl<'a> Iterator for Iter<'a> {
     type Item = EntryRef<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        use crate::view::REF_SEP;
-
-        let entry = self.entries.next()?;
-        let entry_list = self.by_name[&entry.prefix];
-
-        let show_alias = entry_list.head == entry_list.tail || entry.view == self.view;
-
-        let entry = if show_alias {
-            EntryRef {
-                name: Cow::Borrowed(&*entry.prefix as &str),
-                ino: entry.ino,
-                kind: entry.kind,
-            }
+        let idx = self.position;
+        let entry = self.dir.entries.get(idx)?;
+        let entry_list = self.dir.by_name[&entry.prefix];
+        self.position += 1;
+
+        let show_alias = entry_list.head == entry_list.tail
+            || (self.naming.hide_local_view && entry.view == self.dir.view);
+
+        let name = if self.dir.has_siblings(idx) {
+            Cow::Owned(
+                self.naming
+                    .decorate(&entry.prefix, entry.view, Some(entry.ino)),
+            )
+        } else if show_alias {
+            Cow::Borrowed(&*entry.prefix as &[u8])
         } else {
-            let fully_qualified = format!(
-                "{prefix}{sep}{view}",
-                prefix = entry.prefix,
-                sep = REF_SEP,
-                view = entry.view
-            );
-
-            EntryRef {
-                name: Cow::Owned(fully_qualified),
-                ino: entry.ino,
-                kind: entry.kind,
-            }
+            Cow::Owned(self.naming.decorate(&entry.prefix, entry.view, None))
         };
 
-        Some(entry)
+        Some(EntryRef {
+            name,
+            ino: entry.ino,
+            kind: entry.kind,
+            prefix: &entry.prefix,
+            view: entry.view,
+        })
     }
 }
@@ -0,0 +1,100 @@
+use crate::key::{KeyWriter, Ty};
+use antidotec::RawIdent;
+use std::mem;
+
+/* Pages of a file that hold data, so that holes are known without reading
+them. They are recorded by groups to keep each set small. */
+pub const PAGES_PER_GROUP: u64 = 1024;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Key {
+    ino: u64,
+    group: u64,
+}
+
+pub fn key(ino: u64, group: u64) -> Key {
+    Key { ino, group }
+}
+
+pub fn group_of(page: u64) -> u64 {
+    page / PAGES_PER_GROUP
+}
+
+impl Into<RawIdent> for Key {
+    fn into(self) -> RawIdent {
+        KeyWriter::with_capacity(Ty::Extent, 2 * mem::size_of::<u64>())
+            .write_u64(self.ino)
+            .write_u64(self.group)
+            .into()
+    }
+}
+
+pub use ops::*;
+
+mod ops {
+    use super::{group_of, key};
+    use antidotec::{rwset, ReadQuery, ReadReply, UpdateQuery};
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::convert::TryInto;
+    use std::mem::size_of;
+    use std::ops::Range;
+
+    pub fn read(ino: u64, group: u64) -> ReadQuery {
+        rwset::get(key(ino, group))
+    }
+
+    pub fn add(ino: u64, pages: impl IntoIterator<Item = u64>) -> Vec<UpdateQuery> {
+        by_group(pages)
+            .into_iter()
+            .map(|(group, pages)| {
+                pages
+                    .into_iter()
+                    .fold(rwset::insert(key(ino, group)), |set, page| {
+                        set.add(page.to_le_bytes().to_vec())
+                    })
+                    .build()
+            })
+            .collect()
+    }
+
+    pub fn remove(ino: u64, pages: impl IntoIterator<Item = u64>) -> Vec<UpdateQuery> {
+        by_group(pages)
+            .into_iter()
+            .map(|(group, pages)| {
+                pages
+                    .into_iter()
+                    .fold(rwset::remove(key(ino, group)), |set, page| {
+                        set.remove(page.to_le_bytes().to_vec())
+                    })
+                    .build()
+            })
+            .collect()
+    }
+
+    pub fn reset(ino: u64, pages: Range<u64>) -> Vec<UpdateQuery> {
+        let groups = if pages.start < pages.end {
+            group_of(pages.start)..(group_of(pages.end - 1) + 1)
+        } else {
+            0..0
+        };
+
+        groups.map(|group| rwset::reset(key(ino, group))).collect()
+    }
+
+    pub fn decode(reply: &mut ReadReply, index: usize) -> BTreeSet<u64> {
+        let set = reply.rwset(index).unwrap_or_default();
+
+        set.into_iter()
+            .map(|bytes| u64::from_le_bytes(bytes[..size_of::<u64>()].try_into().unwrap()))
+            .collect()
+    }
+
+    fn by_group(pages: impl IntoIterator<Item = u64>) -> BTreeMap<u64, Vec<u64>> {
+        let mut groups: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+        for page in pages {
+            groups.entry(group_of(page)).or_default().push(page);
+        }
+
+        groups
+    }
+}
@@ -1,8 +1,11 @@
 use crate::key::{KeyWriter, Ty};
+use crate::view::View;
 use antidotec::RawIdent;
-use fuse::{FileAttr, FileType};
+use fuser::{FileAttr, FileType};
+use nix::libc;
+use std::convert::TryFrom;
 use std::mem;
-use std::{convert::TryFrom, time::Duration};
+use std::time::{Duration, UNIX_EPOCH};
 
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Ord, PartialOrd)]
@@ -10,6 +13,10 @@ pub enum Kind {
     Regular = 0,
     Directory = 1,
     Symlink = 2,
+    Fifo = 3,
+    Socket = 4,
+    CharDevice = 5,
+    BlockDevice = 6,
 }
 
 impl Kind {
@@ -18,6 +25,23 @@ impl Kind {
             Kind::Regular => FileType::RegularFile,
             Kind::Directory => FileType::Directory,
             Kind::Symlink => FileType::Symlink,
+            Kind::Fifo => FileType::NamedPipe,
+            Kind::Socket => FileType::Socket,
+            Kind::CharDevice => FileType::CharDevice,
+            Kind::BlockDevice => FileType::BlockDevice,
+        }
+    }
+
+    /// Kind of a node created through mknod, directories and symlinks have
+    /// their own operations.
+    pub fn of_node(mode: u32) -> Option<Self> {
+        match mode & libc::S_IFMT {
+            0 | libc::S_IFREG => Some(Kind::Regular),
+            libc::S_IFIFO => Some(Kind::Fifo),
+            libc::S_IFSOCK => Some(Kind::Socket),
+            libc::S_IFCHR => Some(Kind::CharDevice),
+            libc::S_IFBLK => Some(Kind::BlockDevice),
+            _ => None,
         }
     }
 }
@@ -28,6 +52,11 @@ pub struct Owner {
     pub uid: u32,
 }
 
+impl Owner {
+    /// Who the mount and the tools act as, past any permission.
+    pub const ROOT: Owner = Owner { gid: 0, uid: 0 };
+}
+
 impl From<u64> for Owner {
     fn from(x: u64) -> Self {
         let gid = (x >> 32) as u32;
@@ -44,6 +73,17 @@ impl Into<u64> for Owner {
     }
 }
 
+/// Identifies the request that created an inode. A creation that is retried
+/// after an ambiguous failure uses it to recognize its own work.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CreationToken(u64);
+
+impl CreationToken {
+    pub fn new(view: View, unique: u64) -> Self {
+        Self(((view as u64) << 48) | (unique & 0x0000_FFFF_FFFF_FFFF))
+    }
+}
+
 #[derive(Debug)]
 pub struct InvalidKindByte;
 
@@ -55,6 +95,10 @@ impl TryFrom<u8> for Kind {
             0 => Ok(Kind::Regular),
             1 => Ok(Kind::Directory),
             2 => Ok(Kind::Symlink),
+            3 => Ok(Kind::Fifo),
+            4 => Ok(Kind::Socket),
+            5 => Ok(Kind::CharDevice),
+            6 => Ok(Kind::BlockDevice),
             _ => Err(InvalidKindByte),
         }
     }
@@ -68,32 +112,72 @@ pub struct Inode {
     pub atime: Duration,
     pub ctime: Duration,
     pub mtime: Duration,
+    /// When the inode was created, never changed afterward.
+    pub crtime: Duration,
     pub owner: Owner,
     pub mode: u32,
     pub size: u64,
     pub nlink: u64,
+    pub rdev: u32,
+    pub creation_token: Option<CreationToken>,
+    /// Whether pages holding data are tracked by `model::extent`, which
+    /// isn't the case of inodes created before extents were introduced.
+    pub extents: bool,
+    /// How many inodes had the same number before this one.
+    pub generation: u64,
+}
+
+/// Symlinks are always reported with every permission, as on Linux.
+pub const SYMLINK_MODE: u32 = 0o777;
+
+/// The time a field stamped at `stored` takes when changed at `t`: a view
+/// whose clock is behind the one that stamped it takes the next nanosecond
+/// instead, so that the timestamps of an inode never go backward.
+pub fn later(stored: Duration, t: Duration) -> Duration {
+    t.max(stored + Duration::from_nanos(1))
 }
 
 impl Inode {
+    pub fn accessed(&mut self, t: Duration) {
+        self.atime = later(self.atime, t);
+    }
+
+    pub fn modified(&mut self, t: Duration) {
+        self.mtime = later(self.mtime, t);
+    }
+
+    pub fn changed(&mut self, t: Duration) {
+        self.ctime = later(self.ctime, t);
+    }
+
+    /* Symlinks created before with another mode are reported as the others,
+    the device number only means something for devices. */
     pub fn attr(&self) -> FileAttr {
-        let timespec_from_duration = |duration: Duration| {
-            time::Timespec::new(duration.as_secs() as i64, duration.subsec_nanos() as i32)
+        let system_time = |duration: Duration| UNIX_EPOCH + duration;
+        let perm = match self.kind {
+            Kind::Symlink => SYMLINK_MODE,
+            _ => self.mode,
+        };
+        let rdev = match self.kind {
+            Kind::CharDevice | Kind::BlockDevice => self.rdev,
+            _ => 0,
         };
 
         FileAttr {
             ino: self.ino,
             size: self.size,
             blocks: 0,
-            atime: timespec_from_duration(self.atime),
-            mtime: timespec_from_duration(self.mtime),
-            ctime: timespec_from_duration(self.ctime),
-            crtime: timespec_from_duration(self.atime),
+            atime: system_time(self.atime),
+            mtime: system_time(self.mtime),
+            ctime: system_time(self.ctime),
+            crtime: system_time(self.crtime),
             kind: self.kind.to_file_type(),
-            perm: self.mode as u16,
+            perm: perm as u16,
             nlink: self.nlink as u32,
             uid: self.owner.uid,
             gid: self.owner.gid,
-            rdev: 0,
+            rdev,
+            blksize: 0,
             flags: 0,
         }
     }
@@ -112,6 +196,18 @@ enum Field {
     Mode = 7,
     Size = 8,
     NLink = 9,
+    CreationToken = 10,
+    Rdev = 11,
+    Extents = 12,
+    Generation = 13,
+    /* The size as a multi-value register, `Size` is only read for inodes
+    that were not written since. */
+    MergedSize = 14,
+    /* The timestamps as multi-value registers, for the same reason. */
+    MergedAtime = 15,
+    MergedCtime = 16,
+    MergedMtime = 17,
+    Crtime = 18,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -154,12 +250,33 @@ impl Into<RawIdent> for Key {
     }
 }
 
+/* Counts the inodes removed under a number, it outlives them so that the
+next one given the same number gets another generation. */
+#[derive(Debug, Copy, Clone)]
+pub struct GenerationKey {
+    ino: u64,
+}
+
+pub fn generation_key(ino: u64) -> GenerationKey {
+    GenerationKey { ino }
+}
+
+impl Into<RawIdent> for GenerationKey {
+    fn into(self) -> RawIdent {
+        KeyWriter::with_capacity(Ty::Generation, mem::size_of::<u64>())
+            .write_u64(self.ino)
+            .into()
+    }
+}
+
 pub use ops::*;
 
 mod ops {
-    use super::{key, Field, Inode, Owner};
-    use antidotec::{counter, lwwreg, rrmap, ReadQuery, ReadReply, UpdateQuery};
+    use super::{generation_key, key, CreationToken, Field, Inode, Key, Owner};
+    use antidotec::crdts::RrMap;
+    use antidotec::{counter, lwwreg, mvreg, rrmap, ReadQuery, ReadReply, UpdateQuery};
     use std::convert::TryFrom;
+    use std::time::Duration;
 
     pub fn read(ino: u64) -> ReadQuery {
         rrmap::get(key(ino))
@@ -168,17 +285,42 @@ mod ops {
     pub fn create(inode: &Inode) -> UpdateQuery {
         let key = key(inode.ino);
 
-        rrmap::update(key)
+        let update = rrmap::update(key)
             .push(lwwreg::set_u8(key.field(Field::Kind), inode.kind as u8))
             .push(lwwreg::set_u64(key.field(Field::Parent), inode.parent))
-            .push(lwwreg::set_duration(key.field(Field::Atime), inode.atime))
-            .push(lwwreg::set_duration(key.field(Field::Ctime), inode.ctime))
-            .push(lwwreg::set_duration(key.field(Field::Mtime), inode.mtime))
+            .push(mvreg::set_duration(
+                key.field(Field::MergedAtime),
+                inode.atime,
+            ))
+            .push(mvreg::set_duration(
+                key.field(Field::MergedCtime),
+                inode.ctime,
+            ))
+            .push(mvreg::set_duration(
+                key.field(Field::MergedMtime),
+                inode.mtime,
+            ))
+            .push(lwwreg::set_duration(key.field(Field::Crtime), inode.crtime))
             .push(lwwreg::set_u64(key.field(Field::Owner), inode.owner.into()))
             .push(lwwreg::set_u32(key.field(Field::Mode), inode.mode))
-            .push(lwwreg::set_u64(key.field(Field::Size), inode.size))
+            .push(mvreg::set_u64(key.field(Field::MergedSize), inode.size))
             .push(counter::inc(key.field(Field::NLink), inode.nlink as i32))
-            .build()
+            .push(lwwreg::set_u32(key.field(Field::Rdev), inode.rdev))
+            .push(lwwreg::set_u8(
+                key.field(Field::Extents),
+                inode.extents as u8,
+            ))
+            .push(lwwreg::set_u64(
+                key.field(Field::Generation),
+                inode.generation,
+            ));
+
+        match inode.creation_token {
+            Some(CreationToken(token)) => update
+                .push(lwwreg::set_u64(key.field(Field::CreationToken), token))
+                .build(),
+            None => update.build(),
+        }
     }
 
     pub fn update_stats(inode: &Inode) -> UpdateQuery {
@@ -186,9 +328,18 @@ mod ops {
 
         rrmap::update(key)
             .push(lwwreg::set_u64(key.field(Field::Parent), inode.parent))
-            .push(lwwreg::set_duration(key.field(Field::Atime), inode.atime))
-            .push(lwwreg::set_duration(key.field(Field::Ctime), inode.ctime))
-            .push(lwwreg::set_duration(key.field(Field::Mtime), inode.mtime))
+            .push(mvreg::set_duration(
+                key.field(Field::MergedAtime),
+                inode.atime,
+            ))
+            .push(mvreg::set_duration(
+                key.field(Field::MergedCtime),
+                inode.ctime,
+            ))
+            .push(mvreg::set_duration(
+                key.field(Field::MergedMtime),
+                inode.mtime,
+            ))
             .push(lwwreg::set_u64(key.field(Field::Owner), inode.owner.into()))
             .push(lwwreg::set_u32(key.field(Field::Mode), inode.mode))
             .build()
@@ -199,12 +350,29 @@ mod ops {
 
         rrmap::update(key)
             .push(lwwreg::set_u64(key.field(Field::Parent), inode.parent))
-            .push(lwwreg::set_duration(key.field(Field::Atime), inode.atime))
-            .push(lwwreg::set_duration(key.field(Field::Ctime), inode.ctime))
-            .push(lwwreg::set_duration(key.field(Field::Mtime), inode.mtime))
+            .push(mvreg::set_duration(
+                key.field(Field::MergedAtime),
+                inode.atime,
+            ))
+            .push(mvreg::set_duration(
+                key.field(Field::MergedCtime),
+                inode.ctime,
+            ))
+            .push(mvreg::set_duration(
+                key.field(Field::MergedMtime),
+                inode.mtime,
+            ))
             .push(lwwreg::set_u64(key.field(Field::Owner), inode.owner.into()))
             .push(lwwreg::set_u32(key.field(Field::Mode), inode.mode))
-            .push(lwwreg::set_u64(key.field(Field::Size), inode.size))
+            .push(mvreg::set_u64(key.field(Field::MergedSize), inode.size))
+            .build()
+    }
+
+    pub fn update_atime(ino: u64, atime: Duration) -> UpdateQuery {
+        let key = key(ino);
+
+        rrmap::update(key)
+            .push(mvreg::set_duration(key.field(Field::MergedAtime), atime))
             .build()
     }
 
@@ -231,13 +399,44 @@ mod ops {
         let kind_byte =
             lwwreg::read_u8(&map.remove(&key.field(Field::Kind)).unwrap().into_lwwreg());
         let parent = map.remove(&key.field(Field::Parent)).unwrap().into_lwwreg();
-        let atime = map.remove(&key.field(Field::Atime)).unwrap().into_lwwreg();
-        let ctime = map.remove(&key.field(Field::Ctime)).unwrap().into_lwwreg();
-        let mtime = map.remove(&key.field(Field::Mtime)).unwrap().into_lwwreg();
+        let atime = merged_time(&mut map, key, Field::MergedAtime, Field::Atime);
+        let ctime = merged_time(&mut map, key, Field::MergedCtime, Field::Ctime);
+        let mtime = merged_time(&mut map, key, Field::MergedMtime, Field::Mtime);
+        /* Inodes created before their creation time was stored were created
+        at the latest when first stamped. */
+        let crtime = map
+            .remove(&key.field(Field::Crtime))
+            .map_or(atime.min(ctime).min(mtime), |crtime| {
+                lwwreg::read_duration(&crtime.into_lwwreg())
+            });
         let owner = map.remove(&key.field(Field::Owner)).unwrap().into_lwwreg();
         let mode = map.remove(&key.field(Field::Mode)).unwrap().into_lwwreg();
-        let size = map.remove(&key.field(Field::Size)).unwrap().into_lwwreg();
+        /* Views that extended a file concurrently, e.g without locks, each
+        left their own size, the largest one wins. */
+        let size = match map.remove(&key.field(Field::MergedSize)) {
+            Some(sizes) => sizes
+                .into_mvreg()
+                .iter()
+                .map(|size| lwwreg::read_u64(size))
+                .max()
+                .unwrap_or(0),
+            None => lwwreg::read_u64(&map.remove(&key.field(Field::Size)).unwrap().into_lwwreg()),
+        };
         let nlink = map.remove(&key.field(Field::NLink)).unwrap().into_counter();
+        let creation_token = map
+            .remove(&key.field(Field::CreationToken))
+            .map(|token| CreationToken(lwwreg::read_u64(&token.into_lwwreg())));
+        let rdev = map
+            .remove(&key.field(Field::Rdev))
+            .map_or(0, |rdev| lwwreg::read_u32(&rdev.into_lwwreg()));
+        let extents = map
+            .remove(&key.field(Field::Extents))
+            .map_or(false, |extents| {
+                lwwreg::read_u8(&extents.into_lwwreg()) != 0
+            });
+        let generation = map
+            .remove(&key.field(Field::Generation))
+            .map_or(0, |generation| lwwreg::read_u64(&generation.into_lwwreg()));
 
         let kind = TryFrom::try_from(kind_byte).expect("invalid code byte");
         let owner = Owner::from(lwwreg::read_u64(&owner));
@@ -246,17 +445,50 @@ mod ops {
             ino,
             kind,
             parent: lwwreg::read_u64(&parent),
-            atime: lwwreg::read_duration(&atime),
-            ctime: lwwreg::read_duration(&ctime),
-            mtime: lwwreg::read_duration(&mtime),
+            atime,
+            ctime,
+            mtime,
+            crtime,
             owner,
             mode: lwwreg::read_u32(&mode),
-            size: lwwreg::read_u64(&size),
+            size,
             nlink: nlink as u64,
+            rdev,
+            creation_token,
+            extents,
+            generation,
         })
     }
 
+    /* Views that stamped an inode concurrently each left their own time,
+    the latest one wins. */
+    fn merged_time(map: &mut RrMap, key: Key, merged: Field, field: Field) -> Duration {
+        match map.remove(&key.field(merged)) {
+            Some(times) => times
+                .into_mvreg()
+                .iter()
+                .map(|t| lwwreg::read_duration(t))
+                .max()
+                .unwrap_or_default(),
+            None => lwwreg::read_duration(&map.remove(&key.field(field)).unwrap().into_lwwreg()),
+        }
+    }
+
     pub fn remove(ino: u64) -> UpdateQuery {
         rrmap::reset(key(ino))
     }
+
+    pub fn read_generation(ino: u64) -> ReadQuery {
+        counter::get(generation_key(ino))
+    }
+
+    /// The generation of the next inode created under a number.
+    pub fn decode_generation(reply: &mut ReadReply, index: usize) -> u64 {
+        reply.counter(index) as u64
+    }
+
+    /// Recorded along with the removal of an inode.
+    pub fn next_generation(ino: u64) -> UpdateQuery {
+        counter::inc(generation_key(ino), 1)
+    }
 }
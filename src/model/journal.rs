@@ -0,0 +1,110 @@
+use crate::key::{KeyWriter, Ty};
+use crate::view::View;
+use antidotec::RawIdent;
+use std::convert::TryInto;
+use std::mem;
+use std::time::Duration;
+
+/* Work of a view spread over several transactions, recorded by the first
+one and forgotten by the last one. What a crashed mount left is replayed
+by the next mount of its view. */
+#[derive(Debug, Copy, Clone)]
+pub struct Key {
+    view: View,
+}
+
+pub fn key(view: View) -> Key {
+    Key { view }
+}
+
+impl From<Key> for RawIdent {
+    fn from(key: Key) -> RawIdent {
+        KeyWriter::with_capacity(Ty::Journal, mem::size_of::<View>())
+            .write_u16(key.view)
+            .into()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+enum Kind {
+    Extend = 0,
+}
+
+/// An operation in progress.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Intent {
+    /// Content was written up to `size`, the inode is not updated yet.
+    Extend {
+        ino: u64,
+        size: u64,
+        mtime: Duration,
+    },
+}
+
+impl Intent {
+    fn to_bytes(self) -> Vec<u8> {
+        let Intent::Extend { ino, size, mtime } = self;
+
+        let mut bytes = Vec::with_capacity(1 + 4 * mem::size_of::<u64>());
+        bytes.push(Kind::Extend as u8);
+        bytes.extend_from_slice(&ino.to_le_bytes()[..]);
+        bytes.extend_from_slice(&size.to_le_bytes()[..]);
+        bytes.extend_from_slice(&mtime.as_secs().to_le_bytes()[..]);
+        bytes.extend_from_slice(&u64::from(mtime.subsec_nanos()).to_le_bytes()[..]);
+        bytes
+    }
+
+    /* Intents of kinds unknown to this version are skipped, they are left
+    to the mounts knowing them. */
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (&kind, rest) = bytes.split_first()?;
+        if kind != Kind::Extend as u8 || rest.len() < 4 * mem::size_of::<u64>() {
+            return None;
+        }
+
+        let mut fields = rest
+            .chunks_exact(mem::size_of::<u64>())
+            .map(|field| u64::from_le_bytes(field.try_into().unwrap()));
+        let (ino, size, secs, nanos) = (
+            fields.next()?,
+            fields.next()?,
+            fields.next()?,
+            fields.next()?,
+        );
+
+        Some(Intent::Extend {
+            ino,
+            size,
+            mtime: Duration::new(secs, nanos as u32),
+        })
+    }
+}
+
+pub use ops::*;
+
+mod ops {
+    use super::{key, Intent};
+    use crate::view::View;
+    use antidotec::{rwset, ReadQuery, ReadReply, UpdateQuery};
+
+    pub fn read(view: View) -> ReadQuery {
+        rwset::get(key(view))
+    }
+
+    pub fn record(view: View, intent: &Intent) -> UpdateQuery {
+        rwset::insert(key(view)).add(intent.to_bytes()).build()
+    }
+
+    pub fn forget(view: View, intent: &Intent) -> UpdateQuery {
+        rwset::remove(key(view)).remove(intent.to_bytes()).build()
+    }
+
+    pub fn decode(reply: &mut ReadReply, index: usize) -> Vec<Intent> {
+        let set = reply.rwset(index).unwrap_or_default();
+
+        set.into_iter()
+            .filter_map(|bytes| Intent::from_bytes(&bytes))
+            .collect()
+    }
+}
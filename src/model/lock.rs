@@ -0,0 +1,171 @@
+use crate::key::{KeyWriter, Ty};
+use crate::view::View;
+use antidotec::RawIdent;
+use std::convert::TryInto;
+use std::mem;
+
+/* Advisory locks taken on an inode, by any view. */
+#[derive(Debug, Copy, Clone)]
+pub struct Key {
+    ino: u64,
+}
+
+pub fn key(ino: u64) -> Key {
+    Key { ino }
+}
+
+impl Into<RawIdent> for Key {
+    fn into(self) -> RawIdent {
+        KeyWriter::with_capacity(Ty::FileLocks, mem::size_of::<u64>())
+            .write_u64(self.ino)
+            .into()
+    }
+}
+
+/* Last time a view renewed its lease, locks of a view whose lease expired
+are ignored as it is considered gone. */
+#[derive(Debug, Copy, Clone)]
+pub struct LeaseKey {
+    view: View,
+}
+
+pub fn lease_key(view: View) -> LeaseKey {
+    LeaseKey { view }
+}
+
+impl Into<RawIdent> for LeaseKey {
+    fn into(self) -> RawIdent {
+        KeyWriter::with_capacity(Ty::LockLease, mem::size_of::<View>())
+            .write_u16(self.view)
+            .into()
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LockKind {
+    Read = 0,
+    Write = 1,
+}
+
+/// A lock over `start..=end`, the owner being a lock owner of the kernel
+/// which is only unique within a view.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FileLock {
+    pub view: View,
+    pub owner: u64,
+    pub pid: u32,
+    pub kind: LockKind,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl FileLock {
+    pub fn is_owned_by(&self, view: View, owner: u64) -> bool {
+        self.view == view && self.owner == owner
+    }
+
+    pub fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start <= end && start <= self.end
+    }
+
+    pub fn conflicts_with(&self, other: &FileLock) -> bool {
+        !other.is_owned_by(self.view, self.owner)
+            && self.overlaps(other.start, other.end)
+            && (self.kind == LockKind::Write || other.kind == LockKind::Write)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::byte_len());
+        bytes.extend_from_slice(&self.view.to_le_bytes());
+        bytes.extend_from_slice(&self.owner.to_le_bytes());
+        bytes.extend_from_slice(&self.pid.to_le_bytes());
+        bytes.push(self.kind as u8);
+        bytes.extend_from_slice(&self.start.to_le_bytes());
+        bytes.extend_from_slice(&self.end.to_le_bytes());
+
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let (view, bytes) = bytes.split_at(mem::size_of::<View>());
+        let (owner, bytes) = bytes.split_at(mem::size_of::<u64>());
+        let (pid, bytes) = bytes.split_at(mem::size_of::<u32>());
+        let (kind, bytes) = bytes.split_at(mem::size_of::<u8>());
+        let (start, end) = bytes.split_at(mem::size_of::<u64>());
+
+        Self {
+            view: View::from_le_bytes(view.try_into().unwrap()),
+            owner: u64::from_le_bytes(owner.try_into().unwrap()),
+            pid: u32::from_le_bytes(pid.try_into().unwrap()),
+            kind: if kind[0] == LockKind::Write as u8 {
+                LockKind::Write
+            } else {
+                LockKind::Read
+            },
+            start: u64::from_le_bytes(start.try_into().unwrap()),
+            end: u64::from_le_bytes(end[..mem::size_of::<u64>()].try_into().unwrap()),
+        }
+    }
+
+    const fn byte_len() -> usize {
+        mem::size_of::<View>()
+            + mem::size_of::<u64>()
+            + mem::size_of::<u32>()
+            + mem::size_of::<u8>()
+            + 2 * mem::size_of::<u64>()
+    }
+}
+
+pub use ops::*;
+
+mod ops {
+    use super::{key, lease_key, FileLock};
+    use crate::view::View;
+    use antidotec::{lwwreg, rwset, ReadQuery, ReadReply, UpdateQuery};
+    use std::time::Duration;
+
+    pub fn read(ino: u64) -> ReadQuery {
+        rwset::get(key(ino))
+    }
+
+    pub fn add<'a>(ino: u64, locks: impl IntoIterator<Item = &'a FileLock>) -> UpdateQuery {
+        locks
+            .into_iter()
+            .fold(rwset::insert(key(ino)), |set, lock| {
+                set.add(lock.to_bytes())
+            })
+            .build()
+    }
+
+    pub fn remove<'a>(ino: u64, locks: impl IntoIterator<Item = &'a FileLock>) -> UpdateQuery {
+        locks
+            .into_iter()
+            .fold(rwset::remove(key(ino)), |set, lock| {
+                set.remove(lock.to_bytes())
+            })
+            .build()
+    }
+
+    pub fn decode(reply: &mut ReadReply, index: usize) -> Vec<FileLock> {
+        let set = reply.rwset(index).unwrap_or_default();
+
+        set.into_iter()
+            .map(|bytes| FileLock::from_bytes(&bytes))
+            .collect()
+    }
+
+    pub fn read_lease(view: View) -> ReadQuery {
+        lwwreg::get(lease_key(view))
+    }
+
+    pub fn renew_lease(view: View, t: Duration) -> UpdateQuery {
+        lwwreg::set_duration(lease_key(view), t)
+    }
+
+    pub fn decode_lease(reply: &mut ReadReply, index: usize) -> Option<Duration> {
+        reply
+            .lwwreg(index)
+            .map(|lease| lwwreg::read_duration(&lease))
+    }
+}
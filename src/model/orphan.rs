@@ -0,0 +1,96 @@
+use crate::key::{KeyWriter, Ty};
+use antidotec::RawIdent;
+use std::mem;
+
+/* Inodes that are unlinked but can't be removed yet because some views
+still hold them open. */
+#[derive(Debug, Copy, Clone)]
+pub struct Key;
+
+pub fn key() -> Key {
+    Key
+}
+
+impl Into<RawIdent> for Key {
+    fn into(self) -> RawIdent {
+        KeyWriter::with_capacity(Ty::Orphans, 0).into()
+    }
+}
+
+/* Views holding at least one open handle on an inode. */
+#[derive(Debug, Copy, Clone)]
+pub struct HoldersKey {
+    ino: u64,
+}
+
+pub fn holders_key(ino: u64) -> HoldersKey {
+    HoldersKey { ino }
+}
+
+impl Into<RawIdent> for HoldersKey {
+    fn into(self) -> RawIdent {
+        KeyWriter::with_capacity(Ty::Holders, mem::size_of::<u64>())
+            .write_u64(self.ino)
+            .into()
+    }
+}
+
+pub use ops::*;
+
+mod ops {
+    use super::{holders_key, key};
+    use crate::view::View;
+    use antidotec::{rwset, ReadQuery, ReadReply, UpdateQuery};
+    use std::convert::TryInto;
+    use std::mem::size_of;
+
+    pub fn read() -> ReadQuery {
+        rwset::get(key())
+    }
+
+    pub fn add(ino: u64) -> UpdateQuery {
+        rwset::insert(key()).add(ino.to_le_bytes().to_vec()).build()
+    }
+
+    pub fn remove(ino: u64) -> UpdateQuery {
+        rwset::remove(key())
+            .remove(ino.to_le_bytes().to_vec())
+            .build()
+    }
+
+    pub fn decode(reply: &mut ReadReply, index: usize) -> Vec<u64> {
+        let set = reply.rwset(index).unwrap_or_default();
+
+        set.into_iter()
+            .map(|bytes| u64::from_le_bytes(bytes[..size_of::<u64>()].try_into().unwrap()))
+            .collect()
+    }
+
+    pub fn read_holders(ino: u64) -> ReadQuery {
+        rwset::get(holders_key(ino))
+    }
+
+    pub fn hold(ino: u64, view: View) -> UpdateQuery {
+        rwset::insert(holders_key(ino))
+            .add(view.to_le_bytes().to_vec())
+            .build()
+    }
+
+    pub fn unhold(ino: u64, view: View) -> UpdateQuery {
+        rwset::remove(holders_key(ino))
+            .remove(view.to_le_bytes().to_vec())
+            .build()
+    }
+
+    pub fn remove_holders(ino: u64) -> UpdateQuery {
+        rwset::reset(holders_key(ino))
+    }
+
+    pub fn decode_holders(reply: &mut ReadReply, index: usize) -> Vec<View> {
+        let set = reply.rwset(index).unwrap_or_default();
+
+        set.into_iter()
+            .map(|bytes| View::from_le_bytes(bytes[..size_of::<View>()].try_into().unwrap()))
+            .collect()
+    }
+}
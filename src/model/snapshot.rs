@@ -0,0 +1,79 @@
+use crate::key::{KeyWriter, Ty};
+use antidotec::{RawIdent, Timestamp};
+use std::convert::TryInto;
+use std::mem;
+
+/* Snapshots of the bucket, by name. */
+#[derive(Debug, Copy, Clone)]
+pub struct Key;
+
+pub fn key() -> Key {
+    Key
+}
+
+impl Into<RawIdent> for Key {
+    fn into(self) -> RawIdent {
+        KeyWriter::with_capacity(Ty::Snapshots, 0).into()
+    }
+}
+
+/// A named point in time of the whole bucket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub name: String,
+    pub timestamp: Timestamp,
+}
+
+impl Snapshot {
+    fn to_bytes(&self) -> Vec<u8> {
+        let name = self.name.as_bytes();
+        let timestamp = self.timestamp.as_bytes();
+
+        let mut bytes = Vec::with_capacity(mem::size_of::<u16>() + name.len() + timestamp.len());
+        bytes.extend_from_slice(&(name.len() as u16).to_le_bytes()[..]);
+        bytes.extend_from_slice(name);
+        bytes.extend_from_slice(timestamp);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let (len, name_timestamp) = bytes.split_at(mem::size_of::<u16>());
+        let len = u16::from_le_bytes(len.try_into().unwrap()) as usize;
+        let (name, timestamp) = name_timestamp.split_at(len);
+
+        Self {
+            name: String::from_utf8_lossy(name).into_owned(),
+            timestamp: Timestamp::from_bytes(Vec::from(timestamp)),
+        }
+    }
+}
+
+pub use ops::*;
+
+mod ops {
+    use super::{key, Snapshot};
+    use antidotec::{rwset, ReadQuery, ReadReply, UpdateQuery};
+
+    pub fn read() -> ReadQuery {
+        rwset::get(key())
+    }
+
+    pub fn add(snapshot: &Snapshot) -> UpdateQuery {
+        rwset::insert(key()).add(snapshot.to_bytes()).build()
+    }
+
+    pub fn remove(snapshot: &Snapshot) -> UpdateQuery {
+        rwset::remove(key()).remove(snapshot.to_bytes()).build()
+    }
+
+    pub fn decode(reply: &mut ReadReply, index: usize) -> Vec<Snapshot> {
+        let set = reply.rwset(index).unwrap_or_default();
+
+        let mut snapshots: Vec<Snapshot> = set
+            .into_iter()
+            .map(|bytes| Snapshot::from_bytes(&bytes))
+            .collect();
+        snapshots.sort_by(|a, b| a.name.cmp(&b.name));
+        snapshots
+    }
+}
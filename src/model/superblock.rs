@@ -0,0 +1,120 @@
+use crate::driver::PageConflicts;
+use crate::key::{KeyWriter, Ty};
+use antidotec::RawIdent;
+use std::mem;
+
+/// How directories are stored: `1` as a single set of entries, `2` with
+/// their entries spread across shards by the hash of their name.
+pub const DIR_FORMAT: u64 = 2;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Superblock {
+    pub page_size: u64,
+    pub page_conflicts: PageConflicts,
+    pub dir_format: u64,
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(u8)]
+enum Field {
+    Struct = 0,
+    PageSize = 1,
+    PageConflicts = 2,
+    DirFormat = 3,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Key {
+    field: Field,
+}
+
+impl Key {
+    fn new() -> Self {
+        Key {
+            field: Field::Struct,
+        }
+    }
+
+    fn field(self, field: Field) -> RawIdent {
+        Key { field }.into()
+    }
+}
+
+pub fn key() -> Key {
+    Key::new()
+}
+
+impl Into<RawIdent> for Key {
+    fn into(self) -> RawIdent {
+        KeyWriter::with_capacity(Ty::Superblock, mem::size_of::<u8>())
+            .write_u8(self.field as u8)
+            .into()
+    }
+}
+
+pub use ops::*;
+
+mod ops {
+    use super::{key, Field, Superblock};
+    use crate::driver::PageConflicts;
+    use antidotec::{lwwreg, rrmap, ReadQuery, ReadReply, UpdateQuery};
+
+    pub fn read() -> ReadQuery {
+        rrmap::get(key())
+    }
+
+    pub fn create(superblock: &Superblock) -> UpdateQuery {
+        let key = key();
+
+        rrmap::update(key)
+            .push(lwwreg::set_u64(
+                key.field(Field::PageSize),
+                superblock.page_size,
+            ))
+            .push(lwwreg::set_u64(
+                key.field(Field::PageConflicts),
+                superblock.page_conflicts.to_u64(),
+            ))
+            .push(lwwreg::set_u64(
+                key.field(Field::DirFormat),
+                superblock.dir_format,
+            ))
+            .build()
+    }
+
+    pub fn set_dir_format(dir_format: u64) -> UpdateQuery {
+        let key = key();
+
+        rrmap::update(key)
+            .push(lwwreg::set_u64(key.field(Field::DirFormat), dir_format))
+            .build()
+    }
+
+    pub fn decode(reply: &mut ReadReply, index: usize) -> Option<Superblock> {
+        let mut map = reply.rrmap(index)?;
+        let key = key();
+
+        let page_size = map
+            .remove(&key.field(Field::PageSize))
+            .unwrap()
+            .into_lwwreg();
+
+        /* Buckets created before the policy existed hold registers. */
+        let page_conflicts = map
+            .remove(&key.field(Field::PageConflicts))
+            .and_then(|policy| PageConflicts::from_u64(lwwreg::read_u64(&policy.into_lwwreg())))
+            .unwrap_or(PageConflicts::LastWriter);
+
+        /* Buckets created before the format was recorded hold directories
+        as a single set. */
+        let dir_format = map
+            .remove(&key.field(Field::DirFormat))
+            .map_or(1, |format| lwwreg::read_u64(&format.into_lwwreg()));
+
+        Some(Superblock {
+            page_size: lwwreg::read_u64(&page_size),
+            page_conflicts,
+            dir_format,
+        })
+    }
+}
@@ -0,0 +1,132 @@
+use crate::key::{KeyWriter, Ty};
+use crate::view::View;
+use antidotec::RawIdent;
+use std::convert::TryInto;
+use std::mem;
+use std::time::Duration;
+
+/* Views that ever mounted the bucket, by view. */
+#[derive(Debug, Copy, Clone)]
+pub struct Key;
+
+pub fn key() -> Key {
+    Key
+}
+
+impl From<Key> for RawIdent {
+    fn from(_: Key) -> RawIdent {
+        KeyWriter::with_capacity(Ty::Views, 0).into()
+    }
+}
+
+/* Views handed out to mounts that didn't pick their own, the last one. */
+#[derive(Debug, Copy, Clone)]
+pub struct CounterKey;
+
+pub fn counter_key() -> CounterKey {
+    CounterKey
+}
+
+impl From<CounterKey> for RawIdent {
+    fn from(_: CounterKey) -> RawIdent {
+        KeyWriter::with_capacity(Ty::ViewCounter, 0).into()
+    }
+}
+
+/// A view as last announced by the driver mounting it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ViewRecord {
+    pub view: View,
+    pub hostname: String,
+    /// Since the epoch, as told by the clock of the mount.
+    pub last_seen: Duration,
+}
+
+impl ViewRecord {
+    fn to_bytes(&self) -> Vec<u8> {
+        let hostname = self.hostname.as_bytes();
+
+        let mut bytes = Vec::with_capacity(2 * mem::size_of::<u64>() + hostname.len());
+        bytes.extend_from_slice(&self.last_seen.as_secs().to_le_bytes()[..]);
+        bytes.extend_from_slice(&u64::from(self.last_seen.subsec_nanos()).to_le_bytes()[..]);
+        bytes.extend_from_slice(hostname);
+        bytes
+    }
+
+    fn from_bytes(view: View, bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 2 * mem::size_of::<u64>() {
+            return None;
+        }
+
+        let (secs, rest) = bytes.split_at(mem::size_of::<u64>());
+        let (nanos, hostname) = rest.split_at(mem::size_of::<u64>());
+        let secs = u64::from_le_bytes(secs.try_into().unwrap());
+        let nanos = u64::from_le_bytes(nanos.try_into().unwrap());
+
+        Some(Self {
+            view,
+            hostname: String::from_utf8_lossy(hostname).into_owned(),
+            last_seen: Duration::new(secs, nanos as u32),
+        })
+    }
+}
+
+fn field(view: View) -> RawIdent {
+    view.to_le_bytes().to_vec()
+}
+
+pub use ops::*;
+
+mod ops {
+    use super::{counter_key, field, key, ViewRecord};
+    use crate::view::View;
+    use antidotec::{counter, mvreg, rrmap, ReadQuery, ReadReply, UpdateQuery};
+    use std::convert::TryInto;
+
+    pub fn read() -> ReadQuery {
+        rrmap::get(key())
+    }
+
+    pub fn seen(record: &ViewRecord) -> UpdateQuery {
+        rrmap::update(key())
+            .push(mvreg::set(field(record.view), record.to_bytes()))
+            .build()
+    }
+
+    pub fn read_allocated() -> ReadQuery {
+        counter::get(counter_key())
+    }
+
+    /// Moves the last allocated view from `last` to `view`.
+    pub fn allocated(last: View, view: View) -> UpdateQuery {
+        counter::inc(counter_key(), i32::from(view) - i32::from(last))
+    }
+
+    pub fn decode_allocated(reply: &mut ReadReply, index: usize) -> View {
+        reply.counter(index) as View
+    }
+
+    pub fn forget(view: View) -> UpdateQuery {
+        rrmap::update(key()).remove_mvreg(field(view)).build()
+    }
+
+    /* Records written concurrently by a view, e.g once its datacenters
+    were cut off from each other, are all kept, the latest one wins. */
+    pub fn decode(reply: &mut ReadReply, index: usize) -> Vec<ViewRecord> {
+        let map = reply.rrmap(index).unwrap_or_default();
+
+        let mut records: Vec<ViewRecord> = map
+            .into_iter()
+            .filter_map(|(field, records)| {
+                let view = View::from_le_bytes(field.as_slice().try_into().ok()?);
+                records
+                    .into_mvreg()
+                    .iter()
+                    .filter_map(|bytes| ViewRecord::from_bytes(view, bytes))
+                    .max_by_key(|record| record.last_seen)
+            })
+            .collect();
+        records.sort_by_key(|record| record.view);
+        records
+    }
+}
@@ -0,0 +1,67 @@
+use nix::libc;
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::unistd;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/* Only async-signal-safe calls are allowed here, the actual work is left to
+the thread reading the other end of the pipe. */
+extern "C" fn on_signal(_: libc::c_int) {
+    let fd = SIGNAL_PIPE.load(Ordering::Relaxed);
+    let byte = 0u8;
+
+    unsafe {
+        libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+    }
+}
+
+/// Calls `f` from a thread of its own on the first SIGINT or SIGTERM. The
+/// default handlers are put back right away, so that a second signal kills
+/// the process if shutting down takes too long.
+pub(crate) fn on_shutdown_signal(f: impl FnOnce() + Send + 'static) -> io::Result<()> {
+    let (reader, writer) = unistd::pipe().map_err(to_io_error)?;
+    SIGNAL_PIPE.store(writer, Ordering::Relaxed);
+
+    let action = SigAction::new(
+        SigHandler::Handler(on_signal),
+        SaFlags::SA_RESETHAND | SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for signal in &[Signal::SIGINT, Signal::SIGTERM] {
+        unsafe { signal::sigaction(*signal, &action) }.map_err(to_io_error)?;
+    }
+
+    thread::Builder::new()
+        .name(String::from("shutdown"))
+        .spawn(move || {
+            if wait(reader).is_ok() {
+                tracing::info!("shutting down");
+                f();
+            }
+        })?;
+
+    Ok(())
+}
+
+fn wait(reader: RawFd) -> nix::Result<()> {
+    let mut byte = [0u8];
+
+    loop {
+        match unistd::read(reader, &mut byte) {
+            Ok(_) => return Ok(()),
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+fn to_io_error(error: nix::Error) -> io::Error {
+    match error {
+        nix::Error::Sys(errno) => io::Error::from_raw_os_error(errno as i32),
+        error => io::Error::new(io::ErrorKind::Other, error),
+    }
+}
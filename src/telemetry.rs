@@ -0,0 +1,92 @@
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{self, filter::EnvFilter, prelude::*};
+
+/// Keeps the log writer and the trace exporter running, both are flushed
+/// when dropped.
+pub struct Telemetry {
+    _appender: WorkerGuard,
+    #[cfg(feature = "otlp")]
+    exporter: Option<tokio::runtime::Runtime>,
+}
+
+/// Installs the global subscriber: logs are written to stdout as filtered
+/// by `RUST_LOG`, or `log_level` when unset, and, given an OTLP endpoint,
+/// the same spans are exported as traces, one per fuse request.
+pub fn init(log_level: Option<&str>, otlp_endpoint: Option<&str>) -> Telemetry {
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(log_level.unwrap_or_default()))
+        .expect("invalid log level")
+        .add_directive("polling=warn".parse().unwrap())
+        .add_directive("fuser::request=info".parse().unwrap())
+        .add_directive("async_io=info".parse().unwrap())
+        .add_directive("async_std=info".parse().unwrap());
+
+    let (non_blocking_appender, guard) = tracing_appender::non_blocking(std::io::stdout());
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(non_blocking_appender));
+
+    #[cfg(feature = "otlp")]
+    {
+        let (exporter, layer) = match otlp_endpoint {
+            Some(endpoint) => {
+                let (runtime, tracer) = otlp::tracer(endpoint);
+                let layer = tracing_opentelemetry::layer().with_tracer(tracer);
+                (Some(runtime), Some(layer))
+            }
+            None => (None, None),
+        };
+        registry.with(layer).init();
+
+        Telemetry {
+            _appender: guard,
+            exporter,
+        }
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    {
+        assert!(
+            otlp_endpoint.is_none(),
+            "elmerfs was built without the otlp feature"
+        );
+        registry.init();
+
+        Telemetry { _appender: guard }
+    }
+}
+
+#[cfg(feature = "otlp")]
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if self.exporter.is_some() {
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use opentelemetry::sdk::{trace, Resource};
+    use opentelemetry::KeyValue;
+    use tokio::runtime::Runtime;
+
+    const SERVICE_NAME: &str = "elmerfs";
+
+    /* The gRPC exporter needs a tokio runtime of its own, the filesystem
+    itself runs on async-std. */
+    pub(super) fn tracer(endpoint: &str) -> (Runtime, trace::Tracer) {
+        let runtime = Runtime::new().expect("failed to start the trace exporter");
+        let _entered = runtime.enter();
+
+        let resource = Resource::new(vec![KeyValue::new("service.name", SERVICE_NAME)]);
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .with_endpoint(endpoint)
+            .with_trace_config(trace::config().with_resource(resource))
+            .with_tonic()
+            .install_batch(opentelemetry::runtime::Tokio)
+            .expect("failed to install the trace exporter");
+
+        (runtime, tracer)
+    }
+}
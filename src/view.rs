@@ -2,16 +2,20 @@ use std::str::FromStr;
 
 pub const REF_SEP: char = ':';
 
+/// Longest name in bytes, the view decoration aside.
+pub const NAME_MAX: usize = 255;
+
 pub type View = u16;
 
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct Name {
     pub view: View,
-    pub prefix: String,
+    /// Names are bytes, as in Linux, they don't have to be valid UTF-8.
+    pub prefix: Vec<u8>,
 }
 
 impl Name {
-    pub fn new(prefix: impl Into<String>, view: View) -> Self {
+    pub fn new(prefix: impl Into<Vec<u8>>, view: View) -> Self {
         Name {
             view,
             prefix: prefix.into(),
@@ -19,36 +23,168 @@ impl Name {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum NameRef {
-    Partial(String),
+    Partial(Vec<u8>),
     Exact(Name),
+    /// One of the entries sharing the same name, created concurrently by
+    /// the same view, told apart by their inode.
+    Sibling(Name, u64),
+    /// A name that also reads as a decorated one, e.g `x:2`. It names the
+    /// entry its decoration points to when there is one, and itself
+    /// otherwise, see [`Naming::readings`].
+    Ambiguous(Vec<u8>),
 }
 
 impl NameRef {
+    pub fn prefix(&self) -> &[u8] {
+        match self {
+            Self::Partial(prefix) | Self::Ambiguous(prefix) => prefix,
+            Self::Exact(name) | Self::Sibling(name, _) => &name.prefix,
+        }
+    }
+
     pub fn canonicalize(self, view: View) -> Name {
         match self {
-            Self::Partial(prefix) => Name { prefix, view },
-            Self::Exact(name) => name,
+            Self::Partial(prefix) | Self::Ambiguous(prefix) => Name { prefix, view },
+            Self::Exact(name) | Self::Sibling(name, _) => name,
         }
     }
 }
 
-pub struct NameRefParseError;
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NameRefParseError {
+    /// The name is empty or holds `/` or NUL.
+    Invalid,
+    TooLong,
+}
+
+/// How names are decorated with their view, and inode, when listing a
+/// directory and parsed back on lookups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Naming {
+    pub separator: char,
+    /// Whether entries of the local view are listed by their bare name even
+    /// when other views created the same one.
+    pub hide_local_view: bool,
+}
 
-impl FromStr for NameRef {
-    type Err = NameRefParseError;
+impl Naming {
+    /* Separators that would clash with paths or with the numbers in the
+    decoration itself. */
+    pub fn valid_separator(separator: char) -> bool {
+        !(separator == '/' || separator == '\0' || separator.is_ascii_digit())
+    }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut s = s.split(REF_SEP);
+    /* Any name Linux accepts is a name of its own, whether or not it also
+    reads as a decorated one, unless it is too long to be stored as is. */
+    pub fn parse(&self, s: &[u8]) -> Result<NameRef, NameRefParseError> {
+        if s.is_empty() || s.iter().any(|byte| *byte == b'/' || *byte == 0) {
+            return Err(NameRefParseError::Invalid);
+        }
+
+        let decorations = self.decorations(s);
+        if s.len() > NAME_MAX {
+            return decorations
+                .into_iter()
+                .next()
+                .ok_or(NameRefParseError::TooLong);
+        }
+        if decorations.is_empty() {
+            Ok(NameRef::Partial(s.to_vec()))
+        } else {
+            Ok(NameRef::Ambiguous(s.to_vec()))
+        }
+    }
+
+    /// What a name reads as, from the most decorated reading to the name
+    /// itself. It refers to the first of them found in its directory.
+    pub fn readings(&self, s: &[u8]) -> Vec<NameRef> {
+        let mut readings = self.decorations(s);
+        if s.len() <= NAME_MAX {
+            readings.push(NameRef::Partial(s.to_vec()));
+        }
+        readings
+    }
 
-        let prefix = String::from(s.next().ok_or(NameRefParseError)?);
-        let view = match s.next() {
-            Some(view) => view,
-            None => return Ok(Self::Partial(prefix)),
+    /// The name an entry is listed under when decorated with its view, and
+    /// its inode when given.
+    pub fn decorate(&self, prefix: &[u8], view: View, ino: Option<u64>) -> Vec<u8> {
+        let decoration = match ino {
+            Some(ino) => format!("{sep}{}{sep}{}", view, ino, sep = self.separator),
+            None => format!("{}{}", self.separator, view),
         };
 
-        let view = view.parse().map_err(|_| NameRefParseError)?;
-        Ok(Self::Exact(Name::new(prefix, view)))
+        let mut name = prefix.to_vec();
+        name.extend_from_slice(decoration.as_bytes());
+        name
+    }
+
+    /* The decoration is looked for at the end of the name, the name of the
+    entry may hold separators too. They are looked for as their UTF-8
+    encoding, whatever the encoding of the rest of the name. */
+    fn decorations(&self, s: &[u8]) -> Vec<NameRef> {
+        let mut separator = [0; 4];
+        let separator = self.separator.encode_utf8(&mut separator).as_bytes();
+
+        let mut readings = Vec::new();
+        let (head, last) = match rsplit_once(s, separator) {
+            Some(split) => split,
+            None => return readings,
+        };
+
+        if let Some((prefix, view)) = rsplit_once(head, separator) {
+            if let (Some(view), Some(ino), true) =
+                (parse_number(view), parse_number(last), valid_prefix(prefix))
+            {
+                readings.push(NameRef::Sibling(Name::new(prefix, view), ino));
+            }
+        }
+        if let (Some(view), true) = (parse_number(last), valid_prefix(head)) {
+            readings.push(NameRef::Exact(Name::new(head, view)));
+        }
+
+        readings
+    }
+}
+
+fn rsplit_once<'a>(s: &'a [u8], separator: &[u8]) -> Option<(&'a [u8], &'a [u8])> {
+    let at = s.windows(separator.len()).rposition(|w| w == separator)?;
+    Some((&s[..at], &s[at + separator.len()..]))
+}
+
+fn valid_prefix(prefix: &[u8]) -> bool {
+    !prefix.is_empty() && prefix.len() <= NAME_MAX
+}
+
+/* Only numbers as they are printed, so that a name such as `x:02` is never
+taken for the decorated `x:2`. */
+fn parse_number<T: FromStr>(s: &[u8]) -> Option<T> {
+    let canonical = match s {
+        [b'0'] => true,
+        [b'1'..=b'9', rest @ ..] => rest.iter().all(u8::is_ascii_digit),
+        _ => false,
+    };
+    if !canonical {
+        return None;
+    }
+
+    std::str::from_utf8(s).ok()?.parse().ok()
+}
+
+impl Default for Naming {
+    fn default() -> Self {
+        Self {
+            separator: REF_SEP,
+            hide_local_view: true,
+        }
+    }
+}
+
+impl FromStr for NameRef {
+    type Err = NameRefParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Naming::default().parse(s.as_bytes())
     }
 }
@@ -1,11 +1,9 @@
 use elmerfs::{AddressBook, Bucket, Config, View};
-use std::ffi::OsString;
 use std::fs;
+use std::os::unix::fs::{DirEntryExt, MetadataExt};
 use std::path::Path;
 use std::process::{Command, Stdio};
 use std::sync::Arc;
-use std::thread;
-use std::time::Duration;
 use tempfile;
 use tracing::info;
 use tracing_subscriber::{self, filter::EnvFilter};
@@ -13,17 +11,25 @@ use tracing_subscriber::{self, filter::EnvFilter};
 const TEST_VIEW: View = 0;
 const CHTON_PATH: &str = "vendor/cthon04/";
 const CTHON_BASIC_BUCKET: Bucket = Bucket::new(0);
+const READDIR_BUCKET: Bucket = Bucket::new(1);
 const ANTIDOTE_URL: &str = "127.0.0.1:8101";
 
 fn setup_logging() {
     let filter = EnvFilter::try_from_default_env()
         .unwrap_or_default()
         .add_directive("async_std::task=warn".parse().unwrap())
-        .add_directive("fuse=error".parse().unwrap())
+        .add_directive("fuser=error".parse().unwrap())
         .add_directive("antidotec=trace".parse().unwrap())
         .add_directive("elmerfs=trace".parse().unwrap());
 
-    tracing_subscriber::fmt().with_env_filter(filter).init();
+    /* Tests of this file share the process. */
+    let _ = tracing_subscriber::fmt().with_env_filter(filter).try_init();
+}
+
+fn addresses() -> Arc<AddressBook> {
+    Arc::new(AddressBook::with_addresses(vec![String::from(
+        ANTIDOTE_URL,
+    )]))
 }
 
 #[test]
@@ -31,22 +37,13 @@ fn cthon_basic() {
     setup_logging();
 
     let tests_dir = tempfile::tempdir().expect("failed to create mountpoint tmpdir");
-    let cfg = Config {
-        view: TEST_VIEW,
-        bucket: CTHON_BASIC_BUCKET,
-        addresses: Arc::new(AddressBook::with_addresses(vec![String::from(
-            ANTIDOTE_URL,
-        )])),
-        locks: true,
-    };
+    let cfg = Config::new(TEST_VIEW, CTHON_BASIC_BUCKET, addresses());
 
     fs::create_dir_all(&tests_dir.path()).expect("failed ot create test mountpoint");
     info!(workdir = ?tests_dir.path().as_os_str());
 
-    let tests_dir_path = OsString::from(tests_dir.path().as_os_str());
-    let rpfs_thread = thread::spawn(move || elmerfs::run(cfg, &tests_dir_path));
+    let mount = elmerfs::mount(cfg, tests_dir.path()).expect("failed to mount");
 
-    thread::sleep(Duration::from_secs(5));
     let bin_dir = Path::new(CHTON_PATH).join("basic");
     let chton_status = dbg!(Command::new("./runtests")
         .current_dir(bin_dir)
@@ -58,7 +55,7 @@ fn cthon_basic() {
     assert_eq!(chton_status.code(), Some(0));
 
     tracing::info!("cleanup");
-    assert!(rpfs_thread.join().is_ok());
+    mount.unmount().expect("failed to unmount");
 }
 
 #[test]
@@ -66,22 +63,13 @@ fn cthon_general() {
     setup_logging();
 
     let tests_dir = tempfile::tempdir().expect("failed to create mountpoint tmpdir");
-    let cfg = Config {
-        view: TEST_VIEW,
-        bucket: CTHON_BASIC_BUCKET,
-        addresses: Arc::new(AddressBook::with_addresses(vec![String::from(
-            ANTIDOTE_URL,
-        )])),
-        locks: true,
-    };
+    let cfg = Config::new(TEST_VIEW, CTHON_BASIC_BUCKET, addresses());
 
     fs::create_dir_all(&tests_dir.path()).expect("failed ot create test mountpoint");
     info!(workdir = ?tests_dir.path().as_os_str());
 
-    let tests_dir_path = OsString::from(tests_dir.path().as_os_str());
-    let rpfs_thread = thread::spawn(move || elmerfs::run(cfg, &tests_dir_path));
+    let mount = elmerfs::mount(cfg, tests_dir.path()).expect("failed to mount");
 
-    thread::sleep(Duration::from_secs(5));
     let bin_dir = Path::new(CHTON_PATH).join("general");
     let chton_status = dbg!(Command::new("./runtests")
         .current_dir(bin_dir)
@@ -93,5 +81,34 @@ fn cthon_general() {
     assert_eq!(chton_status.code(), Some(0));
 
     tracing::info!("cleanup");
-    assert!(rpfs_thread.join().is_ok());
+    mount.unmount().expect("failed to unmount");
+}
+
+/* Programs such as find or git rely on d_ino. */
+#[test]
+fn readdir_inos() {
+    setup_logging();
+
+    let tests_dir = tempfile::tempdir().expect("failed to create mountpoint tmpdir");
+    let cfg = Config::new(TEST_VIEW, READDIR_BUCKET, addresses());
+
+    let mount = elmerfs::mount(cfg, tests_dir.path()).expect("failed to mount");
+
+    let dir = tempfile::tempdir_in(mount.mountpoint()).expect("failed to create test dir");
+    for name in &["a", "b", "c"] {
+        fs::write(dir.path().join(name), b"").expect("failed to create file");
+    }
+    fs::create_dir(dir.path().join("d")).expect("failed to create dir");
+
+    let mut listed = 0;
+    for entry in fs::read_dir(dir.path()).expect("failed to list dir") {
+        let entry = entry.expect("failed to read entry");
+        let metadata = fs::symlink_metadata(entry.path()).expect("failed to stat entry");
+        assert_eq!(entry.ino(), metadata.ino(), "{:?}", entry.path());
+        listed += 1;
+    }
+    assert_eq!(listed, 4);
+
+    dir.close().expect("failed to remove test dir");
+    mount.unmount().expect("failed to unmount");
 }
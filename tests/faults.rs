@@ -0,0 +1,99 @@
+#![cfg(feature = "fault-injection")]
+
+use elmerfs::{AddressBook, Bucket, Config, View};
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::info;
+use tracing_subscriber::{self, filter::EnvFilter};
+
+const TEST_VIEW: View = 0;
+const FAULTS_BUCKET: Bucket = Bucket::new(4);
+const ANTIDOTE_URL: &str = "127.0.0.1:8101";
+
+/* The same faults on every run, conflicts being retried often enough for
+most operations to get through. */
+const FAULTS: &str = "seed=7,latency=20ms@0.05,drop=0.02,abort=0.1";
+const RETRIES: u32 = 8;
+const FILES: usize = 64;
+
+fn setup_logging() {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_default()
+        .add_directive("async_std::task=warn".parse().unwrap())
+        .add_directive("fuser=error".parse().unwrap())
+        .add_directive("elmerfs=info".parse().unwrap());
+
+    let _ = tracing_subscriber::fmt().with_env_filter(filter).try_init();
+}
+
+fn config() -> Config {
+    let addresses = Arc::new(AddressBook::with_addresses(vec![String::from(
+        ANTIDOTE_URL,
+    )]));
+    Config {
+        retries: RETRIES,
+        ..Config::new(TEST_VIEW, FAULTS_BUCKET, addresses)
+    }
+}
+
+fn content(index: usize) -> Vec<u8> {
+    format!("file {} written under faults\n", index)
+        .repeat(index + 1)
+        .into_bytes()
+}
+
+fn write(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(content)?;
+    file.sync_all()
+}
+
+/* Operations may fail while faults are injected, but whatever was
+acknowledged must be read back once they are not anymore. */
+#[test]
+fn acknowledged_writes_survive_faults() {
+    setup_logging();
+
+    let mountpoint = tempfile::tempdir().expect("failed to create mountpoint tmpdir");
+    let run = format!("faults-{}", std::process::id());
+
+    env::set_var("ELMERFS_FAULTS", FAULTS);
+    let mount = elmerfs::mount(config(), mountpoint.path()).expect("failed to mount");
+
+    let dir = mountpoint.path().join(&run);
+    for _ in 0..RETRIES {
+        match fs::create_dir(&dir) {
+            Ok(()) => break,
+            Err(error) => info!(?error, "retrying the creation of the run directory"),
+        }
+    }
+    assert!(dir.is_dir(), "failed to create the run directory");
+
+    let mut acknowledged = Vec::new();
+    for index in 0..FILES {
+        let path = dir.join(format!("file-{}", index));
+        match write(&path, &content(index)) {
+            Ok(()) => acknowledged.push(index),
+            Err(error) => info!(index, ?error, "write failed under faults"),
+        }
+    }
+    info!(acknowledged = acknowledged.len(), "written under faults");
+    assert!(!acknowledged.is_empty(), "no write got through the faults");
+
+    mount.unmount().expect("failed to unmount");
+
+    env::remove_var("ELMERFS_FAULTS");
+    let mount = elmerfs::mount(config(), mountpoint.path()).expect("failed to mount");
+
+    for index in acknowledged {
+        let path = dir.join(format!("file-{}", index));
+        let read = fs::read(&path).expect("acknowledged file lost");
+        assert_eq!(read, content(index), "acknowledged file {} differs", index);
+    }
+
+    fs::remove_dir_all(&dir).expect("failed to clean up");
+    mount.unmount().expect("failed to unmount");
+}
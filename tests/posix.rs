@@ -0,0 +1,586 @@
+use elmerfs::{AddressBook, Bucket, Config, FuseOptions, ManualClock, View};
+use nix::dir::Dir;
+use nix::fcntl::OFlag;
+use nix::libc;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::stat::Mode;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::fs::{self as unix_fs, MetadataExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::info;
+use tracing_subscriber::{self, filter::EnvFilter};
+
+const TEST_VIEW: View = 0;
+const POSIX_BUCKET: Bucket = Bucket::new(3);
+
+/* Tests run against the Antidote given here, one is started in a container
+otherwise. */
+const ANTIDOTE_ENV: &str = "ELMERFS_TEST_ANTIDOTE";
+const ANTIDOTE_IMAGE: &str = "antidotedb/antidote:latest";
+const ANTIDOTE_PORT: u16 = 8087;
+const ANTIDOTE_STARTUP: Duration = Duration::from_secs(120);
+
+const NOBODY: u32 = 65534;
+
+type Case = fn(&Path);
+
+fn setup_logging() {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_default()
+        .add_directive("async_std::task=warn".parse().unwrap())
+        .add_directive("fuser=error".parse().unwrap())
+        .add_directive("elmerfs=info".parse().unwrap());
+
+    let _ = tracing_subscriber::fmt().with_env_filter(filter).try_init();
+}
+
+/// Antidote for the tests, stopped when dropped if it was started by them.
+struct Antidote {
+    address: String,
+    container: Option<String>,
+}
+
+impl Antidote {
+    fn start() -> Self {
+        if let Ok(address) = env::var(ANTIDOTE_ENV) {
+            return Self {
+                address,
+                container: None,
+            };
+        }
+
+        let output = Command::new("docker")
+            .args(["run", "--detach", "--rm", "--publish"])
+            .arg(format!("127.0.0.1::{}", ANTIDOTE_PORT))
+            .arg(ANTIDOTE_IMAGE)
+            .stderr(Stdio::inherit())
+            .output()
+            .expect("failed to run docker, set ELMERFS_TEST_ANTIDOTE to use a running antidote");
+        assert!(output.status.success(), "failed to start antidote");
+        let container = String::from_utf8(output.stdout).unwrap().trim().to_string();
+
+        let output = Command::new("docker")
+            .args(["port", &container])
+            .arg(ANTIDOTE_PORT.to_string())
+            .output()
+            .expect("failed to find the port of antidote");
+        let address = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .next()
+            .expect("antidote port not published")
+            .trim()
+            .to_string();
+
+        let antidote = Self {
+            address,
+            container: Some(container),
+        };
+        antidote.wait();
+        antidote
+    }
+
+    /* The port is published before Antidote listens on it, its first
+    connections are then closed right away. */
+    fn wait(&self) {
+        let started = Instant::now();
+        loop {
+            if let Ok(mut stream) = TcpStream::connect(&self.address) {
+                let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+                match stream.read(&mut [0u8]) {
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => return,
+                    Err(error) if error.kind() == io::ErrorKind::TimedOut => return,
+                    _ => {}
+                }
+            }
+
+            assert!(
+                started.elapsed() < ANTIDOTE_STARTUP,
+                "antidote didn't start in time"
+            );
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+}
+
+impl Drop for Antidote {
+    fn drop(&mut self) {
+        if let Some(container) = &self.container {
+            let _ = Command::new("docker")
+                .args(["stop", container])
+                .stdout(Stdio::null())
+                .status();
+        }
+    }
+}
+
+fn config(antidote: &Antidote) -> Config {
+    let addresses = Arc::new(AddressBook::with_addresses(vec![antidote.address.clone()]));
+    Config {
+        fuse: FuseOptions {
+            allow_other: true,
+            ..FuseOptions::default()
+        },
+        ..Config::new(TEST_VIEW, POSIX_BUCKET, addresses)
+    }
+}
+
+fn errno(result: io::Result<impl Sized>) -> i32 {
+    match result {
+        Ok(_) => 0,
+        Err(error) => error.raw_os_error().expect("not an os error"),
+    }
+}
+
+#[test]
+fn posix() {
+    setup_logging();
+
+    let antidote = Antidote::start();
+    let mountpoint = tempfile::tempdir().expect("failed to create mountpoint tmpdir");
+    let mount = elmerfs::mount(config(&antidote), mountpoint.path()).expect("failed to mount");
+
+    /* Each case works in a directory of its own, the bucket outlives the
+    runs. */
+    let run = tempfile::Builder::new()
+        .prefix("posix-")
+        .tempdir_in(mountpoint.path())
+        .expect("failed to create the directory of the run");
+    info!(workdir = ?run.path());
+
+    let cases: &[(&str, Case)] = &[
+        ("create_exclusive", create_exclusive),
+        ("rename_over_file", rename_over_file),
+        ("rename_over_directory", rename_over_directory),
+        ("rename_into_itself", rename_into_itself),
+        ("unlink_while_open", unlink_while_open),
+        ("unlink_touches_parent", unlink_touches_parent),
+        ("remove_mismatched_kind", remove_mismatched_kind),
+        ("hard_links", hard_links),
+        ("symlinks", symlinks),
+        ("separator_in_names", separator_in_names),
+        ("truncate", truncate),
+        ("poll_ready", poll_ready),
+        ("permissions", permissions),
+        ("search_permission", search_permission),
+        ("setgid_directories", setgid_directories),
+        ("chown_drops_setuid", chown_drops_setuid),
+        ("sticky_directories", sticky_directories),
+    ];
+    for (name, case) in cases {
+        info!(case = name, "running");
+        let dir = run.path().join(name);
+        fs::create_dir(&dir).unwrap();
+        case(&dir);
+    }
+    root_path(&antidote, run.path(), mountpoint.path());
+    clock_behind(&antidote, run.path(), mountpoint.path());
+
+    drop(run);
+    mount.unmount().expect("failed to unmount");
+}
+
+/* Another view mounts a directory of the run, its root is listed with the
+inode number the kernel knows it by whether listed with attributes or not. */
+fn root_path(antidote: &Antidote, run: &Path, mountpoint: &Path) {
+    info!(case = "root_path", "running");
+    let dir = run.join("root_path");
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("file"), b"").unwrap();
+
+    let root = tempfile::tempdir().expect("failed to create mountpoint tmpdir");
+    let cfg = Config {
+        view: TEST_VIEW + 1,
+        root_path: Some(dir.strip_prefix(mountpoint).unwrap().to_path_buf()),
+        ..config(antidote)
+    };
+    let mount = elmerfs::mount(cfg, root.path()).expect("failed to mount");
+
+    let root_ino = fs::metadata(root.path()).unwrap().ino();
+    let file_ino = fs::metadata(root.path().join("file")).unwrap().ino();
+    let list = || {
+        let mut listing = Dir::open(root.path(), OFlag::O_RDONLY, Mode::empty()).unwrap();
+        let mut entries: Vec<_> = listing
+            .iter()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.file_name().to_bytes().to_vec(), entry.ino())
+            })
+            .collect();
+        entries.sort();
+        entries
+    };
+
+    /* With readdirplus left to the kernel, the first listing of the
+    directory asks for attributes and the next one may not. */
+    let expected = vec![
+        (b".".to_vec(), root_ino),
+        (b"..".to_vec(), root_ino),
+        (b"file".to_vec(), file_ino),
+    ];
+    assert_eq!(list(), expected);
+    assert_eq!(list(), expected);
+
+    mount.unmount().expect("failed to unmount");
+}
+
+/* Another view whose clock is an hour behind changes a file and a
+directory, their mtimes move forward still. */
+fn clock_behind(antidote: &Antidote, run: &Path, mountpoint: &Path) {
+    info!(case = "clock_behind", "running");
+    let dir = run.join("clock_behind");
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("file"), b"ahead").unwrap();
+    let file_mtime = fs::metadata(dir.join("file")).unwrap().modified().unwrap();
+    let dir_mtime = fs::metadata(&dir).unwrap().modified().unwrap();
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let clock = Arc::new(ManualClock::new(now - Duration::from_secs(3600)));
+    let root = tempfile::tempdir().expect("failed to create mountpoint tmpdir");
+    let cfg = Config {
+        view: TEST_VIEW + 2,
+        clock: clock.clone(),
+        root_path: Some(dir.strip_prefix(mountpoint).unwrap().to_path_buf()),
+        ..config(antidote)
+    };
+    let mount = elmerfs::mount(cfg, root.path()).expect("failed to mount");
+
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(root.path().join("file"))
+        .unwrap();
+    file.write_all(b" and behind").unwrap();
+    file.sync_all().unwrap();
+    drop(file);
+    fs::write(root.path().join("other"), b"").unwrap();
+
+    let modified = |path: &Path| fs::metadata(path).unwrap().modified().unwrap();
+    assert!(modified(&root.path().join("file")) >= file_mtime);
+    assert!(modified(root.path()) > dir_mtime);
+
+    /* Stepping further back still keeps them from going back. */
+    let dir_mtime = modified(root.path());
+    clock.set(now - Duration::from_secs(7200));
+    fs::remove_file(root.path().join("other")).unwrap();
+    assert!(modified(root.path()) > dir_mtime);
+
+    mount.unmount().expect("failed to unmount");
+}
+
+fn create_exclusive(dir: &Path) {
+    let path = dir.join("file");
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .unwrap();
+
+    let again = OpenOptions::new().write(true).create_new(true).open(&path);
+    assert_eq!(errno(again), libc::EEXIST);
+}
+
+fn rename_over_file(dir: &Path) {
+    fs::write(dir.join("a"), b"from a").unwrap();
+    fs::write(dir.join("b"), b"from b").unwrap();
+    let replaced = fs::metadata(dir.join("b")).unwrap().ino();
+
+    fs::rename(dir.join("a"), dir.join("b")).unwrap();
+
+    assert_eq!(fs::read(dir.join("b")).unwrap(), b"from a");
+    assert_ne!(fs::metadata(dir.join("b")).unwrap().ino(), replaced);
+    assert_eq!(errno(fs::metadata(dir.join("a"))), libc::ENOENT);
+}
+
+fn rename_over_directory(dir: &Path) {
+    fs::create_dir(dir.join("src")).unwrap();
+    fs::create_dir(dir.join("empty")).unwrap();
+    fs::create_dir(dir.join("full")).unwrap();
+    fs::write(dir.join("full/file"), b"").unwrap();
+    fs::write(dir.join("file"), b"").unwrap();
+
+    assert_eq!(
+        errno(fs::rename(dir.join("src"), dir.join("full"))),
+        libc::ENOTEMPTY
+    );
+    assert_eq!(
+        errno(fs::rename(dir.join("src"), dir.join("file"))),
+        libc::ENOTDIR
+    );
+    assert_eq!(
+        errno(fs::rename(dir.join("file"), dir.join("empty"))),
+        libc::EISDIR
+    );
+
+    fs::rename(dir.join("src"), dir.join("empty")).unwrap();
+    assert!(fs::metadata(dir.join("empty")).unwrap().is_dir());
+    assert_eq!(errno(fs::metadata(dir.join("src"))), libc::ENOENT);
+}
+
+fn rename_into_itself(dir: &Path) {
+    fs::create_dir_all(dir.join("parent/child")).unwrap();
+
+    assert_eq!(
+        errno(fs::rename(
+            dir.join("parent"),
+            dir.join("parent/child/moved")
+        )),
+        libc::EINVAL
+    );
+    assert!(fs::metadata(dir.join("parent/child")).unwrap().is_dir());
+}
+
+fn unlink_while_open(dir: &Path) {
+    let path = dir.join("file");
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    file.write_all(b"still there").unwrap();
+
+    fs::remove_file(&path).unwrap();
+    assert_eq!(errno(fs::metadata(&path)), libc::ENOENT);
+
+    let mut content = [0u8; 11];
+    unix_fs::FileExt::read_exact_at(&file, &mut content, 0).unwrap();
+    assert_eq!(&content, b"still there");
+    assert_eq!(file.metadata().unwrap().nlink(), 0);
+
+    drop(file);
+    assert!(fs::read_dir(dir).unwrap().next().is_none());
+}
+
+fn unlink_touches_parent(dir: &Path) {
+    fs::write(dir.join("file"), b"").unwrap();
+    let before = fs::metadata(dir).unwrap();
+
+    thread::sleep(Duration::from_millis(10));
+    fs::remove_file(dir.join("file")).unwrap();
+
+    let after = fs::metadata(dir).unwrap();
+    assert!(after.modified().unwrap() > before.modified().unwrap());
+    assert!((after.ctime(), after.ctime_nsec()) > (before.ctime(), before.ctime_nsec()));
+}
+
+fn remove_mismatched_kind(dir: &Path) {
+    fs::create_dir(dir.join("dir")).unwrap();
+    fs::write(dir.join("dir/file"), b"").unwrap();
+    fs::write(dir.join("file"), b"").unwrap();
+
+    assert_eq!(errno(fs::remove_file(dir.join("dir"))), libc::EISDIR);
+    assert_eq!(errno(fs::remove_dir(dir.join("file"))), libc::ENOTDIR);
+    assert_eq!(errno(fs::remove_dir(dir.join("dir"))), libc::ENOTEMPTY);
+}
+
+fn hard_links(dir: &Path) {
+    fs::write(dir.join("a"), b"shared").unwrap();
+    fs::hard_link(dir.join("a"), dir.join("b")).unwrap();
+
+    let a = fs::metadata(dir.join("a")).unwrap();
+    let b = fs::metadata(dir.join("b")).unwrap();
+    assert_eq!(a.ino(), b.ino());
+    assert_eq!(a.nlink(), 2);
+
+    fs::remove_file(dir.join("a")).unwrap();
+    assert_eq!(fs::metadata(dir.join("b")).unwrap().nlink(), 1);
+    assert_eq!(fs::read(dir.join("b")).unwrap(), b"shared");
+}
+
+fn symlinks(dir: &Path) {
+    fs::write(dir.join("target"), b"pointed at").unwrap();
+    unix_fs::symlink("target", dir.join("link")).unwrap();
+    unix_fs::symlink("missing", dir.join("dangling")).unwrap();
+
+    assert_eq!(
+        fs::read_link(dir.join("link")).unwrap(),
+        Path::new("target")
+    );
+    assert_eq!(fs::read(dir.join("link")).unwrap(), b"pointed at");
+    assert!(fs::symlink_metadata(dir.join("dangling"))
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(errno(fs::metadata(dir.join("dangling"))), libc::ENOENT);
+}
+
+/* No other view created `x`, the names are taken as they are. */
+fn separator_in_names(dir: &Path) {
+    let names = ["a:b", "x:2", "x::2", "x:", ":2"];
+    for name in &names {
+        fs::write(dir.join(name), name.as_bytes()).unwrap();
+    }
+
+    let mut listed: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    listed.sort_unstable();
+    let mut expected: Vec<_> = names.iter().map(|name| name.to_string()).collect();
+    expected.sort_unstable();
+    assert_eq!(listed, expected);
+
+    for name in &names {
+        assert_eq!(fs::read(dir.join(name)).unwrap(), name.as_bytes());
+    }
+
+    fs::rename(dir.join("x:2"), dir.join("y:3")).unwrap();
+    assert_eq!(fs::read(dir.join("y:3")).unwrap(), b"x:2");
+    assert_eq!(errno(fs::metadata(dir.join("x:2"))), libc::ENOENT);
+}
+
+fn truncate(dir: &Path) {
+    let path = dir.join("file");
+    fs::write(&path, b"0123456789").unwrap();
+
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(4).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"0123");
+
+    file.set_len(8).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"0123\0\0\0\0");
+}
+
+fn poll_ready(dir: &Path) {
+    let file = File::create(dir.join("file")).unwrap();
+    let events = PollFlags::POLLIN | PollFlags::POLLOUT;
+    let mut fds = [PollFd::new(file.as_raw_fd(), events)];
+
+    assert_eq!(poll(&mut fds, 1000).unwrap(), 1);
+    assert_eq!(fds[0].revents(), Some(events));
+}
+
+/* Checked as another user from a thread of its own, the kernel telling
+the caller by its filesystem ids. Only root can switch to them. */
+fn permissions(dir: &Path) {
+    fs::write(dir.join("private"), b"").unwrap();
+    fs::write(dir.join("public"), b"").unwrap();
+    fs::set_permissions(dir.join("private"), fs::Permissions::from_mode(0o600)).unwrap();
+    fs::set_permissions(dir.join("public"), fs::Permissions::from_mode(0o644)).unwrap();
+
+    let mode = fs::metadata(dir.join("private")).unwrap().mode();
+    assert_eq!(mode & 0o7777, 0o600);
+
+    if unsafe { libc::geteuid() } != 0 {
+        info!("not root, skipping the checks as another user");
+        return;
+    }
+
+    let dir = dir.to_path_buf();
+    thread::spawn(move || {
+        unsafe {
+            libc::setfsgid(NOBODY);
+            libc::setfsuid(NOBODY);
+        }
+
+        assert_eq!(errno(File::open(dir.join("private"))), libc::EACCES);
+        File::open(dir.join("public")).unwrap();
+        assert_eq!(
+            errno(OpenOptions::new().write(true).open(dir.join("public"))),
+            libc::EACCES
+        );
+    })
+    .join()
+    .expect("permission checks failed");
+}
+
+/* The name is one the kernel never looked up, so that the lookup is not
+served from its cache and reaches the filesystem. */
+fn search_permission(dir: &Path) {
+    let locked = dir.join("locked");
+    fs::create_dir(&locked).unwrap();
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o700)).unwrap();
+
+    if unsafe { libc::geteuid() } != 0 {
+        info!("not root, skipping the checks as another user");
+        return;
+    }
+
+    thread::spawn(move || {
+        unsafe {
+            libc::setfsgid(NOBODY);
+            libc::setfsuid(NOBODY);
+        }
+
+        assert_eq!(errno(fs::metadata(locked.join("hidden"))), libc::EACCES);
+    })
+    .join()
+    .expect("search permission checks failed");
+}
+
+/* Only root can give the directory a group it is not in, the group is
+then checked to come from the directory rather than from the caller. */
+fn setgid_directories(dir: &Path) {
+    let shared = dir.join("shared");
+    fs::create_dir(&shared).unwrap();
+    if unsafe { libc::geteuid() } == 0 {
+        unix_fs::chown(&shared, None, Some(NOBODY)).unwrap();
+    }
+    fs::set_permissions(&shared, fs::Permissions::from_mode(0o2775)).unwrap();
+    let gid = fs::metadata(&shared).unwrap().gid();
+
+    fs::write(shared.join("file"), b"").unwrap();
+    fs::create_dir(shared.join("subdir")).unwrap();
+
+    assert_eq!(fs::metadata(shared.join("file")).unwrap().gid(), gid);
+    let subdir = fs::metadata(shared.join("subdir")).unwrap();
+    assert_eq!(subdir.gid(), gid);
+    assert_eq!(subdir.mode() & libc::S_ISGID, libc::S_ISGID);
+}
+
+fn chown_drops_setuid(dir: &Path) {
+    let path = dir.join("program");
+    fs::write(&path, b"").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o4755)).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().mode() & 0o7777, 0o4755);
+
+    let uid = fs::metadata(&path).unwrap().uid();
+    unix_fs::chown(&path, Some(uid), None).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().mode() & 0o7777, 0o755);
+}
+
+/* Entries of root in a sticky directory writable by anyone can't be
+removed nor renamed by another user, who still can remove their own. */
+fn sticky_directories(dir: &Path) {
+    let shared = dir.join("shared");
+    fs::create_dir(&shared).unwrap();
+    fs::set_permissions(&shared, fs::Permissions::from_mode(0o1777)).unwrap();
+    assert_eq!(fs::metadata(&shared).unwrap().mode() & 0o7777, 0o1777);
+
+    if unsafe { libc::geteuid() } != 0 {
+        info!("not root, skipping the checks as another user");
+        return;
+    }
+
+    fs::write(shared.join("file"), b"").unwrap();
+    fs::create_dir(shared.join("subdir")).unwrap();
+
+    thread::spawn(move || {
+        unsafe {
+            libc::setfsgid(NOBODY);
+            libc::setfsuid(NOBODY);
+        }
+
+        assert_eq!(errno(fs::remove_file(shared.join("file"))), libc::EPERM);
+        assert_eq!(errno(fs::remove_dir(shared.join("subdir"))), libc::EPERM);
+        assert_eq!(
+            errno(fs::rename(shared.join("file"), shared.join("moved"))),
+            libc::EPERM
+        );
+
+        fs::write(shared.join("own"), b"").unwrap();
+        fs::rename(shared.join("own"), shared.join("renamed")).unwrap();
+        fs::remove_file(shared.join("renamed")).unwrap();
+    })
+    .join()
+    .expect("sticky checks failed");
+}